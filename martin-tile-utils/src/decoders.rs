@@ -1,6 +1,6 @@
 use std::io::{Read as _, Write as _};
 
-use flate2::read::GzDecoder;
+use flate2::read::{GzDecoder, ZlibDecoder};
 use flate2::write::GzEncoder;
 
 pub fn decode_gzip(data: &[u8]) -> Result<Vec<u8>, std::io::Error> {
@@ -16,6 +16,13 @@ pub fn encode_gzip(data: &[u8]) -> Result<Vec<u8>, std::io::Error> {
     encoder.finish()
 }
 
+pub fn decode_zlib(data: &[u8]) -> Result<Vec<u8>, std::io::Error> {
+    let mut decoder = ZlibDecoder::new(data);
+    let mut decompressed = Vec::new();
+    decoder.read_to_end(&mut decompressed)?;
+    Ok(decompressed)
+}
+
 pub fn decode_brotli(data: &[u8]) -> Result<Vec<u8>, std::io::Error> {
     let mut decoder = brotli::Decompressor::new(data, 4096);
     let mut decompressed = Vec::new();
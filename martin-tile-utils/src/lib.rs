@@ -14,6 +14,12 @@ pub const MAX_ZOOM: u8 = 30;
 mod decoders;
 pub use decoders::*;
 
+mod mvt;
+pub use mvt::{
+    MVT_EXTENT_DEFAULT, MvtError, MvtFeature, MvtGeomType, MvtLayer, MvtLayerStats, MvtValue,
+    decode_mvt, get_layer_names, get_mvt_stats, overzoom_mvt, validate_mvt,
+};
+
 #[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
 pub struct TileCoord {
     pub z: u8,
@@ -21,6 +27,14 @@ pub struct TileCoord {
     pub y: u32,
 }
 
+impl TileCoord {
+    /// Check that the zoom is at most [`MAX_ZOOM`], and that `x` and `y` are within the `0..2^z` range of the zoom
+    #[must_use]
+    pub fn is_valid(&self) -> bool {
+        self.z <= MAX_ZOOM && u64::from(self.x) < 1 << self.z && u64::from(self.y) < 1 << self.z
+    }
+}
+
 impl Display for TileCoord {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
         if f.alternate() {
@@ -340,6 +354,20 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_tile_coord_is_valid() {
+        let xyz = |z, x, y| TileCoord { z, x, y };
+        assert!(xyz(0, 0, 0).is_valid());
+        assert!(xyz(3, 7, 7).is_valid());
+        assert!(xyz(30, (1 << 30) - 1, (1 << 30) - 1).is_valid());
+        assert!(!xyz(0, 1, 0).is_valid());
+        assert!(!xyz(0, 0, 1).is_valid());
+        assert!(!xyz(3, 999, 5).is_valid());
+        assert!(!xyz(30, 1 << 30, 0).is_valid());
+        assert!(!xyz(31, 0, 0).is_valid());
+        assert!(!xyz(u8::MAX, 0, 0).is_valid());
+    }
+
     #[test]
     fn test_tile_colrow() {
         assert_eq!((0, 0), tile_index(-180.0, 85.0511, 0));
@@ -0,0 +1,1256 @@
+//! Decoding of Mapbox Vector Tiles, e.g. to find the layers making a tile too large or to inspect its features,
+//! validation against the [MVT 2.1 specification](https://github.com/mapbox/vector-tile-spec/tree/master/2.1),
+//! and overzooming of the tiles by clipping and scaling up their geometries.
+//! Only the parts of the protobuf encoding used by MVT are decoded.
+
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+
+use MvtError::{Feature, Layer, Protobuf};
+
+/// Error of an MVT tile that could not be decoded or does not conform to the specification
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MvtError {
+    Protobuf(&'static str),
+    Layer(String, String),
+    /// Layer name, index of the feature in the layer, and the error
+    Feature(String, usize, String),
+}
+
+impl Display for MvtError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Protobuf(e) => write!(f, "Invalid protobuf encoding: {e}"),
+            Layer(name, e) => write!(f, "Layer {name:?}: {e}"),
+            Feature(name, idx, e) => write!(f, "Layer {name:?}, feature #{idx}: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for MvtError {}
+
+type Result<T> = std::result::Result<T, MvtError>;
+
+const CMD_MOVE_TO: u32 = 1;
+const CMD_LINE_TO: u32 = 2;
+const CMD_CLOSE_PATH: u32 = 7;
+
+/// A geometry command with the absolute coordinates of its points
+type Command = (u32, Vec<(i64, i64)>);
+
+/// Extent of the layers that do not set it, as defined by the MVT specification
+pub const MVT_EXTENT_DEFAULT: u32 = 4096;
+
+/// Number of features and vertices of a layer of an MVT tile
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MvtLayerStats {
+    pub name: String,
+    pub features: usize,
+    /// Points of the geometries of all features, without the implicit closing points of the polygon rings
+    pub vertices: usize,
+}
+
+/// A decoded layer of an MVT tile
+#[derive(Clone, Debug, PartialEq)]
+pub struct MvtLayer {
+    pub name: String,
+    /// Size of the tile in the coordinates of the features
+    pub extent: u32,
+    pub features: Vec<MvtFeature>,
+}
+
+/// A decoded feature of an MVT layer
+#[derive(Clone, Debug, PartialEq)]
+pub struct MvtFeature {
+    pub id: Option<u64>,
+    pub geom_type: MvtGeomType,
+    /// Parts of the geometry in tile coordinates, each starting with a `MoveTo` command.
+    /// The rings of the polygons are closed, i.e. their last point is the same as the first one.
+    pub geometry: Vec<Vec<[i64; 2]>>,
+    /// Properties of the feature, in tile order
+    pub properties: Vec<(String, MvtValue)>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MvtGeomType {
+    Unknown,
+    Point,
+    LineString,
+    Polygon,
+}
+
+/// Value of a feature property
+#[derive(Clone, Debug, PartialEq)]
+pub enum MvtValue {
+    String(String),
+    Float(f32),
+    Double(f64),
+    Int(i64),
+    Uint(u64),
+    Bool(bool),
+}
+
+/// Decode an uncompressed MVT tile, and check that it conforms to the MVT 2.1 specification:
+/// layer versions and unique names, valid value types, unique feature IDs, valid tag indexes,
+/// geometry command sequences, and polygon ring winding order.
+pub fn validate_mvt(data: &[u8]) -> Result<()> {
+    let mut names = HashSet::new();
+    let mut reader = PbReader::new(data);
+    while let Some((field, value)) = reader.next_field()? {
+        if field == 3 {
+            let name = validate_layer(value.bytes()?)?;
+            if !names.insert(name.clone()) {
+                return Err(Layer(name, "layer name is not unique".to_string()));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Decode the names of all layers of an uncompressed MVT tile, without decoding their features
+pub fn get_layer_names(data: &[u8]) -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    let mut reader = PbReader::new(data);
+    while let Some((field, value)) = reader.next_field()? {
+        if field == 3 {
+            let mut layer = PbReader::new(value.bytes()?);
+            while let Some((field, value)) = layer.next_field()? {
+                if field == 1 {
+                    names.push(value.string()?.to_string());
+                }
+            }
+        }
+    }
+    Ok(names)
+}
+
+/// Decode an uncompressed MVT tile, and count the features and vertices of each of its layers, in tile order
+pub fn get_mvt_stats(data: &[u8]) -> Result<Vec<MvtLayerStats>> {
+    let mut layers = Vec::new();
+    let mut reader = PbReader::new(data);
+    while let Some((field, value)) = reader.next_field()? {
+        if field == 3 {
+            layers.push(get_layer_stats(value.bytes()?)?);
+        }
+    }
+    Ok(layers)
+}
+
+fn get_layer_stats(data: &[u8]) -> Result<MvtLayerStats> {
+    let mut name = String::new();
+    let mut features = Vec::new();
+    let mut reader = PbReader::new(data);
+    while let Some((field, value)) = reader.next_field()? {
+        match field {
+            1 => name = value.string()?.to_string(),
+            2 => features.push(value.bytes()?),
+            _ => {}
+        }
+    }
+
+    let mut vertices = 0;
+    for (idx, feature) in features.iter().enumerate() {
+        vertices += count_vertices(feature).map_err(|e| Feature(name.clone(), idx, e))?;
+    }
+    Ok(MvtLayerStats {
+        name,
+        features: features.len(),
+        vertices,
+    })
+}
+
+/// Count the points of the `MoveTo` and `LineTo` commands of the geometry of a feature
+fn count_vertices(data: &[u8]) -> std::result::Result<usize, String> {
+    let mut geometry = Vec::new();
+    let mut reader = PbReader::new(data);
+    while let Some((field, value)) = reader.next_field().map_err(|e| e.to_string())? {
+        if field == 4 {
+            value.packed(&mut geometry).map_err(|e| e.to_string())?;
+        }
+    }
+    let geometry = geometry
+        .into_iter()
+        .map(u32::try_from)
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|_| "geometry integer does not fit into 32 bits".to_string())?;
+    Ok(decode_geometry(&geometry)?
+        .iter()
+        .map(|(_, points)| points.len())
+        .sum())
+}
+
+/// Decode an uncompressed MVT tile into its layers, in tile order
+pub fn decode_mvt(data: &[u8]) -> Result<Vec<MvtLayer>> {
+    let mut layers = Vec::new();
+    let mut reader = PbReader::new(data);
+    while let Some((field, value)) = reader.next_field()? {
+        if field == 3 {
+            layers.push(decode_layer(value.bytes()?)?);
+        }
+    }
+    Ok(layers)
+}
+
+fn decode_layer(data: &[u8]) -> Result<MvtLayer> {
+    let mut name = String::new();
+    let mut extent = MVT_EXTENT_DEFAULT;
+    let mut keys = Vec::new();
+    let mut values = Vec::new();
+    // The keys and values may come after the features that use them
+    let mut features = Vec::new();
+    let mut reader = PbReader::new(data);
+    while let Some((field, value)) = reader.next_field()? {
+        match field {
+            1 => name = value.string()?.to_string(),
+            2 => features.push(value.bytes()?),
+            3 => keys.push(value.string()?.to_string()),
+            4 => values.push(value.bytes()?),
+            5 => {
+                extent = u32::try_from(value.varint()?)
+                    .map_err(|_| Layer(name.clone(), "extent does not fit into 32 bits".into()))?;
+            }
+            _ => {}
+        }
+    }
+
+    let values = values
+        .into_iter()
+        .enumerate()
+        .map(|(idx, v)| {
+            decode_value(v).map_err(|e| Layer(name.clone(), format!("value #{idx} {e}")))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let features = features
+        .into_iter()
+        .enumerate()
+        .map(|(idx, v)| {
+            decode_feature(v, &keys, &values).map_err(|e| Feature(name.clone(), idx, e))
+        })
+        .collect::<Result<_>>()?;
+    Ok(MvtLayer {
+        name,
+        extent,
+        features,
+    })
+}
+
+fn decode_feature(
+    data: &[u8],
+    keys: &[String],
+    values: &[MvtValue],
+) -> std::result::Result<MvtFeature, String> {
+    let mut decoded = MvtFeature {
+        id: None,
+        geom_type: MvtGeomType::Unknown,
+        geometry: Vec::new(),
+        properties: Vec::new(),
+    };
+    let mut tags = Vec::new();
+    let mut geometry = Vec::new();
+    let mut reader = PbReader::new(data);
+    while let Some((field, value)) = reader.next_field().map_err(|e| e.to_string())? {
+        match field {
+            1 => decoded.id = Some(value.varint().map_err(|e| e.to_string())?),
+            2 => value.packed(&mut tags).map_err(|e| e.to_string())?,
+            3 => {
+                decoded.geom_type = match value.varint().map_err(|e| e.to_string())? {
+                    1 => MvtGeomType::Point,
+                    2 => MvtGeomType::LineString,
+                    3 => MvtGeomType::Polygon,
+                    _ => MvtGeomType::Unknown,
+                };
+            }
+            4 => value.packed(&mut geometry).map_err(|e| e.to_string())?,
+            _ => {}
+        }
+    }
+
+    if tags.len() % 2 != 0 {
+        return Err("tags must contain an even number of indexes".to_string());
+    }
+    for pair in tags.chunks_exact(2) {
+        let key = get_tag(keys, pair[0])
+            .ok_or_else(|| format!("tag key index {} is out of range", pair[0]))?;
+        let value = get_tag(values, pair[1])
+            .ok_or_else(|| format!("tag value index {} is out of range", pair[1]))?;
+        decoded.properties.push((key.clone(), value.clone()));
+    }
+    let geometry = geometry
+        .into_iter()
+        .map(u32::try_from)
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|_| "geometry integer does not fit into 32 bits".to_string())?;
+    decoded.geometry = to_parts(decode_geometry(&geometry)?)?;
+    Ok(decoded)
+}
+
+fn get_tag<T>(items: &[T], idx: u64) -> Option<&T> {
+    usize::try_from(idx).ok().and_then(|idx| items.get(idx))
+}
+
+/// Decode the value of a property, which must have one of the typed fields
+#[allow(clippy::cast_possible_wrap)]
+fn decode_value(data: &[u8]) -> std::result::Result<MvtValue, String> {
+    let mut decoded = None;
+    let mut reader = PbReader::new(data);
+    while let Some((field, value)) = reader.next_field().map_err(|e| e.to_string())? {
+        decoded = Some(match (field, value) {
+            (1, PbValue::Bytes(_)) => {
+                MvtValue::String(value.string().map_err(|e| e.to_string())?.to_string())
+            }
+            (2, PbValue::Fixed32(v)) => MvtValue::Float(f32::from_bits(v)),
+            (3, PbValue::Fixed64(v)) => MvtValue::Double(f64::from_bits(v)),
+            (4, PbValue::Varint(v)) => MvtValue::Int(v as i64),
+            (5, PbValue::Varint(v)) => MvtValue::Uint(v),
+            (6, PbValue::Varint(v)) => MvtValue::Int(zigzag(v)),
+            (7, PbValue::Varint(v)) => MvtValue::Bool(v != 0),
+            _ => continue,
+        });
+    }
+    decoded.ok_or_else(|| "has no known typed field".to_string())
+}
+
+/// Split the decoded geometry commands into parts, each starting with a `MoveTo` command, and close the rings
+fn to_parts(commands: Vec<Command>) -> std::result::Result<Vec<Vec<[i64; 2]>>, String> {
+    let mut parts: Vec<Vec<[i64; 2]>> = Vec::new();
+    for (cmd, points) in commands {
+        let points = points.into_iter().map(|(x, y)| [x, y]);
+        match cmd {
+            // Each point of a MultiPoint starts a separate part
+            CMD_MOVE_TO => parts.extend(points.map(|v| vec![v])),
+            CMD_LINE_TO => parts
+                .last_mut()
+                .ok_or("LineTo command without a MoveTo")?
+                .extend(points),
+            _ => {
+                let part = parts
+                    .last_mut()
+                    .ok_or("ClosePath command without a MoveTo")?;
+                part.push(part[0]);
+            }
+        }
+    }
+    Ok(parts)
+}
+
+/// Buffer kept around the overzoomed tiles, in 1/4096 of the tile extent,
+/// so that the clipped lines and polygon edges are not drawn at the tile border
+const OVERZOOM_BUFFER: f64 = 64.0;
+
+/// Get the part of an uncompressed MVT tile covered by one of its descendant tiles `dz` zoom levels higher,
+/// scaled up to the full tile extent. The descendant is at column `dx` and row `dy` of the `2^dz` by `2^dz` grid
+/// of descendants. Geometries are clipped to the descendant tile with a small buffer,
+/// and the features and layers left without any geometry are dropped.
+pub fn overzoom_mvt(data: &[u8], dz: u8, dx: u32, dy: u32) -> Result<Vec<u8>> {
+    let mut tile = Vec::new();
+    let mut reader = PbReader::new(data);
+    while let Some((field, value)) = reader.next_field()? {
+        if field == 3 {
+            if let Some(layer) = overzoom_layer(value.bytes()?, dz, dx, dy)? {
+                write_field_bytes(3, &layer, &mut tile);
+            }
+        }
+    }
+    Ok(tile)
+}
+
+/// Overzoom the features of a layer, copying all other fields as they are
+fn overzoom_layer(data: &[u8], dz: u8, dx: u32, dy: u32) -> Result<Option<Vec<u8>>> {
+    let mut name = String::new();
+    let mut extent = 4096;
+    let mut reader = PbReader::new(data);
+    while let Some((field, value)) = reader.next_field()? {
+        match field {
+            1 => name = value.string()?.to_string(),
+            5 => extent = value.varint()?,
+            _ => {}
+        }
+    }
+
+    let clip = ClipBox::new(extent, dz, dx, dy);
+    let mut layer = Vec::new();
+    let mut has_features = false;
+    let mut idx = 0;
+    let mut reader = PbReader::new(data);
+    while let Some((field, value)) = reader.next_field()? {
+        if field == 2 {
+            let feature = overzoom_feature(value.bytes()?, &clip)
+                .map_err(|e| Feature(name.clone(), idx, e))?;
+            if let Some(feature) = feature {
+                write_field_bytes(2, &feature, &mut layer);
+                has_features = true;
+            }
+            idx += 1;
+        } else {
+            write_field(field, value, &mut layer);
+        }
+    }
+    Ok(has_features.then_some(layer))
+}
+
+fn overzoom_feature(data: &[u8], clip: &ClipBox) -> std::result::Result<Option<Vec<u8>>, String> {
+    let mut fields = Vec::new();
+    let mut geom_type = 0;
+    let mut geometry = Vec::new();
+    let mut reader = PbReader::new(data);
+    while let Some((field, value)) = reader.next_field().map_err(|e| e.to_string())? {
+        match field {
+            3 => {
+                geom_type = value.varint().map_err(|e| e.to_string())?;
+                fields.push((field, value));
+            }
+            4 => value.packed(&mut geometry).map_err(|e| e.to_string())?,
+            _ => fields.push((field, value)),
+        }
+    }
+
+    let geometry = geometry
+        .into_iter()
+        .map(u32::try_from)
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|_| "geometry integer does not fit into 32 bits".to_string())?;
+    let commands = decode_geometry(&geometry)?;
+    let parts = match geom_type {
+        1 => clip.points(commands),
+        2 => clip.lines(commands),
+        3 => clip.polygons(commands),
+        _ => return Err(format!("geometry type {geom_type} is not supported")),
+    };
+    if parts.is_empty() {
+        return Ok(None);
+    }
+
+    let mut feature = Vec::new();
+    for (field, value) in fields {
+        write_field(field, value, &mut feature);
+    }
+    let mut packed = Vec::new();
+    for value in encode_geometry(geom_type, &parts) {
+        write_varint(value, &mut packed);
+    }
+    write_field_bytes(4, &packed, &mut feature);
+    Ok(Some(feature))
+}
+
+/// Mapping of the coordinates of a tile to one of its descendant tiles, and the clipping box around the descendant
+struct ClipBox {
+    scale: f64,
+    offset: [f64; 2],
+    min: f64,
+    max: f64,
+}
+
+impl ClipBox {
+    #[allow(clippy::cast_precision_loss)]
+    fn new(extent: u64, dz: u8, dx: u32, dy: u32) -> Self {
+        let extent = extent as f64;
+        let buffer = extent * OVERZOOM_BUFFER / 4096.0;
+        Self {
+            scale: 2_f64.powi(i32::from(dz)),
+            offset: [f64::from(dx) * extent, f64::from(dy) * extent],
+            min: -buffer,
+            max: extent + buffer,
+        }
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn transform(&self, (x, y): (i64, i64)) -> [f64; 2] {
+        [
+            x as f64 * self.scale - self.offset[0],
+            y as f64 * self.scale - self.offset[1],
+        ]
+    }
+
+    fn contains(&self, point: [f64; 2]) -> bool {
+        point.iter().all(|v| (self.min..=self.max).contains(v))
+    }
+
+    fn points(&self, commands: Vec<Command>) -> Vec<Vec<(i64, i64)>> {
+        let points: Vec<_> = commands
+            .into_iter()
+            .flat_map(|(_, points)| points)
+            .map(|v| self.transform(v))
+            .filter(|v| self.contains(*v))
+            .map(round)
+            .collect();
+        if points.is_empty() {
+            Vec::new()
+        } else {
+            vec![points]
+        }
+    }
+
+    /// Clip each segment of the lines, splitting them where they leave the clipping box
+    fn lines(&self, commands: Vec<Command>) -> Vec<Vec<(i64, i64)>> {
+        let mut parts = Vec::new();
+        for line in split_paths(commands) {
+            let line: Vec<_> = line.into_iter().map(|v| self.transform(v)).collect();
+            let mut part: Vec<(i64, i64)> = Vec::new();
+            for segment in line.windows(2) {
+                let Some((start, end)) = self.clip_segment(segment[0], segment[1]) else {
+                    push_line(&mut parts, &mut part);
+                    continue;
+                };
+                if part.last() != Some(&start) {
+                    push_line(&mut parts, &mut part);
+                    part.push(start);
+                }
+                if part.last() != Some(&end) {
+                    part.push(end);
+                }
+            }
+            push_line(&mut parts, &mut part);
+        }
+        parts
+    }
+
+    /// Clip the segment from `start` to `end` with the Liang-Barsky algorithm
+    fn clip_segment(&self, start: [f64; 2], end: [f64; 2]) -> Option<((i64, i64), (i64, i64))> {
+        let delta = [end[0] - start[0], end[1] - start[1]];
+        let (mut t0, mut t1) = (0.0_f64, 1.0_f64);
+        for axis in 0..2 {
+            for (p, q) in [
+                (-delta[axis], start[axis] - self.min),
+                (delta[axis], self.max - start[axis]),
+            ] {
+                if p == 0.0 {
+                    if q < 0.0 {
+                        return None;
+                    }
+                } else if p < 0.0 {
+                    t0 = t0.max(q / p);
+                } else {
+                    t1 = t1.min(q / p);
+                }
+            }
+        }
+        let at = |t: f64| round([start[0] + t * delta[0], start[1] + t * delta[1]]);
+        (t0 <= t1).then(|| (at(t0), at(t1)))
+    }
+
+    /// Clip each ring with the Sutherland-Hodgman algorithm, which keeps the winding order.
+    /// The interior rings are dropped with their exterior ring.
+    fn polygons(&self, commands: Vec<Command>) -> Vec<Vec<(i64, i64)>> {
+        let mut rings = Vec::new();
+        let mut keep_interior = false;
+        for ring in split_paths(commands) {
+            let is_exterior = ring_area(ring.iter()) > 0;
+            let mut ring: Vec<_> = ring.into_iter().map(|v| self.transform(v)).collect();
+            for axis in 0..2 {
+                ring = clip_ring(&ring, axis, self.min, true);
+                ring = clip_ring(&ring, axis, self.max, false);
+            }
+            let mut ring: Vec<_> = ring.into_iter().map(round).collect();
+            ring.dedup();
+            if ring.len() > 1 && ring.first() == ring.last() {
+                ring.pop();
+            }
+            let is_valid = ring.len() >= 3 && ring_area(ring.iter()) != 0;
+            if is_exterior {
+                keep_interior = is_valid;
+            }
+            if is_valid && (is_exterior || keep_interior) {
+                rings.push(ring);
+            }
+        }
+        rings
+    }
+}
+
+/// Split the decoded geometry commands into the paths starting with each `MoveTo` command
+fn split_paths(commands: Vec<Command>) -> Vec<Vec<(i64, i64)>> {
+    let mut paths: Vec<Vec<(i64, i64)>> = Vec::new();
+    for (cmd, points) in commands {
+        if cmd == CMD_MOVE_TO {
+            paths.push(Vec::new());
+        }
+        if let Some(path) = paths.last_mut() {
+            path.extend(points);
+        }
+    }
+    paths
+}
+
+/// Move the clipped part of a line to the result if it has at least two points
+fn push_line(parts: &mut Vec<Vec<(i64, i64)>>, part: &mut Vec<(i64, i64)>) {
+    if part.len() > 1 {
+        parts.push(std::mem::take(part));
+    } else {
+        part.clear();
+    }
+}
+
+/// Clip a ring by one side of the clipping box, keeping the points above or below `bound` on the `axis`
+fn clip_ring(ring: &[[f64; 2]], axis: usize, bound: f64, keep_above: bool) -> Vec<[f64; 2]> {
+    let inside = |p: &[f64; 2]| {
+        if keep_above {
+            p[axis] >= bound
+        } else {
+            p[axis] <= bound
+        }
+    };
+    let intersect = |a: &[f64; 2], b: &[f64; 2]| {
+        let t = (bound - a[axis]) / (b[axis] - a[axis]);
+        [a[0] + t * (b[0] - a[0]), a[1] + t * (b[1] - a[1])]
+    };
+    let mut result = Vec::new();
+    for (idx, current) in ring.iter().enumerate() {
+        let previous = &ring[(idx + ring.len() - 1) % ring.len()];
+        match (inside(previous), inside(current)) {
+            (true, true) => result.push(*current),
+            (false, true) => {
+                result.push(intersect(previous, current));
+                result.push(*current);
+            }
+            (true, false) => result.push(intersect(previous, current)),
+            (false, false) => {}
+        }
+    }
+    result
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn round(point: [f64; 2]) -> (i64, i64) {
+    (point[0].round() as i64, point[1].round() as i64)
+}
+
+/// Encode the geometry commands of the points, lines, or polygon rings with absolute coordinates
+fn encode_geometry(geom_type: u64, parts: &[Vec<(i64, i64)>]) -> Vec<u64> {
+    let mut geometry = Vec::new();
+    let mut cursor = (0, 0);
+    let mut push = |geometry: &mut Vec<u64>, cmd: u32, points: &[(i64, i64)]| {
+        geometry.push(((points.len() as u64) << 3) | u64::from(cmd));
+        for &(x, y) in points {
+            geometry.push(zigzag_encode(x - cursor.0));
+            geometry.push(zigzag_encode(y - cursor.1));
+            cursor = (x, y);
+        }
+    };
+    for part in parts {
+        if geom_type == 1 {
+            push(&mut geometry, CMD_MOVE_TO, part);
+        } else {
+            push(&mut geometry, CMD_MOVE_TO, &part[..1]);
+            push(&mut geometry, CMD_LINE_TO, &part[1..]);
+            if geom_type == 3 {
+                geometry.push((1 << 3) | u64::from(CMD_CLOSE_PATH));
+            }
+        }
+    }
+    geometry
+}
+
+#[allow(clippy::cast_sign_loss)]
+fn zigzag_encode(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn validate_layer(data: &[u8]) -> Result<String> {
+    let mut version = 1;
+    let mut name = None;
+    let mut features = Vec::new();
+    let mut keys = 0;
+    let mut values = Vec::new();
+    let mut extent = 4096;
+    let mut reader = PbReader::new(data);
+    while let Some((field, value)) = reader.next_field()? {
+        match field {
+            15 => version = value.varint()?,
+            1 => name = Some(value.string()?.to_string()),
+            2 => features.push(value.bytes()?),
+            3 => {
+                value.string()?;
+                keys += 1;
+            }
+            4 => values.push(value.bytes()?),
+            5 => extent = value.varint()?,
+            _ => {}
+        }
+    }
+
+    let name = name.ok_or(Protobuf("layer without a name"))?;
+    let err = |msg: String| Err(Layer(name.clone(), msg));
+    if version != 2 {
+        return err(format!("version is {version} instead of 2"));
+    }
+    if extent == 0 {
+        return err("extent must be greater than zero".to_string());
+    }
+    for (idx, value) in values.iter().enumerate() {
+        if let Err(e) = validate_value(value) {
+            return err(format!("value #{idx} {e}"));
+        }
+    }
+
+    let mut ids = HashSet::new();
+    for (idx, feature) in features.iter().enumerate() {
+        validate_feature(feature, keys, values.len(), &mut ids)
+            .map_err(|e| Feature(name.clone(), idx, e))?;
+    }
+    Ok(name)
+}
+
+/// Each value must have exactly one of the typed fields
+fn validate_value(data: &[u8]) -> std::result::Result<(), String> {
+    let mut count = 0;
+    let mut reader = PbReader::new(data);
+    while let Some((field, value)) = reader.next_field().map_err(|e| e.to_string())? {
+        let valid = match field {
+            1 => value.string().is_ok(),
+            2 => matches!(value, PbValue::Fixed32(_)),
+            3 => matches!(value, PbValue::Fixed64(_)),
+            4..=7 => matches!(value, PbValue::Varint(_)),
+            _ => continue,
+        };
+        if !valid {
+            return Err(format!("has an invalid encoding of field {field}"));
+        }
+        count += 1;
+    }
+    if count == 1 {
+        Ok(())
+    } else {
+        Err(format!(
+            "must have exactly one typed field, but has {count}"
+        ))
+    }
+}
+
+fn validate_feature(
+    data: &[u8],
+    keys: usize,
+    values: usize,
+    ids: &mut HashSet<u64>,
+) -> std::result::Result<(), String> {
+    let mut tags = Vec::new();
+    let mut geom_type = 0;
+    let mut geometry = Vec::new();
+    let mut reader = PbReader::new(data);
+    while let Some((field, value)) = reader.next_field().map_err(|e| e.to_string())? {
+        match field {
+            1 => {
+                let id = value.varint().map_err(|e| e.to_string())?;
+                if !ids.insert(id) {
+                    return Err(format!("feature ID {id} is not unique"));
+                }
+            }
+            2 => value.packed(&mut tags).map_err(|e| e.to_string())?,
+            3 => geom_type = value.varint().map_err(|e| e.to_string())?,
+            4 => value.packed(&mut geometry).map_err(|e| e.to_string())?,
+            _ => {}
+        }
+    }
+
+    if tags.len() % 2 != 0 {
+        return Err("tags must contain an even number of indexes".to_string());
+    }
+    for pair in tags.chunks_exact(2) {
+        if pair[0] >= keys as u64 {
+            return Err(format!("tag key index {} is out of range", pair[0]));
+        }
+        if pair[1] >= values as u64 {
+            return Err(format!("tag value index {} is out of range", pair[1]));
+        }
+    }
+
+    let geometry = geometry
+        .into_iter()
+        .map(u32::try_from)
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|_| "geometry integer does not fit into 32 bits".to_string())?;
+    match geom_type {
+        1 => validate_point(&geometry),
+        2 => validate_linestring(&geometry),
+        3 => validate_polygon(&geometry),
+        _ => Err(format!("geometry type {geom_type} is not supported")),
+    }
+}
+
+/// Decoded geometry commands with absolute coordinates
+fn decode_geometry(geometry: &[u32]) -> std::result::Result<Vec<Command>, String> {
+    let mut commands = Vec::new();
+    let (mut x, mut y) = (0_i64, 0_i64);
+    let mut iter = geometry.iter();
+    while let Some(&cmd_int) = iter.next() {
+        let (cmd, count) = (cmd_int & 0x7, cmd_int >> 3);
+        let mut points = Vec::new();
+        match cmd {
+            CMD_MOVE_TO | CMD_LINE_TO => {
+                if count == 0 {
+                    return Err(format!("command {cmd} must have a positive count"));
+                }
+                for _ in 0..count {
+                    let (Some(&dx), Some(&dy)) = (iter.next(), iter.next()) else {
+                        return Err(format!("command {cmd} is missing parameters"));
+                    };
+                    x += zigzag(u64::from(dx));
+                    y += zigzag(u64::from(dy));
+                    points.push((x, y));
+                }
+            }
+            CMD_CLOSE_PATH => {
+                if count != 1 {
+                    return Err("ClosePath command must have a count of 1".to_string());
+                }
+            }
+            _ => return Err(format!("unknown geometry command {cmd}")),
+        }
+        commands.push((cmd, points));
+    }
+    Ok(commands)
+}
+
+#[allow(clippy::cast_possible_wrap)]
+fn zigzag(value: u64) -> i64 {
+    (value >> 1) as i64 ^ -((value & 1) as i64)
+}
+
+fn validate_point(geometry: &[u32]) -> std::result::Result<(), String> {
+    match decode_geometry(geometry)?.as_slice() {
+        [(CMD_MOVE_TO, _)] => Ok(()),
+        _ => Err("point geometry must be a single MoveTo command".to_string()),
+    }
+}
+
+fn validate_linestring(geometry: &[u32]) -> std::result::Result<(), String> {
+    let commands = decode_geometry(geometry)?;
+    if commands.is_empty() {
+        return Err("linestring geometry is empty".to_string());
+    }
+    for line in commands.chunks(2) {
+        match line {
+            [(CMD_MOVE_TO, start), (CMD_LINE_TO, _)] if start.len() == 1 => {}
+            _ => {
+                return Err("linestring must consist of MoveTo(1) and LineTo commands".to_string());
+            }
+        }
+    }
+    Ok(())
+}
+
+fn validate_polygon(geometry: &[u32]) -> std::result::Result<(), String> {
+    let commands = decode_geometry(geometry)?;
+    if commands.is_empty() {
+        return Err("polygon geometry is empty".to_string());
+    }
+    for (idx, ring) in commands.chunks(3).enumerate() {
+        let [
+            (CMD_MOVE_TO, start),
+            (CMD_LINE_TO, line),
+            (CMD_CLOSE_PATH, _),
+        ] = ring
+        else {
+            return Err(
+                "polygon ring must consist of MoveTo(1), LineTo, and ClosePath commands"
+                    .to_string(),
+            );
+        };
+        if start.len() != 1 || line.len() < 2 {
+            return Err(format!("polygon ring #{idx} has less than 3 points"));
+        }
+        let area = ring_area(start.iter().chain(line));
+        if area == 0 {
+            return Err(format!("polygon ring #{idx} has zero area"));
+        }
+        if idx == 0 && area < 0 {
+            return Err(
+                "first polygon ring must be an exterior ring with clockwise winding order"
+                    .to_string(),
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Twice the signed area of a ring using the surveyor's formula, positive for exterior rings
+fn ring_area<'a>(points: impl Iterator<Item = &'a (i64, i64)> + Clone) -> i64 {
+    let first = points.clone().next().copied();
+    points
+        .clone()
+        .zip(points.skip(1).copied().chain(first))
+        .map(|((x1, y1), (x2, y2))| x1 * y2 - x2 * y1)
+        .sum()
+}
+
+#[derive(Debug, Clone, Copy)]
+enum PbValue<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    Bytes(&'a [u8]),
+    Fixed32(u32),
+}
+
+impl<'a> PbValue<'a> {
+    fn varint(self) -> Result<u64> {
+        match self {
+            PbValue::Varint(v) => Ok(v),
+            _ => Err(Protobuf("expected a varint field")),
+        }
+    }
+
+    fn bytes(self) -> Result<&'a [u8]> {
+        match self {
+            PbValue::Bytes(v) => Ok(v),
+            _ => Err(Protobuf("expected a length-delimited field")),
+        }
+    }
+
+    fn string(self) -> Result<&'a str> {
+        std::str::from_utf8(self.bytes()?).map_err(|_| Protobuf("string is not valid UTF-8"))
+    }
+
+    /// Append a repeated varint field, either packed or not
+    fn packed(self, values: &mut Vec<u64>) -> Result<()> {
+        match self {
+            PbValue::Varint(v) => values.push(v),
+            PbValue::Bytes(data) => {
+                let mut reader = PbReader::new(data);
+                while !reader.is_empty() {
+                    values.push(reader.varint()?);
+                }
+            }
+            _ => return Err(Protobuf("expected a packed varint field")),
+        }
+        Ok(())
+    }
+}
+
+struct PbReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> PbReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn varint(&mut self) -> Result<u64> {
+        let mut value = 0_u64;
+        for (idx, &byte) in self.data.iter().enumerate().take(10) {
+            value |= u64::from(byte & 0x7F) << (7 * idx);
+            if byte & 0x80 == 0 {
+                self.data = &self.data[idx + 1..];
+                return Ok(value);
+            }
+        }
+        Err(Protobuf("truncated or too long varint"))
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.data.len() < len {
+            return Err(Protobuf("truncated field"));
+        }
+        let (value, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(value)
+    }
+
+    fn next_field(&mut self) -> Result<Option<(u32, PbValue<'a>)>> {
+        if self.is_empty() {
+            return Ok(None);
+        }
+        let key = self.varint()?;
+        let field = u32::try_from(key >> 3).map_err(|_| Protobuf("invalid field number"))?;
+        let value = match key & 0x7 {
+            0 => PbValue::Varint(self.varint()?),
+            1 => PbValue::Fixed64(u64::from_le_bytes(self.take(8)?.try_into().unwrap())),
+            2 => {
+                let len = usize::try_from(self.varint()?)
+                    .map_err(|_| Protobuf("invalid field length"))?;
+                PbValue::Bytes(self.take(len)?)
+            }
+            5 => PbValue::Fixed32(u32::from_le_bytes(self.take(4)?.try_into().unwrap())),
+            _ => return Err(Protobuf("unsupported wire type")),
+        };
+        Ok(Some((field, value)))
+    }
+}
+
+fn write_varint(mut value: u64, buf: &mut Vec<u8>) {
+    while value >= 0x80 {
+        buf.push(u8::try_from(value & 0x7F).unwrap() | 0x80);
+        value >>= 7;
+    }
+    buf.push(u8::try_from(value).unwrap());
+}
+
+fn write_field_varint(field: u32, value: u64, buf: &mut Vec<u8>) {
+    write_varint(u64::from(field) << 3, buf);
+    write_varint(value, buf);
+}
+
+fn write_field_bytes(field: u32, value: &[u8], buf: &mut Vec<u8>) {
+    write_varint((u64::from(field) << 3) | 2, buf);
+    write_varint(value.len() as u64, buf);
+    buf.extend_from_slice(value);
+}
+
+/// Write a decoded field with its original wire type
+fn write_field(field: u32, value: PbValue, buf: &mut Vec<u8>) {
+    match value {
+        PbValue::Varint(v) => write_field_varint(field, v, buf),
+        PbValue::Fixed64(v) => {
+            write_varint((u64::from(field) << 3) | 1, buf);
+            buf.extend_from_slice(&v.to_le_bytes());
+        }
+        PbValue::Bytes(v) => write_field_bytes(field, v, buf),
+        PbValue::Fixed32(v) => {
+            write_varint((u64::from(field) << 3) | 5, buf);
+            buf.extend_from_slice(&v.to_le_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packed(values: &[u64]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for &v in values {
+            write_varint(v, &mut buf);
+        }
+        buf
+    }
+
+    fn feature(id: u64, tags: &[u64], geom_type: u64, geometry: &[u64]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        write_field_varint(1, id, &mut buf);
+        write_field_bytes(2, &packed(tags), &mut buf);
+        write_field_varint(3, geom_type, &mut buf);
+        write_field_bytes(4, &packed(geometry), &mut buf);
+        buf
+    }
+
+    fn tile(version: u64, name: &str, features: &[Vec<u8>]) -> Vec<u8> {
+        let mut value = Vec::new();
+        write_field_bytes(1, b"a", &mut value);
+        let mut layer = Vec::new();
+        write_field_varint(15, version, &mut layer);
+        write_field_bytes(1, name.as_bytes(), &mut layer);
+        for f in features {
+            write_field_bytes(2, f, &mut layer);
+        }
+        write_field_bytes(3, b"name", &mut layer);
+        write_field_bytes(4, &value, &mut layer);
+        write_field_varint(5, 4096, &mut layer);
+        let mut tile = Vec::new();
+        write_field_bytes(3, &layer, &mut tile);
+        tile
+    }
+
+    // MoveTo(0,0) LineTo(10,0),(10,10),(0,10) ClosePath, clockwise in tile coordinates
+    const SQUARE: &[u64] = &[9, 0, 0, 26, 20, 0, 0, 20, 19, 0, 15];
+    // MoveTo(0,0) LineTo(0,10),(10,10),(10,0) ClosePath, counter-clockwise in tile coordinates
+    const SQUARE_CCW: &[u64] = &[9, 0, 0, 26, 0, 20, 20, 0, 0, 19, 15];
+
+    #[test]
+    fn valid_tile() {
+        assert_eq!(validate_mvt(&[]), Ok(()));
+        let data = tile(
+            2,
+            "test",
+            &[
+                feature(1, &[0, 0], 3, SQUARE),
+                feature(2, &[], 1, &[9, 50, 34]),
+                feature(3, &[], 2, &[9, 4, 4, 10, 0, 16]),
+            ],
+        );
+        assert_eq!(validate_mvt(&data), Ok(()));
+    }
+
+    #[test]
+    fn layer_names() {
+        assert_eq!(get_layer_names(&[]), Ok(vec![]));
+        let data = [tile(2, "points", &[]), tile(2, "lines", &[])].concat();
+        assert_eq!(
+            get_layer_names(&data),
+            Ok(vec!["points".to_string(), "lines".to_string()])
+        );
+        assert!(get_layer_names(&[0x1a, 0x05]).is_err());
+    }
+
+    /// IDs and geometry points of the features of the first layer
+    fn features(data: &[u8]) -> Vec<(u64, Vec<(i64, i64)>)> {
+        let (_, layer) = PbReader::new(data).next_field().unwrap().unwrap();
+        let mut layer = PbReader::new(layer.bytes().unwrap());
+        let mut result = Vec::new();
+        while let Some((field, value)) = layer.next_field().unwrap() {
+            if field != 2 {
+                continue;
+            }
+            let (mut id, mut geometry) = (0, Vec::new());
+            let mut feature = PbReader::new(value.bytes().unwrap());
+            while let Some((field, value)) = feature.next_field().unwrap() {
+                match field {
+                    1 => id = value.varint().unwrap(),
+                    4 => value.packed(&mut geometry).unwrap(),
+                    _ => {}
+                }
+            }
+            let geometry: Vec<u32> = geometry
+                .into_iter()
+                .map(|v| v.try_into().unwrap())
+                .collect();
+            let points = decode_geometry(&geometry).unwrap();
+            result.push((id, points.into_iter().flat_map(|(_, v)| v).collect()));
+        }
+        result
+    }
+
+    #[test]
+    fn overzoom() {
+        let data = tile(
+            2,
+            "test",
+            &[
+                // Point (3500,1500)
+                feature(1, &[0, 0], 1, &[9, 7000, 3000]),
+                // Point (1000,1000)
+                feature(2, &[], 1, &[9, 2000, 2000]),
+                // Line (2048,1536) to (4096,1536)
+                feature(3, &[], 2, &[9, 4096, 3072, 10, 4096, 0]),
+                // Square (2560,768) to (3840,1792)
+                feature(
+                    4,
+                    &[],
+                    3,
+                    &[9, 5120, 1536, 26, 2560, 0, 0, 2048, 2559, 0, 15],
+                ),
+            ],
+        );
+        // Two zoom levels higher, the tile at column 3 and row 1 covers x 3072..4096 and y 1024..2048 of the parent
+        let result = overzoom_mvt(&data, 2, 3, 1).unwrap();
+        assert_eq!(validate_mvt(&result), Ok(()));
+        assert_eq!(get_layer_names(&result), Ok(vec!["test".to_string()]));
+
+        let features = features(&result);
+        assert_eq!(features.len(), 3);
+        assert_eq!(features[0], (1, vec![(1712, 1904)]));
+        // Clipped at the buffer of 64 around the tile
+        assert_eq!(features[1], (3, vec![(-64, 2048), (4096, 2048)]));
+        let (id, square) = &features[2];
+        assert_eq!(*id, 4);
+        let xs = square.iter().map(|v| v.0);
+        let ys = square.iter().map(|v| v.1);
+        assert_eq!((xs.clone().min(), xs.max()), (Some(-64), Some(3072)));
+        assert_eq!((ys.clone().min(), ys.max()), (Some(-64), Some(3072)));
+
+        // No features in the bottom-left tile
+        assert!(overzoom_mvt(&data, 2, 0, 3).unwrap().is_empty());
+    }
+
+    #[test]
+    fn invalid_tile() {
+        let err = |data: Vec<u8>| validate_mvt(&data).unwrap_err().to_string();
+        assert_eq!(
+            err(tile(1, "test", &[])),
+            r#"Layer "test": version is 1 instead of 2"#
+        );
+        assert_eq!(
+            err(tile(2, "test", &[feature(1, &[], 3, SQUARE_CCW)])),
+            r#"Layer "test", feature #0: first polygon ring must be an exterior ring with clockwise winding order"#
+        );
+        assert_eq!(
+            err(tile(
+                2,
+                "test",
+                &[feature(1, &[], 3, SQUARE), feature(1, &[], 3, SQUARE)]
+            )),
+            r#"Layer "test", feature #1: feature ID 1 is not unique"#
+        );
+        assert_eq!(
+            err(tile(2, "test", &[feature(1, &[0, 1], 3, SQUARE)])),
+            r#"Layer "test", feature #0: tag value index 1 is out of range"#
+        );
+        assert_eq!(
+            err(tile(2, "test", &[feature(1, &[], 1, &[9, 50])])),
+            r#"Layer "test", feature #0: command 1 is missing parameters"#
+        );
+        assert_eq!(
+            err(vec![0x1A, 0x05, 0x01]),
+            "Invalid protobuf encoding: truncated field"
+        );
+
+        let mut data = tile(2, "test", &[]);
+        data.extend(tile(2, "test", &[]));
+        assert_eq!(err(data), r#"Layer "test": layer name is not unique"#);
+    }
+
+    /// Layer "points" with a feature of two points, and layer "area" with a square polygon feature
+    const TILE: [u8; 56] = [
+        26, 24, 120, 2, 10, 6, 112, 111, 105, 110, 116, 115, 18, 9, 24, 1, 34, 5, 17, 2, 2, 4, 4,
+        40, 128, 32, 26, 28, 120, 2, 10, 4, 97, 114, 101, 97, 18, 15, 24, 3, 34, 11, 9, 0, 0, 26,
+        20, 0, 0, 20, 19, 0, 15, 40, 128, 32,
+    ];
+
+    #[test]
+    fn mvt_stats() {
+        let stats = get_mvt_stats(&TILE).unwrap();
+        assert_eq!(
+            stats,
+            vec![
+                MvtLayerStats {
+                    name: "points".to_string(),
+                    features: 1,
+                    vertices: 2,
+                },
+                MvtLayerStats {
+                    name: "area".to_string(),
+                    features: 1,
+                    vertices: 4,
+                },
+            ]
+        );
+        assert!(get_mvt_stats(&TILE[..20]).is_err());
+        assert!(get_mvt_stats(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn mvt_decode() {
+        // Layer "places" with a point at the center of the tile, and a line along its diagonal,
+        // with the keys and values of the properties after the features
+        let tile = std::fs::read("./fixtures/places.mvt").unwrap();
+        let layers = decode_mvt(&tile).unwrap();
+        assert_eq!(
+            layers,
+            vec![MvtLayer {
+                name: "places".to_string(),
+                extent: 4096,
+                features: vec![
+                    MvtFeature {
+                        id: Some(7),
+                        geom_type: MvtGeomType::Point,
+                        geometry: vec![vec![[2048, 2048]]],
+                        properties: vec![
+                            ("name".to_string(), MvtValue::String("center".to_string())),
+                            ("rank".to_string(), MvtValue::Int(-2)),
+                        ],
+                    },
+                    MvtFeature {
+                        id: None,
+                        geom_type: MvtGeomType::LineString,
+                        geometry: vec![vec![[0, 0], [4096, 4096]]],
+                        properties: vec![("rank".to_string(), MvtValue::Double(1.5))],
+                    },
+                ],
+            }]
+        );
+
+        // The rings of the polygons are closed
+        let layers = decode_mvt(&TILE).unwrap();
+        assert_eq!(
+            layers[0].features[0].geometry,
+            vec![vec![[1, 1]], vec![[3, 3]]]
+        );
+        assert_eq!(
+            layers[1].features[0].geometry,
+            vec![vec![[0, 0], [10, 0], [10, 10], [0, 10], [0, 0]]]
+        );
+        assert!(decode_mvt(&tile[..20]).is_err());
+    }
+}
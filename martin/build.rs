@@ -101,7 +101,72 @@ fn webui() {
         .change_detection();
 }
 
+/// Expose the git commit and the build date to the `/version` endpoint.
+/// Both can be overridden with the `MARTIN_GIT_HASH` and `SOURCE_DATE_EPOCH` environment variables,
+/// e.g. when building outside a git checkout or for reproducible builds.
+fn build_info() {
+    println!("cargo:rerun-if-env-changed=MARTIN_GIT_HASH");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    if let Some(git_dir) = git(&["rev-parse", "--git-dir"]) {
+        let git_dir = std::path::PathBuf::from(git_dir);
+        println!("cargo:rerun-if-changed={}", git_dir.join("HEAD").display());
+        // A new commit on the checked out branch only changes the file of the branch
+        if let Some(branch) = git(&["symbolic-ref", "-q", "HEAD"]) {
+            let branch_file = git_dir.join(branch);
+            if branch_file.is_file() {
+                println!("cargo:rerun-if-changed={}", branch_file.display());
+            }
+        }
+    }
+
+    let git_hash = std::env::var("MARTIN_GIT_HASH")
+        .ok()
+        .or_else(|| git(&["rev-parse", "--short=12", "HEAD"]));
+    println!(
+        "cargo:rustc-env=MARTIN_GIT_HASH={}",
+        git_hash.as_deref().unwrap_or("unknown")
+    );
+
+    let timestamp = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default()
+        });
+    let (year, month, day) = civil_from_days(timestamp / 86_400);
+    println!("cargo:rustc-env=MARTIN_BUILD_DATE={year:04}-{month:02}-{day:02}");
+}
+
+/// Run a git command, returning its trimmed output if it succeeded
+fn git(args: &[&str]) -> Option<String> {
+    let output = std::process::Command::new("git").args(args).output().ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Convert days since the Unix epoch to a (year, month, day) date, see
+/// <https://howardhinnant.github.io/date_algorithms.html#civil_from_days>
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
 fn main() {
+    build_info();
+
     #[cfg(feature = "webui")]
     webui();
 }
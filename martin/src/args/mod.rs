@@ -9,10 +9,13 @@ mod pg;
 #[cfg(feature = "postgres")]
 pub use pg::{BoundsCalcType, DEFAULT_BOUNDS_TIMEOUT, PgArgs};
 
+mod profile;
+pub use profile::{Command, ProfileArgs};
+
 mod root;
 pub use root::{Args, ExtraArgs, MetaArgs};
 
 mod srv;
 #[cfg(feature = "webui")]
 pub use srv::WebUiMode;
-pub use srv::{PreferredEncoding, SrvArgs};
+pub use srv::{MvtValidation, PreferredEncoding, SrvArgs};
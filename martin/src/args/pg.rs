@@ -46,6 +46,9 @@ pub struct PgArgs {
     /// Limit the number of features in a tile from a PG table source.
     #[arg(short, long)]
     pub max_feature_count: Option<usize>,
+    /// Exit at once if a PostgreSQL database is not reachable at startup, instead of retrying for `connection_timeout` seconds.
+    #[arg(long)]
+    pub fail_fast: bool,
 }
 
 impl PgArgs {
@@ -62,12 +65,19 @@ impl PgArgs {
             .into_iter()
             .map(|s| PgConfig {
                 connection_string: Some(s),
+                replica_connection_strings: OptOneMany::NoVals,
                 ssl_certificates: certs.clone(),
                 default_srid,
                 auto_bounds: self.auto_bounds,
                 max_feature_count: self.max_feature_count,
                 pool_size: self.pool_size,
+                connection_timeout: self.fail_fast.then_some(0),
+                retry_interval: None,
+                compress_min_size: None,
                 auto_publish: OptBoolObj::NoValue,
+                probe_functions: OptBoolObj::NoValue,
+                source_defaults: None,
+                templates: None,
                 tables: None,
                 functions: None,
             })
@@ -89,6 +99,7 @@ impl PgArgs {
             auto_bounds,
             max_feature_count,
             ca_root_file,
+            fail_fast,
         } = self;
 
         if let Some(value) = default_srid {
@@ -123,6 +134,14 @@ impl PgArgs {
                 c.max_feature_count = max_feature_count;
             });
         }
+        if fail_fast {
+            info!(
+                "Overriding connection timeout to 0 on all Postgres connections because of the --fail-fast CLI parameter"
+            );
+            pg_config.iter_mut().for_each(|c| {
+                c.connection_timeout = Some(0);
+            });
+        }
         if let Some(ref value) = ca_root_file {
             info!(
                 "Overriding root certificate file to {} on all Postgres connections because of a CLI parameter",
@@ -0,0 +1,84 @@
+use clap::{Parser, Subcommand};
+use martin_tile_utils::TileCoord;
+
+#[derive(Subcommand, Debug, Clone, PartialEq)]
+pub enum Command {
+    /// Generate a single tile repeatedly and report how long each stage of the tile pipeline takes, without starting the server
+    Profile(ProfileArgs),
+}
+
+#[derive(Parser, Debug, Clone, PartialEq)]
+#[command()]
+pub struct ProfileArgs {
+    /// ID of the tile source to profile
+    #[arg(short, long)]
+    pub source: String,
+    /// Tile to generate, in the `z/x/y` form, e.g. `14/8185/5449`
+    #[arg(short, long, value_parser = parse_tile_coord)]
+    pub tile: TileCoord,
+    /// Number of times to generate the tile
+    #[arg(short = 'n', long, default_value_t = 100, value_parser = clap::value_parser!(u32).range(1..))]
+    pub iterations: u32,
+}
+
+fn parse_tile_coord(value: &str) -> Result<TileCoord, String> {
+    let err = || format!("Tile must be in the z/x/y form, but is '{value}'");
+    let mut parts = value.split('/');
+    let (Some(z), Some(x), Some(y), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(err());
+    };
+    let xyz = TileCoord {
+        z: z.parse().map_err(|_| err())?,
+        x: x.parse().map_err(|_| err())?,
+        y: y.parse().map_err(|_| err())?,
+    };
+    if !xyz.is_valid() {
+        return Err(format!("Tile {xyz} is outside of the world bounds"));
+    }
+    Ok(xyz)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::Args;
+
+    #[test]
+    fn tile_coords() {
+        assert_eq!(
+            parse_tile_coord("14/8185/5449"),
+            Ok(TileCoord {
+                z: 14,
+                x: 8185,
+                y: 5449
+            })
+        );
+        for value in ["", "1/2", "1/2/3/4", "a/0/0", "1/2/0", "31/0/0"] {
+            assert!(parse_tile_coord(value).is_err(), "{value}");
+        }
+    }
+
+    #[test]
+    fn profile_command() {
+        let args = Args::parse_from([
+            "martin",
+            "../tests/fixtures/mbtiles",
+            "profile",
+            "--source",
+            "world_cities",
+            "--tile",
+            "0/0/0",
+        ]);
+        assert_eq!(args.meta.connection, vec!["../tests/fixtures/mbtiles"]);
+        assert_eq!(
+            args.command,
+            Some(Command::Profile(ProfileArgs {
+                source: "world_cities".to_string(),
+                tile: TileCoord { z: 0, x: 0, y: 0 },
+                iterations: 100,
+            }))
+        );
+    }
+}
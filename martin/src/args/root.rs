@@ -9,6 +9,7 @@ use crate::MartinResult;
 use crate::OptOneMany;
 use crate::args::connections::Arguments;
 use crate::args::environment::Env;
+use crate::args::profile::Command;
 use crate::args::srv::SrvArgs;
 use crate::config::Config;
 #[cfg(any(
@@ -23,9 +24,12 @@ use crate::file_config::FileConfigEnum;
 #[command(
     about,
     version,
-    after_help = "Use RUST_LOG environment variable to control logging level, e.g. RUST_LOG=debug or RUST_LOG=martin=debug. See https://docs.rs/env_logger/latest/env_logger/index.html#enabling-logging for more information."
+    after_help = "Use RUST_LOG environment variable to control logging level, e.g. RUST_LOG=debug or RUST_LOG=martin=debug. See https://docs.rs/env_logger/latest/env_logger/index.html#enabling-logging for more information.",
+    subcommand_precedence_over_arg = true
 )]
 pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
     #[command(flatten)]
     pub meta: MetaArgs,
     #[command(flatten)]
@@ -1,26 +1,32 @@
+use std::path::PathBuf;
+
 use clap::ValueEnum;
 use serde::{Deserialize, Serialize};
 
-use crate::srv::{KEEP_ALIVE_DEFAULT, LISTEN_ADDRESSES_DEFAULT, SrvConfig};
+use crate::srv::{KEEP_ALIVE_DEFAULT, KeepAlive, LISTEN_ADDRESSES_DEFAULT, SrvConfig, TlsConfig};
 
 #[allow(clippy::doc_markdown)]
 #[derive(clap::Args, Debug, PartialEq, Default)]
 #[command(about, version)]
 pub struct SrvArgs {
-    #[arg(help = format!("Connection keep alive timeout. [DEFAULT: {KEEP_ALIVE_DEFAULT}]"), short, long)]
-    pub keep_alive: Option<u64>,
-    #[arg(help = format!("The socket address to bind. [DEFAULT: {LISTEN_ADDRESSES_DEFAULT}]"), short, long)]
+    #[arg(help = format!("Connection keep alive timeout, as seconds or a duration like 75s or 2m, or `off` to disable keep-alive. [DEFAULT: {KEEP_ALIVE_DEFAULT}]"), short, long)]
+    pub keep_alive: Option<KeepAlive>,
+    #[arg(help = format!("The socket address to bind, or a comma-separated list of addresses. [DEFAULT: {LISTEN_ADDRESSES_DEFAULT}]"), short, long)]
     pub listen_addresses: Option<String>,
-    /// Set TileJSON URL path prefix.
+    /// Serve all routes under this URL path prefix, e.g. when running behind a reverse proxy.
     ///
-    /// This overrides the default of respecting the X-Rewrite-URL header.
-    /// Only modifies the JSON (TileJSON) returned, martins' API-URLs remain unchanged.
-    /// If you need to rewrite URLs, please use a reverse proxy.
-    /// Must begin with a `/`.
+    /// The prefix is also used for the tile URLs of the TileJSON, overriding the X-Rewrite-URL header.
+    /// Must begin with a `/`. A trailing `/` is ignored, and `/` serves the routes at the root.
     ///
     /// Examples: `/`, `/tiles`
     #[arg(long)]
     pub base_path: Option<String>,
+    /// Serve HTTPS with the certificate chain from this PEM file. Requires `--tls-key-file`.
+    #[arg(long, requires = "tls_key_file")]
+    pub tls_cert_file: Option<PathBuf>,
+    /// Private key of the `--tls-cert-file` certificate, as a PEM file.
+    #[arg(long, requires = "tls_cert_file")]
+    pub tls_key_file: Option<PathBuf>,
     /// Number of web server workers
     #[arg(short = 'W', long)]
     pub workers: Option<usize>,
@@ -30,6 +36,14 @@ pub struct SrvArgs {
     /// `gzip` is faster, but `brotli` is smaller, and may be faster with caching.
     #[arg(long)]
     pub preferred_encoding: Option<PreferredEncoding>,
+    /// Decode every produced MVT tile and check it against the MVT 2.1 specification.
+    ///
+    /// Useful for debugging client rendering artifacts, but slows down tile serving.
+    #[arg(long, value_name = "MODE")]
+    pub validate_mvt: Option<MvtValidation>,
+    /// Add the `X-Martin-Version` header with the server version and git commit to all responses.
+    #[arg(long)]
+    pub version_header: bool,
     /// Control Martin web UI. [DEFAULT: disabled]
     #[arg(short = 'u', long = "webui")]
     #[cfg(feature = "webui")]
@@ -63,6 +77,16 @@ pub enum PreferredEncoding {
     Gzip,
 }
 
+/// What to do with the produced MVT tiles that do not conform to the MVT specification
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum MvtValidation {
+    /// Log a warning, but still serve the tile
+    Warn,
+    /// Log an error, and respond with an error instead of the tile
+    Reject,
+}
+
 impl SrvArgs {
     pub(crate) fn merge_into_config(self, srv_config: &mut SrvConfig) {
         // Override config values with the ones from the command line
@@ -75,12 +99,24 @@ impl SrvArgs {
         if self.base_path.is_some() {
             srv_config.base_path = self.base_path;
         }
+        if let (Some(cert_file), Some(key_file)) = (self.tls_cert_file, self.tls_key_file) {
+            srv_config.tls = Some(TlsConfig {
+                cert_file,
+                key_file,
+            });
+        }
         if self.workers.is_some() {
             srv_config.worker_processes = self.workers;
         }
         if self.preferred_encoding.is_some() {
             srv_config.preferred_encoding = self.preferred_encoding;
         }
+        if self.validate_mvt.is_some() {
+            srv_config.validate_mvt = self.validate_mvt;
+        }
+        if self.version_header {
+            srv_config.version_header = Some(true);
+        }
         #[cfg(feature = "webui")]
         if self.web_ui.is_some() {
             srv_config.web_ui = self.web_ui;
@@ -17,7 +17,7 @@ use martin::{
     Config, MartinError, MartinResult, ServerState, TileData, TileInfoSource, TileRect,
     append_rect, read_config,
 };
-use martin_tile_utils::{TileCoord, TileInfo, bbox_to_xyz};
+use martin_tile_utils::{TileCoord, TileInfo};
 use mbtiles::UpdateZoomType::GrowOnly;
 use mbtiles::sqlx::SqliteConnection;
 use mbtiles::{
@@ -84,6 +84,10 @@ pub struct CopyArgs {
     /// Number of concurrent connections to use.
     #[arg(long, default_value = "1")]
     pub concurrency: Option<usize>,
+    /// Generate all tiles of a zoom level with a single streaming query instead of one query per tile.
+    /// This is much faster when seeding large areas, but is only supported when copying a single PostgreSQL table source.
+    #[arg(long)]
+    pub bulk: bool,
     /// Bounds to copy, in the format `min_lon,min_lat,max_lon,max_lat`. Can be specified multiple times. Overlapping regions will be handled correctly.
     #[arg(long)]
     pub bbox: Vec<Bounds>,
@@ -136,6 +140,7 @@ async fn start(copy_args: CopierArgs) -> MartinCpResult<()> {
     };
 
     let args = Args {
+        command: None,
         meta: copy_args.meta,
         extras: ExtraArgs::default(),
         srv: SrvArgs::default(),
@@ -166,12 +171,7 @@ fn compute_tile_ranges(args: &CopyArgs) -> Vec<TileRect> {
     };
     for zoom in get_zooms(args).iter() {
         for bbox in &boxes {
-            let (min_x, min_y, max_x, max_y) =
-                bbox_to_xyz(bbox.left, bbox.bottom, bbox.right, bbox.top, *zoom);
-            append_rect(
-                &mut ranges,
-                TileRect::new(*zoom, min_x, min_y, max_x, max_y),
-            );
+            append_rect(&mut ranges, TileRect::from_bounds(*zoom, bbox));
         }
     }
     ranges
@@ -231,6 +231,10 @@ enum MartinCpError {
     Actix(#[from] actix_web::Error),
     #[error(transparent)]
     Mbt(#[from] MbtError),
+    #[error(
+        "Source {0} does not support bulk tile generation. Only a single PostgreSQL table source can be copied with --bulk"
+    )]
+    BulkNotSupported(String),
 }
 
 impl Display for Progress {
@@ -311,23 +315,54 @@ async fn run_tile_copy(args: CopyArgs, state: ServerState) -> MartinCpResult<()>
         args.output_file.display()
     );
 
+    let bulk = args.bulk;
+    let progress_ref = &progress;
+    let source_id = args.source.as_str();
     try_join!(
         // Note: for some reason, tests hang here without the `move` keyword
         async move {
-            stream::iter(iterate_tiles(tiles))
-                .map(MartinResult::Ok)
-                .try_for_each_concurrent(concurrency, |xyz| {
-                    let tx = tx.clone();
-                    async move {
-                        let tile = src.get_tile_content(xyz).await?;
-                        let data = tile.data;
-                        tx.send(TileXyz { xyz, data })
-                            .await
-                            .map_err(|e| MartinError::InternalError(e.into()))?;
-                        Ok(())
-                    }
-                })
-                .await
+            if bulk {
+                stream::iter(tiles)
+                    .map(MartinCpResult::Ok)
+                    .try_for_each_concurrent(concurrency, |rect| {
+                        let tx = tx.clone();
+                        async move {
+                            let Some(mut stream) = src.get_tiles_bulk(rect).await? else {
+                                return Err(MartinCpError::BulkNotSupported(source_id.to_string()));
+                            };
+                            let mut sent = 0;
+                            while let Some((xyz, tile)) = stream.try_next().await? {
+                                let data = tile.data;
+                                tx.send(TileXyz { xyz, data })
+                                    .await
+                                    .map_err(|e| MartinError::InternalError(e.into()))?;
+                                sent += 1;
+                            }
+                            // Bulk queries omit empty tiles, count them all at once
+                            progress_ref
+                                .empty
+                                .fetch_add(rect.size() - sent, Ordering::Relaxed);
+                            Ok(())
+                        }
+                    })
+                    .await
+            } else {
+                stream::iter(iterate_tiles(tiles))
+                    .map(MartinResult::Ok)
+                    .try_for_each_concurrent(concurrency, |xyz| {
+                        let tx = tx.clone();
+                        async move {
+                            let tile = src.get_tile_content(xyz).await?;
+                            let data = tile.data;
+                            tx.send(TileXyz { xyz, data })
+                                .await
+                                .map_err(|e| MartinError::InternalError(e.into()))?;
+                            Ok(())
+                        }
+                    })
+                    .await?;
+                Ok(())
+            }
         },
         async {
             let mut last_saved = Instant::now();
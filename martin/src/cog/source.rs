@@ -14,7 +14,7 @@ use tilejson::{TileJSON, tilejson};
 
 use super::CogError;
 use crate::file_config::{FileError, FileResult};
-use crate::{MartinResult, Source, TileData, UrlQuery};
+use crate::{MartinResult, Source, SourceKind, TileData, UrlQuery};
 
 #[derive(Clone, Debug)]
 struct Meta {
@@ -147,6 +147,10 @@ impl Source for CogSource {
         self.tileinfo
     }
 
+    fn get_kind(&self) -> SourceKind {
+        SourceKind::Cog
+    }
+
     fn clone_source(&self) -> Box<dyn Source> {
         Box::new(self.clone())
     }
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::ffi::OsStr;
 use std::fs::File;
 use std::io::prelude::*;
@@ -6,11 +6,14 @@ use std::path::{Path, PathBuf};
 use std::pin::Pin;
 
 use futures::future::try_join_all;
-use log::info;
+use log::{info, warn};
 use serde::{Deserialize, Serialize};
 use subst::VariableMap;
 
-use crate::MartinError::{ConfigLoadError, ConfigParseError, ConfigWriteError, NoSources};
+use crate::MartinError::{
+    AliasConflictError, AliasReservedError, AliasTargetError, ConfigLoadError, ConfigParseError,
+    ConfigWriteError, FallbackConflictError, FallbackCycleError, FallbackMemberError, NoSources,
+};
 #[cfg(any(feature = "fonts", feature = "postgres"))]
 use crate::OptOneMany;
 #[cfg(any(
@@ -25,15 +28,21 @@ use crate::fonts::FontSources;
 use crate::source::{TileInfoSources, TileSources};
 #[cfg(feature = "sprites")]
 use crate::sprites::{SpriteConfig, SpriteSources};
-use crate::srv::{RESERVED_KEYWORDS, SrvConfig};
-use crate::utils::{CacheValue, MainCache, OptMainCache, init_aws_lc_tls, parse_base_path};
-use crate::{IdResolver, MartinResult};
+use crate::srv::{RESERVED_KEYWORDS, SourceSettings, SrvConfig};
+use crate::utils::{
+    CacheValue, MainCache, OptBoolObj, OptMainCache, init_aws_lc_tls, parse_base_path,
+};
+use crate::{IdResolver, MartinResult, OverzoomSource};
 
 pub type UnrecognizedValues = HashMap<String, serde_yaml::Value>;
 
 pub struct ServerState {
+    /// The configuration before resolving, used to resolve the tile sources again on reload
+    pub config: Config,
     pub cache: OptMainCache,
     pub tiles: TileSources,
+    /// Settings configured for individual tile sources
+    pub settings: SourceSettings,
     #[cfg(feature = "sprites")]
     pub sprites: SpriteSources,
     #[cfg(feature = "fonts")]
@@ -72,6 +81,12 @@ pub struct Config {
     #[serde(default, skip_serializing_if = "OptOneMany::is_none")]
     pub fonts: OptOneMany<PathBuf>,
 
+    /// Additional IDs of the tile sources, e.g. to keep an old ID working after a source is renamed
+    pub aliases: Option<BTreeMap<String, String>>,
+
+    /// Sources serving each tile from the first of the listed sources that has it
+    pub fallbacks: Option<BTreeMap<String, Vec<String>>>,
+
     #[serde(flatten)]
     pub unrecognized: UnrecognizedValues,
 }
@@ -85,6 +100,16 @@ impl Config {
         if let Some(path) = &self.srv.base_path {
             self.srv.base_path = Some(parse_base_path(path)?);
         }
+        self.srv.validate()?;
+        if let OptBoolObj::Object(cors) = &self.srv.cors {
+            cors.validate()?;
+        }
+        if let Some(rate_limit) = &self.srv.rate_limit {
+            rate_limit.validate()?;
+        }
+        if let Some(routes) = &self.srv.routes {
+            routes.validate()?;
+        }
 
         #[cfg(feature = "postgres")]
         for pg in self.postgres.iter_mut() {
@@ -131,31 +156,41 @@ impl Config {
 
     pub async fn resolve(&mut self) -> MartinResult<ServerState> {
         init_aws_lc_tls()?;
-        let resolver = IdResolver::new(RESERVED_KEYWORDS);
+        let config = self.clone();
         let cache_size = self.cache_size_mb.unwrap_or(512) * 1024 * 1024;
+        // Peers and the admin API may invalidate all cached tiles of a source
+        let invalidate_sources = self.srv.cache_peers.is_some();
+        #[cfg(any(feature = "mbtiles", feature = "pmtiles", feature = "cog"))]
+        let invalidate_sources = invalidate_sources || self.srv.admin.is_some();
         let cache = if cache_size > 0 {
             info!("Initializing main cache with maximum size {cache_size}B");
-            Some(
-                MainCache::builder()
-                    .weigher(|_key, value: &CacheValue| -> u32 {
-                        match value {
-                            CacheValue::Tile(v) => v.len().try_into().unwrap_or(u32::MAX),
-                            #[cfg(feature = "pmtiles")]
-                            CacheValue::PmtDirectory(v) => {
-                                v.get_approx_byte_size().try_into().unwrap_or(u32::MAX)
-                            }
+            let mut builder = MainCache::builder()
+                .weigher(|_key, value: &CacheValue| -> u32 {
+                    match value {
+                        CacheValue::Tile(v) => v.len().try_into().unwrap_or(u32::MAX),
+                        #[cfg(feature = "pmtiles")]
+                        CacheValue::PmtDirectory(v) => {
+                            v.get_approx_byte_size().try_into().unwrap_or(u32::MAX)
                         }
-                    })
-                    .max_capacity(cache_size)
-                    .build(),
-            )
+                    }
+                })
+                .max_capacity(cache_size);
+            if invalidate_sources {
+                builder = builder.support_invalidation_closures();
+            }
+            Some(builder.build())
         } else {
             info!("Caching is disabled");
             None
         };
 
+        let tiles = self.resolve_tiles(cache.clone()).await?;
+        let settings = self.get_source_settings()?;
+        settings.check_missing_tile_files(&tiles);
         Ok(ServerState {
-            tiles: self.resolve_tile_sources(&resolver, cache.clone()).await?,
+            config,
+            tiles,
+            settings,
             #[cfg(feature = "sprites")]
             sprites: SpriteSources::resolve(&mut self.sprites)?,
             #[cfg(feature = "fonts")]
@@ -164,6 +199,195 @@ impl Config {
         })
     }
 
+    /// Collect the settings of the individual sources, once their IDs are resolved
+    fn get_source_settings(&self) -> MartinResult<SourceSettings> {
+        #[allow(unused_mut)]
+        let mut settings = SourceSettings::default();
+
+        #[cfg(feature = "postgres")]
+        for s in self.postgres.iter() {
+            settings.cache_control.extend(s.get_cache_control());
+            settings.missing_tile.extend(s.get_missing_tile());
+            settings.query_timeout.extend(s.get_query_timeout());
+            for (id, headers) in s.get_headers() {
+                settings.insert_headers(id, &headers)?;
+            }
+            for (id, path) in s.get_missing_tile_file() {
+                settings.insert_missing_tile_file(id, &path)?;
+            }
+            for (id, limit) in s.get_concurrency_limits() {
+                settings.insert_concurrency_limit(id, limit)?;
+            }
+            settings.check_bounds.extend(s.get_check_bounds());
+            settings.hidden.extend(s.get_hidden());
+        }
+
+        #[cfg(feature = "pmtiles")]
+        {
+            settings
+                .cache_control
+                .extend(self.pmtiles.get_cache_control());
+            settings
+                .missing_tile
+                .extend(self.pmtiles.get_missing_tile());
+            settings
+                .query_timeout
+                .extend(self.pmtiles.get_query_timeout());
+            for (id, headers) in self.pmtiles.get_headers() {
+                settings.insert_headers(id, &headers)?;
+            }
+            for (id, path) in self.pmtiles.get_missing_tile_file() {
+                settings.insert_missing_tile_file(id, &path)?;
+            }
+            for (id, limit) in self.pmtiles.get_concurrency_limits() {
+                settings.insert_concurrency_limit(id, limit)?;
+            }
+            settings
+                .check_bounds
+                .extend(self.pmtiles.get_check_bounds());
+            settings.hidden.extend(self.pmtiles.get_hidden());
+        }
+
+        #[cfg(feature = "mbtiles")]
+        {
+            settings
+                .cache_control
+                .extend(self.mbtiles.get_cache_control());
+            settings
+                .missing_tile
+                .extend(self.mbtiles.get_missing_tile());
+            settings
+                .query_timeout
+                .extend(self.mbtiles.get_query_timeout());
+            for (id, headers) in self.mbtiles.get_headers() {
+                settings.insert_headers(id, &headers)?;
+            }
+            for (id, path) in self.mbtiles.get_missing_tile_file() {
+                settings.insert_missing_tile_file(id, &path)?;
+            }
+            for (id, limit) in self.mbtiles.get_concurrency_limits() {
+                settings.insert_concurrency_limit(id, limit)?;
+            }
+            settings
+                .check_bounds
+                .extend(self.mbtiles.get_check_bounds());
+            settings.hidden.extend(self.mbtiles.get_hidden());
+        }
+
+        #[cfg(feature = "cog")]
+        {
+            settings.cache_control.extend(self.cog.get_cache_control());
+            settings.missing_tile.extend(self.cog.get_missing_tile());
+            settings.query_timeout.extend(self.cog.get_query_timeout());
+            for (id, headers) in self.cog.get_headers() {
+                settings.insert_headers(id, &headers)?;
+            }
+            for (id, path) in self.cog.get_missing_tile_file() {
+                settings.insert_missing_tile_file(id, &path)?;
+            }
+            for (id, limit) in self.cog.get_concurrency_limits() {
+                settings.insert_concurrency_limit(id, limit)?;
+            }
+            settings.check_bounds.extend(self.cog.get_check_bounds());
+            settings.hidden.extend(self.cog.get_hidden());
+        }
+
+        for (alias, target) in self.aliases.iter().flatten() {
+            settings.insert_alias(alias, target);
+        }
+
+        Ok(settings)
+    }
+
+    /// Resolve the tile sources, including the ones added or removed with the admin API.
+    /// Used on startup and to discover new sources on reload.
+    pub async fn resolve_tiles(&mut self, cache: OptMainCache) -> MartinResult<TileSources> {
+        let resolver = IdResolver::new(RESERVED_KEYWORDS);
+        let tiles = self.resolve_tile_sources(&resolver, cache).await?;
+        #[cfg(any(feature = "mbtiles", feature = "pmtiles", feature = "cog"))]
+        if let Some(admin) = &self.srv.admin {
+            let overrides = crate::srv::SourceOverrides::load(&admin.overrides_file)?;
+            overrides.apply(&tiles).await?;
+        }
+        self.resolve_overzoom(&tiles)?;
+        self.resolve_fallbacks(&tiles)?;
+        self.resolve_aliases(&tiles)?;
+        Ok(tiles)
+    }
+
+    /// Replace the sources with a `max_overzoom` setting with an [`OverzoomSource`] serving them above their `maxzoom`
+    fn resolve_overzoom(&self, tiles: &TileSources) -> MartinResult<()> {
+        #[allow(unused_mut)]
+        let mut max_overzoom: Vec<(String, u8)> = Vec::new();
+        #[cfg(feature = "postgres")]
+        for s in self.postgres.iter() {
+            max_overzoom.extend(s.get_max_overzoom());
+        }
+        #[cfg(feature = "pmtiles")]
+        max_overzoom.extend(self.pmtiles.get_max_overzoom());
+        #[cfg(feature = "mbtiles")]
+        max_overzoom.extend(self.mbtiles.get_max_overzoom());
+        #[cfg(feature = "cog")]
+        max_overzoom.extend(self.cog.get_max_overzoom());
+
+        for (id, levels) in max_overzoom {
+            // A configured source may be missing, e.g. if its table was not found in the database
+            if let Some(source) = tiles.remove(&id) {
+                tiles.insert(Box::new(OverzoomSource::new(source, levels)?));
+            }
+        }
+        Ok(())
+    }
+
+    /// Add the sources trying other sources in order, see [`crate::FallbackSource`].
+    /// A fallback source may include other fallback sources, but not the aliases.
+    /// It is only registered once all of its members are available, and is pending in the catalog until then.
+    fn resolve_fallbacks(&self, tiles: &TileSources) -> MartinResult<()> {
+        let Some(fallbacks) = &self.fallbacks else {
+            return Ok(());
+        };
+        for (id, member_ids) in fallbacks {
+            if RESERVED_KEYWORDS.contains(&id.as_str()) || tiles.contains(id) {
+                return Err(FallbackConflictError(id.clone()));
+            }
+            if let Some(alias) = member_ids
+                .iter()
+                .find(|v| self.aliases.iter().flatten().any(|(alias, _)| alias == *v))
+            {
+                return Err(FallbackMemberError(id.clone(), alias.clone()));
+            }
+            if includes_itself(fallbacks, id) {
+                return Err(FallbackCycleError(id.clone()));
+            }
+            tiles.insert_pending(id.clone(), member_ids.clone());
+        }
+        tiles.register_pending()?;
+        for (id, missing) in tiles.get_pending() {
+            warn!(
+                "Fallback source {id} is pending until {} are available",
+                missing.join(", ")
+            );
+        }
+        Ok(())
+    }
+
+    /// Make the sources also available under their aliases.
+    /// An alias cannot be a reserved keyword or the ID of a source, and must point to an existing source.
+    fn resolve_aliases(&self, tiles: &TileSources) -> MartinResult<()> {
+        for (alias, target) in self.aliases.iter().flatten() {
+            if RESERVED_KEYWORDS.contains(&alias.as_str()) {
+                return Err(AliasReservedError(alias.clone()));
+            }
+            if tiles.contains(alias) || self.fallbacks.iter().flatten().any(|(id, _)| id == alias) {
+                return Err(AliasConflictError(alias.clone()));
+            }
+            if !tiles.insert_alias(alias, target) {
+                return Err(AliasTargetError(alias.clone(), target.clone()));
+            }
+        }
+        Ok(())
+    }
+
     async fn resolve_tile_sources(
         &mut self,
         #[allow(unused_variables)] idr: &IdResolver,
@@ -223,6 +447,21 @@ impl Config {
     }
 }
 
+/// Whether a fallback source includes itself, directly or through other fallback sources
+fn includes_itself(fallbacks: &BTreeMap<String, Vec<String>>, id: &str) -> bool {
+    let mut visited = HashSet::new();
+    let mut stack: Vec<&String> = fallbacks[id].iter().collect();
+    while let Some(member_id) = stack.pop() {
+        if member_id == id {
+            return true;
+        }
+        if visited.insert(member_id) {
+            stack.extend(fallbacks.get(member_id).into_iter().flatten());
+        }
+    }
+    false
+}
+
 pub fn copy_unrecognized_config(
     result: &mut UnrecognizedValues,
     prefix: &str,
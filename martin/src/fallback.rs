@@ -0,0 +1,133 @@
+use std::ops::RangeInclusive;
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use itertools::Itertools as _;
+use martin_tile_utils::{TileCoord, TileInfo};
+use tilejson::TileJSON;
+
+use crate::MartinError::FallbackFormatError;
+use crate::MartinResult;
+use crate::source::{
+    Source, SourceKind, TileData, TileInfoSource, TileInfoSources, UrlQuery, get_last_modified,
+};
+use crate::srv::merge_tilejson;
+
+/// A source serving each tile from the first of its member sources that has it,
+/// e.g. a detailed regional source with a low-detail worldwide one for the uncovered areas
+#[derive(Clone, Debug)]
+pub struct FallbackSource {
+    id: String,
+    members: TileInfoSources,
+    tilejson: TileJSON,
+    tile_info: TileInfo,
+}
+
+impl FallbackSource {
+    /// Combine the sources, which must all have the same format and encoding, in the order they are tried.
+    /// The `TileJSON` covers the bounds and zoom levels of all of them.
+    pub fn new(id: String, members: TileInfoSources) -> MartinResult<Self> {
+        let Some(tile_info) = members.first().map(|v| v.get_tile_info()) else {
+            return Err(FallbackFormatError(id));
+        };
+        if members.iter().any(|v| v.get_tile_info() != tile_info) {
+            return Err(FallbackFormatError(id));
+        }
+        let mut tilejson = merge_tilejson(&members, String::new());
+        tilejson.tiles = Vec::new();
+        // The members usually have the same layers, which are only listed once
+        tilejson.vector_layers = tilejson
+            .vector_layers
+            .map(|v| v.into_iter().unique_by(|v| v.id.clone()).collect());
+        Ok(Self {
+            id,
+            members,
+            tilejson,
+            tile_info,
+        })
+    }
+}
+
+#[async_trait]
+impl Source for FallbackSource {
+    fn get_id(&self) -> &str {
+        &self.id
+    }
+
+    fn get_tilejson(&self) -> &TileJSON {
+        &self.tilejson
+    }
+
+    fn get_tile_info(&self) -> TileInfo {
+        self.tile_info
+    }
+
+    fn get_kind(&self) -> SourceKind {
+        self.members[0].get_kind()
+    }
+
+    fn clone_source(&self) -> TileInfoSource {
+        Box::new(self.clone())
+    }
+
+    fn support_url_query(&self) -> bool {
+        self.members.iter().any(|v| v.support_url_query())
+    }
+
+    /// Try the members in order, and return the first non-empty tile.
+    /// Members that can tell if they have a tile are only asked for it if they do.
+    async fn get_tile(
+        &self,
+        xyz: TileCoord,
+        url_query: Option<&UrlQuery>,
+    ) -> MartinResult<TileData> {
+        for member in &self.members {
+            if !member.get_zoom_range().contains(&xyz.z) || !member.is_valid_zoom(xyz.z) {
+                continue;
+            }
+            if member.can_probe_tiles() && !member.has_tile(xyz).await? {
+                continue;
+            }
+            let tile = member.get_tile(xyz, url_query).await?;
+            if !tile.is_empty() {
+                return Ok(tile);
+            }
+        }
+        Ok(Vec::new())
+    }
+
+    async fn check_health(&self) -> MartinResult<()> {
+        for member in &self.members {
+            member.check_health().await?;
+        }
+        Ok(())
+    }
+
+    fn can_probe_tiles(&self) -> bool {
+        self.members.iter().all(|v| v.can_probe_tiles())
+    }
+
+    async fn has_tile(&self, xyz: TileCoord) -> MartinResult<bool> {
+        for member in &self.members {
+            if member.has_tile(xyz).await? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn get_zoom_range(&self) -> RangeInclusive<u8> {
+        let ranges = self.members.iter().map(|v| v.get_zoom_range());
+        let min = ranges.clone().map(|v| *v.start()).min().unwrap_or_default();
+        let max = ranges.map(|v| *v.end()).max().unwrap_or_default();
+        min..=max
+    }
+
+    fn get_last_modified(&self) -> Option<SystemTime> {
+        get_last_modified(&self.members)
+    }
+
+    fn get_tile_scale(&self) -> u8 {
+        self.members[0].get_tile_scale()
+    }
+}
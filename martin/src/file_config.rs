@@ -15,6 +15,7 @@ use crate::file_config::FileError::{
     InvalidFilePath, InvalidSourceFilePath, InvalidSourceUrl, IoError,
 };
 use crate::source::{TileInfoSource, TileInfoSources};
+use crate::srv::{ConcurrencyLimit, MissingTile};
 use crate::utils::{IdResolver, OptMainCache, OptOneMany};
 
 pub type FileResult<T> = Result<T, FileError>;
@@ -159,6 +160,177 @@ impl<T: ConfigExtras> FileConfigEnum<T> {
         Ok(Some(res))
     }
 
+    /// The `cache_control` values of the configured sources, by source ID
+    pub fn get_cache_control(&self) -> impl Iterator<Item = (String, String)> + '_ {
+        let sources = match self {
+            Self::Config(cfg) => cfg.sources.as_ref(),
+            _ => None,
+        };
+        sources
+            .into_iter()
+            .flatten()
+            .filter_map(|(id, src)| match src {
+                FileConfigSrc::Obj(FileConfigSource {
+                    cache_control: Some(value),
+                    ..
+                }) => Some((id.clone(), value.clone())),
+                _ => None,
+            })
+    }
+
+    /// The `missing_tile` values of the configured sources, by source ID
+    pub fn get_missing_tile(&self) -> impl Iterator<Item = (String, MissingTile)> + '_ {
+        let sources = match self {
+            Self::Config(cfg) => cfg.sources.as_ref(),
+            _ => None,
+        };
+        sources
+            .into_iter()
+            .flatten()
+            .filter_map(|(id, src)| match src {
+                FileConfigSrc::Obj(FileConfigSource {
+                    missing_tile: Some(value),
+                    ..
+                }) => Some((id.clone(), *value)),
+                _ => None,
+            })
+    }
+
+    /// The `missing_tile_file` paths of the configured sources, by source ID
+    pub fn get_missing_tile_file(&self) -> impl Iterator<Item = (String, PathBuf)> + '_ {
+        let sources = match self {
+            Self::Config(cfg) => cfg.sources.as_ref(),
+            _ => None,
+        };
+        sources
+            .into_iter()
+            .flatten()
+            .filter_map(|(id, src)| match src {
+                FileConfigSrc::Obj(FileConfigSource {
+                    missing_tile_file: Some(value),
+                    ..
+                }) => Some((id.clone(), value.clone())),
+                _ => None,
+            })
+    }
+
+    /// The `check_bounds` values of the configured sources, by source ID
+    pub fn get_check_bounds(&self) -> impl Iterator<Item = (String, bool)> + '_ {
+        let sources = match self {
+            Self::Config(cfg) => cfg.sources.as_ref(),
+            _ => None,
+        };
+        sources
+            .into_iter()
+            .flatten()
+            .filter_map(|(id, src)| match src {
+                FileConfigSrc::Obj(FileConfigSource {
+                    check_bounds: Some(value),
+                    ..
+                }) => Some((id.clone(), *value)),
+                _ => None,
+            })
+    }
+
+    /// The `hidden` values of the configured sources, by source ID
+    pub fn get_hidden(&self) -> impl Iterator<Item = (String, bool)> + '_ {
+        let sources = match self {
+            Self::Config(cfg) => cfg.sources.as_ref(),
+            _ => None,
+        };
+        sources
+            .into_iter()
+            .flatten()
+            .filter_map(|(id, src)| match src {
+                FileConfigSrc::Obj(FileConfigSource {
+                    hidden: Some(value),
+                    ..
+                }) => Some((id.clone(), *value)),
+                _ => None,
+            })
+    }
+
+    /// The concurrency limits of the configured sources with `max_concurrent_requests` set, by source ID
+    pub fn get_concurrency_limits(&self) -> impl Iterator<Item = (String, ConcurrencyLimit)> + '_ {
+        let sources = match self {
+            Self::Config(cfg) => cfg.sources.as_ref(),
+            _ => None,
+        };
+        sources
+            .into_iter()
+            .flatten()
+            .filter_map(|(id, src)| match src {
+                FileConfigSrc::Obj(FileConfigSource {
+                    max_concurrent_requests: Some(value),
+                    max_queued_requests,
+                    queue_timeout,
+                    ..
+                }) => Some((
+                    id.clone(),
+                    ConcurrencyLimit {
+                        max_concurrent_requests: *value,
+                        max_queued_requests: *max_queued_requests,
+                        queue_timeout: *queue_timeout,
+                    },
+                )),
+                _ => None,
+            })
+    }
+
+    /// The `max_overzoom` values of the configured sources, by source ID
+    pub fn get_max_overzoom(&self) -> impl Iterator<Item = (String, u8)> + '_ {
+        let sources = match self {
+            Self::Config(cfg) => cfg.sources.as_ref(),
+            _ => None,
+        };
+        sources
+            .into_iter()
+            .flatten()
+            .filter_map(|(id, src)| match src {
+                FileConfigSrc::Obj(FileConfigSource {
+                    max_overzoom: Some(value),
+                    ..
+                }) => Some((id.clone(), *value)),
+                _ => None,
+            })
+    }
+
+    /// The `query_timeout` values of the configured sources, by source ID
+    pub fn get_query_timeout(&self) -> impl Iterator<Item = (String, u64)> + '_ {
+        let sources = match self {
+            Self::Config(cfg) => cfg.sources.as_ref(),
+            _ => None,
+        };
+        sources
+            .into_iter()
+            .flatten()
+            .filter_map(|(id, src)| match src {
+                FileConfigSrc::Obj(FileConfigSource {
+                    query_timeout: Some(value),
+                    ..
+                }) => Some((id.clone(), *value)),
+                _ => None,
+            })
+    }
+
+    /// The `headers` values of the configured sources, by source ID
+    pub fn get_headers(&self) -> impl Iterator<Item = (String, BTreeMap<String, String>)> + '_ {
+        let sources = match self {
+            Self::Config(cfg) => cfg.sources.as_ref(),
+            _ => None,
+        };
+        sources
+            .into_iter()
+            .flatten()
+            .filter_map(|(id, src)| match src {
+                FileConfigSrc::Obj(FileConfigSource {
+                    headers: Some(value),
+                    ..
+                }) => Some((id.clone(), value.clone())),
+                _ => None,
+            })
+    }
+
     pub fn finalize(&self, prefix: &str) -> UnrecognizedValues {
         let mut res = UnrecognizedValues::new();
         if let Self::Config(cfg) = self {
@@ -226,9 +398,32 @@ impl FileConfigSrc {
     }
 }
 
+#[serde_with::skip_serializing_none]
 #[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct FileConfigSource {
     pub path: PathBuf,
+    /// `Cache-Control` header of the tile and TileJSON responses of this source
+    pub cache_control: Option<String>,
+    /// Response for the tiles missing from this source
+    pub missing_tile: Option<MissingTile>,
+    /// Image served with `200 OK` for the tiles missing from this source, e.g. a transparent PNG for raster clients
+    pub missing_tile_file: Option<PathBuf>,
+    /// Number of zoom levels above `maxzoom` served by clipping and rescaling the MVT tiles at `maxzoom`
+    pub max_overzoom: Option<u8>,
+    /// Set to `false` to query the source for the tiles outside of its bounds too, e.g. if the bounds are only approximate
+    pub check_bounds: Option<bool>,
+    /// Leave this source out of the `/catalog`, while still serving it to the clients that know its ID
+    pub hidden: Option<bool>,
+    /// Number of tiles of this source read at once, the other requests wait for one of them to finish
+    pub max_concurrent_requests: Option<usize>,
+    /// Number of requests waiting for `max_concurrent_requests`, the others fail with `503 Service Unavailable`
+    pub max_queued_requests: Option<usize>,
+    /// Milliseconds a request may wait for `max_concurrent_requests`, then it fails with `503 Service Unavailable`
+    pub queue_timeout: Option<u64>,
+    /// Milliseconds after which the tile request fails with `504 Gateway Timeout`
+    pub query_timeout: Option<u64>,
+    /// Additional headers of the tile and TileJSON responses of this source, e.g. `X-Attribution`
+    pub headers: Option<BTreeMap<String, String>>,
 }
 
 pub async fn resolve_files<T: SourceConfigExtras>(
@@ -4,9 +4,16 @@
 mod config;
 pub use config::{Config, ServerState, read_config};
 
+mod fallback;
+pub use fallback::FallbackSource;
+
+mod overzoom;
+pub use overzoom::OverzoomSource;
+
 mod source;
 pub use source::{
-    CatalogSourceEntry, Source, Tile, TileData, TileInfoSource, TileSources, UrlQuery,
+    CatalogSourceEntry, PoolStatus, Source, SourceKind, Tile, TileData, TileInfoSource,
+    TileSources, TileStream, UrlQuery,
 };
 
 mod utils;
@@ -1,12 +1,14 @@
 use std::fmt::{Debug, Formatter};
 use std::io;
+use std::ops::RangeInclusive;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::SystemTime;
 
 use async_trait::async_trait;
 use log::trace;
-use martin_tile_utils::{TileCoord, TileInfo};
-use mbtiles::MbtilesPool;
+use martin_tile_utils::{Format, MAX_ZOOM, TileCoord, TileInfo};
+use mbtiles::{MbtError, MbtilesPool};
 use serde::{Deserialize, Serialize};
 use tilejson::TileJSON;
 use url::Url;
@@ -14,16 +16,30 @@ use url::Url;
 use crate::config::UnrecognizedValues;
 use crate::file_config::FileError::{AcquireConnError, InvalidMetadata, IoError};
 use crate::file_config::{ConfigExtras, FileResult, SourceConfigExtras};
-use crate::source::{TileData, TileInfoSource, UrlQuery};
-use crate::{MartinResult, Source};
+use crate::source::{SourceKind, TileData, TileInfoSource, UrlQuery};
+use crate::{MartinError, MartinResult, Source};
 
+/// `TileJSON` vendor extension key listing the number of tiles per zoom level
+pub const ZOOM_STATS_KEY: &str = "x-martin-zoom-stats";
+
+#[serde_with::skip_serializing_none]
 #[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct MbtConfig {
+    /// Add the number of tiles per zoom level to each source's `TileJSON` as
+    /// the `x-martin-zoom-stats` extension. Requires a full scan of each file on startup.
+    pub zoom_stats: Option<bool>,
+    /// Ignore invalid metadata values instead of correcting common mistakes,
+    /// e.g. swapped bounds or zoom levels written as floats. Defaults to `false`.
+    pub strict_metadata: Option<bool>,
     #[serde(flatten)]
     pub unrecognized: UnrecognizedValues,
 }
 
 impl ConfigExtras for MbtConfig {
+    fn is_default(&self) -> bool {
+        self.zoom_stats.is_none() && self.strict_metadata.is_none()
+    }
+
     fn get_unrecognized(&self) -> &UnrecognizedValues {
         &self.unrecognized
     }
@@ -31,7 +47,15 @@ impl ConfigExtras for MbtConfig {
 
 impl SourceConfigExtras for MbtConfig {
     async fn new_sources(&self, id: String, path: PathBuf) -> FileResult<TileInfoSource> {
-        Ok(Box::new(MbtSource::new(id, path).await?))
+        Ok(Box::new(
+            MbtSource::new(
+                id,
+                path,
+                self.zoom_stats.unwrap_or_default(),
+                self.strict_metadata.unwrap_or_default(),
+            )
+            .await?,
+        ))
     }
 
     // TODO: Remove #[allow] after switching to Rust/Clippy v1.78+ in CI
@@ -48,6 +72,12 @@ pub struct MbtSource {
     mbtiles: Arc<MbtilesPool>,
     tilejson: TileJSON,
     tile_info: TileInfo,
+    /// Zoom levels of the `minzoom` and `maxzoom` metadata values
+    zoom_range: RangeInclusive<u8>,
+    /// Pixel ratio of the raster tiles from the `scale` metadata value, e.g. `2` for 512px tiles
+    scale: u8,
+    /// Modification time of the file when it was opened
+    last_modified: Option<SystemTime>,
 }
 
 impl Debug for MbtSource {
@@ -62,26 +92,89 @@ impl Debug for MbtSource {
 }
 
 impl MbtSource {
-    async fn new(id: String, path: PathBuf) -> FileResult<Self> {
+    async fn new(
+        id: String,
+        path: PathBuf,
+        zoom_stats: bool,
+        strict_metadata: bool,
+    ) -> FileResult<Self> {
+        let last_modified = std::fs::metadata(&path)
+            .and_then(|v| v.modified())
+            .map_err(|e| IoError(e, path.clone()))?;
         let mbt = MbtilesPool::new(&path)
             .await
             .map_err(|e| io::Error::other(format!("{e:?}: Cannot open file {}", path.display())))
             .map_err(|e| IoError(e, path.clone()))?;
 
-        let meta = mbt
-            .get_metadata()
+        let mut meta = mbt
+            .get_metadata_ext(strict_metadata)
             .await
-            .map_err(|e| InvalidMetadata(e.to_string(), path))?;
+            .map_err(|e| InvalidMetadata(e.to_string(), path.clone()))?;
+
+        // Keys of the `json` metadata value other than `vector_layers`, e.g. `tilestats`,
+        // are served as TileJSON extensions, without replacing the regular metadata values
+        if let Some(serde_json::Value::Object(json)) = meta.json.take() {
+            for (key, value) in json {
+                meta.tilejson.other.entry(key).or_insert(value);
+            }
+        }
+
+        if zoom_stats {
+            let counts = mbt
+                .get_tile_count_per_zoom()
+                .await
+                .map_err(|e| InvalidMetadata(e.to_string(), path))?;
+            let stats = counts
+                .into_iter()
+                .map(|(zoom, tile_count)| serde_json::json!({"zoom": zoom, "tile_count": tile_count}))
+                .collect();
+            meta.tilejson
+                .other
+                .insert(ZOOM_STATS_KEY.to_string(), serde_json::Value::Array(stats));
+        }
+
+        // High-DPI raster tiles are advertised with their size, so that clients draw them at the right zoom
+        let is_raster = matches!(
+            meta.tile_info.format,
+            Format::Png | Format::Jpeg | Format::Gif | Format::Webp
+        );
+        let scale = if is_raster {
+            get_scale(&meta.tilejson)
+        } else {
+            1
+        };
+        if scale > 1 {
+            meta.tilejson
+                .other
+                .entry("tileSize".to_string())
+                .or_insert_with(|| (256 * u32::from(scale)).into());
+        }
 
+        let zoom_range =
+            meta.tilejson.minzoom.unwrap_or(0)..=meta.tilejson.maxzoom.unwrap_or(MAX_ZOOM);
         Ok(Self {
             id,
             mbtiles: Arc::new(mbt),
             tilejson: meta.tilejson,
             tile_info: meta.tile_info,
+            zoom_range,
+            scale,
+            last_modified: Some(last_modified),
         })
     }
 }
 
+/// The `scale` metadata value written by `TileMill` and other tools, as a number or a string like `"2"`
+fn get_scale(tilejson: &TileJSON) -> u8 {
+    match tilejson.other.get("scale") {
+        Some(serde_json::Value::Number(v)) => v.as_u64().and_then(|v| u8::try_from(v).ok()),
+        Some(serde_json::Value::String(v)) => v.trim().parse().ok(),
+        _ => None,
+    }
+    .filter(|v| (1..=3).contains(v))
+    .unwrap_or(1)
+}
+
 #[async_trait]
 impl Source for MbtSource {
     fn get_id(&self) -> &str {
@@ -96,6 +189,22 @@ impl Source for MbtSource {
         self.tile_info
     }
 
+    fn get_kind(&self) -> SourceKind {
+        SourceKind::Mbtiles
+    }
+
+    fn get_zoom_range(&self) -> RangeInclusive<u8> {
+        self.zoom_range.clone()
+    }
+
+    fn get_tile_scale(&self) -> u8 {
+        self.scale
+    }
+
+    fn get_last_modified(&self) -> Option<SystemTime> {
+        self.last_modified
+    }
+
     fn clone_source(&self) -> TileInfoSource {
         Box::new(self.clone())
     }
@@ -105,11 +214,14 @@ impl Source for MbtSource {
         xyz: TileCoord,
         _url_query: Option<&UrlQuery>,
     ) -> MartinResult<TileData> {
-        if let Some(tile) = self
-            .mbtiles
-            .get_tile(xyz.z, xyz.x, xyz.y)
-            .await
-            .map_err(|_| AcquireConnError(self.id.clone()))?
+        if let Some(tile) =
+            self.mbtiles
+                .get_tile(xyz.z, xyz.x, xyz.y)
+                .await
+                .map_err(|e| match e {
+                    MbtError::InvalidTileCoordinate(..) => MartinError::from(e),
+                    _ => MartinError::from(AcquireConnError(self.id.clone())),
+                })?
         {
             Ok(tile)
         } else {
@@ -120,6 +232,27 @@ impl Source for MbtSource {
             Ok(Vec::new())
         }
     }
+
+    async fn check_health(&self) -> MartinResult<()> {
+        self.mbtiles
+            .check_connection()
+            .await
+            .map_err(|_| MartinError::from(AcquireConnError(self.id.clone())))
+    }
+
+    fn can_probe_tiles(&self) -> bool {
+        true
+    }
+
+    async fn has_tile(&self, xyz: TileCoord) -> MartinResult<bool> {
+        self.mbtiles
+            .has_tile(xyz.z, xyz.x, xyz.y)
+            .await
+            .map_err(|e| match e {
+                MbtError::InvalidTileCoordinate(..) => MartinError::from(e),
+                _ => MartinError::from(AcquireConnError(self.id.clone())),
+            })
+    }
 }
 
 #[cfg(test)]
@@ -131,6 +264,7 @@ mod tests {
 
     use crate::file_config::{FileConfigEnum, FileConfigSource, FileConfigSrc};
     use crate::mbtiles::MbtConfig;
+    use crate::srv::MissingTile;
 
     #[test]
     fn parse() {
@@ -173,6 +307,17 @@ mod tests {
                     "pm-src2".to_string(),
                     FileConfigSrc::Obj(FileConfigSource {
                         path: PathBuf::from("/tmp/file.ext"),
+                        cache_control: None,
+                        missing_tile: None,
+                        missing_tile_file: None,
+                        max_overzoom: None,
+                        check_bounds: None,
+                        hidden: None,
+                        max_concurrent_requests: None,
+                        max_queued_requests: None,
+                        queue_timeout: None,
+                        query_timeout: None,
+                        headers: None,
                     })
                 ),
                 (
@@ -183,9 +328,80 @@ mod tests {
                     "pm-src4".to_string(),
                     FileConfigSrc::Obj(FileConfigSource {
                         path: PathBuf::from("https://example.org/file4.ext"),
+                        cache_control: None,
+                        missing_tile: None,
+                        missing_tile_file: None,
+                        max_overzoom: None,
+                        check_bounds: None,
+                        hidden: None,
+                        max_concurrent_requests: None,
+                        max_queued_requests: None,
+                        queue_timeout: None,
+                        query_timeout: None,
+                        headers: None,
                     })
                 ),
             ]))
         );
     }
+
+    #[test]
+    fn parse_source_settings() {
+        let yaml = indoc! {"
+            sources:
+              basemap:
+                path: /tmp/basemap.mbtiles
+                cache_control: public, max-age=31536000, immutable
+                missing_tile: 404
+                query_timeout: 1000
+                headers:
+                  X-Attribution: © Example
+              other: /tmp/other.mbtiles
+        "};
+        let cfg = serde_yaml::from_str::<FileConfigEnum<MbtConfig>>(yaml).unwrap();
+        assert_eq!(
+            cfg.get_cache_control().collect::<Vec<_>>(),
+            vec![(
+                "basemap".to_string(),
+                "public, max-age=31536000, immutable".to_string()
+            )]
+        );
+        assert_eq!(
+            cfg.get_missing_tile().collect::<Vec<_>>(),
+            vec![("basemap".to_string(), MissingTile::NotFound)]
+        );
+        assert_eq!(
+            cfg.get_query_timeout().collect::<Vec<_>>(),
+            vec![("basemap".to_string(), 1000)]
+        );
+        assert_eq!(
+            cfg.get_headers().collect::<Vec<_>>(),
+            vec![(
+                "basemap".to_string(),
+                BTreeMap::from([("X-Attribution".to_string(), "© Example".to_string())])
+            )]
+        );
+
+        // The setting is preserved when the config is saved
+        let saved = serde_yaml::to_string(&cfg).unwrap();
+        assert_eq!(
+            serde_yaml::from_str::<FileConfigEnum<MbtConfig>>(&saved).unwrap(),
+            cfg
+        );
+    }
+
+    #[test]
+    fn scale_metadata() {
+        let scale = |value: serde_json::Value| {
+            let mut tj = tilejson::tilejson! { tiles: vec![] };
+            tj.other.insert("scale".to_string(), value);
+            super::get_scale(&tj)
+        };
+        assert_eq!(scale(serde_json::json!("2")), 2);
+        assert_eq!(scale(serde_json::json!(3)), 3);
+        assert_eq!(scale(serde_json::json!("1")), 1);
+        assert_eq!(scale(serde_json::json!("8")), 1);
+        assert_eq!(scale(serde_json::json!("retina")), 1);
+        assert_eq!(super::get_scale(&tilejson::tilejson! { tiles: vec![] }), 1);
+    }
 }
@@ -0,0 +1,205 @@
+use std::ops::RangeInclusive;
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use martin_tile_utils::{
+    Encoding, Format, MAX_ZOOM, TileCoord, TileInfo, decode_brotli, decode_gzip, encode_brotli,
+    overzoom_mvt,
+};
+use tilejson::TileJSON;
+
+use crate::MartinError::{OverzoomFormatError, OverzoomTileError};
+use crate::source::{
+    PoolStatus, Source, SourceKind, TileData, TileInfoSource, TileStream, UrlQuery,
+};
+use crate::{MartinResult, TileRect};
+
+/// A source serving the tiles of an MVT source above its `maxzoom`,
+/// by clipping and scaling up the part of its tile at `maxzoom` covered by the requested tile
+#[derive(Clone, Debug)]
+pub struct OverzoomSource {
+    source: TileInfoSource,
+    tilejson: TileJSON,
+    maxzoom: u8,
+}
+
+impl OverzoomSource {
+    /// Serve the tiles of the source up to `max_overzoom` zoom levels above its `maxzoom`.
+    /// The tiles must be MVT, either uncompressed or compressed with gzip or brotli.
+    pub fn new(source: TileInfoSource, max_overzoom: u8) -> MartinResult<Self> {
+        let info = source.get_tile_info();
+        if info.format != Format::Mvt
+            || !matches!(
+                info.encoding,
+                Encoding::Uncompressed | Encoding::Gzip | Encoding::Brotli
+            )
+        {
+            return Err(OverzoomFormatError(source.get_id().to_string()));
+        }
+        let range_max = *source.get_zoom_range().end();
+        let maxzoom = source
+            .get_tilejson()
+            .maxzoom
+            .map_or(range_max, |v| v.min(range_max));
+        let mut tilejson = source.get_tilejson().clone();
+        tilejson.maxzoom = Some(maxzoom.saturating_add(max_overzoom).min(MAX_ZOOM));
+        Ok(Self {
+            source,
+            tilejson,
+            maxzoom,
+        })
+    }
+
+    fn overzoom(&self, xyz: TileCoord, data: &[u8]) -> MartinResult<TileData> {
+        let dz = xyz.z - self.maxzoom;
+        let mask = (1 << dz) - 1;
+        let encoding = self.source.get_tile_info().encoding;
+        let tile = match encoding {
+            Encoding::Gzip if data.starts_with(b"\x1f\x8b") => decode_gzip(data)?,
+            Encoding::Brotli => decode_brotli(data)?,
+            _ => data.to_vec(),
+        };
+        let tile = overzoom_mvt(&tile, dz, xyz.x & mask, xyz.y & mask)
+            .map_err(|e| OverzoomTileError(self.get_id().to_string(), xyz, e))?;
+        // Tiles of gzip sources may be uncompressed, and are compressed for the response if needed
+        Ok(match encoding {
+            Encoding::Brotli if !tile.is_empty() => encode_brotli(&tile)?,
+            _ => tile,
+        })
+    }
+}
+
+#[async_trait]
+impl Source for OverzoomSource {
+    fn get_id(&self) -> &str {
+        self.source.get_id()
+    }
+
+    fn get_tilejson(&self) -> &TileJSON {
+        &self.tilejson
+    }
+
+    fn get_tile_info(&self) -> TileInfo {
+        self.source.get_tile_info()
+    }
+
+    fn get_kind(&self) -> SourceKind {
+        self.source.get_kind()
+    }
+
+    fn clone_source(&self) -> TileInfoSource {
+        Box::new(self.clone())
+    }
+
+    fn support_url_query(&self) -> bool {
+        self.source.support_url_query()
+    }
+
+    fn get_error(&self) -> Option<&str> {
+        self.source.get_error()
+    }
+
+    /// Tiles above the `maxzoom` of the source are cut from their ancestor tile at `maxzoom`
+    async fn get_tile(
+        &self,
+        xyz: TileCoord,
+        url_query: Option<&UrlQuery>,
+    ) -> MartinResult<TileData> {
+        if xyz.z <= self.maxzoom {
+            return self.source.get_tile(xyz, url_query).await;
+        }
+        let dz = xyz.z - self.maxzoom;
+        let parent = TileCoord {
+            z: self.maxzoom,
+            x: xyz.x >> dz,
+            y: xyz.y >> dz,
+        };
+        let data = self.source.get_tile(parent, url_query).await?;
+        if data.is_empty() {
+            return Ok(data);
+        }
+        self.overzoom(xyz, &data)
+    }
+
+    async fn check_health(&self) -> MartinResult<()> {
+        self.source.check_health().await
+    }
+
+    fn get_pool_status(&self) -> Option<(String, PoolStatus)> {
+        self.source.get_pool_status()
+    }
+
+    /// Only the tiles up to `maxzoom` can be probed, as the overzoomed tiles may be left without any features
+    async fn has_tile(&self, xyz: TileCoord) -> MartinResult<bool> {
+        if xyz.z <= self.maxzoom {
+            self.source.has_tile(xyz).await
+        } else {
+            Ok(false)
+        }
+    }
+
+    async fn get_tiles_bulk(&self, rect: TileRect) -> MartinResult<Option<TileStream>> {
+        if rect.zoom <= self.maxzoom {
+            self.source.get_tiles_bulk(rect).await
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn get_zoom_range(&self) -> RangeInclusive<u8> {
+        let range = self.source.get_zoom_range();
+        *range.start()..=self.tilejson.maxzoom.unwrap_or(*range.end())
+    }
+
+    fn get_last_modified(&self) -> Option<SystemTime> {
+        self.source.get_last_modified()
+    }
+
+    fn get_tile_scale(&self) -> u8 {
+        self.source.get_tile_scale()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tilejson::tilejson;
+
+    use super::*;
+    use crate::srv::server::tests::TestSource;
+
+    /// Layer "test" with a point at (3172,1124)
+    const TILE: &[u8] = &[
+        26, 24, 120, 2, 10, 4, 116, 101, 115, 116, 18, 11, 8, 1, 24, 1, 34, 5, 9, 200, 49, 200, 17,
+        40, 128, 32,
+    ];
+    /// Layer "test" with a point at (400,400)
+    const OVERZOOMED: &[u8] = &[
+        26, 24, 120, 2, 10, 4, 116, 101, 115, 116, 18, 11, 8, 1, 24, 1, 34, 5, 9, 160, 6, 160, 6,
+        40, 128, 32,
+    ];
+
+    #[actix_rt::test]
+    async fn overzoom_source() {
+        let mut tj = tilejson! { tiles: vec![] };
+        tj.maxzoom = Some(6);
+        let source = TestSource {
+            id: "src",
+            tj,
+            data: TILE.to_vec(),
+        };
+        let src = OverzoomSource::new(Box::new(source), 2).unwrap();
+        assert_eq!(src.get_tilejson().maxzoom, Some(8));
+        assert_eq!(src.get_zoom_range(), 0..=8);
+
+        let tile =
+            async |z: u8, x: u32, y: u32| src.get_tile(TileCoord { z, x, y }, None).await.unwrap();
+        assert_eq!(tile(6, 10, 21).await, TILE);
+        // The point is in the tile at column 3 and row 1 of the 4x4 tiles covering 6/10/21 at zoom 8
+        assert_eq!(tile(8, 43, 85).await, OVERZOOMED);
+        assert!(tile(8, 40, 84).await.is_empty());
+        assert!(tile(8, 42, 85).await.is_empty());
+        // The same point is in the tile at column 1 and row 0 of the 2x2 tiles at zoom 7
+        assert!(!tile(7, 21, 42).await.is_empty());
+        assert!(tile(7, 20, 42).await.is_empty());
+    }
+}
@@ -1,23 +1,25 @@
 use std::cmp::Ordering;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 
 use futures::future::join_all;
 use itertools::Itertools as _;
 use log::{debug, error, info, warn};
 
+use martin_tile_utils::TileCoord;
+
 use crate::OptBoolObj::{Bool, NoValue, Object};
 use crate::args::BoundsCalcType;
 use crate::pg::PgError::InvalidTableExtent;
 use crate::pg::config::{PgConfig, PgInfo};
 use crate::pg::config_function::{FuncInfoSources, FunctionInfo};
-use crate::pg::config_table::{TableInfo, TableInfoSources};
-use crate::pg::pg_source::{PgSource, PgSqlInfo};
+use crate::pg::config_table::{TableInfo, TableInfoSources, is_id_column_type};
+use crate::pg::pg_source::{COMPRESS_MIN_SIZE_DEFAULT, PgSource, PgSqlInfo};
 use crate::pg::pool::PgPool;
 use crate::pg::query_functions::query_available_function;
 use crate::pg::query_tables::{query_available_tables, table_to_query};
-use crate::pg::utils::{InfoMap, find_info, find_kv_ignore_case, normalize_key};
-use crate::pg::{PgCfgPublish, PgCfgPublishFuncs, PgResult};
-use crate::source::TileInfoSources;
+use crate::pg::utils::{InfoMap, NamePattern, find_info, find_kv_ignore_case, normalize_key};
+use crate::pg::{PgCfgPublish, PgCfgTemplate, PgResult};
+use crate::source::{TileInfoSource, TileInfoSources};
 use crate::utils::IdResolver;
 use crate::utils::OptOneMany::NoVals;
 
@@ -31,6 +33,10 @@ pub struct PgBuilder {
     default_srid: Option<i32>,
     auto_bounds: BoundsCalcType,
     max_feature_count: Option<usize>,
+    probe_tile: Option<TileCoord>,
+    compress_min_size: usize,
+    /// Settings for the auto-published sources, configured sources already have them applied
+    source_defaults: PgCfgTemplate,
     auto_functions: Option<PgBuilderFuncs>,
     auto_tables: Option<PgBuilderTables>,
     id_resolver: IdResolver,
@@ -43,6 +49,7 @@ pub struct PgBuilder {
 pub struct PgBuilderFuncs {
     schemas: Option<HashSet<String>>,
     source_id_format: String,
+    ignore: Option<Vec<String>>,
 }
 
 #[derive(Debug, Default, PartialEq)]
@@ -55,6 +62,7 @@ pub struct PgBuilderTables {
     clip_geom: Option<bool>,
     buffer: Option<u32>,
     extent: Option<u32>,
+    ignore: Option<Vec<String>>,
 }
 
 /// Combine `from_schema` field from the `config.auto_publish` and `config.auto_publish.tables/functions`
@@ -88,6 +96,19 @@ impl PgBuilder {
             default_srid: config.default_srid,
             auto_bounds: config.auto_bounds.unwrap_or_default(),
             max_feature_count: config.max_feature_count,
+            probe_tile: match &config.probe_functions {
+                Bool(true) => Some(TileCoord { z: 0, x: 0, y: 0 }),
+                Object(p) => Some(TileCoord {
+                    z: p.z,
+                    x: p.x,
+                    y: p.y,
+                }),
+                NoValue | Bool(false) => None,
+            },
+            compress_min_size: config
+                .compress_min_size
+                .unwrap_or(COMPRESS_MIN_SIZE_DEFAULT),
+            source_defaults: config.source_defaults.clone().unwrap_or_default(),
             id_resolver,
             tables: config.tables.clone().unwrap_or_default(),
             functions: config.functions.clone().unwrap_or_default(),
@@ -165,12 +186,14 @@ impl PgBuilder {
                 auto_tables.source_id_format,
             );
 
-            for schema in schemas.iter().sorted() {
-                let Some(schema) = normalize_key(&db_tables_info, schema, "schema", "") else {
-                    continue;
-                };
+            let ignore = to_patterns(auto_tables.ignore.as_deref());
+            for schema in find_schemas(&db_tables_info, &schemas) {
                 let db_tables = db_tables_info.remove(&schema).unwrap();
                 for (table, geoms) in db_tables.into_iter().sorted_by(by_key) {
+                    if ignore.iter().any(|v| v.is_match(&table)) {
+                        debug!("Ignoring table {schema}.{table} matching the ignore patterns");
+                        continue;
+                    }
                     for (geom_column, mut db_inf) in geoms.into_iter().sorted_by(by_key) {
                         if used.contains(&(schema.as_str(), table.as_str(), geom_column.as_str())) {
                             continue;
@@ -186,6 +209,7 @@ impl PgBuilder {
                         };
                         db_inf.srid = srid;
                         update_auto_fields(&id2, &mut db_inf, auto_tables);
+                        self.source_defaults.apply_to_table(&mut db_inf);
                         info!("Discovered source {id2} from {}", summary(&db_inf));
                         pending.push(table_to_query(
                             id2,
@@ -220,7 +244,7 @@ impl PgBuilder {
 
     pub async fn instantiate_functions(&self) -> PgResult<(TileInfoSources, FuncInfoSources)> {
         let mut db_funcs_info = query_available_function(&self.pool).await?;
-        let mut res = TileInfoSources::default();
+        let mut res = Vec::<PgSource>::new();
         let mut info_map = FuncInfoSources::new();
         let mut used = HashSet::<(&str, &str)>::new();
 
@@ -241,11 +265,19 @@ impl PgBuilder {
             };
 
             let merged_inf = db_inf.append_cfg_info(cfg_inf);
+            let mut pg_sql = pg_sql.clone();
+            pg_sql.query_param_ranges = merged_inf.query_param_ranges();
+            if !pg_sql.use_url_query && !pg_sql.query_param_ranges.is_empty() {
+                warn!(
+                    "Function source {id} has extent or buffer limits configured, but the function {} does not accept URL query parameters",
+                    pg_sql.signature
+                );
+            }
 
             let dup = !used.insert((&cfg_inf.schema, func_name));
             let dup = if dup { "duplicate " } else { "" };
             let id2 = self.resolve_id(id, &merged_inf);
-            self.add_func_src(&mut res, id2.clone(), &merged_inf, pg_sql.clone());
+            res.push(self.new_pg_src(id2.clone(), &merged_inf, pg_sql.clone()));
             warn_on_rename(id, &id2, "Function");
             let signature = &pg_sql.signature;
             info!("Configured {dup}source {id2} from the function {signature}");
@@ -265,27 +297,40 @@ impl PgBuilder {
                 auto_funcs.source_id_format,
             );
 
-            for schema in schemas.iter().sorted() {
-                let Some(schema) = normalize_key(&db_funcs_info, schema, "schema", "") else {
-                    continue;
-                };
+            let ignore = to_patterns(auto_funcs.ignore.as_deref());
+            for schema in find_schemas(&db_funcs_info, &schemas) {
                 let db_funcs = db_funcs_info.remove(&schema).unwrap();
-                for (func, (pg_sql, db_inf)) in db_funcs.into_iter().sorted_by(by_key) {
+                for (func, (pg_sql, mut db_inf)) in db_funcs.into_iter().sorted_by(by_key) {
                     if used.contains(&(schema.as_str(), func.as_str())) {
                         continue;
                     }
+                    if ignore.iter().any(|v| v.is_match(&func)) {
+                        debug!("Ignoring function {schema}.{func} matching the ignore patterns");
+                        continue;
+                    }
+                    self.source_defaults.apply_to_function(&mut db_inf);
                     let source_id = auto_funcs
                         .source_id_format
                         .replace("{schema}", &schema)
                         .replace("{function}", &func);
                     let id2 = self.resolve_id(&source_id, &db_inf);
-                    self.add_func_src(&mut res, id2.clone(), &db_inf, pg_sql.clone());
+                    res.push(self.new_pg_src(id2.clone(), &db_inf, pg_sql.clone()));
                     info!("Discovered source {id2} from function {}", pg_sql.signature);
                     debug!("{id2} query: {}", pg_sql.sql_query);
                     info_map.insert(id2, db_inf);
                 }
             }
         }
+
+        if let Some(xyz) = self.probe_tile {
+            info!("Probing function sources with tile {xyz:#}");
+            join_all(res.iter_mut().map(|src| src.probe(xyz))).await;
+        }
+
+        let res = res
+            .into_iter()
+            .map(|src| Box::new(src) as TileInfoSource)
+            .collect();
         Ok((res, info_map))
     }
 
@@ -301,12 +346,44 @@ impl PgBuilder {
         pg_info: &impl PgInfo,
         sql_info: PgSqlInfo,
     ) {
+        sources.push(Box::new(self.new_pg_src(id, pg_info, sql_info)));
+    }
+
+    fn new_pg_src(&self, id: String, pg_info: &impl PgInfo, sql_info: PgSqlInfo) -> PgSource {
         let tilejson = pg_info.to_tilejson(id.clone());
-        let source = PgSource::new(id, sql_info, tilejson, self.pool.clone());
-        sources.push(Box::new(source));
+        PgSource::new(id, pg_info.kind(), sql_info, tilejson, self.pool.clone())
+            .with_query_timeout(pg_info.query_timeout())
+            .with_compression(pg_info.compress_tiles(), self.compress_min_size)
     }
 }
 
+/// Find the schemas of the database to auto-publish. The configured names are looked up like those
+/// of the configured sources, and the patterns are expanded to all matching schemas.
+fn find_schemas<T>(db_info: &InfoMap<T>, schemas: &HashSet<String>) -> BTreeSet<String> {
+    let mut found = BTreeSet::new();
+    for schema in schemas.iter().sorted() {
+        if NamePattern::is_pattern(schema) {
+            let pattern = NamePattern::new(schema);
+            let len = found.len();
+            found.extend(db_info.keys().filter(|v| pattern.is_match(v)).cloned());
+            if found.len() == len {
+                warn!("No new schemas match the pattern '{schema}' of the auto-published schemas");
+            }
+        } else if let Some(schema) = normalize_key(db_info, schema, "schema", "") {
+            found.insert(schema);
+        }
+    }
+    found
+}
+
+fn to_patterns(patterns: Option<&[String]>) -> Vec<NamePattern> {
+    patterns
+        .into_iter()
+        .flatten()
+        .map(|v| NamePattern::new(v))
+        .collect()
+}
+
 fn update_auto_fields(id: &str, inf: &mut TableInfo, auto_tables: &PgBuilderTables) {
     if inf.clip_geom.is_none() {
         inf.clip_geom = auto_tables.clip_geom;
@@ -319,14 +396,28 @@ fn update_auto_fields(id: &str, inf: &mut TableInfo, auto_tables: &PgBuilderTabl
     }
 
     // Try to find any ID column in a list of table columns (properties) that match one of the given `id_column` values.
-    // If found, modify `id_column` value on the table info.
-    let Some(props) = inf.properties.as_mut() else {
-        return;
-    };
-    let Some(try_columns) = &auto_tables.id_columns else {
+    // If none is found, use the primary key of the table if it is an integer.
+    let Some(props) = inf.properties.as_ref() else {
         return;
     };
+    let column = find_id_column(id, inf, props, auto_tables.id_columns.as_deref())
+        .or_else(|| find_primary_key(id, inf, props));
+    if let Some(column) = column {
+        let mut final_props = props.clone();
+        final_props.remove(&column);
+        inf.properties = Some(final_props);
+        inf.id_column = Some(column);
+    }
+}
 
+/// Find the first integer column of the table matching one of the `id_columns` of the auto-publishing configuration
+fn find_id_column(
+    id: &str,
+    inf: &TableInfo,
+    props: &BTreeMap<String, String>,
+    try_columns: Option<&[String]>,
+) -> Option<String> {
+    let try_columns = try_columns?;
     for key in try_columns {
         let (column, typ) = if let Some(typ) = props.get(key) {
             (key, typ)
@@ -348,21 +439,14 @@ fn update_auto_fields(id: &str, inf: &mut TableInfo, auto_tables: &PgBuilderTabl
                 }
             }
         };
-        // ID column can be any integer type as defined in
-        // https://github.com/postgis/postgis/blob/559c95d85564fb74fa9e3b7eafb74851810610da/postgis/mvt.c#L387C4-L387C66
-        if typ != "int4" && typ != "int8" && typ != "int2" {
+        if !is_id_column_type(typ) {
             warn!(
                 "Unable to use column `{key}` in table {}.{} as a tile feature ID because it has a non-integer type `{typ}`.",
                 inf.schema, inf.table
             );
             continue;
         }
-
-        inf.id_column = Some(column.to_string());
-        let mut final_props = props.clone();
-        final_props.remove(column);
-        inf.properties = Some(final_props);
-        return;
+        return Some(column.to_string());
     }
 
     info!(
@@ -371,6 +455,26 @@ fn update_auto_fields(id: &str, inf: &mut TableInfo, auto_tables: &PgBuilderTabl
         inf.table,
         try_columns.join(", ")
     );
+    None
+}
+
+/// Use the single column primary key of the table as the ID column if it is an integer
+fn find_primary_key(id: &str, inf: &TableInfo, props: &BTreeMap<String, String>) -> Option<String> {
+    let column = inf.primary_key.as_ref()?;
+    let typ = props.get(column)?;
+    if is_id_column_type(typ) {
+        info!(
+            "Source {id} uses the primary key `{column}` of table {}.{} as its feature ID",
+            inf.schema, inf.table
+        );
+        Some(column.clone())
+    } else {
+        debug!(
+            "Primary key `{column}` of table {}.{} has a non-integer type `{typ}` and cannot be the feature ID of source {id}",
+            inf.schema, inf.table
+        );
+        None
+    }
 }
 
 fn calc_auto(config: &PgConfig) -> (Option<PgBuilderTables>, Option<PgBuilderFuncs>) {
@@ -391,6 +495,7 @@ fn calc_auto(config: &PgConfig) -> (Option<PgBuilderTables>, Option<PgBuilderFun
                 clip_geom: v.clip_geom,
                 buffer: v.buffer,
                 extent: v.extent,
+                ignore: v.ignore.opt_iter().map(|v| v.cloned().collect()),
             }
         } else {
             PgBuilderTables {
@@ -405,21 +510,21 @@ fn calc_auto(config: &PgConfig) -> (Option<PgBuilderTables>, Option<PgBuilderFun
     };
 
     let auto_functions = if use_auto_publish(config, true) {
+        let funcs = if let Object(PgCfgPublish {
+            functions: Object(v),
+            ..
+        }) = &config.auto_publish
+        {
+            Some(v)
+        } else {
+            None
+        };
         Some(PgBuilderFuncs {
             schemas: get_auto_schemas!(config, functions),
-            source_id_format: if let Object(PgCfgPublish {
-                functions:
-                    Object(PgCfgPublishFuncs {
-                        source_id_format: Some(v),
-                        ..
-                    }),
-                ..
-            }) = &config.auto_publish
-            {
-                v.clone()
-            } else {
-                "{function}".to_string()
-            },
+            source_id_format: funcs
+                .and_then(|v| v.source_id_format.clone())
+                .unwrap_or_else(|| "{function}".to_string()),
+            ignore: funcs.and_then(|v| v.ignore.opt_iter().map(|v| v.cloned().collect())),
         })
     } else {
         None
@@ -459,8 +564,9 @@ fn warn_on_rename(old_id: &String, new_id: &String, typ: &str) {
 }
 
 fn summary(info: &TableInfo) -> String {
-    let relkind = match info.is_view {
-        Some(true) => "view",
+    let relkind = match (info.is_view, info.is_materialized_view) {
+        (Some(true), _) => "view",
+        (_, Some(true)) => "materialized view",
         _ => "table",
     };
     // TODO: add column_id to the summary if it is set
@@ -632,4 +738,62 @@ mod tests {
         auto_funcs: ~
         "#);
     }
+
+    #[test]
+    fn test_auto_publish_ignore() {
+        let cfg = auto(indoc! {"
+            auto_publish:
+                tables:
+                    from_schemas: [public, gis_*]
+                    ignore: '*_backup'
+                functions:
+                    ignore: ['_*', tmp_?]"});
+        assert_yaml_snapshot!(cfg, @r#"
+        auto_table:
+          schemas:
+            - gis_*
+            - public
+          source_id_format: "{table}"
+          ignore:
+            - "*_backup"
+        auto_funcs:
+          source_id_format: "{function}"
+          ignore:
+            - _*
+            - tmp_?
+        "#);
+    }
+
+    #[test]
+    fn test_name_pattern() {
+        let is_match = |pattern: &str, name: &str| NamePattern::new(pattern).is_match(name);
+        assert!(is_match("gis_*", "gis_"));
+        assert!(is_match("gis_*", "gis_roads"));
+        assert!(!is_match("gis_*", "public"));
+        assert!(is_match("*_backup", "roads_backup"));
+        assert!(!is_match("*_backup", "roads_backup_old"));
+        assert!(is_match("*_b*p*", "roads_backup_old"));
+        assert!(is_match("tmp_?", "tmp_1"));
+        assert!(!is_match("tmp_?", "tmp_12"));
+        assert!(is_match("*", ""));
+        // Unquoted patterns are folded to lowercase like the PostgreSQL identifiers
+        assert!(!is_match("GIS_*", "GIS_Roads"));
+        assert!(is_match("GIS_*", "gis_roads"));
+        assert!(is_match(r#""GIS_*""#, "GIS_Roads"));
+        assert!(!is_match(r#""GIS_*""#, "gis_roads"));
+
+        let db_info = InfoMap::from([
+            ("MixedCase".to_string(), ()),
+            ("gis_a".to_string(), ()),
+            ("gis_b".to_string(), ()),
+            ("public".to_string(), ()),
+        ]);
+        let schemas = HashSet::from(["gis_*".to_string(), "mixedcase".to_string()]);
+        assert_eq!(
+            find_schemas(&db_info, &schemas)
+                .into_iter()
+                .collect::<Vec<_>>(),
+            vec!["MixedCase", "gis_a", "gis_b"]
+        );
+    }
 }
@@ -1,24 +1,32 @@
+use std::collections::BTreeMap;
 use std::ops::Add;
+use std::path::PathBuf;
 use std::time::Duration;
 
 use futures::future::try_join;
 use log::warn;
+use martin_tile_utils::MAX_ZOOM;
 use serde::{Deserialize, Serialize};
-use tilejson::TileJSON;
+use tilejson::{Bounds, TileJSON};
 
 use crate::MartinResult;
 use crate::args::{BoundsCalcType, DEFAULT_BOUNDS_TIMEOUT};
 use crate::config::{UnrecognizedValues, copy_unrecognized_config};
 use crate::pg::builder::PgBuilder;
-use crate::pg::config_function::FuncInfoSources;
-use crate::pg::config_table::TableInfoSources;
+use crate::pg::config_function::{FuncInfoSources, FunctionInfo};
+use crate::pg::config_table::{TableInfo, TableInfoSources};
 use crate::pg::utils::on_slow;
 use crate::pg::{PgError, PgResult};
-use crate::source::TileInfoSources;
+use crate::source::{SourceKind, TileInfoSources};
+use crate::srv::{ConcurrencyLimit, MissingTile};
 use crate::utils::{IdResolver, OptBoolObj, OptOneMany};
 
 pub trait PgInfo {
     fn format_id(&self) -> String;
+    fn kind(&self) -> SourceKind;
+    /// Milliseconds after which the tile query is cancelled by `PostgreSQL`
+    fn query_timeout(&self) -> Option<u64>;
+    fn compress_tiles(&self) -> TileCompression;
     fn to_tilejson(&self, source_id: String) -> TileJSON;
 }
 
@@ -40,14 +48,34 @@ pub struct PgSslCerts {
 #[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct PgConfig {
     pub connection_string: Option<String>,
+    /// Read replicas of the database, taking turns with `connection_string` to run the tile queries.
+    /// The tables and functions are always discovered with `connection_string`.
+    #[serde(default, skip_serializing_if = "OptOneMany::is_none")]
+    pub replica_connection_strings: OptOneMany<String>,
     #[serde(flatten)]
     pub ssl_certificates: PgSslCerts,
     pub default_srid: Option<i32>,
     pub auto_bounds: Option<BoundsCalcType>,
     pub max_feature_count: Option<usize>,
     pub pool_size: Option<usize>,
+    /// Seconds to keep retrying to connect at startup while the database is not reachable,
+    /// `0` to fail at once like `--fail-fast`. Defaults to `60`.
+    pub connection_timeout: Option<u64>,
+    /// Milliseconds to wait before retrying to connect at startup, doubled after each attempt. Defaults to `500`.
+    pub retry_interval: Option<u64>,
+    /// Tiles smaller than this many bytes are not compressed by the sources with `compress_tiles: gzip`.
+    /// Defaults to `512`.
+    pub compress_min_size: Option<usize>,
     #[serde(default, skip_serializing_if = "OptBoolObj::is_none")]
     pub auto_publish: OptBoolObj<PgCfgPublish>,
+    /// Request a test tile from each function source at startup,
+    /// and mark the sources that fail as degraded in the catalog.
+    #[serde(default, skip_serializing_if = "OptBoolObj::is_none")]
+    pub probe_functions: OptBoolObj<PgCfgProbe>,
+    /// Settings used by all table and function sources, unless set by the source itself or by its template
+    pub source_defaults: Option<PgCfgTemplate>,
+    /// Named settings that table and function sources can use with `template: <name>`
+    pub templates: Option<BTreeMap<String, PgCfgTemplate>>,
     pub tables: Option<TableInfoSources>,
     pub functions: Option<FuncInfoSources>,
 }
@@ -80,6 +108,9 @@ pub struct PgCfgPublishTables {
     pub clip_geom: Option<bool>,
     pub buffer: Option<u32>,
     pub extent: Option<u32>,
+    /// Patterns of the table names not to publish, e.g. `*_backup`
+    #[serde(default, skip_serializing_if = "OptOneMany::is_none")]
+    pub ignore: OptOneMany<String>,
 }
 
 #[serde_with::skip_serializing_none]
@@ -90,6 +121,86 @@ pub struct PgCfgPublishFuncs {
     pub from_schemas: OptOneMany<String>,
     #[serde(alias = "id_format")]
     pub source_id_format: Option<String>,
+    /// Patterns of the function names not to publish, e.g. `_*`
+    #[serde(default, skip_serializing_if = "OptOneMany::is_none")]
+    pub ignore: OptOneMany<String>,
+}
+
+/// The tile requested from each function source by the startup probe
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PgCfgProbe {
+    pub z: u8,
+    pub x: u32,
+    pub y: u32,
+}
+
+/// Settings shared by many sources, see `source_defaults` and `templates`
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PgCfgTemplate {
+    pub minzoom: Option<u8>,
+    pub maxzoom: Option<u8>,
+    pub bounds: Option<Bounds>,
+    /// Only used by table sources
+    pub extent: Option<u32>,
+    /// Only used by table sources
+    pub buffer: Option<u32>,
+    /// Only used by table sources
+    pub clip_geom: Option<bool>,
+    pub query_timeout: Option<u64>,
+    pub compress_tiles: Option<TileCompression>,
+}
+
+/// Compression applied by `PostgreSQL` sources to their tiles before they are cached and served
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TileCompression {
+    /// Serve the tiles as returned by the query, compressing them for each response if the client accepts it
+    #[default]
+    None,
+    /// Compress the tiles once with gzip, so that they are cached and served compressed
+    Gzip,
+}
+
+/// Simplification of the geometries of a table source before they are encoded into the tiles
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Simplify {
+    Mode(SimplifyMode),
+    /// Tolerance in web-mercator units (meters) of the zooms up to each zoom threshold, e.g. `{ 0: 1000, 5: 100 }`
+    /// simplifies zoom 0 with 1000, zooms 1 to 5 with 100, and leaves the zooms above 5 as is
+    Zooms(BTreeMap<u8, f64>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SimplifyMode {
+    /// Use the size of a tile extent unit at each zoom as the tolerance
+    Auto,
+}
+
+impl PgCfgTemplate {
+    /// Use the template values for the settings that are not set by the table source
+    pub fn apply_to_table(&self, inf: &mut TableInfo) {
+        inf.minzoom = inf.minzoom.or(self.minzoom);
+        inf.maxzoom = inf.maxzoom.or(self.maxzoom);
+        inf.bounds = inf.bounds.or(self.bounds);
+        inf.extent = inf.extent.or(self.extent);
+        inf.buffer = inf.buffer.or(self.buffer);
+        inf.clip_geom = inf.clip_geom.or(self.clip_geom);
+        inf.query_timeout = inf.query_timeout.or(self.query_timeout);
+        inf.compress_tiles = inf.compress_tiles.or(self.compress_tiles);
+    }
+
+    /// Use the template values for the settings that are not set by the function source
+    pub fn apply_to_function(&self, inf: &mut FunctionInfo) {
+        inf.minzoom = inf.minzoom.or(self.minzoom);
+        inf.maxzoom = inf.maxzoom.or(self.maxzoom);
+        inf.bounds = inf.bounds.or(self.bounds);
+        inf.query_timeout = inf.query_timeout.or(self.query_timeout);
+        inf.compress_tiles = inf.compress_tiles.or(self.compress_tiles);
+    }
 }
 
 impl PgConfig {
@@ -102,6 +213,27 @@ impl PgConfig {
                 ));
             }
         }
+        if let OptBoolObj::Object(probe) = &self.probe_functions {
+            if probe.z > MAX_ZOOM || probe.x >= 1 << probe.z || probe.y >= 1 << probe.z {
+                return Err(PgError::ConfigError(
+                    "probe_functions must be a valid tile for its zoom level.",
+                ));
+            }
+        }
+        if let Some(functions) = &self.functions {
+            for func in functions.values() {
+                if !func.query_param_ranges().iter().all(|(_, v)| v.is_valid()) {
+                    return Err(PgError::ConfigError(
+                        "Function extent and buffer limits must have min <= default <= max.",
+                    ));
+                }
+            }
+        }
+        if self.retry_interval == Some(0) {
+            return Err(PgError::ConfigError(
+                "retry_interval must be greater than or equal to 1.",
+            ));
+        }
         if self.connection_string.is_none() {
             return Err(PgError::ConfigError(
                 "A connection string must be provided.",
@@ -127,10 +259,40 @@ impl PgConfig {
             self.auto_publish = OptBoolObj::Bool(true);
         }
 
+        self.apply_templates()?;
         self.validate()?;
         Ok(res)
     }
 
+    /// Fill the settings of the configured sources from their templates, and then from the `source_defaults`
+    fn apply_templates(&mut self) -> PgResult<()> {
+        let get_template = |id: &str, name: Option<&String>| -> PgResult<Option<&PgCfgTemplate>> {
+            let Some(name) = name else {
+                return Ok(None);
+            };
+            self.templates
+                .as_ref()
+                .and_then(|v| v.get(name))
+                .map(Some)
+                .ok_or_else(|| PgError::UnknownTemplate(id.to_string(), name.clone()))
+        };
+        let defaults = self.source_defaults.clone().unwrap_or_default();
+
+        for (id, inf) in self.tables.iter_mut().flatten() {
+            if let Some(template) = get_template(id, inf.template.as_ref())? {
+                template.apply_to_table(inf);
+            }
+            defaults.apply_to_table(inf);
+        }
+        for (id, inf) in self.functions.iter_mut().flatten() {
+            if let Some(template) = get_template(id, inf.template.as_ref())? {
+                template.apply_to_function(inf);
+            }
+            defaults.apply_to_function(inf);
+        }
+        Ok(())
+    }
+
     pub async fn resolve(&mut self, id_resolver: IdResolver) -> MartinResult<TileInfoSources> {
         let pg = PgBuilder::new(self, id_resolver).await?;
         let inst_tables = on_slow(
@@ -159,6 +321,110 @@ impl PgConfig {
         tables.extend(funcs);
         Ok(tables)
     }
+
+    /// The `cache_control` values of the table and function sources, by source ID
+    pub fn get_cache_control(&self) -> impl Iterator<Item = (String, String)> + '_ {
+        let tables = self.tables.iter().flatten();
+        let tables = tables.filter_map(|(id, v)| Some((id.clone(), v.cache_control.clone()?)));
+        let functions = self.functions.iter().flatten();
+        let functions =
+            functions.filter_map(|(id, v)| Some((id.clone(), v.cache_control.clone()?)));
+        tables.chain(functions)
+    }
+
+    /// The `missing_tile` values of the table and function sources, by source ID
+    pub fn get_missing_tile(&self) -> impl Iterator<Item = (String, MissingTile)> + '_ {
+        let tables = self.tables.iter().flatten();
+        let tables = tables.filter_map(|(id, v)| Some((id.clone(), v.missing_tile?)));
+        let functions = self.functions.iter().flatten();
+        let functions = functions.filter_map(|(id, v)| Some((id.clone(), v.missing_tile?)));
+        tables.chain(functions)
+    }
+
+    /// The `check_bounds` values of the table and function sources, by source ID
+    pub fn get_check_bounds(&self) -> impl Iterator<Item = (String, bool)> + '_ {
+        let tables = self.tables.iter().flatten();
+        let tables = tables.filter_map(|(id, v)| Some((id.clone(), v.check_bounds?)));
+        let functions = self.functions.iter().flatten();
+        let functions = functions.filter_map(|(id, v)| Some((id.clone(), v.check_bounds?)));
+        tables.chain(functions)
+    }
+
+    /// The `hidden` values of the table and function sources, by source ID
+    pub fn get_hidden(&self) -> impl Iterator<Item = (String, bool)> + '_ {
+        let tables = self.tables.iter().flatten();
+        let tables = tables.filter_map(|(id, v)| Some((id.clone(), v.hidden?)));
+        let functions = self.functions.iter().flatten();
+        let functions = functions.filter_map(|(id, v)| Some((id.clone(), v.hidden?)));
+        tables.chain(functions)
+    }
+
+    /// The concurrency limits of the table and function sources with `max_concurrent_requests` set, by source ID
+    pub fn get_concurrency_limits(&self) -> impl Iterator<Item = (String, ConcurrencyLimit)> + '_ {
+        let limit = |max_concurrent_requests: Option<usize>, max_queued_requests, queue_timeout| {
+            Some(ConcurrencyLimit {
+                max_concurrent_requests: max_concurrent_requests?,
+                max_queued_requests,
+                queue_timeout,
+            })
+        };
+        let tables = self.tables.iter().flatten();
+        let tables = tables.filter_map(move |(id, v)| {
+            let limit = limit(
+                v.max_concurrent_requests,
+                v.max_queued_requests,
+                v.queue_timeout,
+            )?;
+            Some((id.clone(), limit))
+        });
+        let functions = self.functions.iter().flatten();
+        let functions = functions.filter_map(move |(id, v)| {
+            let limit = limit(
+                v.max_concurrent_requests,
+                v.max_queued_requests,
+                v.queue_timeout,
+            )?;
+            Some((id.clone(), limit))
+        });
+        tables.chain(functions)
+    }
+
+    /// The `max_overzoom` values of the table and function sources, by source ID
+    pub fn get_max_overzoom(&self) -> impl Iterator<Item = (String, u8)> + '_ {
+        let tables = self.tables.iter().flatten();
+        let tables = tables.filter_map(|(id, v)| Some((id.clone(), v.max_overzoom?)));
+        let functions = self.functions.iter().flatten();
+        let functions = functions.filter_map(|(id, v)| Some((id.clone(), v.max_overzoom?)));
+        tables.chain(functions)
+    }
+
+    /// The `missing_tile_file` paths of the table and function sources, by source ID
+    pub fn get_missing_tile_file(&self) -> impl Iterator<Item = (String, PathBuf)> + '_ {
+        let tables = self.tables.iter().flatten();
+        let tables = tables.filter_map(|(id, v)| Some((id.clone(), v.missing_tile_file.clone()?)));
+        let functions = self.functions.iter().flatten();
+        let functions =
+            functions.filter_map(|(id, v)| Some((id.clone(), v.missing_tile_file.clone()?)));
+        tables.chain(functions)
+    }
+
+    /// The `query_timeout` values of the table and function sources, by source ID
+    pub fn get_query_timeout(&self) -> impl Iterator<Item = (String, u64)> + '_ {
+        let tables = self.tables.iter().flatten();
+        let tables = tables.filter_map(|(id, v)| Some((id.clone(), v.query_timeout?)));
+        let functions = self.functions.iter().flatten();
+        let functions = functions.filter_map(|(id, v)| Some((id.clone(), v.query_timeout?)));
+        tables.chain(functions)
+    }
+
+    /// The `headers` values of the table and function sources, by source ID
+    pub fn get_headers(&self) -> impl Iterator<Item = (String, BTreeMap<String, String>)> + '_ {
+        let tables = self.tables.iter().flatten();
+        let tables = tables.filter_map(|(id, v)| Some((id.clone(), v.headers.clone()?)));
+        let functions = self.functions.iter().flatten();
+        let functions = functions.filter_map(|(id, v)| Some((id.clone(), v.headers.clone()?)));
+        tables.chain(functions)
+    }
 }
 
 #[cfg(test)]
@@ -298,4 +564,246 @@ mod tests {
             },
         );
     }
+
+    #[test]
+    fn parse_pg_probe_functions() {
+        assert_config(
+            indoc! {"
+            postgres:
+              connection_string: 'postgresql://postgres@localhost/db'
+              probe_functions:
+                z: 2
+                x: 1
+                y: 3
+        "},
+            &Config {
+                postgres: One(PgConfig {
+                    connection_string: some("postgresql://postgres@localhost/db"),
+                    auto_publish: OptBoolObj::Bool(true),
+                    probe_functions: OptBoolObj::Object(PgCfgProbe { z: 2, x: 1, y: 3 }),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        );
+
+        let mut cfg = PgConfig {
+            connection_string: some("postgresql://postgres@localhost/db"),
+            probe_functions: OptBoolObj::Bool(true),
+            ..Default::default()
+        };
+        assert!(cfg.validate().is_ok());
+        cfg.probe_functions = OptBoolObj::Object(PgCfgProbe { z: 1, x: 0, y: 2 });
+        assert!(cfg.validate().is_err());
+        cfg.probe_functions = OptBoolObj::Object(PgCfgProbe { z: 31, x: 0, y: 0 });
+        assert!(cfg.validate().is_err());
+    }
+
+    #[test]
+    fn parse_pg_templates() {
+        let mut cfg: PgConfig = serde_yaml::from_str(indoc! {"
+            connection_string: 'postgresql://postgres@localhost/db'
+            source_defaults:
+              maxzoom: 14
+              extent: 4096
+            templates:
+              roads:
+                minzoom: 5
+                extent: 2048
+                buffer: 64
+            tables:
+              highways:
+                schema: public
+                table: highways
+                geometry_column: geom
+                template: roads
+                buffer: 32
+              points:
+                schema: public
+                table: points
+                geometry_column: geom
+            functions:
+              streets:
+                schema: public
+                function: streets
+                template: roads
+        "})
+        .unwrap();
+        assert!(cfg.finalize().unwrap().is_empty());
+
+        let tables = cfg.tables.as_ref().unwrap();
+        let highways = &tables["highways"];
+        assert_eq!(highways.minzoom, Some(5));
+        assert_eq!(highways.maxzoom, Some(14));
+        assert_eq!(highways.extent, Some(2048));
+        assert_eq!(highways.buffer, Some(32));
+        let points = &tables["points"];
+        assert_eq!(points.minzoom, None);
+        assert_eq!(points.maxzoom, Some(14));
+        assert_eq!(points.extent, Some(4096));
+        assert_eq!(points.buffer, None);
+        let streets = &cfg.functions.as_ref().unwrap()["streets"];
+        assert_eq!(streets.minzoom, Some(5));
+        assert_eq!(streets.maxzoom, Some(14));
+        assert_eq!(streets.extent, None);
+
+        let mut cfg: PgConfig = serde_yaml::from_str(indoc! {"
+            connection_string: 'postgresql://postgres@localhost/db'
+            tables:
+              highways:
+                schema: public
+                table: highways
+                geometry_column: geom
+                template: missing
+        "})
+        .unwrap();
+        assert!(matches!(
+            cfg.finalize(),
+            Err(PgError::UnknownTemplate(id, name)) if id == "highways" && name == "missing"
+        ));
+    }
+
+    #[test]
+    fn parse_pg_headers() {
+        let cfg: PgConfig = serde_yaml::from_str(indoc! {"
+            connection_string: 'postgresql://postgres@localhost/db'
+            tables:
+              roads:
+                schema: public
+                table: roads
+                geometry_column: geom
+                headers:
+                  X-Attribution: City of Example
+            functions:
+              streets:
+                schema: public
+                function: streets
+                headers:
+                  Access-Control-Expose-Headers: X-Attribution
+        "})
+        .unwrap();
+        let header =
+            |name: &str, value: &str| BTreeMap::from([(name.to_string(), value.to_string())]);
+        assert_eq!(
+            cfg.get_headers().collect::<Vec<_>>(),
+            vec![
+                (
+                    "roads".to_string(),
+                    header("X-Attribution", "City of Example")
+                ),
+                (
+                    "streets".to_string(),
+                    header("Access-Control-Expose-Headers", "X-Attribution")
+                ),
+            ]
+        );
+
+        // The setting is preserved when the config is saved
+        let saved = serde_yaml::to_string(&cfg).unwrap();
+        assert_eq!(serde_yaml::from_str::<PgConfig>(&saved).unwrap(), cfg);
+    }
+
+    #[test]
+    fn parse_pg_table_filter() {
+        let cfg: PgConfig = serde_yaml::from_str(indoc! {"
+            connection_string: 'postgresql://postgres@localhost/db'
+            tables:
+              roads:
+                schema: public
+                table: roads
+                geometry_column: geom
+              active_roads:
+                schema: public
+                table: roads
+                geometry_column: geom
+                filter: status = 'active'
+        "})
+        .unwrap();
+        let tables = cfg.tables.as_ref().unwrap();
+        assert_eq!(tables["roads"].filter, None);
+        assert_eq!(
+            tables["active_roads"].filter.as_deref(),
+            Some("status = 'active'")
+        );
+        // Both sources of the same table are kept apart
+        assert_eq!(tables["roads"].format_id(), "public.roads.geom");
+        assert_eq!(
+            tables["active_roads"].format_id(),
+            "public.roads.geom WHERE status = 'active'"
+        );
+
+        // The setting is preserved when the config is saved
+        let saved = serde_yaml::to_string(&cfg).unwrap();
+        assert_eq!(serde_yaml::from_str::<PgConfig>(&saved).unwrap(), cfg);
+    }
+
+    #[test]
+    fn parse_pg_table_simplify() {
+        let cfg: PgConfig = serde_yaml::from_str(indoc! {"
+            connection_string: 'postgresql://postgres@localhost/db'
+            tables:
+              zooms:
+                schema: public
+                table: countries
+                geometry_column: geom
+                simplify: { 0: 1000, 5: 100.5, 10: 0 }
+              auto:
+                schema: public
+                table: countries
+                geometry_column: geom
+                simplify: auto
+        "})
+        .unwrap();
+        let tables = cfg.tables.as_ref().unwrap();
+        assert_eq!(
+            tables["zooms"].simplify,
+            Some(Simplify::Zooms(BTreeMap::from([
+                (0, 1000.0),
+                (5, 100.5),
+                (10, 0.0)
+            ])))
+        );
+        assert_eq!(
+            tables["auto"].simplify,
+            Some(Simplify::Mode(SimplifyMode::Auto))
+        );
+
+        let saved = serde_yaml::to_string(&cfg).unwrap();
+        assert_eq!(serde_yaml::from_str::<PgConfig>(&saved).unwrap(), cfg);
+
+        let err = serde_yaml::from_str::<Simplify>("always").unwrap_err();
+        assert!(err.to_string().contains("untagged"), "{err}");
+    }
+
+    #[test]
+    fn parse_pg_replicas() {
+        let cfg: PgConfig = serde_yaml::from_str(indoc! {"
+            connection_string: 'postgresql://postgres@primary/db'
+            replica_connection_strings: 'postgresql://postgres@replica1/db'
+        "})
+        .unwrap();
+        assert_eq!(
+            cfg.replica_connection_strings,
+            One("postgresql://postgres@replica1/db".to_string())
+        );
+
+        let cfg: PgConfig = serde_yaml::from_str(indoc! {"
+            connection_string: 'postgresql://postgres@primary/db'
+            replica_connection_strings:
+              - 'postgresql://postgres@replica1/db'
+              - 'postgresql://postgres@replica2/db'
+        "})
+        .unwrap();
+        assert_eq!(cfg.replica_connection_strings.as_slice().len(), 2);
+        let saved = serde_yaml::to_string(&cfg).unwrap();
+        assert_eq!(serde_yaml::from_str::<PgConfig>(&saved).unwrap(), cfg);
+
+        // Without replicas, the setting is left out of the saved config
+        let cfg = PgConfig {
+            connection_string: some("postgresql://postgres@primary/db"),
+            ..Default::default()
+        };
+        let saved = serde_yaml::to_string(&cfg).unwrap();
+        assert!(!saved.contains("replica"), "{saved}");
+    }
 }
@@ -1,9 +1,16 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+
+use log::debug;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use tilejson::{Bounds, TileJSON};
 
 use crate::config::UnrecognizedValues;
-use crate::pg::config::PgInfo;
-use crate::pg::utils::{InfoMap, patch_json};
+use crate::pg::config::{PgInfo, TileCompression};
+use crate::pg::utils::{InfoMap, legend_to_string, patch_json};
+use crate::source::SourceKind;
+use crate::srv::MissingTile;
 
 pub type FuncInfoSources = InfoMap<FunctionInfo>;
 
@@ -28,6 +35,54 @@ pub struct FunctionInfo {
     /// Values may be integers or floating point numbers.
     pub bounds: Option<Bounds>,
 
+    /// Legend served at `/{source_id}/legend`, either an HTML string or any JSON value
+    pub legend: Option<serde_json::Value>,
+
+    /// `Cache-Control` header of the tile and TileJSON responses of this source
+    pub cache_control: Option<String>,
+
+    /// Response for the tiles missing from this source
+    pub missing_tile: Option<MissingTile>,
+
+    /// Image served with `200 OK` for the tiles missing from this source, e.g. a transparent PNG for raster clients
+    pub missing_tile_file: Option<PathBuf>,
+
+    /// Number of zoom levels above `maxzoom` served by clipping and rescaling the tiles at `maxzoom`
+    pub max_overzoom: Option<u8>,
+
+    /// Set to `false` to query the source for the tiles outside of its bounds too, e.g. if the bounds are only approximate
+    pub check_bounds: Option<bool>,
+
+    /// Leave this source out of the `/catalog`, while still serving it to the clients that know its ID
+    pub hidden: Option<bool>,
+
+    /// Number of tiles of this source generated at once, the other requests wait for one of them to finish
+    pub max_concurrent_requests: Option<usize>,
+
+    /// Number of requests waiting for `max_concurrent_requests`, the others fail with `503 Service Unavailable`
+    pub max_queued_requests: Option<usize>,
+
+    /// Milliseconds a request may wait for `max_concurrent_requests`, then it fails with `503 Service Unavailable`
+    pub queue_timeout: Option<u64>,
+
+    /// Milliseconds after which the tile query is cancelled, and the request fails with `504 Gateway Timeout`
+    pub query_timeout: Option<u64>,
+
+    /// Additional headers of the tile and TileJSON responses of this source, e.g. `X-Attribution`
+    pub headers: Option<BTreeMap<String, String>>,
+
+    /// Compress the tiles once before they are cached, instead of for each response
+    pub compress_tiles: Option<TileCompression>,
+
+    /// Name of the template to use for the settings not set by this source
+    pub template: Option<String>,
+
+    /// Limits for the `extent` URL query parameter passed to the function
+    pub extent: Option<QueryParamRange>,
+
+    /// Limits for the `buffer` URL query parameter passed to the function
+    pub buffer: Option<QueryParamRange>,
+
     /// TileJSON provided by the SQL function comment. Not serialized.
     #[serde(skip)]
     pub tilejson: Option<serde_json::Value>,
@@ -36,7 +91,53 @@ pub struct FunctionInfo {
     pub unrecognized: UnrecognizedValues,
 }
 
+/// Allowed range and default value of an integer URL query parameter
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub struct QueryParamRange {
+    /// Smaller values are replaced with this one
+    pub min: Option<u32>,
+    /// Larger values are replaced with this one
+    pub max: Option<u32>,
+    /// Value to use if the parameter is missing or is not a non-negative integer
+    pub default: Option<u32>,
+}
+
+impl QueryParamRange {
+    #[must_use]
+    pub fn is_valid(&self) -> bool {
+        let min = self.min.unwrap_or(u32::MIN);
+        let max = self.max.unwrap_or(u32::MAX);
+        min <= max && self.default.is_none_or(|v| min <= v && v <= max)
+    }
+
+    /// Replace `key` in the URL query with a value that is within the allowed range,
+    /// or remove it if there is no valid value and no default.
+    pub fn apply(&self, key: &str, query: &mut HashMap<String, Value>) {
+        let value = query
+            .get(key)
+            .and_then(Value::as_u64)
+            .map(|v| u32::try_from(v).unwrap_or(u32::MAX));
+        if let Some(value) = value.or(self.default) {
+            let value = self.min.map_or(value, |min| value.max(min));
+            let value = self.max.map_or(value, |max| value.min(max));
+            query.insert(key.to_string(), Value::from(value));
+        } else if let Some(v) = query.remove(key) {
+            debug!("Ignoring invalid query parameter {key}={v}");
+        }
+    }
+}
+
 impl FunctionInfo {
+    /// Get the configured limits of the URL query parameters, keyed by the parameter name
+    #[must_use]
+    pub fn query_param_ranges(&self) -> Vec<(String, QueryParamRange)> {
+        [("extent", &self.extent), ("buffer", &self.buffer)]
+            .into_iter()
+            .filter_map(|(k, v)| v.clone().map(|v| (k.to_string(), v)))
+            .collect()
+    }
+
     #[must_use]
     pub fn new(schema: String, function: String, tilejson: Option<serde_json::Value>) -> Self {
         Self {
@@ -71,6 +172,18 @@ impl PgInfo for FunctionInfo {
         format!("{}.{}", self.schema, self.function)
     }
 
+    fn kind(&self) -> SourceKind {
+        SourceKind::Function
+    }
+
+    fn query_timeout(&self) -> Option<u64> {
+        self.query_timeout
+    }
+
+    fn compress_tiles(&self) -> TileCompression {
+        self.compress_tiles.unwrap_or_default()
+    }
+
     fn to_tilejson(&self, source_id: String) -> TileJSON {
         let mut tilejson = tilejson::tilejson! {
             tiles: vec![],  // tile source is required, but not yet known
@@ -80,6 +193,7 @@ impl PgInfo for FunctionInfo {
         tilejson.minzoom = self.minzoom;
         tilejson.maxzoom = self.maxzoom;
         tilejson.bounds = self.bounds;
+        tilejson.legend = self.legend.as_ref().map(legend_to_string);
         patch_json(tilejson, self.tilejson.as_ref())
     }
 }
@@ -95,3 +209,60 @@ impl FunctionInfo {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn query_param_range() {
+        let range = QueryParamRange {
+            min: Some(256),
+            max: Some(8192),
+            default: Some(4096),
+        };
+        assert!(range.is_valid());
+
+        let apply = |value: Option<Value>| {
+            let mut query = HashMap::new();
+            if let Some(value) = value {
+                query.insert("extent".to_string(), value);
+            }
+            range.apply("extent", &mut query);
+            query.remove("extent")
+        };
+        assert_eq!(apply(None), Some(json!(4096)));
+        assert_eq!(apply(Some(json!(512))), Some(json!(512)));
+        assert_eq!(apply(Some(json!(16))), Some(json!(256)));
+        assert_eq!(apply(Some(json!(100_000))), Some(json!(8192)));
+        assert_eq!(apply(Some(json!(-1))), Some(json!(4096)));
+        assert_eq!(apply(Some(json!("abc"))), Some(json!(4096)));
+
+        let range = QueryParamRange {
+            max: Some(64),
+            ..Default::default()
+        };
+        let mut query = HashMap::from([("buffer".to_string(), json!("abc"))]);
+        range.apply("buffer", &mut query);
+        assert!(query.is_empty());
+
+        assert!(
+            !QueryParamRange {
+                min: Some(10),
+                max: Some(5),
+                default: None,
+            }
+            .is_valid()
+        );
+        assert!(
+            !QueryParamRange {
+                min: Some(10),
+                max: None,
+                default: Some(5),
+            }
+            .is_valid()
+        );
+    }
+}
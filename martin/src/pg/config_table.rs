@@ -1,12 +1,15 @@
 use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
 
 use log::{info, warn};
 use serde::{Deserialize, Serialize};
 use tilejson::{Bounds, TileJSON, VectorLayer};
 
 use crate::config::UnrecognizedValues;
-use crate::pg::config::PgInfo;
-use crate::pg::utils::{InfoMap, normalize_key, patch_json};
+use crate::pg::config::{PgInfo, Simplify, TileCompression};
+use crate::pg::utils::{InfoMap, legend_to_string, normalize_key, patch_json};
+use crate::source::SourceKind;
+use crate::srv::MissingTile;
 
 pub type TableInfoSources = InfoMap<TableInfo>;
 
@@ -36,6 +39,18 @@ pub struct TableInfo {
     #[serde(skip)]
     pub is_view: Option<bool>,
 
+    /// Flag indicating if table is actually a materialized view (PostgreSQL relkind = 'm')
+    #[serde(skip)]
+    pub is_materialized_view: Option<bool>,
+
+    /// Flag indicating if the geometry column is a `geography` column
+    #[serde(skip)]
+    pub is_geography: Option<bool>,
+
+    /// Single column primary key of the table, used as the `id_column` of the auto-published tables
+    #[serde(skip)]
+    pub primary_key: Option<String>,
+
     /// Feature id column name
     pub id_column: Option<String>,
 
@@ -66,6 +81,55 @@ pub struct TableInfo {
     /// List of columns, that should be encoded as tile properties
     pub properties: Option<BTreeMap<String, String>>,
 
+    /// SQL condition the rows must match to be included in the tiles, e.g. `status = 'active'`
+    pub filter: Option<String>,
+
+    /// Simplify the geometries of the low zooms with `ST_SimplifyPreserveTopology` before encoding them,
+    /// either `auto` or a tolerance in web-mercator units for each zoom threshold
+    pub simplify: Option<Simplify>,
+
+    /// Legend served at `/{source_id}/legend`, either an HTML string or any JSON value
+    pub legend: Option<serde_json::Value>,
+
+    /// `Cache-Control` header of the tile and TileJSON responses of this source
+    pub cache_control: Option<String>,
+
+    /// Response for the tiles missing from this source
+    pub missing_tile: Option<MissingTile>,
+
+    /// Image served with `200 OK` for the tiles missing from this source, e.g. a transparent PNG for raster clients
+    pub missing_tile_file: Option<PathBuf>,
+
+    /// Number of zoom levels above `maxzoom` served by clipping and rescaling the tiles at `maxzoom`
+    pub max_overzoom: Option<u8>,
+
+    /// Set to `false` to query the source for the tiles outside of its bounds too, e.g. if the bounds are only approximate
+    pub check_bounds: Option<bool>,
+
+    /// Leave this source out of the `/catalog`, while still serving it to the clients that know its ID
+    pub hidden: Option<bool>,
+
+    /// Number of tiles of this source generated at once, the other requests wait for one of them to finish
+    pub max_concurrent_requests: Option<usize>,
+
+    /// Number of requests waiting for `max_concurrent_requests`, the others fail with `503 Service Unavailable`
+    pub max_queued_requests: Option<usize>,
+
+    /// Milliseconds a request may wait for `max_concurrent_requests`, then it fails with `503 Service Unavailable`
+    pub queue_timeout: Option<u64>,
+
+    /// Milliseconds after which the tile query is cancelled, and the request fails with `504 Gateway Timeout`
+    pub query_timeout: Option<u64>,
+
+    /// Additional headers of the tile and TileJSON responses of this source, e.g. `X-Attribution`
+    pub headers: Option<BTreeMap<String, String>>,
+
+    /// Compress the tiles once before they are cached, instead of for each response
+    pub compress_tiles: Option<TileCompression>,
+
+    /// Name of the template to use for the settings not set by this source
+    pub template: Option<String>,
+
     /// Mapping of properties to the actual table columns
     #[serde(skip)]
     pub prop_mapping: HashMap<String, String>,
@@ -80,7 +144,24 @@ pub struct TableInfo {
 
 impl PgInfo for TableInfo {
     fn format_id(&self) -> String {
-        format!("{}.{}.{}", self.schema, self.table, self.geometry_column)
+        let id = format!("{}.{}.{}", self.schema, self.table, self.geometry_column);
+        // Sources of the same table with different filters are different sources
+        match &self.filter {
+            Some(filter) => format!("{id} WHERE {filter}"),
+            None => id,
+        }
+    }
+
+    fn kind(&self) -> SourceKind {
+        SourceKind::Table
+    }
+
+    fn query_timeout(&self) -> Option<u64> {
+        self.query_timeout
+    }
+
+    fn compress_tiles(&self) -> TileCompression {
+        self.compress_tiles.unwrap_or_default()
     }
 
     /// Result `TileJson` will be patched by the `TileJson` from SQL comment if provided.
@@ -94,6 +175,7 @@ impl PgInfo for TableInfo {
         tilejson.minzoom = self.minzoom;
         tilejson.maxzoom = self.maxzoom;
         tilejson.bounds = self.bounds;
+        tilejson.legend = self.legend.as_ref().map(legend_to_string);
 
         let id = if let Some(id) = &self.layer_id {
             id.clone()
@@ -132,6 +214,9 @@ impl TableInfo {
             // These values are not serialized, so copy auto-detected values from the database
             geometry_index: self.geometry_index,
             is_view: self.is_view,
+            is_materialized_view: self.is_materialized_view,
+            is_geography: self.is_geography,
+            primary_key: self.primary_key.clone(),
             tilejson: self.tilejson.clone(),
             // Srid requires some logic
             srid: self.calc_srid(new_id, cfg_inf.srid, default_srid)?,
@@ -154,7 +239,16 @@ impl TableInfo {
 
         if let Some(id_column) = &cfg_inf.id_column {
             let prop = normalize_key(props, id_column.as_str(), "id_column", new_id)?;
-            inf.prop_mapping.insert(id_column.clone(), prop);
+            let typ = &props[&prop];
+            if is_id_column_type(typ) {
+                inf.prop_mapping.insert(id_column.clone(), prop);
+            } else {
+                warn!(
+                    "Source {new_id} cannot use the column {prop} of table {} as its id_column because it has the non-integer type {typ}, its tiles will have no feature IDs",
+                    self.format_id()
+                );
+                inf.id_column = None;
+            }
         }
 
         if let Some(p) = &cfg_inf.properties {
@@ -196,3 +290,10 @@ impl TableInfo {
         }
     }
 }
+
+/// Check if a column type can be used as the feature ID, which `ST_AsMVT` requires to be an integer, see
+/// https://github.com/postgis/postgis/blob/559c95d85564fb74fa9e3b7eafb74851810610da/postgis/mvt.c#L387C4-L387C66
+#[must_use]
+pub fn is_id_column_type(typ: &str) -> bool {
+    matches!(typ, "int2" | "int4" | "int8")
+}
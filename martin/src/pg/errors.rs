@@ -2,6 +2,7 @@ use std::io;
 use std::path::PathBuf;
 
 use deadpool_postgres::tokio_postgres::Error as TokioPgError;
+use deadpool_postgres::tokio_postgres::error::SqlState;
 use deadpool_postgres::{BuildError, PoolError};
 use martin_tile_utils::TileCoord;
 use semver::Version;
@@ -61,6 +62,9 @@ pub enum PgError {
     #[error("Invalid extent setting in source {0} for table {1}: extent=0")]
     InvalidTableExtent(String, String),
 
+    #[error("Invalid filter of source {1}: {2}: {0}")]
+    InvalidTableFilter(#[source] TokioPgError, String, String),
+
     #[error("Error preparing a query for the tile '{1}' ({2}): {3} {0}")]
     PrepareQueryError(#[source] TokioPgError, String, String, String),
 
@@ -70,6 +74,25 @@ pub enum PgError {
     #[error(r"Unable to get tile {2:#} with {json_query:?} params from {1}: {0}", json_query=query_to_json(.3.as_ref()))]
     GetTileWithQueryError(#[source] TokioPgError, String, TileCoord, Option<UrlQuery>),
 
+    #[error(r"Unable to get tiles at zoom {2} from {1}: {0}")]
+    GetTilesBulkError(#[source] TokioPgError, String, u8),
+
     #[error("Configuration error: {0}")]
     ConfigError(&'static str),
+
+    #[error("Source {0} uses template {1}, which is not defined in the templates")]
+    UnknownTemplate(String, String),
+}
+
+impl PgError {
+    /// Check if the tile query was cancelled by `PostgreSQL` once the `statement_timeout` has passed
+    #[must_use]
+    pub fn is_query_canceled(&self) -> bool {
+        match self {
+            Self::GetTileError(e, ..) | Self::GetTileWithQueryError(e, ..) => {
+                e.code() == Some(&SqlState::QUERY_CANCELED)
+            }
+            _ => false,
+        }
+    }
 }
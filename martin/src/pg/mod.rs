@@ -10,9 +10,13 @@ mod query_tables;
 mod tls;
 mod utils;
 
-pub use config::{PgCfgPublish, PgCfgPublishFuncs, PgCfgPublishTables, PgConfig, PgSslCerts};
+pub use config::{
+    PgCfgProbe, PgCfgPublish, PgCfgPublishFuncs, PgCfgPublishTables, PgCfgTemplate, PgConfig,
+    PgSslCerts, Simplify, SimplifyMode, TileCompression,
+};
 pub use config_function::FunctionInfo;
 pub use config_table::TableInfo;
 pub use errors::{PgError, PgResult};
+pub use pg_source::COMPRESS_MIN_SIZE_DEFAULT;
 pub use pool::{POOL_SIZE_DEFAULT, PgPool};
 pub use query_functions::query_available_function;
@@ -1,65 +1,104 @@
 use async_trait::async_trait;
+use deadpool_postgres::GenericClient;
 use deadpool_postgres::tokio_postgres::types::{ToSql, Type};
-use log::debug;
-use martin_tile_utils::Encoding::Uncompressed;
+use futures::{StreamExt as _, TryStreamExt as _};
+use log::{debug, warn};
+use martin_tile_utils::Encoding::{Gzip, Uncompressed};
 use martin_tile_utils::Format::Mvt;
-use martin_tile_utils::{TileCoord, TileInfo};
+use martin_tile_utils::{TileCoord, TileInfo, encode_gzip};
 use tilejson::TileJSON;
 
-use crate::MartinResult;
-use crate::pg::PgError::{GetTileError, GetTileWithQueryError, PrepareQueryError};
+use crate::pg::PgError::{
+    GetTileError, GetTileWithQueryError, GetTilesBulkError, PostgresError, PrepareQueryError,
+};
+use crate::pg::config::TileCompression;
+use crate::pg::config_function::QueryParamRange;
 use crate::pg::pool::PgPool;
 use crate::pg::utils::query_to_json;
-use crate::source::{Source, TileData, TileInfoSource, UrlQuery};
+use crate::source::{
+    PoolStatus, Source, SourceKind, TileData, TileInfoSource, TileStream, UrlQuery,
+};
+use crate::{MartinError, MartinResult, TileRect};
+
+pub const COMPRESS_MIN_SIZE_DEFAULT: usize = 512;
 
 #[derive(Clone, Debug)]
 pub struct PgSource {
     id: String,
+    kind: SourceKind,
     info: PgSqlInfo,
     pool: PgPool,
     tilejson: TileJSON,
+    /// Milliseconds after which `PostgreSQL` cancels the tile query
+    query_timeout: Option<u64>,
+    /// Gzip the tiles of at least this many bytes, smaller tiles are returned uncompressed
+    gzip_min_size: Option<usize>,
+    /// The error returned by the startup probe, if any
+    error: Option<String>,
 }
 
 impl PgSource {
     #[must_use]
-    pub fn new(id: String, info: PgSqlInfo, tilejson: TileJSON, pool: PgPool) -> Self {
+    pub fn new(
+        id: String,
+        kind: SourceKind,
+        info: PgSqlInfo,
+        tilejson: TileJSON,
+        pool: PgPool,
+    ) -> Self {
         Self {
             id,
+            kind,
             info,
             pool,
             tilejson,
+            query_timeout: None,
+            gzip_min_size: None,
+            error: None,
         }
     }
-}
 
-#[async_trait]
-impl Source for PgSource {
-    fn get_id(&self) -> &str {
-        &self.id
-    }
-
-    fn get_tilejson(&self) -> &TileJSON {
-        &self.tilejson
+    #[must_use]
+    pub fn with_query_timeout(mut self, query_timeout: Option<u64>) -> Self {
+        self.query_timeout = query_timeout;
+        self
     }
 
-    fn get_tile_info(&self) -> TileInfo {
-        TileInfo::new(Mvt, Uncompressed)
+    #[must_use]
+    pub fn with_compression(mut self, compression: TileCompression, min_size: usize) -> Self {
+        self.gzip_min_size = match compression {
+            TileCompression::None => None,
+            TileCompression::Gzip => Some(min_size),
+        };
+        self
     }
 
-    fn clone_source(&self) -> TileInfoSource {
-        Box::new(self.clone())
+    /// Request a single tile, and mark the source as degraded if the query fails.
+    pub async fn probe(&mut self, xyz: TileCoord) {
+        if let Err(e) = self.get_tile(xyz, None).await {
+            warn!(
+                "Source {} is degraded, failed to get probe tile {xyz:#}: {e}",
+                self.id
+            );
+            self.error = Some(e.to_string());
+        }
     }
 
-    fn support_url_query(&self) -> bool {
-        self.info.use_url_query
+    /// Gzip the tile if compression is enabled and the tile is not too small to benefit from it
+    fn compress(&self, tile: TileData) -> MartinResult<TileData> {
+        match self.gzip_min_size {
+            Some(min_size) if !tile.is_empty() && tile.len() >= min_size => Ok(encode_gzip(&tile)?),
+            _ => Ok(tile),
+        }
     }
 
-    async fn get_tile(
+    /// Run the tile query on a pooled connection, or on a transaction with a `statement_timeout`
+    async fn query_tile(
         &self,
+        conn: &impl GenericClient,
         xyz: TileCoord,
         url_query: Option<&UrlQuery>,
     ) -> MartinResult<TileData> {
-        let conn = self.pool.get().await?;
         let param_types: &[Type] = if self.support_url_query() {
             &[Type::INT2, Type::INT8, Type::INT8, Type::JSON]
         } else {
@@ -80,7 +119,10 @@ impl Source for PgSource {
             })?;
 
         let tile = if self.support_url_query() {
-            let json = query_to_json(url_query);
+            let mut json = query_to_json(url_query);
+            for (key, range) in &self.info.query_param_ranges {
+                range.apply(key, &mut json.0);
+            }
             debug!("SQL: {sql} [{xyz}, {json:?}]");
             let params: &[&(dyn ToSql + Sync)] = &[
                 &i16::from(xyz.z),
@@ -113,11 +155,140 @@ impl Source for PgSource {
     }
 }
 
+#[async_trait]
+impl Source for PgSource {
+    fn get_id(&self) -> &str {
+        &self.id
+    }
+
+    fn get_tilejson(&self) -> &TileJSON {
+        &self.tilejson
+    }
+
+    fn get_tile_info(&self) -> TileInfo {
+        // Tiles under the size threshold are still uncompressed, which the tile handler detects
+        if self.gzip_min_size.is_some() {
+            TileInfo::new(Mvt, Gzip)
+        } else {
+            TileInfo::new(Mvt, Uncompressed)
+        }
+    }
+
+    fn get_kind(&self) -> SourceKind {
+        self.kind
+    }
+
+    fn clone_source(&self) -> TileInfoSource {
+        Box::new(self.clone())
+    }
+
+    fn support_url_query(&self) -> bool {
+        self.info.use_url_query
+    }
+
+    fn get_error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    fn get_pool_status(&self) -> Option<(String, PoolStatus)> {
+        Some((self.pool.get_id().to_string(), self.pool.status()))
+    }
+
+    async fn check_health(&self) -> MartinResult<()> {
+        let conn = self.pool.get().await?;
+        conn.simple_query("SELECT 1")
+            .await
+            .map_err(|e| PostgresError(e, "checking the connection health"))?;
+        Ok(())
+    }
+
+    async fn get_tile(
+        &self,
+        xyz: TileCoord,
+        url_query: Option<&UrlQuery>,
+    ) -> MartinResult<TileData> {
+        let mut conn = self.pool.get_reader().await?;
+        let Some(timeout) = self.query_timeout else {
+            let tile = self.query_tile(&*conn, xyz, url_query).await?;
+            return self.compress(tile);
+        };
+        // `SET LOCAL` only lasts until the end of the transaction, so the connection goes back
+        // to the pool without the timeout. If the request is dropped, the transaction is rolled back.
+        let tx = conn
+            .transaction()
+            .await
+            .map_err(|e| PostgresError(e, "starting a tile query transaction"))?;
+        tx.batch_execute(&format!("SET LOCAL statement_timeout = {timeout}"))
+            .await
+            .map_err(|e| PostgresError(e, "setting the statement timeout"))?;
+        let tile = self.query_tile(&tx, xyz, url_query).await?;
+        tx.commit()
+            .await
+            .map_err(|e| PostgresError(e, "committing a tile query transaction"))?;
+        self.compress(tile)
+    }
+
+    #[allow(clippy::cast_sign_loss)]
+    async fn get_tiles_bulk(&self, rect: TileRect) -> MartinResult<Option<TileStream>> {
+        let Some(sql) = &self.info.bulk_query else {
+            return Ok(None);
+        };
+        let conn = self.pool.get_reader().await?;
+        let param_types = &[Type::INT2, Type::INT8, Type::INT8, Type::INT8, Type::INT8];
+        let prep_query = conn
+            .prepare_typed_cached(sql, param_types)
+            .await
+            .map_err(|e| {
+                PrepareQueryError(
+                    e,
+                    self.id.to_string(),
+                    self.info.signature.to_string(),
+                    sql.to_string(),
+                )
+            })?;
+
+        debug!("SQL: {sql} [{rect:?}]");
+        let params: [&(dyn ToSql + Sync); 5] = [
+            &i16::from(rect.zoom),
+            &i64::from(rect.min_x),
+            &i64::from(rect.min_y),
+            &i64::from(rect.max_x),
+            &i64::from(rect.max_y),
+        ];
+        let id = self.id.clone();
+        let z = rect.zoom;
+        let rows = conn
+            .query_raw(&prep_query, params)
+            .await
+            .map_err(|e| GetTilesBulkError(e, id.clone(), z))?;
+
+        let tiles = rows
+            .map_err(move |e| MartinError::from(GetTilesBulkError(e, id.clone(), z)))
+            .map_ok(move |row| {
+                // keep the connection checked out of the pool until the stream is dropped
+                let _conn = &conn;
+                let x: i32 = row.get(0);
+                let y: i32 = row.get(1);
+                let xyz = TileCoord {
+                    z,
+                    x: x as u32,
+                    y: y as u32,
+                };
+                (xyz, row.get::<_, Option<TileData>>(2).unwrap_or_default())
+            });
+        Ok(Some(tiles.boxed()))
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct PgSqlInfo {
     pub sql_query: String,
     pub use_url_query: bool,
     pub signature: String,
+    /// Limits applied to the URL query parameters before they are passed to a function
+    pub query_param_ranges: Vec<(String, QueryParamRange)>,
+    /// A query generating all tiles of a zoom level rectangle `($1, $2, $3) - ($1, $4, $5)` at once
+    pub bulk_query: Option<String>,
 }
 
 impl PgSqlInfo {
@@ -127,6 +298,8 @@ impl PgSqlInfo {
             sql_query: query,
             use_url_query: has_query_params,
             signature,
+            query_param_ranges: Vec::new(),
+            bulk_query: None,
         }
     }
 }
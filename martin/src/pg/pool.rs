@@ -1,4 +1,15 @@
-use deadpool_postgres::{Manager, ManagerConfig, Object, Pool, RecyclingMethod};
+use std::collections::hash_map::RandomState;
+use std::error::Error as _;
+use std::fmt::Display;
+use std::hash::BuildHasher as _;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use deadpool_postgres::tokio_postgres::error::SqlState;
+use deadpool_postgres::{
+    Manager, ManagerConfig, Object, Pool, PoolError, RecyclingMethod, Runtime,
+};
 use log::{info, warn};
 use postgres::config::SslMode;
 use semver::Version;
@@ -10,8 +21,18 @@ use crate::pg::PgError::{
 use crate::pg::PgResult;
 use crate::pg::config::PgConfig;
 use crate::pg::tls::{SslModeOverride, make_connector, parse_conn_str};
+use crate::source::PoolStatus;
 
 pub const POOL_SIZE_DEFAULT: usize = 20;
+pub const CONNECTION_TIMEOUT_DEFAULT: u64 = 60;
+pub const RETRY_INTERVAL_DEFAULT: u64 = 500;
+/// The interval between the connection attempts doubles after each attempt, up to this value
+const RETRY_INTERVAL_MAX: Duration = Duration::from_secs(10);
+/// A replica failing to connect is left out of the rotation for this long, then tried again
+const REPLICA_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+/// Time a tile query waits for a replica connection, so that an unreachable replica fails fast
+/// and is left out of the rotation instead of delaying the tile
+const REPLICA_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
 
 /// We require `ST_TileEnvelope` that was added in [`PostGIS 3.0.0`](https://postgis.net/2019/10/PostGIS-3.0.0/)
 /// See <https://postgis.net/docs/ST_TileEnvelope.html>
@@ -35,18 +56,86 @@ pub struct PgPool {
     /// `true` if running postgis >= 3.1
     /// This being `false` indicates that tiles may be cut off at the edges.
     supports_tile_margin: bool,
+    /// Read replicas sharing the tile queries with the primary database
+    replicas: Arc<Replicas>,
+}
+
+#[derive(Debug, Default)]
+struct Replicas {
+    pools: Vec<Replica>,
+    /// Round-robin counter of the tile queries, `0` being the primary database
+    next: AtomicUsize,
+}
+
+#[derive(Debug)]
+struct Replica {
+    id: String,
+    pool: Pool,
+    /// Set while the replica is left out of the rotation, until the next time it is tried
+    down_until: Mutex<Option<Instant>>,
+}
+
+impl Replica {
+    /// Get a connection, or `None` if the replica is out of the rotation or fails to connect
+    async fn get(&self) -> Option<Object> {
+        let was_down = {
+            let down_until = self.down_until.lock().expect("replica lock poisoned");
+            match *down_until {
+                Some(until) if Instant::now() < until => return None,
+                v => v.is_some(),
+            }
+        };
+        let result = self.pool.get().await;
+        let mut down_until = self.down_until.lock().expect("replica lock poisoned");
+        match result {
+            Ok(conn) => {
+                if was_down {
+                    info!(
+                        "PostgreSQL {} recovered, adding it back to the rotation",
+                        self.id
+                    );
+                    *down_until = None;
+                }
+                Some(conn)
+            }
+            Err(e) => {
+                if !was_down {
+                    warn!(
+                        "PostgreSQL {} failed to connect, removing it from the rotation for {}s: {e}",
+                        self.id,
+                        REPLICA_RETRY_INTERVAL.as_secs()
+                    );
+                }
+                *down_until = Some(Instant::now() + REPLICA_RETRY_INTERVAL);
+                None
+            }
+        }
+    }
 }
 
 impl PgPool {
     pub async fn new(config: &PgConfig) -> PgResult<Self> {
-        let (id, mgr) = Self::parse_config(config)?;
+        let conn_str = config.connection_string.as_ref().unwrap().as_str();
+        let (id, mgr) = Self::parse_config(config, conn_str)?;
 
         let pool = Pool::builder(mgr)
             .max_size(config.pool_size.unwrap_or(POOL_SIZE_DEFAULT))
             .build()
             .map_err(|e| PostgresPoolBuildError(e, id.clone()))?;
 
-        let conn = get_conn(&pool, &id).await?;
+        let timeout = config
+            .connection_timeout
+            .unwrap_or(CONNECTION_TIMEOUT_DEFAULT);
+        let interval = config.retry_interval.unwrap_or(RETRY_INTERVAL_DEFAULT);
+        let conn = retry_connect(
+            &id,
+            Duration::from_secs(timeout),
+            Duration::from_millis(interval),
+            is_unreachable,
+            || pool.get(),
+        )
+        .await
+        .map_err(|e| PostgresPoolConnError(e, id.clone()))?;
         let pg_ver = get_postgres_version(&conn).await?;
         if pg_ver < MINIMUM_POSTGRES_VERSION {
             return Err(PostgresqlTooOld(pg_ver, MINIMUM_POSTGRES_VERSION));
@@ -79,15 +168,35 @@ impl PgPool {
 
         info!("Connected to PostgreSQL {pg_ver} / PostGIS {postgis_ver} for source {id}");
 
+        let mut replicas = Replicas::default();
+        for (idx, conn_str) in config.replica_connection_strings.iter().enumerate() {
+            let (_, mgr) = Self::parse_config(config, conn_str)?;
+            let replica_id = format!("replica #{} of {id}", idx + 1);
+            // The timeouts are only applied with a runtime
+            let pool = Pool::builder(mgr)
+                .max_size(config.pool_size.unwrap_or(POOL_SIZE_DEFAULT))
+                .runtime(Runtime::Tokio1)
+                .wait_timeout(Some(REPLICA_CONNECT_TIMEOUT))
+                .create_timeout(Some(REPLICA_CONNECT_TIMEOUT))
+                .build()
+                .map_err(|e| PostgresPoolBuildError(e, replica_id.clone()))?;
+            info!("Using PostgreSQL {replica_id} for the tile queries");
+            replicas.pools.push(Replica {
+                id: replica_id,
+                pool,
+                down_until: Mutex::new(None),
+            });
+        }
+
         Ok(Self {
             id,
             pool,
             supports_tile_margin,
+            replicas: Arc::new(replicas),
         })
     }
 
-    fn parse_config(config: &PgConfig) -> PgResult<(String, Manager)> {
-        let conn_str = config.connection_string.as_ref().unwrap().as_str();
+    fn parse_config(config: &PgConfig, conn_str: &str) -> PgResult<(String, Manager)> {
         let (pg_cfg, ssl_mode) = parse_conn_str(conn_str)?;
 
         let id = pg_cfg.get_dbname().map_or_else(
@@ -122,15 +231,46 @@ impl PgPool {
         Ok((id, mgr))
     }
 
+    /// Get a connection to the primary database, e.g. to discover the tables and functions
     pub async fn get(&self) -> PgResult<Object> {
         get_conn(&self.pool, self.id.as_str()).await
     }
 
+    /// Get a connection for a tile query, taking turns between the primary database and the replicas.
+    /// The replicas failing to connect are skipped until they recover.
+    pub async fn get_reader(&self) -> PgResult<Object> {
+        let count = self.replicas.pools.len() + 1;
+        let start = self.replicas.next.fetch_add(1, Ordering::Relaxed);
+        for offset in 0..count {
+            let Some(replica) = ((start + offset) % count)
+                .checked_sub(1)
+                .and_then(|idx| self.replicas.pools.get(idx))
+            else {
+                break;
+            };
+            if let Some(conn) = replica.get().await {
+                return Ok(conn);
+            }
+        }
+        self.get().await
+    }
+
     #[must_use]
     pub fn get_id(&self) -> &str {
         self.id.as_str()
     }
 
+    /// Current usage of the pool, without waiting for a connection
+    #[must_use]
+    pub fn status(&self) -> PoolStatus {
+        let status = self.pool.status();
+        PoolStatus {
+            max_size: status.max_size,
+            size: status.size,
+            in_use: status.size.saturating_sub(status.available),
+        }
+    }
+
     /// Indicates if `ST_TileEnvelope` supports the margin parameter.
     ///
     /// `true` if running postgis >= `3.1`
@@ -147,6 +287,59 @@ async fn get_conn(pool: &Pool, id: &str) -> PgResult<Object> {
         .map_err(|e| PostgresPoolConnError(e, id.to_string()))
 }
 
+/// Call `connect` until it succeeds, retrying with an exponential backoff for `timeout` while `is_retryable`
+/// accepts its errors, e.g. while the database is still starting next to Martin in docker-compose or Kubernetes
+async fn retry_connect<T, E: Display, F: Future<Output = Result<T, E>>>(
+    id: &str,
+    timeout: Duration,
+    interval: Duration,
+    is_retryable: impl Fn(&E) -> bool,
+    mut connect: impl FnMut() -> F,
+) -> Result<T, E> {
+    let deadline = tokio::time::Instant::now() + timeout;
+    let mut interval = interval;
+    let mut attempt = 1;
+    loop {
+        let err = match connect().await {
+            Ok(v) => return Ok(v),
+            Err(e) => e,
+        };
+        let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
+        if remaining.is_zero() || !is_retryable(&err) {
+            return Err(err);
+        }
+        let delay = with_jitter(interval).min(remaining);
+        warn!(
+            "Unable to connect to PostgreSQL {id} (attempt {attempt}), retrying in {:.1}s: {err}",
+            delay.as_secs_f64()
+        );
+        tokio::time::sleep(delay).await;
+        interval = (interval * 2).min(RETRY_INTERVAL_MAX);
+        attempt += 1;
+    }
+}
+
+/// Randomize the delay between 50% and 100% of `interval`, so that several instances starting together
+/// do not all retry at the same time
+fn with_jitter(interval: Duration) -> Duration {
+    #[allow(clippy::cast_precision_loss)]
+    let random = RandomState::new().hash_one(0) as f64 / u64::MAX as f64;
+    interval.mul_f64(0.5 + random / 2.0)
+}
+
+/// Whether the database could not be reached or is still starting up, as opposed to rejecting the connection,
+/// e.g. because the authentication failed
+fn is_unreachable(err: &PoolError) -> bool {
+    match err {
+        PoolError::Timeout(_) => true,
+        PoolError::Backend(e) => match e.as_db_error() {
+            Some(db_err) => *db_err.code() == SqlState::CANNOT_CONNECT_NOW,
+            None => e.is_closed() || e.source().is_some_and(|v| v.is::<std::io::Error>()),
+        },
+        _ => false,
+    }
+}
+
 /// Get [PostgreSQL version](https://www.postgresql.org/support/versioning/).
 /// `PostgreSQL` only has a Major.Minor versioning, so we use 0 the patch version
 async fn get_postgres_version(conn: &Object) -> PgResult<Version> {
@@ -201,6 +394,7 @@ mod tests {
     use testcontainers_modules::testcontainers::runners::AsyncRunner as _;
 
     use super::*;
+    use crate::utils::OptOneMany::Many;
 
     #[tokio::test]
     async fn parse_version() -> anyhow::Result<()> {
@@ -238,4 +432,126 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn retry_closed_port() {
+        // Nothing listens on port 1, so each attempt fails at once with a connection refused error
+        let conn_str = "postgresql://postgres@127.0.0.1:1/db";
+        let (id, mgr) = PgPool::parse_config(&PgConfig::default(), conn_str).unwrap();
+        let pool = Pool::builder(mgr).max_size(1).build().unwrap();
+        let attempts = AtomicUsize::new(0);
+        let connect = || {
+            attempts.fetch_add(1, Ordering::Relaxed);
+            pool.get()
+        };
+        let count = || attempts.swap(0, Ordering::Relaxed);
+
+        // No retry with --fail-fast
+        let err = retry_connect(
+            &id,
+            Duration::ZERO,
+            Duration::from_millis(100),
+            is_unreachable,
+            connect,
+        )
+        .await;
+        assert!(err.is_err_and(|e| is_unreachable(&e)));
+        assert_eq!(count(), 1);
+
+        // The attempts are 200 to 400 ms apart, then 400 to 800 ms, until the last attempt after 1s
+        let start = std::time::Instant::now();
+        let err = retry_connect(
+            &id,
+            Duration::from_secs(1),
+            Duration::from_millis(400),
+            is_unreachable,
+            connect,
+        )
+        .await;
+        assert!(err.is_err());
+        assert!(start.elapsed() >= Duration::from_secs(1));
+        assert!((3..=4).contains(&count()));
+
+        // Errors other than an unreachable server are not retried
+        let err = retry_connect(
+            &id,
+            Duration::from_secs(1),
+            Duration::from_millis(100),
+            |_| false,
+            connect,
+        )
+        .await;
+        assert!(err.is_err());
+        assert_eq!(count(), 1);
+    }
+
+    #[tokio::test]
+    async fn retry_until_connected() {
+        let mut attempts = 0;
+        let result = retry_connect(
+            "db",
+            Duration::from_secs(10),
+            Duration::from_millis(1),
+            |e: &&str| *e == "starting",
+            || {
+                attempts += 1;
+                let result = if attempts < 3 {
+                    Err("starting")
+                } else {
+                    Ok(attempts)
+                };
+                async move { result }
+            },
+        )
+        .await;
+        assert_eq!(result, Ok(3));
+    }
+
+    #[tokio::test]
+    async fn replicas_rotation() -> anyhow::Result<()> {
+        let node = Postgres::default()
+            .with_name("postgis/postgis")
+            .with_tag("11-3.0")
+            .start()
+            .await?;
+        let host = node.get_host().await?;
+        let port = node.get_host_port_ipv4(5432).await?;
+        let conn_str = |name: &str| {
+            format!(
+                "postgresql://postgres:postgres@{host}:{port}/postgres?sslmode=disable&application_name={name}"
+            )
+        };
+        let config = PgConfig {
+            connection_string: Some(conn_str("primary")),
+            // Nothing listens on port 1, so the second replica is removed from the rotation
+            replica_connection_strings: Many(vec![
+                conn_str("replica"),
+                "postgresql://postgres@127.0.0.1:1/postgres?sslmode=disable".to_string(),
+            ]),
+            ..Default::default()
+        };
+        let pool = PgPool::new(&config).await?;
+        let mut names = Vec::new();
+        for _ in 0..6 {
+            let conn = pool.get_reader().await?;
+            let row = conn
+                .query_one("SELECT current_setting('application_name') AS name", &[])
+                .await?;
+            names.push(row.get::<_, String>("name"));
+        }
+        assert_eq!(
+            names,
+            [
+                "primary", "replica", "primary", "primary", "replica", "primary"
+            ]
+        );
+
+        // The discovery always uses the primary database
+        let conn = pool.get().await?;
+        let row = conn
+            .query_one("SELECT current_setting('application_name') AS name", &[])
+            .await?;
+        assert_eq!(row.get::<_, String>("name"), "primary");
+        Ok(())
+    }
 }
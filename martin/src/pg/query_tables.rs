@@ -2,6 +2,7 @@ use std::collections::HashMap;
 
 use futures::pin_mut;
 use log::{debug, warn};
+use martin_tile_utils::EARTH_CIRCUMFERENCE;
 use postgis::ewkb;
 use postgres_protocol::escape::{escape_identifier, escape_literal};
 use serde_json::Value;
@@ -9,10 +10,10 @@ use tilejson::Bounds;
 use tokio::time::timeout;
 
 use crate::args::{BoundsCalcType, DEFAULT_BOUNDS_TIMEOUT};
-use crate::pg::PgError::PostgresError;
+use crate::pg::PgError::{InvalidTableFilter, PostgresError};
 use crate::pg::PgResult;
 use crate::pg::builder::SqlTableInfoMapMapMap;
-use crate::pg::config::PgInfo;
+use crate::pg::config::{PgInfo, Simplify, SimplifyMode};
 use crate::pg::config_table::TableInfo;
 use crate::pg::pg_source::PgSqlInfo;
 use crate::pg::pool::PgPool;
@@ -58,9 +59,12 @@ pub async fn query_available_tables(pool: &PgPool) -> PgResult<SqlTableInfoMapMa
             geometry_column: row.get("geom"),
             geometry_index: row.get("geom_idx"),
             is_view: row.get("is_view"),
+            is_materialized_view: row.get("is_materialized_view"),
+            is_geography: row.get("is_geography"),
             srid: row.get("srid"), // casting i32 to u32?
             geometry_type: row.get("type"),
             properties: Some(json_to_hashmap(&row.get("properties"))),
+            primary_key: row.get("primary_key"),
             tilejson,
             ..Default::default()
         };
@@ -122,7 +126,8 @@ pub async fn table_to_query(
             BoundsCalcType::Skip => {}
             BoundsCalcType::Calc => {
                 debug!("Computing {} table bounds for {id}", info.format_id());
-                info.bounds = calc_bounds(&pool, &schema, &table, &geometry_column, srid).await?;
+                let bounds = calc_bounds(&pool, &schema, &table, &geometry_column, srid).await;
+                info.bounds = bounds_or_warn(&id, &info, bounds)?;
             }
             BoundsCalcType::Quick => {
                 debug!(
@@ -133,7 +138,7 @@ pub async fn table_to_query(
                 let bounds = calc_bounds(&pool, &schema, &table, &geometry_column, srid);
                 pin_mut!(bounds);
                 if let Ok(bounds) = timeout(DEFAULT_BOUNDS_TIMEOUT, &mut bounds).await {
-                    info.bounds = bounds?;
+                    info.bounds = bounds_or_warn(&id, &info, bounds)?;
                 } else {
                     warn!(
                         "Timeout computing {} bounds for {id}, aborting query. Use --auto-bounds=calc to wait until complete, or check the table for missing indices.",
@@ -172,47 +177,145 @@ pub async fn table_to_query(
     let extent = info.extent.unwrap_or(DEFAULT_EXTENT);
     let buffer = info.buffer.unwrap_or(DEFAULT_BUFFER);
 
-    let bbox_search = if buffer == 0 {
-        "ST_TileEnvelope($1::integer, $2::integer, $3::integer)".to_string()
-    } else if pool.supports_tile_margin() {
-        let margin = f64::from(buffer) / f64::from(extent);
-        format!("ST_TileEnvelope($1::integer, $2::integer, $3::integer, margin => {margin})")
-    } else {
-        // TODO: we should use ST_Expand here, but it may require a bit more math work,
-        //       so might not be worth it as it is only used for PostGIS < v3.1.
-        //       v3.1 has been out for 2+ years (december 2020)
-        // let val = EARTH_CIRCUMFERENCE * buffer as f64 / extent as f64;
-        // format!("ST_Expand(ST_TileEnvelope($1::integer, $2::integer, $3::integer), {val}/2^$1::integer)")
-        "ST_TileEnvelope($1::integer, $2::integer, $3::integer)".to_string()
+    // Tile coordinates are passed in as SQL expressions, to share the query between single and bulk tile generation
+    let tile_envelope = |x: &str, y: &str| format!("ST_TileEnvelope($1::integer, {x}, {y})");
+    let bbox_search = |x: &str, y: &str| {
+        if buffer == 0 {
+            tile_envelope(x, y)
+        } else if pool.supports_tile_margin() {
+            let margin = f64::from(buffer) / f64::from(extent);
+            format!("ST_TileEnvelope($1::integer, {x}, {y}, margin => {margin})")
+        } else {
+            // TODO: we should use ST_Expand here, but it may require a bit more math work,
+            //       so might not be worth it as it is only used for PostGIS < v3.1.
+            //       v3.1 has been out for 2+ years (december 2020)
+            // let val = EARTH_CIRCUMFERENCE * buffer as f64 / extent as f64;
+            // format!("ST_Expand(ST_TileEnvelope($1::integer, $2::integer, $3::integer), {val}/2^$1::integer)")
+            tile_envelope(x, y)
+        }
     };
 
     let limit_clause = max_feature_count.map_or(String::new(), |v| format!("LIMIT {v}"));
+    let filter_clause = info
+        .filter
+        .as_ref()
+        .map_or(String::new(), |v| format!("AND ({v})"));
     let layer_id = escape_literal(info.layer_id.as_ref().unwrap_or(&id));
     let clip_geom = info.clip_geom.unwrap_or(DEFAULT_CLIP_GEOM);
-    let query = format!(
-        r"
-SELECT
-  ST_AsMVT(tile, {layer_id}, {extent}, 'geom'{id_name})
-FROM (
+    // Geography columns cannot contain curves, and are only cast to a geometry in WGS 84
+    let geometry = if info.is_geography == Some(true) {
+        format!("ST_Transform({geometry_column}::geometry, 3857)")
+    } else {
+        format!("ST_Transform(ST_CurveToLine({geometry_column}::geometry), 3857)")
+    };
+    let geometry = match info
+        .simplify
+        .as_ref()
+        .and_then(|v| simplify_tolerance(v, extent))
+    {
+        Some(tolerance) => format!("ST_SimplifyPreserveTopology({geometry}, {tolerance})"),
+        None => geometry,
+    };
+    let features = |x: &str, y: &str| {
+        format!(
+            r"
   SELECT
     ST_AsMVTGeom(
-        ST_Transform(ST_CurveToLine({geometry_column}::geometry), 3857),
-        ST_TileEnvelope($1::integer, $2::integer, $3::integer),
+        {geometry},
+        {envelope},
         {extent}, {buffer}, {clip_geom}
     ) AS geom
     {id_field}{properties}
   FROM
     {schema}.{table}
   WHERE
-    {geometry_column} && ST_Transform({bbox_search}, {srid})
-  {limit_clause}
+    {geometry_column} && ST_Transform({bbox}, {srid})
+    {filter_clause}
+  {limit_clause}",
+            envelope = tile_envelope(x, y),
+            bbox = bbox_search(x, y),
+        )
+    };
+
+    let query = format!(
+        r"
+SELECT
+  ST_AsMVT(tile, {layer_id}, {extent}, 'geom'{id_name})
+FROM ({features}
 ) AS tile;
-"
+",
+        features = features("$2::integer", "$3::integer"),
+    )
+    .trim()
+    .to_string();
+
+    // Generate all non-empty tiles in the ($1, $2, $3) - ($1, $4, $5) rectangle with a single query
+    let bulk_query = format!(
+        r"
+SELECT
+  _martin_x, _martin_y, ST_AsMVT(tile, {layer_id}, {extent}, 'geom'{id_name})
+FROM
+  generate_series($2::integer, $4::integer) AS _martin_x,
+  generate_series($3::integer, $5::integer) AS _martin_y,
+  LATERAL ({features}
+  ) AS tile
+GROUP BY _martin_x, _martin_y;
+",
+        features = features("_martin_x", "_martin_y"),
     )
     .trim()
     .to_string();
 
-    Ok((id, PgSqlInfo::new(query, false, info.format_id()), info))
+    // Fail when creating the source rather than for each tile if the filter is not valid SQL for this table
+    if let Some(filter) = &info.filter {
+        pool.get()
+            .await?
+            .query(&format!("EXPLAIN {query}"), &[&0_i32, &0_i32, &0_i32])
+            .await
+            .map_err(|e| InvalidTableFilter(e, id.clone(), filter.clone()))?;
+    }
+
+    let mut sql_info = PgSqlInfo::new(query, false, info.format_id());
+    sql_info.bulk_query = Some(bulk_query);
+    Ok((id, sql_info, info))
+}
+
+/// SQL expression of the simplification tolerance of the requested zoom `$1`, `None` if nothing is simplified
+fn simplify_tolerance(simplify: &Simplify, extent: u32) -> Option<String> {
+    match simplify {
+        Simplify::Mode(SimplifyMode::Auto) => Some(format!(
+            "{EARTH_CIRCUMFERENCE} / ({extent} * 2 ^ $1::integer)"
+        )),
+        Simplify::Zooms(zooms) => {
+            if zooms.values().all(|tolerance| *tolerance <= 0.0) {
+                return None;
+            }
+            let cases: Vec<_> = zooms
+                .iter()
+                .map(|(zoom, tolerance)| format!("WHEN $1::integer <= {zoom} THEN {tolerance}"))
+                .collect();
+            Some(format!("CASE {} ELSE 0 END", cases.join(" ")))
+        }
+    }
+}
+
+/// Materialized views fail to compute their bounds if they have not been populated yet,
+/// publish them without bounds rather than failing, as they may be refreshed later
+fn bounds_or_warn(
+    id: &str,
+    info: &TableInfo,
+    bounds: PgResult<Option<Bounds>>,
+) -> PgResult<Option<Bounds>> {
+    match bounds {
+        Err(e) if info.is_materialized_view == Some(true) => {
+            warn!(
+                "Unable to compute the bounds of materialized view {} for {id}, it may need a REFRESH MATERIALIZED VIEW: {e}",
+                info.format_id()
+            );
+            Ok(None)
+        }
+        v => v,
+    }
 }
 
 /// Compute the bounds of a table. This could be slow if the table is large or has no geo index.
@@ -32,6 +32,20 @@ WITH
                                    'gist_geography_ops')
         GROUP BY 1, 2, 3),
     --
+    primary_keys AS (
+        -- list of single column primary keys, used as the feature ID of the discovered tables
+        SELECT ns.nspname    AS table_schema,
+               class.relname AS table_name,
+               attr.attname  AS column_name
+        FROM pg_index ix
+                 JOIN pg_class class ON class.oid = ix.indrelid
+                 JOIN pg_namespace ns ON ns.oid = class.relnamespace
+                 JOIN pg_attribute attr ON
+                    attr.attrelid = class.oid AND
+                    attr.attnum = ix.indkey[0]
+        WHERE ix.indisprimary
+          AND ix.indnkeyatts = 1),
+    --
     annotated_geometry_columns AS (
         -- list of geometry columns with additional metadata
         SELECT f_table_schema                       AS schema,
@@ -39,40 +53,49 @@ WITH
                f_geometry_column                    AS geom,
                srid,
                type,
-               -- 'geometry' AS column_type
                COALESCE(class.relkind = 'v', false) AS is_view,
-               bool_or(sic.column_name is not null) as geom_idx
+               COALESCE(class.relkind = 'm', false) AS is_materialized_view,
+               bool_or(sic.column_name is not null) as geom_idx,
+               false                                AS is_geography
         FROM geometry_columns
                  JOIN pg_catalog.pg_class AS class
                       ON class.relname = geometry_columns.f_table_name
                  JOIN pg_catalog.pg_namespace AS ns
-                      ON ns.nspname = geometry_columns.f_table_schema
+                      ON ns.oid = class.relnamespace AND
+                         ns.nspname = geometry_columns.f_table_schema
                  LEFT JOIN spatially_indexed_columns AS sic ON
                     geometry_columns.f_table_schema = sic.table_schema AND
                     geometry_columns.f_table_name = sic.table_name AND
                     geometry_columns.f_geometry_column = sic.column_name
-        GROUP BY 1, 2, 3, 4, 5, 6),
+        -- ordinary and partitioned tables, views, materialized views and foreign tables
+        WHERE class.relkind IN ('r', 'p', 'v', 'm', 'f')
+        GROUP BY 1, 2, 3, 4, 5, 6, 7),
     --
     annotated_geography_columns AS (
         -- list of geography columns with additional metadata
         SELECT f_table_schema                       AS schema,
                f_table_name                         AS name,
                f_geography_column                   AS geom,
-               srid,
+               -- geography columns without an SRID use WGS 84
+               CASE WHEN srid = 0 THEN 4326 ELSE srid END AS srid,
                type,
-               -- 'geography' AS column_type
                COALESCE(class.relkind = 'v', false) AS is_view,
-               bool_or(sic.column_name is not null) as geom_idx
+               COALESCE(class.relkind = 'm', false) AS is_materialized_view,
+               bool_or(sic.column_name is not null) as geom_idx,
+               true                                 AS is_geography
         FROM geography_columns
                  JOIN pg_catalog.pg_class AS class
                       ON class.relname = geography_columns.f_table_name
                  JOIN pg_catalog.pg_namespace AS ns
-                      ON ns.nspname = geography_columns.f_table_schema
+                      ON ns.oid = class.relnamespace AND
+                         ns.nspname = geography_columns.f_table_schema
                  LEFT JOIN spatially_indexed_columns AS sic ON
                     geography_columns.f_table_schema = sic.table_schema AND
                     geography_columns.f_table_name = sic.table_name AND
                     geography_columns.f_geography_column = sic.column_name
-        GROUP BY 1, 2, 3, 4, 5, 6),
+        -- ordinary and partitioned tables, views, materialized views and foreign tables
+        WHERE class.relkind IN ('r', 'p', 'v', 'm', 'f')
+        GROUP BY 1, 2, 3, 4, 5, 6, 7),
     --
     annotated_geo_columns AS (
         SELECT * FROM annotated_geometry_columns
@@ -88,7 +111,7 @@ WITH
         FROM pg_class
             JOIN pg_namespace ON pg_class.relnamespace = pg_namespace.oid
             LEFT JOIN pg_description ON pg_class.oid = pg_description.objoid
-        WHERE relkind = 'r' OR relkind = 'v'
+        WHERE relkind IN ('r', 'p', 'v', 'm', 'f')
     )
 SELECT schema,
        name,
@@ -96,7 +119,9 @@ SELECT schema,
        srid,
        type,
        is_view,
+       is_materialized_view,
        geom_idx,
+       is_geography,
        COALESCE(
            jsonb_object_agg(columns.column_name, columns.type_name)
            FILTER (
@@ -106,7 +131,8 @@ SELECT schema,
                    ),
            '{}'::jsonb
            ) as properties,
-      dc.description
+      dc.description,
+      pk.column_name AS primary_key
 FROM annotated_geo_columns AS gc
          LEFT JOIN columns ON
             gc.schema = columns.table_schema AND
@@ -115,4 +141,7 @@ FROM annotated_geo_columns AS gc
          LEFT JOIN descriptions AS dc on
             gc.schema = dc.schema_name AND
             gc.name = dc.table_name
-GROUP BY gc.schema, gc.name, gc.geom, gc.srid, gc.type, gc.is_view, gc.geom_idx,dc.description;
+         LEFT JOIN primary_keys AS pk on
+            gc.schema = pk.table_schema AND
+            gc.name = pk.table_name
+GROUP BY gc.schema, gc.name, gc.geom, gc.srid, gc.type, gc.is_view, gc.is_materialized_view, gc.geom_idx, gc.is_geography, dc.description, pk.column_name;
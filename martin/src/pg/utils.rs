@@ -85,6 +85,16 @@ pub fn patch_json(target: TileJSON, patch: Option<&serde_json::Value>) -> TileJS
     }
 }
 
+/// Convert a configured legend into the `TileJSON` `legend` string.
+/// Strings are used as is (e.g. HTML), other values are serialized as JSON.
+#[must_use]
+pub fn legend_to_string(legend: &serde_json::Value) -> String {
+    match legend {
+        serde_json::Value::String(v) => v.clone(),
+        v => v.to_string(),
+    }
+}
+
 #[must_use]
 pub fn query_to_json(query: Option<&UrlQuery>) -> Json<HashMap<String, serde_json::Value>> {
     let mut query_as_json = HashMap::new();
@@ -162,6 +172,58 @@ fn find_info_kv<'a, T>(
     }
 }
 
+/// A pattern of schema, table, or function names used by the automatic discovery, where `*` matches any
+/// characters and `?` matches a single one. Like `PostgreSQL` identifiers, the patterns are folded to lowercase
+/// unless they are double-quoted, e.g. `gis_*` matches `gis_roads` but not `GIS_Roads`, which `"GIS_*"` matches.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NamePattern(Vec<char>);
+
+impl NamePattern {
+    #[must_use]
+    pub fn new(pattern: &str) -> Self {
+        match pattern.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+            Some(quoted) => Self(quoted.chars().collect()),
+            None => Self(pattern.to_lowercase().chars().collect()),
+        }
+    }
+
+    /// Check if a configured name is a pattern, rather than a name looked up like the configured sources
+    #[must_use]
+    pub fn is_pattern(value: &str) -> bool {
+        value.contains(['*', '?'])
+    }
+
+    #[must_use]
+    pub fn is_match(&self, name: &str) -> bool {
+        let name: Vec<char> = name.chars().collect();
+        let (mut pat_idx, mut name_idx) = (0, 0);
+        // Position after the last `*`, and the characters of the name it matched so far
+        let mut last_star = None;
+        while name_idx < name.len() {
+            match self.0.get(pat_idx) {
+                Some('*') => {
+                    pat_idx += 1;
+                    last_star = Some((pat_idx, name_idx));
+                }
+                Some(&c) if c == '?' || c == name[name_idx] => {
+                    pat_idx += 1;
+                    name_idx += 1;
+                }
+                _ => {
+                    // Let the last `*` match one more character, and try the rest of the pattern again
+                    let Some((star_pat_idx, star_name_idx)) = last_star else {
+                        return false;
+                    };
+                    pat_idx = star_pat_idx;
+                    name_idx = star_name_idx + 1;
+                    last_star = Some((star_pat_idx, name_idx));
+                }
+            }
+        }
+        self.0[pat_idx..].iter().all(|&c| c == '*')
+    }
+}
+
 /// Find a key in a map, ignoring case.
 /// If there is no exact match, but there is a case-insensitive match, return that as `Ok(Some(value))`.
 /// If there is no exact match and there are multiple case-insensitive matches, return an error with a vector of the possible matches.
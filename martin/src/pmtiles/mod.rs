@@ -20,7 +20,7 @@ use url::Url;
 use crate::config::UnrecognizedValues;
 use crate::file_config::FileError::{InvalidMetadata, InvalidUrlMetadata, IoError};
 use crate::file_config::{ConfigExtras, FileError, FileResult, SourceConfigExtras};
-use crate::source::{TileInfoSource, UrlQuery};
+use crate::source::{SourceKind, TileInfoSource, UrlQuery};
 use crate::utils::cache::get_cached_value;
 use crate::utils::{CacheKey, CacheValue, OptMainCache};
 use crate::{MartinResult, Source, TileData};
@@ -252,6 +252,10 @@ macro_rules! impl_pmtiles_source {
                 self.tile_info
             }
 
+            fn get_kind(&self) -> SourceKind {
+                SourceKind::Pmtiles
+            }
+
             fn clone_source(&self) -> TileInfoSource {
                 Box::new(self.clone())
             }
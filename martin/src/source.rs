@@ -1,59 +1,243 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::Debug;
+use std::ops::RangeInclusive;
+use std::time::{Duration, SystemTime};
 
-use actix_web::error::ErrorNotFound;
+use actix_web::error::{ErrorNotFound, ErrorServiceUnavailable};
 use async_trait::async_trait;
 use dashmap::DashMap;
+use futures::future::join_all;
+use futures::stream::BoxStream;
 use log::debug;
-use martin_tile_utils::{TileCoord, TileInfo};
+use martin_tile_utils::{Format, MAX_ZOOM, TileCoord, TileInfo};
 use serde::{Deserialize, Serialize};
 use tilejson::TileJSON;
 
-use crate::MartinResult;
+use crate::{FallbackSource, MartinResult, OverzoomSource, TileRect};
 
 pub type TileData = Vec<u8>;
 pub type UrlQuery = HashMap<String, String>;
+pub type TileStream = BoxStream<'static, MartinResult<(TileCoord, TileData)>>;
 
 pub type TileInfoSource = Box<dyn Source>;
 
 pub type TileInfoSources = Vec<TileInfoSource>;
 
 #[derive(Default, Clone)]
-pub struct TileSources(DashMap<String, TileInfoSource>);
+pub struct TileSources {
+    sources: DashMap<String, TileInfoSource>,
+    /// Fallback sources waiting for some of their members, with the IDs of all of their members
+    pending: DashMap<String, Vec<String>>,
+}
 pub type TileCatalog = DashMap<String, CatalogSourceEntry>;
 
 impl TileSources {
     #[must_use]
     pub fn new(sources: Vec<TileInfoSources>) -> Self {
-        Self(
-            sources
+        Self {
+            sources: sources
                 .into_iter()
                 .flatten()
                 .map(|src| (src.get_id().to_string(), src))
                 .collect(),
-        )
+            pending: DashMap::new(),
+        }
     }
 
     #[must_use]
     pub fn get_catalog(&self) -> TileCatalog {
-        self.0
+        let catalog: TileCatalog = self
+            .sources
+            .iter()
+            .map(|v| {
+                let mut entry = v.get_catalog_entry();
+                if v.key() != v.get_id() {
+                    entry.alias_of = Some(v.get_id().to_string());
+                }
+                (v.key().to_string(), entry)
+            })
+            .collect();
+        for (id, missing) in self.get_pending() {
+            let entry = CatalogSourceEntry {
+                pending: Some(missing),
+                ..Default::default()
+            };
+            catalog.insert(id, entry);
+        }
+        catalog
+    }
+
+    #[must_use]
+    pub fn contains(&self, id: &str) -> bool {
+        self.sources.contains_key(id)
+    }
+
+    /// Make a source also available under another ID.
+    /// Returns `false` if the target is not the ID of a source, e.g. if it is missing or is an alias itself.
+    pub fn insert_alias(&self, alias: &str, target: &str) -> bool {
+        let Some(source) = self
+            .sources
+            .get(target)
+            .filter(|v| v.get_id() == target)
+            .map(|v| v.value().clone())
+        else {
+            return false;
+        };
+        self.sources.insert(alias.to_string(), source);
+        true
+    }
+
+    /// Add a new source at runtime, replacing any existing source or pending fallback source with the same ID
+    pub fn insert(&self, source: TileInfoSource) {
+        self.pending.remove(source.get_id());
+        self.sources.insert(source.get_id().to_string(), source);
+    }
+
+    /// Remove a source at runtime, returning it if it existed
+    pub fn remove(&self, id: &str) -> Option<TileInfoSource> {
+        self.sources.remove(id).map(|(_, source)| source)
+    }
+
+    /// Add a fallback source that is only registered by [`TileSources::register_pending`]
+    /// once all of its members are available
+    pub fn insert_pending(&self, id: String, member_ids: Vec<String>) {
+        self.pending.insert(id, member_ids);
+    }
+
+    /// Register the pending fallback sources whose members are all available, and return their IDs.
+    /// A fallback source including other pending fallback sources is registered after them.
+    pub fn register_pending(&self) -> MartinResult<Vec<String>> {
+        let mut registered = Vec::new();
+        loop {
+            let ready: Vec<(String, Vec<String>)> = self
+                .pending
+                .iter()
+                .filter(|v| v.iter().all(|id| self.sources.contains_key(id)))
+                .map(|v| (v.key().clone(), v.value().clone()))
+                .collect();
+            if ready.is_empty() {
+                return Ok(registered);
+            }
+            for (id, member_ids) in ready {
+                let Some(members) = member_ids
+                    .iter()
+                    .map(|v| self.sources.get(v).map(|v| v.value().clone()))
+                    .collect::<Option<TileInfoSources>>()
+                else {
+                    // A member was removed in the meantime
+                    continue;
+                };
+                let source = FallbackSource::new(id.clone(), members)?;
+                self.pending.remove(&id);
+                self.sources.insert(id.clone(), Box::new(source));
+                registered.push(id);
+            }
+        }
+    }
+
+    /// Pending fallback sources, with the IDs of the members they are waiting for
+    #[must_use]
+    pub fn get_pending(&self) -> BTreeMap<String, Vec<String>> {
+        self.pending
+            .iter()
+            .map(|v| (v.key().clone(), self.get_missing(v.value())))
+            .collect()
+    }
+
+    fn get_missing(&self, member_ids: &[String]) -> Vec<String> {
+        member_ids
+            .iter()
+            .filter(|v| !self.sources.contains_key(*v))
+            .cloned()
+            .collect()
+    }
+
+    /// Replace all sources with the ones resolved again on reload, returning the IDs of the removed ones.
+    /// Sources are swapped one by one, so a source present before and after the reload is always available.
+    pub fn replace_all(&self, sources: TileSources) -> Vec<String> {
+        let removed: Vec<String> = self
+            .sources
+            .iter()
+            .filter(|v| !sources.sources.contains_key(v.key()))
+            .map(|v| v.key().clone())
+            .collect();
+        for (id, source) in sources.sources {
+            self.sources.insert(id, source);
+        }
+        for id in &removed {
+            self.sources.remove(id);
+        }
+        self.pending
+            .retain(|id, _| sources.pending.contains_key(id));
+        for (id, member_ids) in sources.pending {
+            self.pending.insert(id, member_ids);
+        }
+        removed
+    }
+
+    /// Number of sources of each kind, not counting the aliases
+    #[must_use]
+    pub fn count_by_kind(&self) -> BTreeMap<SourceKind, usize> {
+        let mut counts = BTreeMap::new();
+        for source in self.sources.iter().filter(|v| v.key() == v.get_id()) {
+            *counts.entry(source.get_kind()).or_default() += 1;
+        }
+        counts
+    }
+
+    /// Usage of the connection pools of the sources, by pool ID. Pools shared by several sources are listed once.
+    #[must_use]
+    pub fn get_pool_statuses(&self) -> BTreeMap<String, PoolStatus> {
+        self.sources
             .iter()
-            .map(|v| (v.key().to_string(), v.get_catalog_entry()))
+            .filter_map(|v| v.get_pool_status())
             .collect()
     }
 
+    /// Check the health of all sources in parallel, and return the errors of the unhealthy ones by source ID.
+    /// Sources that do not complete the check within the timeout are unhealthy.
+    pub async fn check_health(&self, timeout: Duration) -> BTreeMap<String, String> {
+        // Aliases share the source of their target, so it is only checked once
+        let sources: Vec<TileInfoSource> = self
+            .sources
+            .iter()
+            .filter(|v| v.key() == v.get_id())
+            .map(|v| v.value().clone())
+            .collect();
+        join_all(sources.iter().map(|src| async move {
+            let error = match tokio::time::timeout(timeout, src.check_health()).await {
+                Ok(Ok(())) => return None,
+                Ok(Err(e)) => e.to_string(),
+                Err(_) => format!("health check timed out after {}ms", timeout.as_millis()),
+            };
+            Some((src.get_id().to_string(), error))
+        }))
+        .await
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+
     pub fn get_source(&self, id: &str) -> actix_web::Result<TileInfoSource> {
-        Ok(self
-            .0
-            .get(id)
-            .ok_or_else(|| ErrorNotFound(format!("Source {id} does not exist")))?
-            .value()
-            .clone())
+        if let Some(source) = self.sources.get(id) {
+            return Ok(source.value().clone());
+        }
+        if let Some(member_ids) = self.pending.get(id) {
+            let missing = self.get_missing(&member_ids).join(", ");
+            return Err(ErrorServiceUnavailable(format!(
+                "Source {id} is waiting for {missing}"
+            )));
+        }
+        Err(ErrorNotFound(format!("Source {id} does not exist")))
     }
 
     /// Get a list of sources, and the tile info for the merged sources.
     /// Ensure that all sources have the same format and encoding.
     /// If zoom is specified, filter out sources that do not support it.
+    /// The zoom must be within the merged zoom range of the sources, from the lowest `minzoom` to the highest `maxzoom`,
+    /// but the list may be empty if it falls between the zoom ranges of the sources.
+    /// Sources are also filtered out at zooms outside of their [`Source::get_zoom_range`], which is not an error,
+    /// so the tile is simply missing.
     pub fn get_sources(
         &self,
         source_ids: &str,
@@ -62,6 +246,8 @@ impl TileSources {
         let mut sources = Vec::new();
         let mut info: Option<TileInfo> = None;
         let mut use_url_query = false;
+        let mut above_minzoom = false;
+        let mut below_maxzoom = false;
 
         for id in source_ids.split(',') {
             let src = self.get_source(id)?;
@@ -78,6 +264,18 @@ impl TileSources {
                 None => info = Some(src_inf),
             }
 
+            if let Some(zoom) = zoom {
+                if !src.get_zoom_range().contains(&zoom) {
+                    debug!("Source {id} has no tiles at zoom {zoom}");
+                    above_minzoom = true;
+                    below_maxzoom = true;
+                    continue;
+                }
+                let tj = src.get_tilejson();
+                above_minzoom |= tj.minzoom.is_none_or(|minzoom| zoom >= minzoom);
+                below_maxzoom |= tj.maxzoom.is_none_or(|maxzoom| zoom <= maxzoom);
+            }
+
             // TODO: Use chained-if-let once available
             if match zoom {
                 Some(zoom) if Self::check_zoom(&*src, id, zoom) => true,
@@ -88,10 +286,44 @@ impl TileSources {
             }
         }
 
+        if let Some(zoom) = zoom {
+            if !above_minzoom || !below_maxzoom {
+                return Err(ErrorNotFound(format!(
+                    "Zoom {zoom} is not valid for sources {source_ids}"
+                )));
+            }
+        }
+
         // format is guaranteed to be Some() here
         Ok((sources, use_url_query, info.unwrap()))
     }
 
+    /// Get the sources of `source_ids`, with the MVT sources serving the tiles above their `maxzoom` up to `zoom`
+    /// by clipping their tiles at `maxzoom`, like the sources configured with `max_overzoom`.
+    /// The sources that cannot be overzoomed are returned as they are.
+    pub fn get_overzoomed(&self, source_ids: &str, zoom: u8) -> actix_web::Result<Self> {
+        let sources = DashMap::new();
+        for id in source_ids.split(',') {
+            let src = self.get_source(id)?;
+            let range_max = *src.get_zoom_range().end();
+            let maxzoom = src
+                .get_tilejson()
+                .maxzoom
+                .map_or(range_max, |v| v.min(range_max));
+            let src = if zoom > maxzoom {
+                OverzoomSource::new(src.clone_source(), zoom - maxzoom)
+                    .map_or(src, |v| Box::new(v) as TileInfoSource)
+            } else {
+                src
+            };
+            sources.insert(id.to_string(), src);
+        }
+        Ok(Self {
+            sources,
+            pending: DashMap::new(),
+        })
+    }
+
     pub fn check_zoom(src: &dyn Source, id: &str, zoom: u8) -> bool {
         let is_valid = src.is_valid_zoom(zoom);
         if !is_valid {
@@ -101,26 +333,100 @@ impl TileSources {
     }
 }
 
+/// The time the data of the sources last changed, or `None` unless all of them know it
+#[must_use]
+pub fn get_last_modified(sources: &[TileInfoSource]) -> Option<SystemTime> {
+    sources
+        .iter()
+        .map(|v| v.get_last_modified())
+        .collect::<Option<Vec<_>>>()?
+        .into_iter()
+        .max()
+}
+
+/// A source of tiles. Sources are shared by all workers, so they must be `Sync`.
+/// The methods with a default implementation can be left out by the sources implemented outside of Martin.
 #[async_trait]
-pub trait Source: Send + Debug {
+pub trait Source: Send + Sync + Debug {
     fn get_id(&self) -> &str;
 
     fn get_tilejson(&self) -> &TileJSON;
 
     fn get_tile_info(&self) -> TileInfo;
 
+    /// The kind of the source, used to filter the catalog.
+    /// Defaults to [`SourceKind::Other`] for the sources implemented outside of Martin.
+    fn get_kind(&self) -> SourceKind {
+        SourceKind::Other
+    }
+
     fn clone_source(&self) -> TileInfoSource;
 
     fn support_url_query(&self) -> bool {
         false
     }
 
+    /// An error detected while initializing the source, e.g. by a startup probe.
+    /// Sources with an error are still served, but are reported as degraded in the catalog.
+    fn get_error(&self) -> Option<&str> {
+        None
+    }
+
     async fn get_tile(
         &self,
         xyz: TileCoord,
         url_query: Option<&UrlQuery>,
     ) -> MartinResult<TileData>;
 
+    /// Check that the source is still able to serve tiles, e.g. that its database can be queried.
+    /// Used by the deep health check, so it should be quick.
+    async fn check_health(&self) -> MartinResult<()> {
+        Ok(())
+    }
+
+    /// ID and current usage of the connection pool of the source, without waiting for a connection.
+    /// `None` for the sources that do not use a connection pool.
+    fn get_pool_status(&self) -> Option<(String, PoolStatus)> {
+        None
+    }
+
+    /// Check if the source has a stored tile at `xyz`, even if the tile is empty.
+    /// Sources that generate their tiles cannot tell an empty tile from a missing one, and return `false`.
+    async fn has_tile(&self, _xyz: TileCoord) -> MartinResult<bool> {
+        Ok(false)
+    }
+
+    /// Whether [`Source::has_tile`] tells if a tile is missing, e.g. with a cheap lookup of the tile index,
+    /// without fetching the tile data. Sources generating their tiles cannot tell, and return `false`.
+    fn can_probe_tiles(&self) -> bool {
+        false
+    }
+
+    /// Generate all tiles of a rectangle with a single query, streaming them as they are produced.
+    /// Empty tiles may be omitted from the stream.
+    /// Returns `None` if the source can only generate tiles one at a time with [`Source::get_tile`].
+    async fn get_tiles_bulk(&self, _rect: TileRect) -> MartinResult<Option<TileStream>> {
+        Ok(None)
+    }
+
+    /// Zoom levels at which the source may have tiles. Tiles at other zooms are missing without querying the source.
+    /// Unlimited by default.
+    fn get_zoom_range(&self) -> RangeInclusive<u8> {
+        0..=MAX_ZOOM
+    }
+
+    /// The time the data of the source last changed, e.g. the modification time of its file when it was opened.
+    /// `None` if unknown, e.g. for sources generating their tiles from a database.
+    fn get_last_modified(&self) -> Option<SystemTime> {
+        None
+    }
+
+    /// Pixel ratio of the tiles, e.g. `2` for 512px raster tiles, which are also served at the `@2x` tile URLs.
+    /// Defaults to `1`, so that the `@2x` and `@3x` URLs are not found.
+    fn get_tile_scale(&self) -> u8 {
+        1
+    }
+
     fn is_valid_zoom(&self, zoom: u8) -> bool {
         let tj = self.get_tilejson();
         tj.minzoom.is_none_or(|minzoom| zoom >= minzoom)
@@ -137,6 +443,14 @@ pub trait Source: Send + Debug {
             name: tilejson.name.as_ref().filter(|v| *v != id).cloned(),
             description: tilejson.description.clone(),
             attribution: tilejson.attribution.clone(),
+            error: self.get_error().map(ToString::to_string),
+            alias_of: None,
+            pending: None,
+            supports_url_query: self.support_url_query().then_some(true),
+            tilejson_url: None,
+            tiles: None,
+            kind: Some(self.get_kind()),
+            format: Some(info.format),
         }
     }
 }
@@ -150,11 +464,58 @@ impl Clone for TileInfoSource {
 #[serde_with::skip_serializing_none]
 #[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
 pub struct CatalogSourceEntry {
+    /// Empty while the source is pending
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub content_type: String,
     pub content_encoding: Option<String>,
     pub name: Option<String>,
     pub description: Option<String>,
     pub attribution: Option<String>,
+    /// Set if the source is degraded
+    pub error: Option<String>,
+    /// Set if this ID is an alias of another source
+    pub alias_of: Option<String>,
+    /// Set while the source waits for the listed sources it depends on, it has no tiles until then
+    pub pending: Option<Vec<String>>,
+    /// Set if the tiles accept additional URL query parameters, e.g. for `PostgreSQL` functions with a `query` argument
+    pub supports_url_query: Option<bool>,
+    /// URL of the `TileJSON`, as seen by the client. Set for each catalog request.
+    pub tilejson_url: Option<String>,
+    /// URL template of the tiles with the `{z}`, `{x}` and `{y}` placeholders, as seen by the client.
+    /// Set for each catalog request.
+    pub tiles: Option<String>,
+    /// Not part of the catalog response, only used to filter it
+    #[serde(skip)]
+    pub kind: Option<SourceKind>,
+    /// Not part of the catalog response, only used to build the tile URLs
+    #[serde(skip)]
+    pub format: Option<Format>,
+}
+
+/// Connections of a database pool, see [`Source::get_pool_status`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct PoolStatus {
+    /// Maximum number of connections
+    pub max_size: usize,
+    /// Number of open connections
+    pub size: usize,
+    /// Number of open connections currently used by a query
+    pub in_use: usize,
+}
+
+/// Where the tiles of a source come from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceKind {
+    /// `PostgreSQL` table or view
+    Table,
+    /// `PostgreSQL` function
+    Function,
+    Mbtiles,
+    Pmtiles,
+    Cog,
+    /// Source implemented outside of Martin, see [`Source::get_kind`]
+    Other,
 }
 
 #[cfg(test)]
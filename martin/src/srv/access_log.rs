@@ -0,0 +1,49 @@
+use actix_web::middleware::Logger;
+use serde::{Deserialize, Serialize};
+
+/// The format of the actix-web `Logger::default()`
+pub const ACCESS_LOG_FORMAT_DEFAULT: &str = r#"%a "%r" %s %b "%{Referer}i" "%{User-Agent}i" %T"#;
+/// The log target of the actix-web logger, so that the existing `RUST_LOG` filters keep working
+pub const ACCESS_LOG_TARGET_DEFAULT: &str = "actix_web::middleware::logger";
+
+/// Settings of the line logged for each request
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct AccessLogConfig {
+    /// Log a line for each request. Defaults to `true`.
+    pub enabled: Option<bool>,
+    /// Format of the lines, using the actix-web `Logger` placeholders, plus `%{source}xo` for the requested source IDs
+    pub format: Option<String>,
+    /// Target of the log records, e.g. to filter them with `RUST_LOG`. Defaults to `actix_web::middleware::logger`.
+    pub target: Option<String>,
+    /// Request paths that are never logged, e.g. the health checks of a load balancer
+    pub exclude_paths: Option<Vec<String>>,
+}
+
+impl AccessLogConfig {
+    /// Create the logging middleware, or `None` if the access log is disabled
+    #[must_use]
+    pub fn new_middleware(&self) -> Option<Logger> {
+        if !self.enabled.unwrap_or(true) {
+            return None;
+        }
+        let mut logger = Logger::new(self.format.as_deref().unwrap_or(ACCESS_LOG_FORMAT_DEFAULT))
+            .log_target(
+                self.target
+                    .clone()
+                    .unwrap_or_else(|| ACCESS_LOG_TARGET_DEFAULT.to_string()),
+            )
+            .custom_response_replace("source", |res| {
+                // The route is resolved by now, so the source IDs of the tile, TileJSON, and other source routes are known
+                let info = res.request().match_info();
+                info.get("source_ids")
+                    .or_else(|| info.get("source_id"))
+                    .unwrap_or("-")
+                    .to_string()
+            });
+        for path in self.exclude_paths.iter().flatten() {
+            logger = logger.exclude(path.clone());
+        }
+        Some(logger)
+    }
+}
@@ -0,0 +1,426 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use actix_web::error::{ErrorBadRequest, ErrorNotFound, ErrorUnauthorized};
+use actix_web::web::{Data, Json, Path as UrlPath};
+use actix_web::{HttpRequest, HttpResponse, Result as ActixResult, route};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::MartinError::{
+    ConfigLoadError, ConfigWriteError, OverridesParseError, OverridesSerializeError,
+};
+use crate::MartinResult;
+use crate::file_config::FileError::InvalidFilePath;
+use crate::file_config::{FileResult, SourceConfigExtras as _};
+use crate::source::{TileInfoSource, TileSources};
+use crate::srv::server::{Catalog, has_bearer_token, is_valid_source_id, map_internal_error};
+use crate::srv::{SourceReloader, SrvConfig};
+use crate::utils::{CacheKey, OptMainCache};
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct AdminConfig {
+    /// Bearer token that admin requests must pass in the `Authorization` header
+    pub token: String,
+    /// File storing the sources added, modified, or removed with the admin API.
+    /// It is applied on top of the configured sources on startup.
+    pub overrides_file: PathBuf,
+}
+
+/// Definition of a file source managed with the admin API
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum AdminSource {
+    #[cfg(feature = "mbtiles")]
+    Mbtiles { path: PathBuf },
+    #[cfg(feature = "pmtiles")]
+    Pmtiles { path: PathBuf },
+    #[cfg(feature = "cog")]
+    Cog { path: PathBuf },
+}
+
+impl AdminSource {
+    fn path(&self) -> &Path {
+        match self {
+            #[cfg(feature = "mbtiles")]
+            Self::Mbtiles { path } => path,
+            #[cfg(feature = "pmtiles")]
+            Self::Pmtiles { path } => path,
+            #[cfg(feature = "cog")]
+            Self::Cog { path } => path,
+        }
+    }
+
+    /// Open the file and create the source, failing if the file is missing or invalid
+    async fn new_source(&self, id: String) -> FileResult<TileInfoSource> {
+        let path = self.path().to_path_buf();
+        if !path.is_file() {
+            return Err(InvalidFilePath(path));
+        }
+        match self {
+            #[cfg(feature = "mbtiles")]
+            Self::Mbtiles { .. } => {
+                crate::mbtiles::MbtConfig::default()
+                    .new_sources(id, path)
+                    .await
+            }
+            #[cfg(feature = "pmtiles")]
+            Self::Pmtiles { .. } => {
+                crate::pmtiles::PmtConfig::default()
+                    .new_sources(id, path)
+                    .await
+            }
+            #[cfg(feature = "cog")]
+            Self::Cog { .. } => crate::cog::CogConfig::default().new_sources(id, path).await,
+        }
+    }
+}
+
+/// Changes made to the sources with the admin API, stored in [`AdminConfig::overrides_file`]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SourceOverrides {
+    /// Sources added or replaced at runtime
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub sources: BTreeMap<String, AdminSource>,
+    /// IDs of the configured sources removed at runtime
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub removed: BTreeSet<String>,
+}
+
+impl SourceOverrides {
+    /// Read the overrides file, or return empty overrides if it does not exist yet
+    pub fn load(file_name: &Path) -> MartinResult<Self> {
+        match std::fs::read_to_string(file_name) {
+            Ok(contents) => serde_yaml::from_str(&contents)
+                .map_err(|e| OverridesParseError(e, file_name.to_path_buf())),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(ConfigLoadError(e, file_name.to_path_buf())),
+        }
+    }
+
+    /// Write the overrides to a temporary file first, so that a failed write never leaves a partial file
+    pub fn save(&self, file_name: &Path) -> MartinResult<()> {
+        let yaml = serde_yaml::to_string(self)
+            .map_err(|e| OverridesSerializeError(e, file_name.to_path_buf()))?;
+        let tmp = file_name.with_extension("tmp");
+        std::fs::write(&tmp, yaml)
+            .and_then(|()| std::fs::rename(&tmp, file_name))
+            .map_err(|e| ConfigWriteError(e, file_name.to_path_buf()))
+    }
+
+    /// Apply the overrides to the configured sources on startup
+    pub async fn apply(&self, sources: &TileSources) -> MartinResult<()> {
+        for id in &self.removed {
+            if sources.remove(id).is_some() {
+                info!("Removed source {id} as configured in the source overrides");
+            } else {
+                debug!("Source {id} removed in the source overrides does not exist");
+            }
+        }
+        for (id, src) in &self.sources {
+            sources.insert(src.new_source(id.clone()).await?);
+            info!("Published source {id} from {}", src.path().display());
+        }
+        Ok(())
+    }
+}
+
+/// Reject the request unless it has the admin token in the `Authorization` header
+pub(crate) fn check_token<'a>(
+    req: &HttpRequest,
+    config: &'a SrvConfig,
+) -> ActixResult<&'a AdminConfig> {
+    let Some(cfg) = &config.admin else {
+        return Err(ErrorNotFound("Admin API is not enabled"));
+    };
+    if has_bearer_token(req, &cfg.token) {
+        Ok(cfg)
+    } else {
+        Err(ErrorUnauthorized("Invalid or missing admin token"))
+    }
+}
+
+/// Publish the fallback sources that were waiting for a source added at runtime,
+/// and list the sources they are still waiting for in the catalog
+pub(crate) fn register_pending(sources: &TileSources, catalog: &Catalog) {
+    match sources.register_pending() {
+        Ok(ids) => {
+            for id in ids {
+                info!("Published fallback source {id}, all of its members are available");
+            }
+        }
+        Err(e) => warn!("Unable to publish the fallback sources waiting for a new source: {e}"),
+    }
+    catalog.set_tiles(sources.get_catalog());
+}
+
+/// Remove all cached tiles of a source that was replaced or removed
+fn invalidate_cache(cache: &OptMainCache, id: &str) -> ActixResult<()> {
+    if let Some(cache) = cache {
+        let id = id.to_string();
+        cache
+            .invalidate_entries_if(move |key, _| match key {
+                CacheKey::Tile(src, _) | CacheKey::TileWithQuery(src, _, _) => *src == id,
+                CacheKey::PmtDirectory(..) => false,
+            })
+            .map_err(map_internal_error)?;
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct AdminSourceRequest {
+    source_id: String,
+}
+
+/// List the sources added, modified, or removed with the admin API
+#[route("/admin/config/sources", method = "GET")]
+async fn get_admin_sources(
+    req: HttpRequest,
+    config: Data<SrvConfig>,
+    overrides: Data<Mutex<SourceOverrides>>,
+) -> ActixResult<HttpResponse> {
+    check_token(&req, &config)?;
+    let overrides = overrides.lock().map_err(map_internal_error)?.clone();
+    Ok(HttpResponse::Ok().json(overrides))
+}
+
+/// Get the definition of a source managed with the admin API
+#[route("/admin/config/sources/{source_id}", method = "GET")]
+async fn get_admin_source(
+    req: HttpRequest,
+    path: UrlPath<AdminSourceRequest>,
+    config: Data<SrvConfig>,
+    overrides: Data<Mutex<SourceOverrides>>,
+) -> ActixResult<HttpResponse> {
+    check_token(&req, &config)?;
+    let id = &path.source_id;
+    let overrides = overrides.lock().map_err(map_internal_error)?;
+    match overrides.sources.get(id) {
+        Some(src) => Ok(HttpResponse::Ok().json(src)),
+        None => Err(ErrorNotFound(format!(
+            "Source {id} is not managed with the admin API"
+        ))),
+    }
+}
+
+/// Add a new source or replace an existing one, publishing it immediately and storing it in the overrides file
+#[route("/admin/config/sources/{source_id}", method = "PUT")]
+#[allow(clippy::too_many_arguments)]
+async fn put_admin_source(
+    req: HttpRequest,
+    path: UrlPath<AdminSourceRequest>,
+    body: Json<AdminSource>,
+    config: Data<SrvConfig>,
+    overrides: Data<Mutex<SourceOverrides>>,
+    sources: Data<TileSources>,
+    catalog: Data<Catalog>,
+    cache: Data<OptMainCache>,
+) -> ActixResult<HttpResponse> {
+    let cfg = check_token(&req, &config)?;
+    let id = &path.source_id;
+    if !is_valid_source_id(id) {
+        return Err(ErrorBadRequest(format!("Invalid source ID {id}")));
+    }
+    let definition = body.into_inner();
+    let src = definition
+        .new_source(id.clone())
+        .await
+        .map_err(|e| ErrorBadRequest(format!("Invalid source {id}: {e}")))?;
+
+    {
+        let mut overrides = overrides.lock().map_err(map_internal_error)?;
+        overrides.removed.remove(id);
+        overrides.sources.insert(id.clone(), definition.clone());
+        overrides
+            .save(&cfg.overrides_file)
+            .map_err(map_internal_error)?;
+    }
+
+    let entry = src.get_catalog_entry();
+    let existed = sources.get_source(id).is_ok();
+    sources.insert(src);
+    catalog.tiles.insert(id.clone(), entry.clone());
+    catalog.invalidate_json();
+    register_pending(&sources, &catalog);
+    invalidate_cache(&cache, id)?;
+    info!(
+        "Published source {id} from {} with the admin API",
+        definition.path().display()
+    );
+
+    if existed {
+        Ok(HttpResponse::Ok().json(entry))
+    } else {
+        Ok(HttpResponse::Created().json(entry))
+    }
+}
+
+/// Stop serving a source, and record its removal in the overrides file
+#[route("/admin/config/sources/{source_id}", method = "DELETE")]
+async fn delete_admin_source(
+    req: HttpRequest,
+    path: UrlPath<AdminSourceRequest>,
+    config: Data<SrvConfig>,
+    overrides: Data<Mutex<SourceOverrides>>,
+    sources: Data<TileSources>,
+    catalog: Data<Catalog>,
+    cache: Data<OptMainCache>,
+) -> ActixResult<HttpResponse> {
+    let cfg = check_token(&req, &config)?;
+    let id = &path.source_id;
+    if sources.remove(id).is_none() {
+        return Err(ErrorNotFound(format!("Source {id} does not exist")));
+    }
+    catalog.tiles.remove(id);
+    catalog.invalidate_json();
+    invalidate_cache(&cache, id)?;
+
+    {
+        let mut overrides = overrides.lock().map_err(map_internal_error)?;
+        // Sources added with the admin API are simply forgotten, configured ones must stay removed
+        if overrides.sources.remove(id).is_none() {
+            overrides.removed.insert(id.clone());
+        }
+        if let Err(e) = overrides.save(&cfg.overrides_file) {
+            warn!("Source {id} was removed, but the removal could not be saved");
+            return Err(map_internal_error(e));
+        }
+    }
+    info!("Removed source {id} with the admin API");
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Resolve the configured sources again, e.g. to publish the tables added to the database since the start
+#[route("/-/reload", method = "POST")]
+async fn post_reload(
+    req: HttpRequest,
+    config: Data<SrvConfig>,
+    reloader: Data<SourceReloader>,
+) -> ActixResult<HttpResponse> {
+    check_token(&req, &config)?;
+    let summary = reloader.reload().await.map_err(map_internal_error)?;
+    Ok(HttpResponse::Ok().json(summary))
+}
+
+#[cfg(all(test, feature = "mbtiles"))]
+mod tests {
+    use actix_web::App;
+    use actix_web::http::StatusCode;
+    use actix_web::http::header::AUTHORIZATION;
+    use actix_web::test::{TestRequest, call_service, init_service, read_body_json};
+
+    use super::*;
+    use crate::NO_MAIN_CACHE;
+    use crate::srv::server::tests::TestSource;
+
+    #[actix_rt::test]
+    async fn admin_sources() {
+        let dir = tempfile::tempdir().unwrap();
+        let overrides_file = dir.path().join("overrides.yaml");
+        let config = SrvConfig {
+            admin: Some(AdminConfig {
+                token: "secret".to_string(),
+                overrides_file: overrides_file.clone(),
+            }),
+            ..Default::default()
+        };
+        let sources = Data::new(TileSources::new(vec![vec![Box::new(TestSource {
+            id: "configured",
+            tj: tilejson::tilejson! { tiles: vec![] },
+            data: Vec::new(),
+        })]]));
+        let catalog = Data::new(Catalog {
+            tiles: sources.get_catalog(),
+            ..Default::default()
+        });
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(config))
+                .app_data(Data::new(Mutex::new(SourceOverrides::default())))
+                .app_data(sources.clone())
+                .app_data(catalog.clone())
+                .app_data(Data::new(NO_MAIN_CACHE))
+                .service(get_admin_sources)
+                .service(get_admin_source)
+                .service(put_admin_source)
+                .service(delete_admin_source),
+        )
+        .await;
+        let auth = (AUTHORIZATION, "Bearer secret");
+        let definition = AdminSource::Mbtiles {
+            path: PathBuf::from("../tests/fixtures/mbtiles/world_cities.mbtiles"),
+        };
+
+        let req = TestRequest::put()
+            .uri("/admin/config/sources/cities")
+            .set_json(&definition)
+            .to_request();
+        let response = call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let req = TestRequest::put()
+            .uri("/admin/config/sources/cities")
+            .insert_header(auth.clone())
+            .set_json(AdminSource::Mbtiles {
+                path: dir.path().join("missing.mbtiles"),
+            })
+            .to_request();
+        let response = call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(sources.get_source("cities").is_err());
+
+        let req = TestRequest::put()
+            .uri("/admin/config/sources/cities")
+            .insert_header(auth.clone())
+            .set_json(&definition)
+            .to_request();
+        let response = call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert!(sources.get_source("cities").is_ok());
+        assert!(catalog.tiles.contains_key("cities"));
+
+        let req = TestRequest::get()
+            .uri("/admin/config/sources/cities")
+            .insert_header(auth.clone())
+            .to_request();
+        let response = call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: AdminSource = read_body_json(response).await;
+        assert_eq!(body, definition);
+
+        let req = TestRequest::delete()
+            .uri("/admin/config/sources/configured")
+            .insert_header(auth.clone())
+            .to_request();
+        let response = call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(sources.get_source("configured").is_err());
+        assert!(!catalog.tiles.contains_key("configured"));
+
+        let req = TestRequest::get()
+            .uri("/admin/config/sources")
+            .insert_header(auth)
+            .to_request();
+        let body: SourceOverrides = read_body_json(call_service(&app, req).await).await;
+        let expected = SourceOverrides {
+            sources: BTreeMap::from([("cities".to_string(), definition)]),
+            removed: BTreeSet::from(["configured".to_string()]),
+        };
+        assert_eq!(body, expected);
+        assert_eq!(SourceOverrides::load(&overrides_file).unwrap(), expected);
+
+        // Overrides are applied to the configured sources on the next startup
+        let restarted = TileSources::new(vec![vec![Box::new(TestSource {
+            id: "configured",
+            tj: tilejson::tilejson! { tiles: vec![] },
+            data: Vec::new(),
+        })]]);
+        expected.apply(&restarted).await.unwrap();
+        assert!(restarted.get_source("configured").is_err());
+        assert!(restarted.get_source("cities").is_ok());
+    }
+}
@@ -0,0 +1,118 @@
+use std::future::Future;
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use dashmap::mapref::entry::Entry;
+use tokio::sync::broadcast;
+
+use crate::MartinError::CoalescedTileError;
+use crate::utils::CacheKey;
+use crate::{MartinError, MartinResult, TileData};
+
+type SharedResult = Result<TileData, Arc<MartinError>>;
+
+/// Tiles being generated, so that the concurrent requests for the same tile of a source
+/// wait for a single call to the source and share its result, instead of each generating the tile
+#[derive(Debug, Default)]
+pub struct InFlightTiles(DashMap<CacheKey, broadcast::Sender<SharedResult>>);
+
+/// Removes the tile from the tiles being generated if the generating request is cancelled,
+/// so that the waiting requests generate it themselves
+struct InFlightGuard<'a> {
+    tiles: &'a InFlightTiles,
+    key: Option<CacheKey>,
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.tiles.0.remove(&key);
+        }
+    }
+}
+
+impl InFlightTiles {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether no tile is being generated
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Get the result of `generate` for the tile `key`, or the result of the concurrent request already generating it.
+    /// The requests waiting for a failed tile get a [`CoalescedTileError`], and the next requests try again.
+    pub async fn get_or_generate(
+        &self,
+        key: CacheKey,
+        generate: impl Future<Output = MartinResult<TileData>>,
+    ) -> MartinResult<TileData> {
+        loop {
+            let mut receiver = match self.0.entry(key.clone()) {
+                Entry::Occupied(entry) => entry.get().subscribe(),
+                Entry::Vacant(entry) => {
+                    entry.insert(broadcast::channel(1).0);
+                    break;
+                }
+            };
+            // Otherwise the generating request was cancelled, and one of the waiting requests takes over
+            if let Ok(result) = receiver.recv().await {
+                return result.map_err(CoalescedTileError);
+            }
+        }
+
+        let mut guard = InFlightGuard {
+            tiles: self,
+            key: Some(key),
+        };
+        let result = generate.await;
+        let Some((_, sender)) = guard.key.take().and_then(|key| self.0.remove(&key)) else {
+            return result;
+        };
+        if sender.receiver_count() == 0 {
+            return result;
+        }
+        let result = result.map_err(Arc::new);
+        // Fails only if all waiting requests were cancelled in the meantime
+        let _ = sender.send(result.clone());
+        result.map_err(CoalescedTileError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use martin_tile_utils::TileCoord;
+
+    use super::*;
+    use crate::MartinError::SourceBusyError;
+
+    #[actix_rt::test]
+    async fn coalesced_error() {
+        let tiles = InFlightTiles::new();
+        let key = CacheKey::Tile("src".to_string(), TileCoord { z: 0, x: 0, y: 0 });
+        let failing = async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            Err(SourceBusyError("src".to_string(), 1))
+        };
+        let waiting = async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            tiles
+                .get_or_generate(key.clone(), async { Ok(vec![1]) })
+                .await
+        };
+        let (first, second) = futures::join!(tiles.get_or_generate(key.clone(), failing), waiting);
+        // The waiting request shares the error instead of generating the tile itself
+        assert!(matches!(first, Err(CoalescedTileError(e)) if matches!(*e, SourceBusyError(..))));
+        assert!(matches!(second, Err(CoalescedTileError(e)) if matches!(*e, SourceBusyError(..))));
+
+        // The error is not kept for the next requests
+        assert!(tiles.is_empty());
+        let third = tiles.get_or_generate(key, async { Ok(vec![1]) }).await;
+        assert_eq!(third.unwrap(), vec![1]);
+    }
+}
@@ -0,0 +1,128 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use serde::Serialize;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+use crate::MartinError::SourceBusyError;
+use crate::MartinResult;
+
+/// The `max_concurrent_requests`, `max_queued_requests`, and `queue_timeout` settings of a source
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ConcurrencyLimit {
+    /// Number of tiles the source may generate at once
+    pub max_concurrent_requests: usize,
+    /// Number of requests waiting for one of the others to finish, unlimited if not set
+    pub max_queued_requests: Option<usize>,
+    /// Milliseconds a request may wait for one of the others to finish, unlimited if not set
+    pub queue_timeout: Option<u64>,
+}
+
+/// Current usage of the concurrency limit of a source
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct ConcurrencyStatus {
+    pub max_concurrent_requests: usize,
+    /// Requests generating a tile
+    pub in_flight: usize,
+    /// Requests waiting for one of the others to finish
+    pub queued: usize,
+}
+
+/// Limits the number of tiles a source generates at once, so that an expensive source
+/// cannot use all connections of a shared pool. The other requests wait in a queue.
+#[derive(Debug)]
+pub struct ConcurrencyLimiter {
+    limit: ConcurrencyLimit,
+    semaphore: Semaphore,
+    in_flight: AtomicUsize,
+    queued: AtomicUsize,
+}
+
+impl PartialEq for ConcurrencyLimiter {
+    fn eq(&self, other: &Self) -> bool {
+        self.limit == other.limit
+    }
+}
+
+/// Allows a request to generate a tile, until it is dropped
+pub struct ConcurrencyPermit<'a> {
+    _permit: SemaphorePermit<'a>,
+    in_flight: &'a AtomicUsize,
+}
+
+impl Drop for ConcurrencyPermit<'_> {
+    fn drop(&mut self) {
+        self.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Counts a request as queued until it is dropped, including when the request is cancelled while waiting
+struct QueuedGuard<'a>(&'a AtomicUsize);
+
+impl Drop for QueuedGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl ConcurrencyLimiter {
+    #[must_use]
+    pub fn new(limit: ConcurrencyLimit) -> Self {
+        Self {
+            limit,
+            semaphore: Semaphore::new(limit.max_concurrent_requests),
+            in_flight: AtomicUsize::new(0),
+            queued: AtomicUsize::new(0),
+        }
+    }
+
+    /// Wait until the source `id` may generate another tile. Fails with [`SourceBusyError`]
+    /// if the queue is full, or if the request waited for longer than the `queue_timeout`.
+    pub async fn acquire(&self, id: &str) -> MartinResult<ConcurrencyPermit<'_>> {
+        let permit = if let Ok(permit) = self.semaphore.try_acquire() {
+            permit
+        } else {
+            let queued = self.queued.fetch_add(1, Ordering::Relaxed);
+            let _guard = QueuedGuard(&self.queued);
+            if self
+                .limit
+                .max_queued_requests
+                .is_some_and(|max| queued >= max)
+            {
+                return Err(self.busy_error(id));
+            }
+            let acquire = self.semaphore.acquire();
+            let permit = match self.limit.queue_timeout {
+                Some(ms) => tokio::time::timeout(Duration::from_millis(ms), acquire)
+                    .await
+                    .map_err(|_| self.busy_error(id))?,
+                None => acquire.await,
+            };
+            // The semaphore is never closed
+            permit.map_err(|_| self.busy_error(id))?
+        };
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        Ok(ConcurrencyPermit {
+            _permit: permit,
+            in_flight: &self.in_flight,
+        })
+    }
+
+    fn busy_error(&self, id: &str) -> crate::MartinError {
+        // Retry once a queued request could have been served
+        let retry_after = self
+            .limit
+            .queue_timeout
+            .map_or(1, |ms| ms.div_ceil(1000).max(1));
+        SourceBusyError(id.to_string(), retry_after)
+    }
+
+    #[must_use]
+    pub fn status(&self) -> ConcurrencyStatus {
+        ConcurrencyStatus {
+            max_concurrent_requests: self.limit.max_concurrent_requests,
+            in_flight: self.in_flight.load(Ordering::Relaxed),
+            queued: self.queued.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -1,22 +1,344 @@
-use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
 
-use crate::args::PreferredEncoding;
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::MartinError::{EmptyTokenError, SrvValueError, UnknownRequestFeature};
+use crate::MartinResult;
+use crate::args::{MvtValidation, PreferredEncoding};
+use crate::srv::static_files::validate_static_files;
+use crate::srv::{CachePeersConfig, CorsConfig, REQUEST_FEATURES, TlsConfig};
+use crate::utils::OptBoolObj;
 
 pub const KEEP_ALIVE_DEFAULT: u64 = 75;
 pub const LISTEN_ADDRESSES_DEFAULT: &str = "0.0.0.0:3000";
+/// Tiles smaller than this many bytes are sent uncompressed, as compressing them costs more than it saves
+pub const COMPRESSION_MIN_SIZE_DEFAULT: usize = 1024;
+/// Clients may keep the catalog, but must check with its `ETag` that it has not changed before using it
+pub const CATALOG_CACHE_CONTROL_DEFAULT: &str = "no-cache";
+/// Features of a tile converted to GeoJSON by the debug endpoint, the others are left out with a warning
+pub const GEOJSON_MAX_FEATURES_DEFAULT: usize = 10_000;
+/// Tiles returned by the sources larger than this many bytes are rejected instead of being sent and cached
+pub const MAX_TILE_SIZE_BYTES_DEFAULT: usize = 10 * 1024 * 1024;
 
 #[serde_with::skip_serializing_none]
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Default)]
 pub struct SrvConfig {
-    pub keep_alive: Option<u64>,
+    /// Connection keep-alive timeout, as seconds or a duration like `75s` or `2m`, or `off` to disable keep-alive.
+    /// Defaults to `75` seconds.
+    pub keep_alive: Option<KeepAlive>,
+    /// Seconds to let in-flight requests finish after `SIGTERM` or `Ctrl+C` before closing their connections.
+    /// Defaults to `5`.
+    pub shutdown_timeout: Option<u64>,
+    /// Socket addresses to bind, as a comma-separated list or a YAML sequence
+    #[serde(default, deserialize_with = "deserialize_listen_addresses")]
     pub listen_addresses: Option<String>,
+    /// Serve HTTPS instead of HTTP with this certificate and private key
+    pub tls: Option<TlsConfig>,
     pub base_path: Option<String>,
+    /// Paths of the tile and `TileJSON` routes, for clients that expect another layout
+    pub routes: Option<crate::srv::RoutesConfig>,
+    /// Build the tile URLs of the `TileJSON` from the `Forwarded` and `X-Forwarded-Proto`, `X-Forwarded-Host`,
+    /// and `X-Forwarded-Prefix` headers set by a reverse proxy. Defaults to `true`.
+    pub trust_proxy_headers: Option<bool>,
     pub worker_processes: Option<usize>,
+    /// Maximum number of pending connections waiting to be accepted. Defaults to the actix-web default of `1024`.
+    pub backlog: Option<u32>,
+    /// Maximum number of concurrent connections of each worker. Defaults to the actix-web default of `25000`.
+    pub max_connections: Option<usize>,
+    /// Milliseconds a client has to send the request headers before getting `408 Request Timeout`.
+    /// Defaults to the actix-web default of `5000`.
+    pub client_request_timeout: Option<u64>,
+    /// Milliseconds to wait for a client to close the connection after the response. Defaults to the actix-web default of `1000`.
+    pub client_disconnect_timeout: Option<u64>,
     pub preferred_encoding: Option<PreferredEncoding>,
+    /// Compression of the tiles stored uncompressed in the sources
+    pub compression: Option<CompressionConfig>,
+    /// Reject the tiles returned by the sources larger than this many bytes with `500 Internal Server Error`,
+    /// defaults to [`MAX_TILE_SIZE_BYTES_DEFAULT`]
+    pub max_tile_size_bytes: Option<usize>,
+    /// Experimental features that clients may enable per request with the `X-Martin-Features` header,
+    /// from [`REQUEST_FEATURES`]
+    pub request_features: Option<Vec<String>>,
+    /// Check produced MVT tiles against the MVT specification, and log or reject the invalid ones
+    pub validate_mvt: Option<MvtValidation>,
+    /// Limits of the URL query passed to the function sources, over which tile requests are rejected
+    pub url_query_limits: Option<crate::srv::UrlQueryLimits>,
+    /// `Cache-Control` header of the tile and TileJSON responses of the sources without their own `cache_control` setting
+    pub cache_control: Option<String>,
+    /// `Cache-Control` header of the `/catalog` responses, defaults to [`CATALOG_CACHE_CONTROL_DEFAULT`]
+    pub catalog_cache_control: Option<String>,
+    /// Response for tiles missing from the sources without their own `missing_tile` setting, defaults to `204`
+    pub missing_tile: Option<MissingTile>,
+    /// Format, target, and excluded paths of the line logged for each request, or `enabled: false` to disable it
+    pub access_log: Option<crate::srv::AccessLogConfig>,
+    /// Export of the tile and TileJSON request spans to an OpenTelemetry collector
+    pub tracing: Option<crate::srv::TracingConfig>,
+    /// Directory of static files served along with the tiles, e.g. a map viewer page with its scripts and styles
+    pub static_dir: Option<PathBuf>,
+    /// URL path the `static_dir` files are served at. Defaults to `/static`.
+    pub static_mount: Option<String>,
+    /// Cross-origin requests allowed by the CORS middleware, or `false` to disable it. Defaults to any origin.
+    #[serde(default, skip_serializing_if = "OptBoolObj::is_none")]
+    pub cors: OptBoolObj<CorsConfig>,
+    /// Limit the number of requests of each client IP, responding with `429 Too Many Requests` over the limit
+    pub rate_limit: Option<crate::srv::RateLimitConfig>,
+    /// Add the `Server-Timing` header to the tile responses, with the time spent in the sources and whether the tile
+    /// was cached. Defaults to `false`.
+    pub debug_headers: Option<bool>,
+    /// Serve a JSON description of each tile at `/{source_ids}/{z}/{x}/{y}.json`, with its size, encoding,
+    /// and the feature and vertex counts of its MVT layers, and the features of the MVT tiles as GeoJSON
+    /// at `/{source_ids}/{z}/{x}/{y}.geojson`. Defaults to `false`.
+    pub debug_endpoints: Option<bool>,
+    /// Number of features of a tile returned by the GeoJSON debug endpoint, defaults to [`GEOJSON_MAX_FEATURES_DEFAULT`]
+    pub geojson_max_features: Option<usize>,
+    /// Add the `X-Martin-Version` header with the server version and git commit to all responses
+    pub version_header: Option<bool>,
+    /// Other instances to share cache invalidations and cached tiles with
+    pub cache_peers: Option<CachePeersConfig>,
+    /// Serve an HTML page showing each source on a map at `/{source}/preview`
+    pub preview: Option<crate::srv::PreviewConfig>,
+    /// Allow publishing new `MBTiles` sources with uploads authenticated with the admin token
+    #[cfg(feature = "mbtiles")]
+    pub upload: Option<crate::srv::UploadConfig>,
+    /// Allow adding, replacing, and removing file sources at runtime with an authenticated API
+    #[cfg(any(feature = "mbtiles", feature = "pmtiles", feature = "cog"))]
+    pub admin: Option<crate::srv::AdminConfig>,
     #[cfg(feature = "webui")]
     pub web_ui: Option<crate::args::WebUiMode>,
 }
 
+impl SrvConfig {
+    /// Check that the HTTP server settings are within sane ranges
+    pub fn validate(&self) -> MartinResult<()> {
+        let check = |name, value: Option<u64>, max| match value {
+            Some(v) if v == 0 || v > max => Err(SrvValueError(name, max, v)),
+            _ => Ok(()),
+        };
+        check("backlog", self.backlog.map(u64::from), 65_535)?;
+        check(
+            "max_connections",
+            self.max_connections
+                .map(|v| u64::try_from(v).unwrap_or(u64::MAX)),
+            1_000_000,
+        )?;
+        check(
+            "client_request_timeout",
+            self.client_request_timeout,
+            3_600_000,
+        )?;
+        check(
+            "client_disconnect_timeout",
+            self.client_disconnect_timeout,
+            3_600_000,
+        )?;
+        if let Some(dir) = &self.static_dir {
+            validate_static_files(dir, self.static_mount.as_deref())?;
+        }
+        if self
+            .cache_peers
+            .as_ref()
+            .is_some_and(|v| v.token.is_empty())
+        {
+            return Err(EmptyTokenError("cache_peers.token"));
+        }
+        if let Some(feature) = self
+            .request_features
+            .iter()
+            .flatten()
+            .find(|v| !REQUEST_FEATURES.contains(&v.as_str()))
+        {
+            return Err(UnknownRequestFeature(
+                feature.clone(),
+                REQUEST_FEATURES.join(", "),
+            ));
+        }
+        #[cfg(any(feature = "mbtiles", feature = "pmtiles", feature = "cog"))]
+        if self.admin.as_ref().is_some_and(|v| v.token.is_empty()) {
+            return Err(EmptyTokenError("admin.token"));
+        }
+        #[cfg(feature = "mbtiles")]
+        if self.upload.is_some() && self.admin.is_none() {
+            return Err(crate::MartinError::AdminRequiredError("upload"));
+        }
+        Ok(())
+    }
+
+    /// All socket addresses to bind, defaulting to [`LISTEN_ADDRESSES_DEFAULT`]
+    #[must_use]
+    pub fn get_listen_addresses(&self) -> Vec<String> {
+        self.listen_addresses
+            .as_deref()
+            .unwrap_or(LISTEN_ADDRESSES_DEFAULT)
+            .split(',')
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(ToString::to_string)
+            .collect()
+    }
+}
+
+/// The `listen_addresses` setting as written in the config, either one string or a list of addresses
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ListenAddresses {
+    One(String),
+    Many(Vec<String>),
+}
+
+fn deserialize_listen_addresses<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error> {
+    Ok(
+        Option::<ListenAddresses>::deserialize(deserializer)?.map(|v| match v {
+            ListenAddresses::One(v) => v,
+            ListenAddresses::Many(v) => v.join(","),
+        }),
+    )
+}
+
+/// How long idle connections are kept open for the next request of the client
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "KeepAliveValue", into = "KeepAliveValue")]
+pub enum KeepAlive {
+    /// Close idle connections after this many seconds
+    Timeout(u64),
+    /// Close the connections after each response
+    Disabled,
+}
+
+impl Default for KeepAlive {
+    fn default() -> Self {
+        Self::Timeout(KEEP_ALIVE_DEFAULT)
+    }
+}
+
+impl FromStr for KeepAlive {
+    type Err = String;
+
+    /// Parse seconds like `75`, a duration like `75s`, `2m` or `1h`, or `off` or `disabled`
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        if matches!(value, "off" | "disabled") {
+            return Ok(Self::Disabled);
+        }
+        let (number, multiplier) = match value.as_bytes().last() {
+            Some(b's') => (&value[..value.len() - 1], 1),
+            Some(b'm') => (&value[..value.len() - 1], 60),
+            Some(b'h') => (&value[..value.len() - 1], 3600),
+            _ => (value, 1),
+        };
+        number
+            .parse::<u64>()
+            .ok()
+            .and_then(|v| v.checked_mul(multiplier))
+            .map(Self::Timeout)
+            .ok_or_else(|| {
+                format!(
+                    "Unsupported keep_alive value {value}, expected seconds like 75, a duration like 75s or 2m, or off"
+                )
+            })
+    }
+}
+
+impl From<KeepAlive> for actix_web::http::KeepAlive {
+    fn from(value: KeepAlive) -> Self {
+        match value {
+            KeepAlive::Timeout(secs) => Self::Timeout(Duration::from_secs(secs)),
+            KeepAlive::Disabled => Self::Disabled,
+        }
+    }
+}
+
+/// The `keep_alive` setting as written in the config, e.g. `75`, `2m` or `off`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum KeepAliveValue {
+    Seconds(u64),
+    Duration(String),
+}
+
+impl TryFrom<KeepAliveValue> for KeepAlive {
+    type Error = String;
+
+    fn try_from(value: KeepAliveValue) -> Result<Self, Self::Error> {
+        match value {
+            KeepAliveValue::Seconds(v) => Ok(Self::Timeout(v)),
+            KeepAliveValue::Duration(v) => v.parse(),
+        }
+    }
+}
+
+impl From<KeepAlive> for KeepAliveValue {
+    fn from(value: KeepAlive) -> Self {
+        match value {
+            KeepAlive::Timeout(v) => Self::Seconds(v),
+            KeepAlive::Disabled => Self::Duration("off".to_string()),
+        }
+    }
+}
+
+/// Compression of the tile responses
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CompressionConfig {
+    /// Tiles smaller than this many bytes are sent uncompressed. Tiles stored compressed in the sources are sent as is
+    /// if the client accepts their encoding. Defaults to [`COMPRESSION_MIN_SIZE_DEFAULT`].
+    pub min_size: Option<usize>,
+}
+
+/// Response for a tile that none of the requested sources have
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "MissingTileValue", into = "MissingTileValue")]
+pub enum MissingTile {
+    /// `204 No Content`
+    #[default]
+    NoContent,
+    /// `404 Not Found`
+    NotFound,
+    /// `200 OK` with an empty vector tile or a transparent PNG image,
+    /// or `204 No Content` for the other tile formats
+    Blank,
+}
+
+/// The `missing_tile` setting as written in the config, e.g. `404` or `blank`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum MissingTileValue {
+    Status(u16),
+    Name(String),
+}
+
+impl TryFrom<MissingTileValue> for MissingTile {
+    type Error = String;
+
+    fn try_from(value: MissingTileValue) -> Result<Self, Self::Error> {
+        let value = match value {
+            MissingTileValue::Status(v) => v.to_string(),
+            MissingTileValue::Name(v) => v,
+        };
+        match value.as_str() {
+            "204" => Ok(Self::NoContent),
+            "404" => Ok(Self::NotFound),
+            "blank" => Ok(Self::Blank),
+            _ => Err(format!(
+                "Unsupported missing_tile value {value}, expected 204, 404, or blank"
+            )),
+        }
+    }
+}
+
+impl From<MissingTile> for MissingTileValue {
+    fn from(value: MissingTile) -> Self {
+        match value {
+            MissingTile::NoContent => Self::Status(204),
+            MissingTile::NotFound => Self::Status(404),
+            MissingTile::Blank => Self::Name("blank".to_string()),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use indoc::indoc;
@@ -34,7 +356,7 @@ mod tests {
             "})
             .unwrap(),
             SrvConfig {
-                keep_alive: Some(75),
+                keep_alive: Some(KeepAlive::Timeout(75)),
                 listen_addresses: some("0.0.0.0:3000"),
                 worker_processes: Some(8),
                 ..Default::default()
@@ -49,7 +371,7 @@ mod tests {
             "})
             .unwrap(),
             SrvConfig {
-                keep_alive: Some(75),
+                keep_alive: Some(KeepAlive::Timeout(75)),
                 listen_addresses: some("0.0.0.0:3000"),
                 worker_processes: Some(8),
                 preferred_encoding: Some(PreferredEncoding::Brotli),
@@ -62,15 +384,147 @@ mod tests {
                 listen_addresses: '0.0.0.0:3000'
                 worker_processes: 8
                 preferred_encoding: brotli
+                request_features: [overzoom]
             "})
             .unwrap(),
             SrvConfig {
-                keep_alive: Some(75),
+                keep_alive: Some(KeepAlive::Timeout(75)),
                 listen_addresses: some("0.0.0.0:3000"),
                 worker_processes: Some(8),
                 preferred_encoding: Some(PreferredEncoding::Brotli),
+                request_features: Some(vec!["overzoom".to_string()]),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            serde_yaml::from_str::<SrvConfig>(indoc! {"
+                cache_peers:
+                  peers: ['http://martin-2:3000', 'http://martin-3:3000']
+                  token: secret
+                  fetch_tiles: true
+            "})
+            .unwrap(),
+            SrvConfig {
+                cache_peers: Some(CachePeersConfig {
+                    peers: vec![
+                        "http://martin-2:3000".to_string(),
+                        "http://martin-3:3000".to_string()
+                    ],
+                    token: "secret".to_string(),
+                    fetch_tiles: Some(true),
+                    timeout_ms: None,
+                }),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn parse_server_tuning() {
+        let cfg = serde_yaml::from_str::<SrvConfig>(indoc! {"
+            backlog: 4096
+            max_connections: 10000
+            client_request_timeout: 10000
+            client_disconnect_timeout: 2000
+        "})
+        .unwrap();
+        assert_eq!(
+            cfg,
+            SrvConfig {
+                backlog: Some(4096),
+                max_connections: Some(10000),
+                client_request_timeout: Some(10000),
+                client_disconnect_timeout: Some(2000),
                 ..Default::default()
             }
         );
+        assert!(cfg.validate().is_ok());
+        assert_eq!(
+            serde_yaml::from_str::<SrvConfig>(&serde_yaml::to_string(&cfg).unwrap()).unwrap(),
+            cfg
+        );
+
+        for yaml in [
+            "backlog: 0",
+            "backlog: 100000",
+            "max_connections: 0",
+            "client_request_timeout: 0",
+            "client_disconnect_timeout: 3600001",
+            "request_features: [canonical-mvt]",
+        ] {
+            let cfg = serde_yaml::from_str::<SrvConfig>(yaml).unwrap();
+            let err = cfg.validate().unwrap_err().to_string();
+            assert!(err.contains(yaml.split(':').next().unwrap()), "{err}");
+        }
+    }
+
+    #[test]
+    fn parse_keep_alive() {
+        for (yaml, expected) in [
+            ("keep_alive: 75", KeepAlive::Timeout(75)),
+            ("keep_alive: 75s", KeepAlive::Timeout(75)),
+            ("keep_alive: 2m", KeepAlive::Timeout(120)),
+            ("keep_alive: '30'", KeepAlive::Timeout(30)),
+            ("keep_alive: off", KeepAlive::Disabled),
+            ("keep_alive: disabled", KeepAlive::Disabled),
+        ] {
+            let cfg = serde_yaml::from_str::<SrvConfig>(yaml).unwrap();
+            assert_eq!(cfg.keep_alive, Some(expected), "{yaml}");
+        }
+        for yaml in ["keep_alive: 75x", "keep_alive: 2 minutes", "keep_alive: m"] {
+            let err = serde_yaml::from_str::<SrvConfig>(yaml).unwrap_err();
+            let value = yaml.trim_start_matches("keep_alive: ");
+            assert!(err.to_string().contains(value), "{yaml}: {err}");
+        }
+
+        // Timeouts are saved as seconds, to stay readable by older versions
+        let cfg = SrvConfig {
+            keep_alive: Some(KeepAlive::Timeout(120)),
+            ..Default::default()
+        };
+        assert_eq!(serde_yaml::to_string(&cfg).unwrap(), "keep_alive: 120\n");
+        let cfg = SrvConfig {
+            keep_alive: Some(KeepAlive::Disabled),
+            ..Default::default()
+        };
+        let yaml = serde_yaml::to_string(&cfg).unwrap();
+        assert_eq!(yaml, "keep_alive: off\n");
+        assert_eq!(serde_yaml::from_str::<SrvConfig>(&yaml).unwrap(), cfg);
+    }
+
+    #[test]
+    fn parse_listen_addresses() {
+        for yaml in [
+            "listen_addresses: '0.0.0.0:3000, 127.0.0.1:3001'",
+            "listen_addresses: ['0.0.0.0:3000', '127.0.0.1:3001']",
+        ] {
+            let cfg = serde_yaml::from_str::<SrvConfig>(yaml).unwrap();
+            assert_eq!(
+                cfg.get_listen_addresses(),
+                vec!["0.0.0.0:3000", "127.0.0.1:3001"],
+                "{yaml}"
+            );
+        }
+        assert_eq!(
+            SrvConfig::default().get_listen_addresses(),
+            vec![LISTEN_ADDRESSES_DEFAULT]
+        );
+    }
+
+    #[test]
+    fn parse_missing_tile() {
+        for (yaml, expected) in [
+            ("missing_tile: 204", MissingTile::NoContent),
+            ("missing_tile: 404", MissingTile::NotFound),
+            ("missing_tile: '404'", MissingTile::NotFound),
+            ("missing_tile: blank", MissingTile::Blank),
+        ] {
+            let cfg = serde_yaml::from_str::<SrvConfig>(yaml).unwrap();
+            assert_eq!(cfg.missing_tile, Some(expected), "{yaml}");
+            let saved = serde_yaml::to_string(&cfg).unwrap();
+            assert_eq!(serde_yaml::from_str::<SrvConfig>(&saved).unwrap(), cfg);
+        }
+        assert!(serde_yaml::from_str::<SrvConfig>("missing_tile: 500").is_err());
+        assert!(serde_yaml::from_str::<SrvConfig>("missing_tile: empty").is_err());
     }
 }
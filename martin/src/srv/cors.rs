@@ -0,0 +1,308 @@
+use actix_cors::Cors;
+use actix_web::http::Method;
+use actix_web::http::header::HeaderName;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::MartinError::{CorsCredentialsError, CorsHeaderError, CorsMethodError, CorsOriginError};
+use crate::MartinResult;
+use crate::srv::{FEATURES_HEADER, TIMEOUT_HEADER, VERSION_HEADER};
+use crate::utils::OptBoolObj;
+
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CorsConfig {
+    /// Origins allowed to make cross-origin requests, e.g. `https://maps.example.com`. Defaults to any origin.
+    pub origins: Option<Vec<String>>,
+    /// Allow cross-origin requests with credentials like cookies. Requires `origins`. Defaults to `false`.
+    pub allow_credentials: Option<bool>,
+    /// Methods allowed in cross-origin requests. Defaults to `GET`, `HEAD`, and `OPTIONS`.
+    pub allowed_methods: Option<Vec<String>>,
+    /// Request headers allowed in cross-origin requests, e.g. `Authorization`, in addition to the Martin headers
+    pub allowed_headers: Option<Vec<String>>,
+    /// Response headers the browser apps may read, e.g. `ETag` or `Server-Timing`, in addition to the Martin headers
+    pub expose_headers: Option<Vec<String>>,
+    /// Seconds the browsers may cache the response to a preflight request. Not sent by default.
+    pub max_age: Option<usize>,
+}
+
+pub const CORS_ALLOWED_METHODS_DEFAULT: [&str; 3] = ["GET", "HEAD", "OPTIONS"];
+
+impl CorsConfig {
+    /// Check the configured origins, which `actix-cors` would otherwise only reject by panicking
+    pub fn validate(&self) -> MartinResult<()> {
+        let is_valid = |origin: &str| {
+            Url::parse(origin).is_ok_and(|url| {
+                url.origin().is_tuple() && url.origin().ascii_serialization() == origin
+            })
+        };
+        if let Some(origin) = self.origins.iter().flatten().find(|v| !is_valid(v)) {
+            return Err(CorsOriginError(origin.clone()));
+        }
+        if self.allow_credentials.unwrap_or_default() && self.origins.is_none() {
+            return Err(CorsCredentialsError);
+        }
+        let methods = self.allowed_methods.iter().flatten();
+        if let Some(method) = methods
+            .clone()
+            .find(|v| Method::from_bytes(v.as_bytes()).is_err())
+        {
+            return Err(CorsMethodError(method.clone()));
+        }
+        let headers = self
+            .allowed_headers
+            .iter()
+            .chain(self.expose_headers.iter())
+            .flatten();
+        if let Some(header) = headers
+            .clone()
+            .find(|v| HeaderName::from_bytes(v.as_bytes()).is_err())
+        {
+            return Err(CorsHeaderError(header.clone()));
+        }
+        Ok(())
+    }
+
+    #[must_use]
+    pub fn make_middleware(&self) -> Cors {
+        let mut cors = match &self.origins {
+            Some(origins) => origins
+                .iter()
+                .fold(Cors::default(), |cors, origin| cors.allowed_origin(origin)),
+            None => Cors::default().allow_any_origin(),
+        };
+        if self.allow_credentials.unwrap_or_default() {
+            cors = cors.supports_credentials();
+        }
+        cors = match &self.allowed_methods {
+            Some(methods) => cors.allowed_methods(methods.iter().map(String::as_str)),
+            None => cors.allowed_methods(CORS_ALLOWED_METHODS_DEFAULT),
+        };
+        cors = self
+            .allowed_headers
+            .iter()
+            .flatten()
+            .fold(cors, |cors, header| cors.allowed_header(header.as_str()));
+        if let Some(max_age) = self.max_age {
+            cors = cors.max_age(max_age);
+        }
+        let expose_headers = self.expose_headers.iter().flatten().map(String::as_str);
+        // Preflight requests are answered by the middleware itself, without reaching the routes
+        cors.allowed_header(FEATURES_HEADER)
+            .allowed_header(TIMEOUT_HEADER)
+            .expose_headers(expose_headers.chain([FEATURES_HEADER, VERSION_HEADER]))
+    }
+}
+
+/// Create the CORS middleware, or `None` if CORS is disabled with `cors: false`
+#[must_use]
+pub fn new_cors_middleware(config: &OptBoolObj<CorsConfig>) -> Option<Cors> {
+    match config {
+        OptBoolObj::Bool(false) => None,
+        OptBoolObj::Object(cfg) => Some(cfg.make_middleware()),
+        OptBoolObj::NoValue | OptBoolObj::Bool(true) => {
+            Some(CorsConfig::default().make_middleware())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::http::header::{
+        ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_HEADERS,
+        ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_EXPOSE_HEADERS,
+        ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_HEADERS, ACCESS_CONTROL_REQUEST_METHOD,
+        ORIGIN,
+    };
+    use actix_web::test::{TestRequest, call_service, init_service};
+    use actix_web::{App, HttpResponse, web};
+
+    use super::*;
+
+    #[test]
+    fn validate_origins() {
+        let cfg = |origins: &[&str], allow_credentials| CorsConfig {
+            origins: Some(origins.iter().map(ToString::to_string).collect()),
+            allow_credentials: Some(allow_credentials),
+            ..Default::default()
+        };
+        assert!(
+            cfg(&["https://maps.example.com", "http://localhost:8080"], true)
+                .validate()
+                .is_ok()
+        );
+        for origin in [
+            "*",
+            "maps.example.com",
+            "https://maps.example.com/",
+            "https://",
+        ] {
+            assert!(cfg(&[origin], false).validate().is_err(), "{origin}");
+        }
+        let any_origin = CorsConfig {
+            allow_credentials: Some(true),
+            ..Default::default()
+        };
+        assert!(any_origin.validate().is_err());
+
+        let methods = CorsConfig {
+            allowed_methods: Some(vec!["GET".to_string(), "BAD METHOD".to_string()]),
+            ..Default::default()
+        };
+        assert!(methods.validate().is_err());
+        let headers = CorsConfig {
+            allowed_headers: Some(vec!["Authorization".to_string(), "bad:header".to_string()]),
+            ..Default::default()
+        };
+        assert!(headers.validate().is_err());
+        let expose_headers = CorsConfig {
+            expose_headers: Some(vec!["ETag".to_string(), "bad header".to_string()]),
+            ..Default::default()
+        };
+        assert!(expose_headers.validate().is_err());
+    }
+
+    #[actix_rt::test]
+    async fn allowed_origins() {
+        let cors_cfg = OptBoolObj::Object(CorsConfig {
+            origins: Some(vec!["https://maps.example.com".to_string()]),
+            ..Default::default()
+        });
+        for (config, origin, expected) in [
+            (
+                OptBoolObj::NoValue,
+                "https://other.com",
+                Some("https://other.com"),
+            ),
+            (
+                cors_cfg.clone(),
+                "https://maps.example.com",
+                Some("https://maps.example.com"),
+            ),
+            (cors_cfg, "https://other.com", None),
+            (OptBoolObj::Bool(false), "https://maps.example.com", None),
+        ] {
+            let cors = new_cors_middleware(&config);
+            let app = init_service(
+                App::new()
+                    .wrap(actix_web::middleware::Condition::new(
+                        cors.is_some(),
+                        cors.unwrap_or_default(),
+                    ))
+                    .route("/", web::get().to(HttpResponse::Ok)),
+            )
+            .await;
+            let req = TestRequest::get()
+                .uri("/")
+                .insert_header((ORIGIN, origin))
+                .to_request();
+            let response = call_service(&app, req).await;
+            let header = response.headers().get(ACCESS_CONTROL_ALLOW_ORIGIN);
+            assert_eq!(
+                header.map(|v| v.to_str().unwrap()),
+                expected,
+                "{config:?} {origin}"
+            );
+        }
+    }
+
+    #[actix_rt::test]
+    async fn preflight_request() {
+        let cors = new_cors_middleware(&OptBoolObj::Object(CorsConfig {
+            allowed_headers: Some(vec!["Authorization".to_string()]),
+            max_age: Some(3600),
+            ..Default::default()
+        }));
+        // Preflight requests are answered by the middleware, so the route is never reached
+        let app = init_service(App::new().wrap(cors.unwrap()).route(
+            "/{source}/{z}/{x}/{y}",
+            web::route().to(HttpResponse::ImATeapot),
+        ))
+        .await;
+
+        let preflight = |request_headers: &str| {
+            TestRequest::default()
+                .method(Method::OPTIONS)
+                .uri("/points/0/0/0")
+                .insert_header((ORIGIN, "https://maps.example.com"))
+                .insert_header((ACCESS_CONTROL_REQUEST_METHOD, "GET"))
+                .insert_header((ACCESS_CONTROL_REQUEST_HEADERS, request_headers))
+                .to_request()
+        };
+        let response = call_service(&app, preflight("authorization, x-martin-features")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .map(|v| v.to_str().unwrap().to_lowercase())
+                .unwrap_or_default()
+        };
+        assert_eq!(
+            header(ACCESS_CONTROL_ALLOW_ORIGIN),
+            "https://maps.example.com"
+        );
+        assert_eq!(header(ACCESS_CONTROL_MAX_AGE), "3600");
+        for method in ["get", "head", "options"] {
+            assert!(header(ACCESS_CONTROL_ALLOW_METHODS).contains(method));
+        }
+        for name in ["authorization", "x-martin-features", "x-martin-timeout"] {
+            assert!(header(ACCESS_CONTROL_ALLOW_HEADERS).contains(name));
+        }
+
+        let response = call_service(&app, preflight("x-other")).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn credentials_and_exposed_headers() {
+        let get = async |config: CorsConfig| {
+            let cors = new_cors_middleware(&OptBoolObj::Object(config));
+            let app = init_service(
+                App::new()
+                    .wrap(cors.unwrap())
+                    .route("/", web::get().to(HttpResponse::Ok)),
+            )
+            .await;
+            let req = TestRequest::get()
+                .uri("/")
+                .insert_header((ORIGIN, "https://maps.example.com"))
+                .to_request();
+            call_service(&app, req).await
+        };
+
+        let response = get(CorsConfig {
+            origins: Some(vec!["https://maps.example.com".to_string()]),
+            allow_credentials: Some(true),
+            expose_headers: Some(vec!["ETag".to_string(), "Server-Timing".to_string()]),
+            ..Default::default()
+        })
+        .await;
+        let headers = response.headers();
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+        let exposed = headers[ACCESS_CONTROL_EXPOSE_HEADERS]
+            .to_str()
+            .unwrap()
+            .to_lowercase();
+        for name in [
+            "etag",
+            "server-timing",
+            "x-martin-features",
+            "x-martin-version",
+        ] {
+            assert!(exposed.contains(name), "{exposed}");
+        }
+
+        // Only the Martin headers are exposed by default, and credentials are not allowed
+        let response = get(CorsConfig::default()).await;
+        let headers = response.headers();
+        assert!(headers.get(ACCESS_CONTROL_ALLOW_CREDENTIALS).is_none());
+        let exposed = headers[ACCESS_CONTROL_EXPOSE_HEADERS]
+            .to_str()
+            .unwrap()
+            .to_lowercase();
+        assert!(!exposed.contains("etag"), "{exposed}");
+        assert!(exposed.contains("x-martin-version"), "{exposed}");
+    }
+}
@@ -0,0 +1,135 @@
+use actix_web::error::ErrorNotFound;
+use actix_web::http::header::Accept;
+use actix_web::web::{Data, Path};
+use actix_web::{HttpMessage, HttpRequest, HttpResponse, Result as ActixResult, mime, route};
+use serde::Deserialize;
+
+use crate::source::TileSources;
+
+#[derive(Deserialize)]
+pub struct LegendRequest {
+    pub source_id: String,
+}
+
+/// Serve the `legend` of a single source, taken from its TileJSON.
+///
+/// Legends are either HTML fragments (e.g. `MBTiles` `legend` metadata),
+/// or JSON values (e.g. configured for `PostgreSQL` sources).
+/// HTML legends are returned as `text/html` unless the client prefers JSON,
+/// in which case the HTML is returned as a JSON string.
+/// JSON legends are always returned as `application/json`.
+#[route("/{source_id}/legend", method = "GET", method = "HEAD")]
+#[allow(clippy::unused_async)]
+async fn get_source_legend(
+    req: HttpRequest,
+    path: Path<LegendRequest>,
+    sources: Data<TileSources>,
+) -> ActixResult<HttpResponse> {
+    let src = sources.get_source(&path.source_id)?;
+    let Some(legend) = &src.get_tilejson().legend else {
+        return Err(ErrorNotFound(format!(
+            "Source {} has no legend",
+            path.source_id
+        )));
+    };
+
+    Ok(match serde_json::from_str::<serde_json::Value>(legend) {
+        Ok(value) if value.is_object() || value.is_array() => HttpResponse::Ok().json(value),
+        _ if prefers_html(&req) => HttpResponse::Ok()
+            .content_type(mime::TEXT_HTML_UTF_8)
+            .body(legend.clone()),
+        _ => HttpResponse::Ok().json(legend),
+    })
+}
+
+/// Returns true if the `Accept` header ranks `text/html` above `application/json`.
+fn prefers_html(req: &HttpRequest) -> bool {
+    req.get_header::<Accept>()
+        .and_then(|accept| {
+            accept
+                .ranked()
+                .into_iter()
+                .find_map(|m| match (m.type_(), m.subtype()) {
+                    (mime::TEXT, mime::HTML) => Some(true),
+                    (mime::APPLICATION, mime::JSON) => Some(false),
+                    _ => None,
+                })
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::header::{ACCEPT, CONTENT_TYPE};
+    use actix_web::test::{TestRequest, call_service, init_service, read_body};
+    use actix_web::{App, http};
+    use tilejson::tilejson;
+
+    use super::*;
+    use crate::srv::server::tests::TestSource;
+
+    async fn get_legend(legend: Option<&str>, accept: &str) -> (http::StatusCode, String, String) {
+        let mut tj = tilejson! { tiles: vec![] };
+        tj.legend = legend.map(ToString::to_string);
+        let sources = TileSources::new(vec![vec![Box::new(TestSource {
+            id: "src",
+            tj,
+            data: Vec::default(),
+        })]]);
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(sources))
+                .service(get_source_legend),
+        )
+        .await;
+
+        let req = TestRequest::get()
+            .uri("/src/legend")
+            .insert_header((ACCEPT, accept))
+            .to_request();
+        let response = call_service(&app, req).await;
+        let status = response.status();
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .map(|v| v.to_str().unwrap().to_string())
+            .unwrap_or_default();
+        let body = read_body(response).await;
+        (
+            status,
+            content_type,
+            String::from_utf8(body.to_vec()).unwrap(),
+        )
+    }
+
+    #[actix_rt::test]
+    async fn legend_html() {
+        let html = "<div>legend</div>";
+        let (status, ct, body) = get_legend(Some(html), "text/html,*/*;q=0.8").await;
+        assert!(status.is_success());
+        assert_eq!(ct, "text/html; charset=utf-8");
+        assert_eq!(body, html);
+
+        let (status, ct, body) = get_legend(Some(html), "application/json").await;
+        assert!(status.is_success());
+        assert_eq!(ct, "application/json");
+        assert_eq!(body, r#""<div>legend</div>""#);
+    }
+
+    #[actix_rt::test]
+    async fn legend_json() {
+        let json = r##"{"items":[{"color":"#f00","label":"Roads"}]}"##;
+        for accept in ["text/html", "application/json", "*/*"] {
+            let (status, ct, body) = get_legend(Some(json), accept).await;
+            assert!(status.is_success());
+            assert_eq!(ct, "application/json");
+            assert_eq!(body, json);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn legend_missing() {
+        let (status, _, _) = get_legend(None, "*/*").await;
+        assert_eq!(status, http::StatusCode::NOT_FOUND);
+    }
+}
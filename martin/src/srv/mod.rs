@@ -1,17 +1,97 @@
+#[cfg(any(feature = "mbtiles", feature = "pmtiles", feature = "cog"))]
+mod admin;
+#[cfg(any(feature = "mbtiles", feature = "pmtiles", feature = "cog"))]
+pub use admin::{AdminConfig, AdminSource, SourceOverrides};
+
+mod access_log;
+pub use access_log::{ACCESS_LOG_FORMAT_DEFAULT, ACCESS_LOG_TARGET_DEFAULT, AccessLogConfig};
+
+mod coalesce;
+pub use coalesce::InFlightTiles;
+
+mod concurrency;
+pub use concurrency::{ConcurrencyLimit, ConcurrencyLimiter, ConcurrencyPermit, ConcurrencyStatus};
+
+mod cors;
+pub use cors::{CORS_ALLOWED_METHODS_DEFAULT, CorsConfig, new_cors_middleware};
+
 mod config;
-pub use config::{KEEP_ALIVE_DEFAULT, LISTEN_ADDRESSES_DEFAULT, SrvConfig};
+pub use config::{
+    CATALOG_CACHE_CONTROL_DEFAULT, COMPRESSION_MIN_SIZE_DEFAULT, CompressionConfig,
+    GEOJSON_MAX_FEATURES_DEFAULT, KEEP_ALIVE_DEFAULT, KeepAlive, LISTEN_ADDRESSES_DEFAULT,
+    MAX_TILE_SIZE_BYTES_DEFAULT, MissingTile, SrvConfig,
+};
 
 #[cfg(feature = "fonts")]
 mod fonts;
 
+mod legend;
+
+mod peers;
+pub use peers::{CachePeers, CachePeersConfig, InvalidateRequest, PEER_HEADER};
+
+mod preview;
+pub use preview::{MAPLIBRE_URL_DEFAULT, PreviewConfig};
+
+mod profile;
+pub use profile::{ProfileReport, StageTiming, profile_tile};
+
+mod rate_limit;
+pub use rate_limit::{RATE_LIMIT_PERIOD_SECS_DEFAULT, RateLimitConfig};
+
+mod reload;
+#[cfg(unix)]
+pub use reload::reload_on_sighup;
+pub use reload::{ReloadStatus, ReloadSummary, SourceReloader};
+
+mod routes;
+pub use routes::{RoutesConfig, TILE_ROUTE_DEFAULT, TILEJSON_ROUTE_DEFAULT};
+
 mod server;
-pub use server::{Catalog, RESERVED_KEYWORDS, new_server, router};
+pub use server::{Catalog, MissingTileFile, RESERVED_KEYWORDS, SourceSettings, new_server, router};
+
+mod shutdown;
+pub use shutdown::SHUTDOWN_TIMEOUT_DEFAULT;
+
+mod static_files;
+pub use static_files::STATIC_MOUNT_DEFAULT;
+
+mod status;
+pub use status::{ServerStatus, init_start_time};
+
+mod telemetry;
+pub use telemetry::{OTLP_SERVICE_NAME_DEFAULT, TracingConfig, TracingGuard, init_logging};
+
+mod tls;
+pub use tls::TlsConfig;
 
 mod tiles;
-pub use tiles::{DynTileSource, TileRequest};
+pub use tiles::{
+    DynTileSource, FEATURES_HEADER, OVERZOOM_FEATURE, REQUEST_FEATURES, SERVER_TIMING_HEADER,
+    TIMEOUT_HEADER, TileRequest, TileTiming, get_request_features,
+};
+
+#[cfg(feature = "mbtiles")]
+mod upload;
+#[cfg(feature = "mbtiles")]
+pub use upload::{UPLOAD_MAX_SIZE_MB_DEFAULT, UploadConfig};
+
+mod url_query;
+pub use url_query::{
+    URL_QUERY_MAX_KEY_LENGTH_DEFAULT, URL_QUERY_MAX_PARAMS_DEFAULT, URL_QUERY_MAX_SIZE_DEFAULT,
+    URL_QUERY_MAX_VALUE_LENGTH_DEFAULT, UrlQueryLimits,
+};
+
+mod version;
+pub use version::{VERSION_HEADER, VersionInfo};
+
+mod tile_geojson;
+
+mod tile_stats;
+pub use tile_stats::{LayerStats, TileStats};
 
 mod tiles_info;
-pub use tiles_info::{SourceIDsRequest, merge_tilejson};
+pub use tiles_info::{EtaggedJson, SourceIDsRequest, merge_tilejson};
 
 #[cfg(feature = "sprites")]
 mod sprites;
@@ -0,0 +1,303 @@
+use std::time::Duration;
+
+use actix_web::error::{ErrorNotFound, ErrorUnauthorized};
+use actix_web::web::{Data, Json, Path};
+use actix_web::{HttpRequest, HttpResponse, Result as ActixResult, route};
+use futures::future::{join_all, select_ok};
+use log::{debug, trace, warn};
+use martin_tile_utils::TileCoord;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::MartinError::{PeerClientError, PeerUrlError};
+use crate::srv::server::{has_bearer_token, map_internal_error};
+use crate::utils::{CacheKey, CacheValue, OptMainCache};
+use crate::{MartinResult, TileData};
+
+/// Request header marking requests sent by a peer. Such requests are never forwarded to other peers.
+pub const PEER_HEADER: &str = "x-martin-peer";
+pub const PEER_TIMEOUT_MS_DEFAULT: u64 = 200;
+
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CachePeersConfig {
+    /// Base URLs of the other Martin instances of the cluster, e.g. `http://martin-2:3000`
+    pub peers: Vec<String>,
+    /// Bearer token shared by all peers, required by the `/_/cache/...` endpoints and sent to the other peers
+    pub token: String,
+    /// Ask the peers for a cached tile before generating it. Defaults to `false`.
+    pub fetch_tiles: Option<bool>,
+    /// Timeout of each request to a peer in milliseconds
+    pub timeout_ms: Option<u64>,
+}
+
+/// Body of the cache invalidation request
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct InvalidateRequest {
+    /// IDs of the sources whose cached tiles are no longer valid
+    pub sources: Vec<String>,
+}
+
+/// Other Martin instances sharing cache invalidations and cached tiles with this one.
+#[derive(Clone, Debug)]
+pub struct CachePeers {
+    client: reqwest::Client,
+    peers: Vec<String>,
+    token: String,
+    fetch_tiles: bool,
+}
+
+impl CachePeers {
+    pub fn new(config: &CachePeersConfig) -> MartinResult<Self> {
+        let peers = config
+            .peers
+            .iter()
+            .map(|peer| match Url::parse(peer) {
+                Ok(url) if matches!(url.scheme(), "http" | "https") => {
+                    Ok(peer.trim_end_matches('/').to_string())
+                }
+                _ => Err(PeerUrlError(peer.clone())),
+            })
+            .collect::<MartinResult<_>>()?;
+        let timeout = config.timeout_ms.unwrap_or(PEER_TIMEOUT_MS_DEFAULT);
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(timeout))
+            .build()
+            .map_err(PeerClientError)?;
+        Ok(Self {
+            client,
+            peers,
+            token: config.token.clone(),
+            fetch_tiles: config.fetch_tiles.unwrap_or_default(),
+        })
+    }
+
+    /// Notify all peers that the cached tiles of the given sources are no longer valid.
+    /// Failures are only logged, so an unreachable peer keeps serving its cached tiles.
+    pub async fn notify_invalidation(&self, request: &InvalidateRequest) {
+        join_all(self.peers.iter().map(|peer| async move {
+            let res = self
+                .client
+                .post(format!("{peer}/_/cache/invalidate"))
+                .bearer_auth(&self.token)
+                .header(PEER_HEADER, "1")
+                .json(request)
+                .send()
+                .await
+                .and_then(reqwest::Response::error_for_status);
+            match res {
+                Ok(_) => debug!("Notified cache peer {peer} of invalidated sources"),
+                Err(e) => warn!("Unable to notify cache peer {peer} of invalidated sources: {e}"),
+            }
+        }))
+        .await;
+    }
+
+    /// Get a tile from the cache of the first peer that has it, without generating it on the peer.
+    pub async fn fetch_tile(&self, source_id: &str, xyz: TileCoord) -> Option<TileData> {
+        if !self.fetch_tiles || self.peers.is_empty() {
+            return None;
+        }
+        let requests = self.peers.iter().map(|peer| {
+            Box::pin(async move {
+                let url = format!(
+                    "{peer}/_/cache/tile/{source_id}/{}/{}/{}",
+                    xyz.z, xyz.x, xyz.y
+                );
+                let response = self
+                    .client
+                    .get(url)
+                    .bearer_auth(&self.token)
+                    .header(PEER_HEADER, "1")
+                    .send()
+                    .await?
+                    .error_for_status()?;
+                Ok::<_, reqwest::Error>(response.bytes().await?.to_vec())
+            })
+        });
+        match select_ok(requests).await {
+            Ok((data, _)) => {
+                trace!("Fetched tile {xyz} of {source_id} from a cache peer");
+                Some(data)
+            }
+            Err(e) => {
+                trace!("No cache peer has tile {xyz} of {source_id}: {e}");
+                None
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct CachedTileRequest {
+    source_id: String,
+    z: u8,
+    x: u32,
+    y: u32,
+}
+
+/// Reject the requests without the token shared by the peers
+fn check_peer_token(req: &HttpRequest, peers: &CachePeers) -> ActixResult<()> {
+    if has_bearer_token(req, &peers.token) {
+        Ok(())
+    } else {
+        Err(ErrorUnauthorized("Invalid or missing cache peers token"))
+    }
+}
+
+/// Invalidate all cached tiles of the given sources, and unless the request came from a peer,
+/// forward the invalidation to all peers.
+#[route("/_/cache/invalidate", method = "POST")]
+async fn post_cache_invalidate(
+    req: HttpRequest,
+    body: Json<InvalidateRequest>,
+    cache: Data<OptMainCache>,
+    peers: Data<CachePeers>,
+) -> ActixResult<HttpResponse> {
+    check_peer_token(&req, &peers)?;
+    let body = body.into_inner();
+    if let Some(cache) = cache.as_ref() {
+        let sources = body.sources.clone();
+        cache
+            .invalidate_entries_if(move |key, _| match key {
+                CacheKey::Tile(id, _) | CacheKey::TileWithQuery(id, _, _) => sources.contains(id),
+                CacheKey::PmtDirectory(..) => false,
+            })
+            .map_err(map_internal_error)?;
+    }
+    if !req.headers().contains_key(PEER_HEADER) {
+        peers.notify_invalidation(&body).await;
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Return a tile only if it is in the cache, as stored by the source. Used by peers.
+#[route("/_/cache/tile/{source_id}/{z}/{x}/{y}", method = "GET")]
+async fn get_cached_tile(
+    req: HttpRequest,
+    path: Path<CachedTileRequest>,
+    cache: Data<OptMainCache>,
+    peers: Data<CachePeers>,
+) -> ActixResult<HttpResponse> {
+    check_peer_token(&req, &peers)?;
+    let xyz = TileCoord {
+        z: path.z,
+        x: path.x,
+        y: path.y,
+    };
+    let key = CacheKey::Tile(path.source_id.clone(), xyz);
+    let value = match cache.as_ref() {
+        Some(cache) => cache.get(&key).await,
+        None => None,
+    };
+    if let Some(CacheValue::Tile(data)) = value {
+        Ok(HttpResponse::Ok()
+            .content_type("application/octet-stream")
+            .body(data))
+    } else {
+        Err(ErrorNotFound(format!(
+            "Tile {xyz} of {} is not cached",
+            path.source_id
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::App;
+    use actix_web::http::StatusCode;
+    use actix_web::http::header::AUTHORIZATION;
+    use actix_web::test::{TestRequest, call_service, init_service, read_body};
+
+    use super::*;
+    use crate::utils::MainCache;
+
+    #[test]
+    fn invalid_peer_url() {
+        let cfg = CachePeersConfig {
+            peers: vec!["martin-2:3000".to_string()],
+            ..Default::default()
+        };
+        assert!(matches!(CachePeers::new(&cfg), Err(PeerUrlError(_))));
+    }
+
+    #[actix_rt::test]
+    async fn invalidate_cached_tiles() {
+        let cache = MainCache::builder().support_invalidation_closures().build();
+        let xyz = TileCoord { z: 1, x: 0, y: 1 };
+        for id in ["src1", "src2"] {
+            cache
+                .insert(
+                    CacheKey::Tile(id.to_string(), xyz),
+                    CacheValue::Tile(id.as_bytes().to_vec()),
+                )
+                .await;
+        }
+        let peers = CachePeers::new(&CachePeersConfig {
+            token: "secret".to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(Some(cache)))
+                .app_data(Data::new(peers))
+                .service(post_cache_invalidate)
+                .service(get_cached_tile),
+        )
+        .await;
+        let auth = (AUTHORIZATION, "Bearer secret");
+
+        // Only the peers with the shared token may read or invalidate the cache
+        for token in [None, Some("Bearer wrong"), Some("Bearer ")] {
+            let mut req = TestRequest::get().uri("/_/cache/tile/src1/1/0/1");
+            if let Some(token) = token {
+                req = req.insert_header((AUTHORIZATION, token));
+            }
+            let response = call_service(&app, req.to_request()).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+        let req = TestRequest::post()
+            .uri("/_/cache/invalidate")
+            .insert_header((PEER_HEADER, "1"))
+            .set_json(InvalidateRequest {
+                sources: vec!["src1".to_string()],
+            })
+            .to_request();
+        let response = call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let req = TestRequest::get()
+            .uri("/_/cache/tile/src1/1/0/1")
+            .insert_header(auth.clone())
+            .to_request();
+        let response = call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(read_body(response).await.as_ref(), b"src1");
+
+        let req = TestRequest::post()
+            .uri("/_/cache/invalidate")
+            .insert_header(auth.clone())
+            .insert_header((PEER_HEADER, "1"))
+            .set_json(InvalidateRequest {
+                sources: vec!["src1".to_string()],
+            })
+            .to_request();
+        let response = call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let req = TestRequest::get()
+            .uri("/_/cache/tile/src1/1/0/1")
+            .insert_header(auth.clone())
+            .to_request();
+        let response = call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let req = TestRequest::get()
+            .uri("/_/cache/tile/src2/1/0/1")
+            .insert_header(auth)
+            .to_request();
+        let response = call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
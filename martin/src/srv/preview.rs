@@ -0,0 +1,265 @@
+use actix_web::error::ErrorBadRequest;
+use actix_web::web::{Data, Path};
+use actix_web::{HttpRequest, HttpResponse, Result as ActixResult, mime, route};
+use martin_tile_utils::Format;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::source::TileSources;
+use crate::srv::SrvConfig;
+use crate::srv::tiles_info::{SourceIDsRequest, get_tilejson_url, merge_tilejson};
+
+pub const MAPLIBRE_URL_DEFAULT: &str = "https://unpkg.com/maplibre-gl@5/dist";
+
+/// Settings of the `/{source}/preview` pages
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PreviewConfig {
+    /// URL of the directory with `maplibre-gl.js` and `maplibre-gl.css`. Defaults to the `unpkg` CDN.
+    pub maplibre_url: Option<String>,
+}
+
+/// Serve an HTML page showing the sources on a map, using their `TileJSON` as seen by the client
+#[route("/{source_ids}/preview", method = "GET", method = "HEAD")]
+#[allow(clippy::unused_async)]
+async fn get_source_preview(
+    req: HttpRequest,
+    path: Path<SourceIDsRequest>,
+    sources: Data<TileSources>,
+    srv_config: Data<SrvConfig>,
+) -> ActixResult<HttpResponse> {
+    let sources = sources.get_sources(&path.source_ids, None)?.0;
+    let tilejson_url = get_tilejson_url(&req, &srv_config, &path.source_ids)?;
+    let style = match sources[0].get_tile_info().format {
+        Format::Mvt => {
+            let layers = merge_tilejson(&sources, String::new())
+                .vector_layers
+                .unwrap_or_default();
+            vector_style(&tilejson_url, layers.iter().map(|v| v.id.as_str()))
+        }
+        Format::Gif | Format::Jpeg | Format::Png | Format::Webp => raster_style(&tilejson_url),
+        Format::Json => {
+            return Err(ErrorBadRequest(format!(
+                "Source {} has JSON tiles, which cannot be previewed",
+                path.source_ids
+            )));
+        }
+    };
+    let maplibre_url = srv_config
+        .preview
+        .as_ref()
+        .and_then(|v| v.maplibre_url.as_deref())
+        .unwrap_or(MAPLIBRE_URL_DEFAULT)
+        .trim_end_matches('/');
+
+    Ok(HttpResponse::Ok()
+        .content_type(mime::TEXT_HTML_UTF_8)
+        .body(preview_page(&path.source_ids, maplibre_url, &style)))
+}
+
+/// Style showing the polygons, lines and points of each vector layer in its own color
+fn vector_style<'a>(tilejson_url: &str, layer_ids: impl Iterator<Item = &'a str>) -> Value {
+    let mut layers = Vec::new();
+    for id in layer_ids {
+        let color = format!("hsl({}, 70%, 50%)", xxh3_64(id.as_bytes()) % 360);
+        let layer = |kind: &str, geometry: &str, paint: Value| {
+            json!({
+                "id": format!("{id}-{kind}"),
+                "type": kind,
+                "source": "preview",
+                "source-layer": id,
+                "filter": ["==", ["geometry-type"], geometry],
+                "paint": paint,
+            })
+        };
+        layers.push(layer(
+            "fill",
+            "Polygon",
+            json!({"fill-color": color, "fill-opacity": 0.3, "fill-outline-color": color}),
+        ));
+        layers.push(layer(
+            "line",
+            "LineString",
+            json!({"line-color": color, "line-width": 1.5}),
+        ));
+        layers.push(layer(
+            "circle",
+            "Point",
+            json!({"circle-color": color, "circle-radius": 3}),
+        ));
+    }
+    json!({
+        "version": 8,
+        "sources": {"preview": {"type": "vector", "url": tilejson_url}},
+        "layers": layers,
+    })
+}
+
+fn raster_style(tilejson_url: &str) -> Value {
+    json!({
+        "version": 8,
+        "sources": {"preview": {"type": "raster", "url": tilejson_url}},
+        "layers": [{"id": "preview", "type": "raster", "source": "preview"}],
+    })
+}
+
+fn preview_page(title: &str, maplibre_url: &str, style: &Value) -> String {
+    // The style is embedded in a script, so it must not be able to close the script element
+    let style = style.to_string().replace("</", "<\\/");
+    let title = title
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;");
+    let maplibre_url = maplibre_url.replace('"', "%22");
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>{title} - Martin preview</title>
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<link rel="stylesheet" href="{maplibre_url}/maplibre-gl.css">
+<script src="{maplibre_url}/maplibre-gl.js"></script>
+<style>body {{ margin: 0; }} #map {{ position: absolute; top: 0; bottom: 0; width: 100%; }}</style>
+</head>
+<body>
+<div id="map"></div>
+<script>
+const map = new maplibregl.Map({{ container: "map", style: {style}, hash: true }});
+map.addControl(new maplibregl.NavigationControl());
+map.once("sourcedata", () => {{
+  const bounds = map.getSource("preview").bounds;
+  if (bounds) map.fitBounds(bounds, {{ animate: false }});
+}});
+</script>
+</body>
+</html>
+"#
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use actix_web::test::{TestRequest, call_service, init_service, read_body};
+    use actix_web::{App, http};
+    use martin_tile_utils::{Encoding, TileInfo};
+    use tilejson::{VectorLayer, tilejson};
+
+    use super::*;
+    use crate::source::{Source, TileInfoSource};
+    use crate::srv::server::tests::TestSource;
+
+    #[derive(Clone, Debug)]
+    struct PngSource(TestSource);
+
+    #[async_trait::async_trait]
+    impl Source for PngSource {
+        fn get_id(&self) -> &str {
+            self.0.get_id()
+        }
+
+        fn get_tilejson(&self) -> &tilejson::TileJSON {
+            self.0.get_tilejson()
+        }
+
+        fn get_tile_info(&self) -> TileInfo {
+            TileInfo::new(Format::Png, Encoding::Internal)
+        }
+
+        fn get_kind(&self) -> crate::source::SourceKind {
+            self.0.get_kind()
+        }
+
+        fn clone_source(&self) -> TileInfoSource {
+            Box::new(self.clone())
+        }
+
+        async fn get_tile(
+            &self,
+            xyz: martin_tile_utils::TileCoord,
+            url_query: Option<&crate::source::UrlQuery>,
+        ) -> crate::MartinResult<crate::source::TileData> {
+            self.0.get_tile(xyz, url_query).await
+        }
+    }
+
+    async fn get_preview(
+        source: TileInfoSource,
+        config: SrvConfig,
+        req: TestRequest,
+    ) -> (http::StatusCode, String) {
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(TileSources::new(vec![vec![source]])))
+                .app_data(Data::new(config))
+                .service(get_source_preview),
+        )
+        .await;
+        let response = call_service(&app, req.uri("/src/preview").to_request()).await;
+        let status = response.status();
+        let body = read_body(response).await;
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    fn test_source() -> TestSource {
+        let mut tj = tilejson! { tiles: vec![] };
+        tj.vector_layers = Some(
+            ["roads", "buildings"]
+                .into_iter()
+                .map(|id| VectorLayer::new(id.to_string(), BTreeMap::new()))
+                .collect(),
+        );
+        TestSource {
+            id: "src",
+            tj,
+            data: Vec::default(),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn vector_preview() {
+        let config = SrvConfig {
+            preview: Some(PreviewConfig::default()),
+            ..Default::default()
+        };
+        let req = TestRequest::get().insert_header(("host", "localhost:3000"));
+        let (status, body) = get_preview(Box::new(test_source()), config, req).await;
+        assert_eq!(status, http::StatusCode::OK);
+        assert!(
+            body.contains(r#""url":"http://localhost:3000/src""#),
+            "{body}"
+        );
+        assert!(body.contains(&format!("{MAPLIBRE_URL_DEFAULT}/maplibre-gl.js")));
+        for id in ["roads-fill", "roads-line", "buildings-circle"] {
+            assert!(body.contains(&format!(r#""id":"{id}""#)), "{body}");
+        }
+        assert!(body.contains(r#""source-layer":"buildings""#));
+    }
+
+    #[actix_rt::test]
+    async fn raster_preview_behind_proxy() {
+        let config = SrvConfig {
+            preview: Some(PreviewConfig {
+                maplibre_url: Some("https://cdn.example.com/maplibre/".to_string()),
+            }),
+            ..Default::default()
+        };
+        let req = TestRequest::get()
+            .insert_header(("host", "localhost:3000"))
+            .insert_header(("x-forwarded-proto", "https"))
+            .insert_header(("x-forwarded-host", "tiles.example.com"))
+            .insert_header(("x-forwarded-prefix", "/martin"));
+        let (status, body) = get_preview(Box::new(PngSource(test_source())), config, req).await;
+        assert_eq!(status, http::StatusCode::OK);
+        assert!(
+            body.contains(r#""url":"https://tiles.example.com/martin/src""#),
+            "{body}"
+        );
+        assert!(body.contains(r#""type":"raster""#));
+        assert!(!body.contains("roads"));
+        assert!(body.contains("https://cdn.example.com/maplibre/maplibre-gl.css"));
+    }
+}
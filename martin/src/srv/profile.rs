@@ -0,0 +1,212 @@
+use std::fmt::{Display, Formatter};
+use std::time::{Duration, Instant};
+
+use martin_tile_utils::{
+    Encoding, TileCoord, decode_brotli, decode_gzip, encode_brotli, encode_gzip,
+};
+
+use crate::MartinResult;
+use crate::source::TileSources;
+use crate::utils::{CacheKey, CacheValue, OptMainCache};
+
+/// Durations of a single stage of the tile pipeline, one per profiling iteration
+#[derive(Clone, Debug, PartialEq)]
+pub struct StageTiming {
+    pub name: &'static str,
+    pub durations: Vec<Duration>,
+}
+
+impl StageTiming {
+    #[must_use]
+    pub fn min(&self) -> Duration {
+        self.durations.iter().min().copied().unwrap_or_default()
+    }
+
+    #[must_use]
+    pub fn max(&self) -> Duration {
+        self.durations.iter().max().copied().unwrap_or_default()
+    }
+
+    #[must_use]
+    pub fn mean(&self) -> Duration {
+        match u32::try_from(self.durations.len()) {
+            Ok(0) | Err(_) => Duration::ZERO,
+            Ok(count) => self.durations.iter().sum::<Duration>() / count,
+        }
+    }
+
+    #[must_use]
+    pub fn median(&self) -> Duration {
+        let mut sorted = self.durations.clone();
+        sorted.sort_unstable();
+        sorted.get(sorted.len() / 2).copied().unwrap_or_default()
+    }
+}
+
+/// Stage-by-stage timing of generating the same tile repeatedly, see [`profile_tile`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProfileReport {
+    pub source_id: String,
+    pub xyz: TileCoord,
+    pub iterations: u32,
+    /// Size of the tile in bytes, as returned by the source
+    pub tile_size: usize,
+    pub stages: Vec<StageTiming>,
+}
+
+impl ProfileReport {
+    fn record(&mut self, name: &'static str, duration: Duration) {
+        if let Some(stage) = self.stages.iter_mut().find(|s| s.name == name) {
+            stage.durations.push(duration);
+        } else {
+            self.stages.push(StageTiming {
+                name,
+                durations: vec![duration],
+            });
+        }
+    }
+}
+
+impl Display for ProfileReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Tile {:#} of {} ({} bytes), {} iterations",
+            self.xyz, self.source_id, self.tile_size, self.iterations
+        )?;
+        writeln!(
+            f,
+            "{:<12}{:>12}{:>12}{:>12}{:>12}",
+            "stage", "min", "median", "mean", "max"
+        )?;
+        for stage in &self.stages {
+            writeln!(
+                f,
+                "{:<12}{:>12}{:>12}{:>12}{:>12}",
+                stage.name,
+                fmt_duration(stage.min()),
+                fmt_duration(stage.median()),
+                fmt_duration(stage.mean()),
+                fmt_duration(stage.max()),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn fmt_duration(duration: Duration) -> String {
+    format!("{duration:.3?}")
+}
+
+/// Generate a single tile of a source `iterations` times, timing each stage of the tile pipeline:
+///
+/// * `query` - getting the tile from the source, i.e. the SQL query for `PostgreSQL` sources,
+///   which includes encoding the features as MVT, or reading the tile from a file
+/// * `decompress` - decoding a tile stored as gzip or brotli, e.g. most `MBTiles` vector tiles
+/// * `gzip`, `brotli` - compressing an uncompressed tile for clients accepting these encodings
+/// * `cache` - storing the tile in the main cache and reading it back, if the cache is enabled
+pub async fn profile_tile(
+    sources: &TileSources,
+    cache: &OptMainCache,
+    source_id: &str,
+    xyz: TileCoord,
+    iterations: u32,
+) -> MartinResult<ProfileReport> {
+    let src = sources.get_source(source_id)?;
+    let info = src.get_tile_info();
+    let mut report = ProfileReport {
+        source_id: source_id.to_string(),
+        xyz,
+        iterations,
+        tile_size: 0,
+        stages: Vec::new(),
+    };
+
+    for _ in 0..iterations {
+        let start = Instant::now();
+        let data = src.get_tile(xyz, None).await?;
+        report.record("query", start.elapsed());
+        report.tile_size = data.len();
+
+        let mut encoding = info.encoding;
+        let uncompressed = match encoding {
+            Encoding::Gzip | Encoding::Brotli => {
+                let start = Instant::now();
+                let decoded = if encoding == Encoding::Gzip {
+                    decode_gzip(&data)?
+                } else {
+                    decode_brotli(&data)?
+                };
+                report.record("decompress", start.elapsed());
+                encoding = Encoding::Uncompressed;
+                decoded
+            }
+            _ => data.clone(),
+        };
+
+        if encoding == Encoding::Uncompressed {
+            let start = Instant::now();
+            encode_gzip(&uncompressed)?;
+            report.record("gzip", start.elapsed());
+
+            let start = Instant::now();
+            encode_brotli(&uncompressed)?;
+            report.record("brotli", start.elapsed());
+        }
+
+        if let Some(cache) = cache {
+            let key = CacheKey::Tile(source_id.to_string(), xyz);
+            let lookup_key = CacheKey::Tile(source_id.to_string(), xyz);
+            let start = Instant::now();
+            cache.insert(key, CacheValue::Tile(data)).await;
+            cache.get(&lookup_key).await;
+            report.record("cache", start.elapsed());
+        }
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use tilejson::tilejson;
+
+    use super::*;
+    use crate::srv::server::tests::TestSource;
+    use crate::utils::MainCache;
+
+    #[actix_rt::test]
+    async fn profile() {
+        let sources = TileSources::new(vec![vec![Box::new(TestSource {
+            id: "src",
+            tj: tilejson! { tiles: vec![] },
+            data: vec![1_u8; 1000],
+        })]]);
+        let xyz = TileCoord { z: 1, x: 0, y: 1 };
+
+        let report = profile_tile(&sources, &None, "src", xyz, 3).await.unwrap();
+        assert_eq!(report.tile_size, 1000);
+        let names: Vec<_> = report.stages.iter().map(|s| s.name).collect();
+        assert_eq!(names, ["query", "gzip", "brotli"]);
+        assert!(report.stages.iter().all(|s| s.durations.len() == 3));
+        assert!(report.stages.iter().all(|s| s.min() <= s.mean()));
+        assert!(report.stages.iter().all(|s| s.median() <= s.max()));
+
+        let cache = Some(MainCache::builder().build());
+        let report = profile_tile(&sources, &cache, "src", xyz, 1).await.unwrap();
+        assert_eq!(report.stages.last().unwrap().name, "cache");
+        assert!(
+            cache
+                .unwrap()
+                .get(&CacheKey::Tile("src".to_string(), xyz))
+                .await
+                .is_some()
+        );
+
+        assert!(
+            profile_tile(&sources, &None, "missing", xyz, 1)
+                .await
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,267 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::RETRY_AFTER;
+use actix_web::middleware::Next;
+use actix_web::web::Data;
+use actix_web::{Error, HttpResponse};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::MartinError::RateLimitError;
+use crate::MartinResult;
+use crate::srv::SrvConfig;
+
+pub const RATE_LIMIT_PERIOD_SECS_DEFAULT: u64 = 60;
+
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    /// Number of requests each client IP may make per period
+    pub requests: u32,
+    /// Length of the period in seconds. Defaults to `60`.
+    pub period_secs: Option<u64>,
+    /// Number of requests each client IP may make at once. Defaults to `requests`.
+    pub burst: Option<u32>,
+}
+
+impl RateLimitConfig {
+    /// Check that the limit allows any requests at all
+    pub fn validate(&self) -> MartinResult<()> {
+        if self.requests == 0 || self.period_secs == Some(0) || self.burst == Some(0) {
+            return Err(RateLimitError);
+        }
+        Ok(())
+    }
+}
+
+/// Requests a client may still make, refilled continuously at the configured rate
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token buckets of all clients, in a sharded map so that clients rarely wait for each other
+pub struct RateLimiter {
+    buckets: DashMap<String, Bucket>,
+    /// Tokens added to each bucket per second
+    rate: f64,
+    capacity: f64,
+    period: Duration,
+    last_cleanup: Mutex<Instant>,
+    /// Clients can send any `Forwarded` header, so it is only used if proxy headers are explicitly trusted
+    trust_proxy_headers: bool,
+    /// Health checks of load balancers and orchestrators are never limited
+    health_path: String,
+}
+
+impl RateLimiter {
+    #[must_use]
+    pub fn new(config: &RateLimitConfig, srv: &SrvConfig) -> Self {
+        let period_secs = config.period_secs.unwrap_or(RATE_LIMIT_PERIOD_SECS_DEFAULT);
+        Self {
+            buckets: DashMap::new(),
+            rate: f64::from(config.requests) / Duration::from_secs(period_secs).as_secs_f64(),
+            capacity: f64::from(config.burst.unwrap_or(config.requests)),
+            period: Duration::from_secs(period_secs),
+            last_cleanup: Mutex::new(Instant::now()),
+            trust_proxy_headers: srv.trust_proxy_headers == Some(true),
+            health_path: format!("{}/health", srv.base_path.as_deref().unwrap_or_default()),
+        }
+    }
+
+    /// Take a token from the bucket of the client,
+    /// or return how long the client has to wait for the next one
+    fn check(&self, client: &str, now: Instant) -> Result<(), Duration> {
+        self.cleanup(now);
+        let mut bucket = self
+            .buckets
+            .entry(client.to_string())
+            .or_insert_with(|| Bucket {
+                tokens: self.capacity,
+                updated: now,
+            });
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.capacity);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate))
+        }
+    }
+
+    /// Once per period, forget the clients whose buckets have been refilled,
+    /// as they are in the same state as the clients that were never seen
+    fn cleanup(&self, now: Instant) {
+        let Ok(mut last_cleanup) = self.last_cleanup.try_lock() else {
+            return;
+        };
+        if now.saturating_duration_since(*last_cleanup) < self.period {
+            return;
+        }
+        *last_cleanup = now;
+        self.buckets.retain(|_, bucket| {
+            let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+            bucket.tokens + elapsed * self.rate < self.capacity
+        });
+    }
+
+    /// IP address of the client, from the `Forwarded` or `X-Forwarded-For` headers if they are trusted
+    fn client_ip(&self, req: &ServiceRequest) -> String {
+        if self.trust_proxy_headers {
+            if let Some(ip) = req.connection_info().realip_remote_addr() {
+                return ip.to_string();
+            }
+        }
+        req.peer_addr()
+            .map(|addr| addr.ip().to_string())
+            .unwrap_or_default()
+    }
+}
+
+/// Middleware rejecting the requests of clients over the rate limit with `429 Too Many Requests`
+pub async fn rate_limit(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    if let Some(limiter) = req.app_data::<Data<RateLimiter>>() {
+        if req.path() != limiter.health_path {
+            let client = limiter.client_ip(&req);
+            if let Err(wait) = limiter.check(&client, Instant::now()) {
+                let retry_after = (wait.as_secs() + u64::from(wait.subsec_nanos() > 0)).max(1);
+                let response = HttpResponse::TooManyRequests()
+                    .insert_header((RETRY_AFTER, retry_after))
+                    .body("Too many requests");
+                return Ok(req.into_response(response).map_into_right_body());
+            }
+        }
+    }
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_left_body)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::middleware::from_fn;
+    use actix_web::test::{TestRequest, call_service, init_service};
+    use actix_web::{App, web};
+
+    use super::*;
+
+    fn limiter(requests: u32, period_secs: u64, burst: u32) -> RateLimiter {
+        let config = RateLimitConfig {
+            requests,
+            period_secs: Some(period_secs),
+            burst: Some(burst),
+        };
+        RateLimiter::new(&config, &SrvConfig::default())
+    }
+
+    #[test]
+    fn validate_config() {
+        let cfg = |requests, period_secs, burst| RateLimitConfig {
+            requests,
+            period_secs,
+            burst,
+        };
+        assert!(cfg(500, Some(60), Some(100)).validate().is_ok());
+        assert!(cfg(500, None, None).validate().is_ok());
+        assert!(cfg(0, None, None).validate().is_err());
+        assert!(cfg(500, Some(0), None).validate().is_err());
+        assert!(cfg(500, None, Some(0)).validate().is_err());
+    }
+
+    #[test]
+    fn burst_over_limit() {
+        let limiter = limiter(60, 60, 5);
+        let now = Instant::now();
+        for _ in 0..5 {
+            assert!(limiter.check("1.2.3.4", now).is_ok());
+        }
+        assert_eq!(limiter.check("1.2.3.4", now), Err(Duration::from_secs(1)));
+        assert!(limiter.check("5.6.7.8", now).is_ok());
+        assert!(
+            limiter
+                .check("1.2.3.4", now + Duration::from_secs(1))
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn slow_client() {
+        let limiter = limiter(60, 60, 1);
+        let start = Instant::now();
+        for i in 0..1000 {
+            let now = start + Duration::from_secs(i);
+            assert!(limiter.check("1.2.3.4", now).is_ok(), "request {i}");
+        }
+        assert_eq!(limiter.buckets.len(), 1);
+        limiter
+            .check("5.6.7.8", start + Duration::from_secs(2000))
+            .unwrap();
+        assert_eq!(limiter.buckets.len(), 1, "refilled buckets are removed");
+    }
+
+    #[actix_rt::test]
+    async fn too_many_requests() {
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(limiter(60, 60, 2)))
+                .wrap(from_fn(rate_limit))
+                .route("/health", web::get().to(HttpResponse::Ok))
+                .route("/catalog", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        let get = |path: &str| {
+            TestRequest::get()
+                .uri(path)
+                .insert_header(("x-forwarded-for", "1.2.3.4"))
+                .to_request()
+        };
+
+        for _ in 0..2 {
+            let response = call_service(&app, get("/catalog")).await;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        let response = call_service(&app, get("/catalog")).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[RETRY_AFTER], "1");
+
+        let response = call_service(&app, get("/health")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn spoofed_forwarded_for() {
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(limiter(60, 60, 2)))
+                .wrap(from_fn(rate_limit))
+                .route("/catalog", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        let peer = "10.0.0.1:12345".parse().unwrap();
+        for i in 0..2 {
+            let req = TestRequest::get()
+                .uri("/catalog")
+                .peer_addr(peer)
+                .insert_header(("x-forwarded-for", format!("1.2.3.{i}")))
+                .to_request();
+            let response = call_service(&app, req).await;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        let req = TestRequest::get()
+            .uri("/catalog")
+            .peer_addr(peer)
+            .insert_header(("x-forwarded-for", "5.6.7.8"))
+            .to_request();
+        let response = call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+}
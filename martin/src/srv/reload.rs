@@ -0,0 +1,224 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use actix_web::web::Data;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::MartinResult;
+use crate::config::Config;
+use crate::source::TileSources;
+use crate::srv::server::Catalog;
+use crate::utils::OptMainCache;
+
+/// IDs of the sources published and removed by a reload
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ReloadSummary {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+/// Outcome of the last reload, reported by the `/-/status` endpoint
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ReloadStatus {
+    pub succeeded: bool,
+    /// Unix time of the end of the reload, in seconds
+    pub time: u64,
+    pub error: Option<String>,
+}
+
+/// Resolves the tile sources of the configuration again, and swaps them into the running server
+pub struct SourceReloader {
+    /// The configuration before resolving, so that auto-discovery runs again
+    config: Config,
+    sources: Data<TileSources>,
+    catalog: Data<Catalog>,
+    cache: OptMainCache,
+    /// Reloads triggered at the same time by a signal and a request run one after another
+    lock: Mutex<()>,
+    last_status: std::sync::Mutex<Option<ReloadStatus>>,
+}
+
+impl SourceReloader {
+    #[must_use]
+    pub fn new(
+        config: Config,
+        sources: Data<TileSources>,
+        catalog: Data<Catalog>,
+        cache: OptMainCache,
+    ) -> Self {
+        Self {
+            config,
+            sources,
+            catalog,
+            cache,
+            lock: Mutex::new(()),
+            last_status: std::sync::Mutex::default(),
+        }
+    }
+
+    /// Outcome of the last reload, or `None` if the sources were not reloaded since the start
+    #[must_use]
+    pub fn last_status(&self) -> Option<ReloadStatus> {
+        self.last_status
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Resolve the sources again, publishing the new ones and removing the ones that no longer exist.
+    /// Requests in flight keep the sources they started with until they finish.
+    pub async fn reload(&self) -> MartinResult<ReloadSummary> {
+        let _guard = self.lock.lock().await;
+        let result = self.reload_sources().await;
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        *self
+            .last_status
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner) = Some(ReloadStatus {
+            succeeded: result.is_ok(),
+            time,
+            error: result.as_ref().err().map(ToString::to_string),
+        });
+        result
+    }
+
+    async fn reload_sources(&self) -> MartinResult<ReloadSummary> {
+        let mut config = self.config.clone();
+        let tiles = config.resolve_tiles(self.cache.clone()).await?;
+        let catalog = tiles.get_catalog();
+
+        let mut added: Vec<String> = catalog
+            .iter()
+            .filter(|v| v.pending.is_none() && !self.sources.contains(v.key()))
+            .map(|v| v.key().clone())
+            .collect();
+        let mut removed = self.sources.replace_all(tiles);
+        self.catalog.set_tiles(catalog);
+        // Sources that were resolved again may return different tiles
+        if let Some(cache) = &self.cache {
+            cache.invalidate_all();
+        }
+
+        added.sort();
+        removed.sort();
+        info!(
+            "Reloaded sources, added: [{}], removed: [{}]",
+            added.join(", "),
+            removed.join(", ")
+        );
+        Ok(ReloadSummary { added, removed })
+    }
+}
+
+/// Reload the sources every time the process receives `SIGHUP`
+#[cfg(unix)]
+pub async fn reload_on_sighup(reloader: Data<SourceReloader>) {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(v) => v,
+        Err(e) => {
+            warn!("Unable to listen for SIGHUP, sources can only be reloaded with the API: {e}");
+            return;
+        }
+    };
+    while hangup.recv().await.is_some() {
+        info!("Received SIGHUP, reloading sources");
+        if let Err(e) = reloader.reload().await {
+            error!("Unable to reload sources: {e}");
+        }
+    }
+}
+
+#[cfg(all(test, feature = "mbtiles"))]
+mod tests {
+    use actix_web::App;
+    use actix_web::http::StatusCode;
+    use actix_web::http::header::{AUTHORIZATION, CACHE_CONTROL, ETAG, IF_NONE_MATCH};
+    use actix_web::test::{TestRequest, call_service, init_service, read_body_json};
+
+    use super::*;
+    use crate::srv::admin::post_reload;
+
+    #[actix_rt::test]
+    async fn reload_sources() {
+        let dir = std::env::temp_dir().join("martin-reload-sources");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let fixture = "../tests/fixtures/mbtiles/world_cities.mbtiles";
+        std::fs::copy(fixture, dir.join("cities.mbtiles")).unwrap();
+
+        let mut config: Config = serde_yaml::from_str(&format!(
+            "admin:\n  token: secret\n  overrides_file: {}\nmbtiles:\n  paths: {}",
+            dir.join("overrides.yaml").display(),
+            dir.display()
+        ))
+        .unwrap();
+        config.finalize().unwrap();
+        let state = config.resolve().await.unwrap();
+        let catalog = Data::new(Catalog::new(&state).unwrap());
+        let sources = Data::new(state.tiles);
+        let reloader =
+            SourceReloader::new(state.config, sources.clone(), catalog.clone(), state.cache);
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(config.srv))
+                .app_data(Data::new(reloader))
+                .app_data(catalog.clone())
+                .service(post_reload)
+                .service(crate::srv::server::get_catalog),
+        )
+        .await;
+        let reload = |token: &str| {
+            TestRequest::post()
+                .uri("/-/reload")
+                .insert_header((AUTHORIZATION, format!("Bearer {token}")))
+                .to_request()
+        };
+        let get_catalog = || TestRequest::get().uri("/catalog");
+        assert!(catalog.tiles.contains_key("cities"));
+        let response = call_service(&app, get_catalog().to_request()).await;
+        assert_eq!(response.headers().get(CACHE_CONTROL).unwrap(), "no-cache");
+        let etag = response.headers().get(ETAG).unwrap().clone();
+
+        // The unchanged catalog is served from memory
+        let response = call_service(&app, get_catalog().to_request()).await;
+        assert_eq!(response.headers().get(ETAG).unwrap(), etag);
+        let req = get_catalog().insert_header((IF_NONE_MATCH, etag.clone()));
+        let response = call_service(&app, req.to_request()).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        let response = call_service(&app, reload("wrong")).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        std::fs::copy(fixture, dir.join("world.mbtiles")).unwrap();
+        let response = call_service(&app, reload("secret")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let summary: ReloadSummary = read_body_json(response).await;
+        assert_eq!(summary.added, vec!["world"]);
+        assert!(summary.removed.is_empty());
+        assert!(catalog.tiles.contains_key("world"));
+        assert!(sources.get_source("world").is_ok());
+
+        // The catalog changed, so the client's copy is no longer valid
+        let req = get_catalog().insert_header((IF_NONE_MATCH, etag.clone()));
+        let response = call_service(&app, req.to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_ne!(response.headers().get(ETAG).unwrap(), etag);
+
+        std::fs::remove_file(dir.join("cities.mbtiles")).unwrap();
+        let response = call_service(&app, reload("secret")).await;
+        let summary: ReloadSummary = read_body_json(response).await;
+        assert!(summary.added.is_empty());
+        assert_eq!(summary.removed, vec!["cities"]);
+        assert!(!catalog.tiles.contains_key("cities"));
+        assert!(sources.get_source("cities").is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,164 @@
+use serde::{Deserialize, Serialize};
+
+use crate::MartinError::RouteTemplateError;
+use crate::MartinResult;
+
+pub const TILE_ROUTE_DEFAULT: &str = "/{source}/{z}/{x}/{y}";
+pub const TILEJSON_ROUTE_DEFAULT: &str = "/{source}";
+
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct RoutesConfig {
+    /// Path of the tiles, with the `{source}`, `{z}`, `{x}`, `{y}` and optional `{format}` placeholders.
+    /// Defaults to `/{source}/{z}/{x}/{y}`.
+    pub tile: Option<String>,
+    /// Path of the `TileJSON` of the sources, with the `{source}` placeholder. Defaults to `/{source}`.
+    pub tilejson: Option<String>,
+}
+
+impl RoutesConfig {
+    /// Check that each template begins with a `/` and has all of its placeholders exactly once
+    pub fn validate(&self) -> MartinResult<()> {
+        if let Some(template) = &self.tile {
+            check_template(
+                "tile",
+                template,
+                &["{source}", "{z}", "{x}", "{y}"],
+                &["{format}"],
+            )?;
+        }
+        if let Some(template) = &self.tilejson {
+            check_template("tilejson", template, &["{source}"], &[])?;
+        }
+        Ok(())
+    }
+
+    /// Path pattern of the tile route, with the placeholders named after the [`TileRequest`](crate::srv::TileRequest) fields
+    #[must_use]
+    pub fn tile_route(&self) -> String {
+        to_route(self.tile.as_deref().unwrap_or(TILE_ROUTE_DEFAULT))
+    }
+
+    /// Path pattern of the `TileJSON` route
+    #[must_use]
+    pub fn tilejson_route(&self) -> String {
+        to_route(self.tilejson.as_deref().unwrap_or(TILEJSON_ROUTE_DEFAULT))
+    }
+
+    /// Path of the `TileJSON` of the sources
+    #[must_use]
+    pub fn tilejson_path(&self, source_ids: &str) -> String {
+        self.tilejson
+            .as_deref()
+            .unwrap_or(TILEJSON_ROUTE_DEFAULT)
+            .replace("{source}", source_ids)
+    }
+
+    /// Path of the tiles of the sources, keeping the `{z}`, `{x}` and `{y}` placeholders for the `TileJSON`.
+    /// Returns `None` with the default template, whose path is derived from the `TileJSON` request instead.
+    #[must_use]
+    pub fn tile_path(&self, source_ids: &str, format: &str) -> Option<String> {
+        let template = self.tile.as_deref()?;
+        Some(
+            template
+                .replace("{source}", source_ids)
+                .replace("{format}", format),
+        )
+    }
+}
+
+fn check_template(
+    route: &'static str,
+    template: &str,
+    required: &[&str],
+    optional: &[&str],
+) -> MartinResult<()> {
+    let mut found: Vec<&str> = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(len) = rest[start..].find('}') else {
+            break;
+        };
+        found.push(&rest[start..=start + len]);
+        rest = &rest[start + len + 1..];
+    }
+    let is_valid = template.starts_with('/')
+        && required
+            .iter()
+            .all(|v| found.iter().filter(|f| *f == v).count() == 1)
+        && optional
+            .iter()
+            .all(|v| found.iter().filter(|f| *f == v).count() <= 1)
+        && found
+            .iter()
+            .all(|v| required.contains(v) || optional.contains(v));
+    if is_valid {
+        Ok(())
+    } else {
+        Err(RouteTemplateError(
+            route,
+            required.join(", "),
+            template.to_string(),
+        ))
+    }
+}
+
+/// Convert a template to an actix path pattern
+fn to_route(template: &str) -> String {
+    template.replace("{source}", "{source_ids}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_templates() {
+        let cfg = |tile: &str, tilejson: &str| RoutesConfig {
+            tile: Some(tile.to_string()),
+            tilejson: Some(tilejson.to_string()),
+        };
+        assert!(RoutesConfig::default().validate().is_ok());
+        assert!(
+            cfg("/tiles/{source}/{z}/{x}/{y}.pbf", "/tilejson/{source}.json")
+                .validate()
+                .is_ok()
+        );
+        assert!(
+            cfg("/{source}/{z}/{x}/{y}.{format}", "/{source}")
+                .validate()
+                .is_ok()
+        );
+        for (tile, tilejson) in [
+            ("/tiles/{source}/{z}/{x}", "/{source}"),
+            ("tiles/{source}/{z}/{x}/{y}", "/{source}"),
+            ("/{source}/{z}/{x}/{y}/{y}", "/{source}"),
+            ("/{source}/{z}/{x}/{y}.{ext}", "/{source}"),
+            ("/{source}/{z}/{x}/{y}", "/tilejson.json"),
+            ("/{source}/{z}/{x}/{y}", "/{source}/{z}"),
+        ] {
+            assert!(cfg(tile, tilejson).validate().is_err(), "{tile} {tilejson}");
+        }
+    }
+
+    #[test]
+    fn routes() {
+        let cfg = RoutesConfig::default();
+        assert_eq!(cfg.tile_route(), "/{source_ids}/{z}/{x}/{y}");
+        assert_eq!(cfg.tilejson_route(), "/{source_ids}");
+        assert_eq!(cfg.tile_path("a,b", "pbf"), None);
+        assert_eq!(cfg.tilejson_path("a,b"), "/a,b");
+
+        let cfg = RoutesConfig {
+            tile: Some("/tiles/{source}/{z}/{x}/{y}.{format}".to_string()),
+            tilejson: Some("/tilejson/{source}.json".to_string()),
+        };
+        assert_eq!(cfg.tile_route(), "/tiles/{source_ids}/{z}/{x}/{y}.{format}");
+        assert_eq!(cfg.tilejson_route(), "/tilejson/{source_ids}.json");
+        assert_eq!(cfg.tilejson_path("a,b"), "/tilejson/a,b.json");
+        assert_eq!(
+            cfg.tile_path("a,b", "pbf").unwrap(),
+            "/tiles/a,b/{z}/{x}/{y}.pbf"
+        );
+    }
+}
@@ -1,29 +1,53 @@
+use std::collections::{BTreeMap, HashMap};
 use std::future::Future;
+use std::path::Path;
 use std::pin::Pin;
+use std::str::FromStr as _;
 use std::string::ToString;
+use std::sync::Arc;
 use std::time::Duration;
 
-use actix_cors::Cors;
 use actix_web::error::ErrorInternalServerError;
-use actix_web::http::header::CACHE_CONTROL;
+use actix_web::http::header::{AUTHORIZATION, CACHE_CONTROL, HeaderMap, HeaderName, HeaderValue};
 use actix_web::middleware::TrailingSlash;
-use actix_web::web::Data;
-use actix_web::{App, HttpResponse, HttpServer, Responder, middleware, route, web};
+use actix_web::web::{Data, Query};
+use actix_web::{
+    App, HttpRequest, HttpResponse, HttpServer, Result as ActixResult, Route, guard, middleware,
+    route, web,
+};
+use dashmap::DashMap;
 use futures::TryFutureExt;
+use itertools::Itertools as _;
 #[cfg(feature = "lambda")]
 use lambda_web::{is_running_on_lambda, run_actix_on_lambda};
-use log::error;
+use log::{error, warn};
+use martin_tile_utils::{Format, TileInfo};
 use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq as _;
 
-use crate::MartinError::BindingError;
+use crate::MartinError::{
+    BindingError, ConcurrencyLimitError, MissingTileFileError, MissingTileFormatError,
+    SourceHeaderError,
+};
 use crate::MartinResult;
 #[cfg(feature = "webui")]
 use crate::args::WebUiMode;
 use crate::config::ServerState;
-use crate::source::TileCatalog;
-use crate::srv::config::{KEEP_ALIVE_DEFAULT, LISTEN_ADDRESSES_DEFAULT, SrvConfig};
+use crate::source::{SourceKind, TileCatalog, TileSources};
+use crate::srv::coalesce::InFlightTiles;
+use crate::srv::concurrency::{ConcurrencyLimit, ConcurrencyLimiter};
+use crate::srv::config::{CATALOG_CACHE_CONTROL_DEFAULT, MissingTile, SrvConfig};
+use crate::srv::cors::new_cors_middleware;
+use crate::srv::legend::get_source_legend;
+use crate::srv::peers::{CachePeers, get_cached_tile, post_cache_invalidate};
+use crate::srv::rate_limit::{RateLimiter, rate_limit};
+use crate::srv::shutdown::{
+    SHUTDOWN_TIMEOUT_DEFAULT, ShuttingDown, close_on_shutdown, stop_on_signal,
+};
 use crate::srv::tiles::get_tile;
-use crate::srv::tiles_info::get_source_info;
+use crate::srv::tiles_info::{EtaggedJson, get_source_info, get_tilejson_url, get_tiles_url};
+use crate::srv::version::{VERSION_HEADER, VersionInfo, get_version};
+use crate::srv::{SourceReloader, TlsConfig};
 
 #[cfg(feature = "webui")]
 mod webui {
@@ -33,21 +57,43 @@ mod webui {
     include!(concat!(env!("OUT_DIR"), "/generated.rs"));
 }
 
+/// Timeout of the connection checks of each source with `/health?deep=true`
+pub const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(3);
+
 /// List of keywords that cannot be used as source IDs. Some of these are reserved for future use.
 /// Reserved keywords must never end in a "dot number" (e.g. ".1").
 /// This list is documented in the `docs/src/using.md` file, which should be kept in sync.
 pub const RESERVED_KEYWORDS: &[&str] = &[
-    "_", "catalog", "config", "font", "health", "help", "index", "manifest", "metrics", "refresh",
-    "reload", "sprite", "status",
+    "-", "_", "admin", "catalog", "config", "font", "health", "help", "index", "manifest",
+    "metrics", "refresh", "reload", "sprite", "status", "version",
 ];
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Catalog {
+    #[serde(serialize_with = "serialize_sorted")]
     pub tiles: TileCatalog,
     #[cfg(feature = "sprites")]
+    #[serde(serialize_with = "serialize_sorted")]
     pub sprites: crate::sprites::SpriteCatalog,
     #[cfg(feature = "fonts")]
+    #[serde(serialize_with = "serialize_sorted")]
     pub fonts: crate::fonts::FontCatalog,
+    /// The `/catalog` responses listing all sources, by the public base URL of their entries.
+    /// Must be cleared with [`Catalog::invalidate_json`] whenever the sources change.
+    #[serde(skip)]
+    json_cache: DashMap<String, EtaggedJson>,
+}
+
+/// Number of public base URLs to keep the `/catalog` response of, as clients may send any `Host` header
+const CATALOG_JSON_CACHE_SIZE: usize = 16;
+
+/// Serialize the entries ordered by ID, so that the same catalog always has the same body and `ETag`
+fn serialize_sorted<V: Serialize, S: serde::Serializer>(
+    map: &DashMap<String, V>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let entries: Vec<_> = map.iter().sorted_by(|a, b| a.key().cmp(b.key())).collect();
+    serializer.collect_map(entries.iter().map(|v| (v.key(), v.value())))
 }
 
 impl Catalog {
@@ -58,8 +104,284 @@ impl Catalog {
             sprites: state.sprites.get_catalog()?,
             #[cfg(feature = "fonts")]
             fonts: state.fonts.get_catalog(),
+            json_cache: DashMap::new(),
         })
     }
+
+    /// Get the serialized catalog of all sources built for the public base URL `key`, building it if needed
+    pub fn get_or_insert_json(
+        &self,
+        key: String,
+        build: impl FnOnce() -> ActixResult<EtaggedJson>,
+    ) -> ActixResult<EtaggedJson> {
+        if let Some(json) = self.json_cache.get(&key) {
+            return Ok(json.clone());
+        }
+        let json = build()?;
+        if self.json_cache.len() < CATALOG_JSON_CACHE_SIZE {
+            self.json_cache.insert(key, json.clone());
+        }
+        Ok(json)
+    }
+
+    /// Replace the entries of the tile sources, e.g. once the sources were resolved again
+    pub fn set_tiles(&self, tiles: TileCatalog) {
+        self.tiles.retain(|id, _| tiles.contains_key(id));
+        for (id, entry) in tiles {
+            self.tiles.insert(id, entry);
+        }
+        self.invalidate_json();
+    }
+
+    /// Forget the serialized catalogs, once the sources were added, replaced, or removed
+    pub fn invalidate_json(&self) {
+        self.json_cache.clear();
+    }
+
+    /// Only keep the sources of the given kind, and the sources whose ID or name contains `text`, ignoring case.
+    /// Sprites and fonts are not tile sources, so they are left out when filtering by kind.
+    #[must_use]
+    pub fn filter(&self, kind: Option<SourceKind>, text: Option<&str>) -> Self {
+        let text = text.map(str::to_lowercase);
+        let matches = |id: &str, name: Option<&str>| {
+            text.as_ref().is_none_or(|text| {
+                id.to_lowercase().contains(text)
+                    || name.is_some_and(|v| v.to_lowercase().contains(text))
+            })
+        };
+        Self {
+            tiles: self
+                .tiles
+                .iter()
+                .filter(|v| kind.is_none_or(|kind| v.kind == Some(kind)))
+                .filter(|v| matches(v.key(), v.name.as_deref()))
+                .map(|v| (v.key().clone(), v.value().clone()))
+                .collect(),
+            #[cfg(feature = "sprites")]
+            sprites: self
+                .sprites
+                .iter()
+                .filter(|v| kind.is_none() && matches(v.key(), None))
+                .map(|v| (v.key().clone(), v.value().clone()))
+                .collect(),
+            #[cfg(feature = "fonts")]
+            fonts: self
+                .fonts
+                .iter()
+                .filter(|v| kind.is_none() && matches(v.key(), None))
+                .map(|v| (v.key().clone(), v.value().clone()))
+                .collect(),
+            json_cache: DashMap::new(),
+        }
+    }
+}
+
+/// Headers that cannot be set with the `headers` setting of a source, as they would break the responses
+pub const DENIED_SOURCE_HEADERS: &[&str] = &[
+    "connection",
+    "content-encoding",
+    "content-length",
+    "transfer-encoding",
+];
+
+/// Settings configured for individual sources, by source ID
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceSettings {
+    /// `Cache-Control` header values of the tile and `TileJSON` responses
+    pub cache_control: HashMap<String, String>,
+    /// Responses for the tiles missing from the sources
+    pub missing_tile: HashMap<String, MissingTile>,
+    /// Milliseconds after which the tile requests fail with `504 Gateway Timeout`
+    pub query_timeout: HashMap<String, u64>,
+    /// Additional headers of the tile and `TileJSON` responses
+    pub headers: HashMap<String, HeaderMap>,
+    /// Images served for the tiles missing from the sources, read on startup
+    pub missing_tile_file: HashMap<String, MissingTileFile>,
+    /// Whether the tiles outside of the bounds of the sources are missing without querying them
+    pub check_bounds: HashMap<String, bool>,
+    /// Whether the sources are left out of the catalog
+    pub hidden: HashMap<String, bool>,
+    /// Limits of the tiles generated at once, shared by the aliases of the sources
+    pub concurrency: HashMap<String, Arc<ConcurrencyLimiter>>,
+}
+
+/// Content of a `missing_tile_file`, served with `200 OK` instead of the missing tiles
+#[derive(Debug, Clone, PartialEq)]
+pub struct MissingTileFile {
+    pub data: Vec<u8>,
+    pub format: Format,
+}
+
+impl SourceSettings {
+    /// Apply the settings of a source to its alias as well
+    pub fn insert_alias(&mut self, alias: &str, target: &str) {
+        if let Some(v) = self.cache_control.get(target).cloned() {
+            self.cache_control.insert(alias.to_string(), v);
+        }
+        if let Some(v) = self.missing_tile.get(target).copied() {
+            self.missing_tile.insert(alias.to_string(), v);
+        }
+        if let Some(v) = self.query_timeout.get(target).copied() {
+            self.query_timeout.insert(alias.to_string(), v);
+        }
+        if let Some(v) = self.headers.get(target).cloned() {
+            self.headers.insert(alias.to_string(), v);
+        }
+        if let Some(v) = self.missing_tile_file.get(target).cloned() {
+            self.missing_tile_file.insert(alias.to_string(), v);
+        }
+        if let Some(v) = self.check_bounds.get(target).copied() {
+            self.check_bounds.insert(alias.to_string(), v);
+        }
+        if let Some(v) = self.hidden.get(target).copied() {
+            self.hidden.insert(alias.to_string(), v);
+        }
+        if let Some(v) = self.concurrency.get(target).cloned() {
+            self.concurrency.insert(alias.to_string(), v);
+        }
+    }
+
+    /// Read the `missing_tile_file` of a source, which must be in a known tile format
+    pub fn insert_missing_tile_file(&mut self, id: String, path: &Path) -> MartinResult<()> {
+        let data =
+            std::fs::read(path).map_err(|e| MissingTileFileError(e, id.clone(), path.into()))?;
+        let Some(info) = TileInfo::detect(&data) else {
+            return Err(MissingTileFormatError(id, path.into()));
+        };
+        self.missing_tile_file.insert(
+            id,
+            MissingTileFile {
+                data,
+                format: info.format,
+            },
+        );
+        Ok(())
+    }
+
+    /// Limit the tiles of a source generated at once, which must be at least one
+    pub fn insert_concurrency_limit(
+        &mut self,
+        id: String,
+        limit: ConcurrencyLimit,
+    ) -> MartinResult<()> {
+        if limit.max_concurrent_requests == 0 {
+            return Err(ConcurrencyLimitError(id));
+        }
+        self.concurrency
+            .insert(id, Arc::new(ConcurrencyLimiter::new(limit)));
+        Ok(())
+    }
+
+    /// Warn about the `missing_tile_file` images in another format than the tiles of their source,
+    /// e.g. a PNG file for a JPEG source
+    pub fn check_missing_tile_files(&self, sources: &TileSources) {
+        for (id, file) in &self.missing_tile_file {
+            let Ok(source) = sources.get_source(id) else {
+                continue;
+            };
+            let format = source.get_tile_info().format;
+            if format != file.format {
+                warn!(
+                    "The missing_tile_file of source {id} is in {} format, but the tiles are in {format} format",
+                    file.format
+                );
+            }
+        }
+    }
+
+    /// Add the `headers` setting of a source, rejecting the invalid headers and the [`DENIED_SOURCE_HEADERS`]
+    pub fn insert_headers(
+        &mut self,
+        id: String,
+        headers: &BTreeMap<String, String>,
+    ) -> MartinResult<()> {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            let header_name = HeaderName::from_str(name)
+                .ok()
+                .filter(|v| !DENIED_SOURCE_HEADERS.contains(&v.as_str()));
+            let (Some(header_name), Ok(value)) = (header_name, HeaderValue::from_str(value)) else {
+                return Err(SourceHeaderError(id, name.clone()));
+            };
+            map.insert(header_name, value);
+        }
+        self.headers.insert(id, map);
+        Ok(())
+    }
+
+    /// The `Cache-Control` header value for the responses of the comma-separated sources,
+    /// if all of them have the same value configured
+    #[must_use]
+    pub fn get_cache_control(&self, source_ids: &str) -> Option<&str> {
+        get_common_value(&self.cache_control, source_ids).map(String::as_str)
+    }
+
+    /// The response for tiles missing from all of the comma-separated sources,
+    /// if all of them have the same value configured
+    #[must_use]
+    pub fn get_missing_tile(&self, source_ids: &str) -> Option<MissingTile> {
+        get_common_value(&self.missing_tile, source_ids).copied()
+    }
+
+    /// The image served for the tiles missing from all of the comma-separated sources,
+    /// if all of them have the same file configured
+    #[must_use]
+    pub fn get_missing_tile_file(&self, source_ids: &str) -> Option<&MissingTileFile> {
+        get_common_value(&self.missing_tile_file, source_ids)
+    }
+
+    /// Whether the tiles outside of the bounds of a source are missing without querying it, which is the default
+    #[must_use]
+    pub fn is_bounds_checked(&self, source_id: &str) -> bool {
+        self.check_bounds.get(source_id).copied().unwrap_or(true)
+    }
+
+    /// Whether a source is left out of the catalog, while still being served. Sources are listed by default.
+    #[must_use]
+    pub fn is_hidden(&self, source_id: &str) -> bool {
+        self.hidden.get(source_id).copied().unwrap_or_default()
+    }
+
+    /// The limit of the tiles of a source generated at once, unlimited by default
+    #[must_use]
+    pub fn get_concurrency_limiter(&self, source_id: &str) -> Option<&ConcurrencyLimiter> {
+        self.concurrency.get(source_id).map(Arc::as_ref)
+    }
+
+    /// The longest time to wait for a tile of the comma-separated sources,
+    /// if all of them have the same value configured
+    #[must_use]
+    pub fn get_query_timeout(&self, source_ids: &str) -> Option<Duration> {
+        get_common_value(&self.query_timeout, source_ids)
+            .copied()
+            .map(Duration::from_millis)
+    }
+
+    /// The additional headers of the responses of the comma-separated sources.
+    /// Unlike the other settings, the headers of each source are added even if the other sources do not have them.
+    pub fn get_headers(
+        &self,
+        source_ids: &str,
+    ) -> impl Iterator<Item = (&HeaderName, &HeaderValue)> {
+        source_ids
+            .split(',')
+            .filter_map(|id| self.headers.get(id))
+            .flatten()
+    }
+}
+
+fn get_common_value<'a, T: PartialEq>(
+    values: &'a HashMap<String, T>,
+    source_ids: &str,
+) -> Option<&'a T> {
+    match source_ids
+        .split(',')
+        .map(|id| values.get(id))
+        .all_equal_value()
+    {
+        Ok(Some(value)) => Some(value),
+        _ => None,
+    }
 }
 
 pub fn map_internal_error<T: std::fmt::Display>(e: T) -> actix_web::Error {
@@ -67,6 +389,27 @@ pub fn map_internal_error<T: std::fmt::Display>(e: T) -> actix_web::Error {
     ErrorInternalServerError(e.to_string())
 }
 
+/// Check that the request has the given token in an `Authorization: Bearer <token>` header.
+/// The tokens are compared in constant time, so that the response time does not reveal how much of the token matched.
+pub(crate) fn has_bearer_token(req: &HttpRequest, token: &str) -> bool {
+    req.headers()
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .is_some_and(|v| !token.is_empty() && bool::from(v.as_bytes().ct_eq(token.as_bytes())))
+}
+
+/// Source IDs published at runtime may be used as file names, so only allow a safe subset of characters
+#[cfg(any(feature = "mbtiles", feature = "pmtiles", feature = "cog"))]
+pub(crate) fn is_valid_source_id(id: &str) -> bool {
+    !id.is_empty()
+        && !id.starts_with('.')
+        && !RESERVED_KEYWORDS.contains(&id)
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
 /// Root path in case web front is disabled.
 #[cfg(not(feature = "webui"))]
 #[route("/", method = "GET", method = "HEAD")]
@@ -88,13 +431,47 @@ async fn get_index_ui_disabled() -> &'static str {
     See documentation https://github.com/maplibre/martin"
 }
 
+#[derive(Deserialize)]
+struct HealthRequest {
+    /// Also check that all sources can reach their database or file
+    #[serde(default)]
+    deep: bool,
+}
+
 /// Return 200 OK if healthy. Used for readiness and liveness probes.
+/// With `?deep=true`, also check the connections of all sources, and return
+/// 503 Service Unavailable with the unhealthy sources if any of them fail.
 #[route("/health", method = "GET", method = "HEAD")]
-#[allow(clippy::unused_async)]
-async fn get_health() -> impl Responder {
-    HttpResponse::Ok()
-        .insert_header((CACHE_CONTROL, "no-cache"))
-        .message_body("OK")
+async fn get_health(query: Query<HealthRequest>, sources: Data<TileSources>) -> HttpResponse {
+    let errors = if query.deep {
+        sources.check_health(HEALTH_CHECK_TIMEOUT).await
+    } else {
+        BTreeMap::new()
+    };
+    let mut response = if errors.is_empty() {
+        HttpResponse::Ok()
+    } else {
+        HttpResponse::ServiceUnavailable()
+    };
+    response.insert_header((CACHE_CONTROL, "no-cache"));
+    if errors.is_empty() {
+        response.body("OK")
+    } else {
+        response.body(
+            errors
+                .iter()
+                .map(|(id, error)| format!("{id}: {error}"))
+                .join("\n"),
+        )
+    }
+}
+
+#[derive(Deserialize)]
+struct CatalogRequest {
+    /// Only list the tile sources of this kind. Unknown kinds are rejected with 400 Bad Request.
+    kind: Option<SourceKind>,
+    /// Only list the sources whose ID or name contains this text
+    q: Option<String>,
 }
 
 #[route(
@@ -104,15 +481,115 @@ async fn get_health() -> impl Responder {
     wrap = "middleware::Compress::default()"
 )]
 #[allow(clippy::unused_async)]
-async fn get_catalog(catalog: Data<Catalog>) -> impl Responder {
-    HttpResponse::Ok().json(catalog)
+pub(crate) async fn get_catalog(
+    req: HttpRequest,
+    catalog: Data<Catalog>,
+    srv_config: Data<SrvConfig>,
+    settings: Option<Data<SourceSettings>>,
+    query: Query<CatalogRequest>,
+) -> ActixResult<HttpResponse> {
+    let mut response = HttpResponse::Ok();
+    response.insert_header((
+        CACHE_CONTROL,
+        srv_config
+            .catalog_cache_control
+            .as_deref()
+            .unwrap_or(CATALOG_CACHE_CONTROL_DEFAULT),
+    ));
+    let build = || {
+        let catalog = catalog.filter(query.kind, query.q.as_deref());
+        // Hidden sources are still served to the clients that know their ID
+        if let Some(settings) = &settings {
+            catalog.tiles.retain(|id, _| !settings.is_hidden(id));
+        }
+        // The URLs depend on the host and the proxy headers of each request
+        for mut entry in catalog.tiles.iter_mut() {
+            // Pending sources have no tiles yet
+            if entry.pending.is_some() {
+                continue;
+            }
+            let id = entry.key().clone();
+            entry.tilejson_url = Some(get_tilejson_url(&req, &srv_config, &id)?);
+            if let Some(format) = entry.format {
+                entry.tiles = Some(get_tiles_url(&req, &srv_config, &id, format)?);
+            }
+        }
+        EtaggedJson::new(&catalog)
+    };
+    // The whole catalog, e.g. polled by the UIs, is only serialized once for each public base URL
+    let json = if query.kind.is_none() && query.q.is_none() {
+        let base_url = get_tilejson_url(&req, &srv_config, "")?;
+        catalog.get_or_insert_json(base_url, build)?
+    } else {
+        build()?
+    };
+    Ok(json.respond_to(&req, response))
+}
+
+/// Route matching both `GET` and `HEAD` requests
+fn get_or_head() -> Route {
+    web::route().guard(guard::Any(guard::Get()).or(guard::Head()))
 }
 
-pub fn router(cfg: &mut web::ServiceConfig, #[allow(unused_variables)] usr_cfg: &SrvConfig) {
+/// Register all routes, under the `base_path` prefix if it is configured
+pub fn router(cfg: &mut web::ServiceConfig, usr_cfg: &SrvConfig) {
+    match usr_cfg.base_path.as_deref() {
+        Some(base_path) if !base_path.is_empty() => {
+            cfg.service(web::scope(base_path).configure(|c| register_routes(c, usr_cfg)));
+        }
+        _ => register_routes(cfg, usr_cfg),
+    }
+}
+
+fn register_routes(cfg: &mut web::ServiceConfig, usr_cfg: &SrvConfig) {
+    // Registered first, so that the source routes do not take the requests of the mount path
+    if let Some(dir) = &usr_cfg.static_dir {
+        cfg.service(crate::srv::static_files::new_static_files(
+            dir,
+            usr_cfg.static_mount.as_deref(),
+        ));
+    }
+
+    if usr_cfg.cache_peers.is_some() {
+        cfg.service(post_cache_invalidate).service(get_cached_tile);
+    }
+
+    if usr_cfg.preview.is_some() {
+        cfg.service(crate::srv::preview::get_source_preview);
+    }
+
+    #[cfg(feature = "mbtiles")]
+    if usr_cfg.upload.is_some() {
+        cfg.service(crate::srv::upload::put_source_upload);
+    }
+
+    #[cfg(any(feature = "mbtiles", feature = "pmtiles", feature = "cog"))]
+    if usr_cfg.admin.is_some() {
+        cfg.service(crate::srv::admin::get_admin_sources)
+            .service(crate::srv::admin::get_admin_source)
+            .service(crate::srv::admin::put_admin_source)
+            .service(crate::srv::admin::delete_admin_source)
+            .service(crate::srv::admin::post_reload);
+    }
+
+    let routes = usr_cfg.routes.clone().unwrap_or_default();
     cfg.service(get_health)
+        .service(get_version)
+        .service(crate::srv::status::get_status)
         .service(get_catalog)
-        .service(get_source_info)
-        .service(get_tile);
+        .service(
+            web::resource(routes.tilejson_route())
+                .wrap(middleware::Compress::default())
+                .route(get_or_head().to(get_source_info)),
+        )
+        .service(get_source_legend);
+
+    // Registered before the tile route, which would otherwise take the `.json` requests
+    if usr_cfg.debug_endpoints.unwrap_or_default() {
+        cfg.service(crate::srv::tile_stats::get_tile_stats)
+            .service(crate::srv::tile_geojson::get_tile_geojson);
+    }
+    cfg.service(web::resource(routes.tile_route()).route(get_or_head().to(get_tile)));
 
     #[cfg(feature = "sprites")]
     cfg.service(crate::srv::sprites::get_sprite_sdf_json)
@@ -143,37 +620,119 @@ pub fn router(cfg: &mut web::ServiceConfig, #[allow(unused_variables)] usr_cfg:
 
 type Server = Pin<Box<dyn Future<Output = MartinResult<()>>>>;
 
-/// Create a future for an Actix web server together with the listening address.
+/// Create a future for an Actix web server together with the comma-separated listening addresses.
 pub fn new_server(config: SrvConfig, state: ServerState) -> MartinResult<(Server, String)> {
-    let catalog = Catalog::new(&state)?;
-
-    let keep_alive = Duration::from_secs(config.keep_alive.unwrap_or(KEEP_ALIVE_DEFAULT));
+    crate::srv::init_start_time();
+    // Sources and catalog are shared by all workers, allowing sources to be published at runtime
+    let catalog = Data::new(Catalog::new(&state)?);
+    let tiles = Data::new(state.tiles);
+    let settings = Data::new(state.settings);
+    let reloader = Data::new(SourceReloader::new(
+        state.config,
+        tiles.clone(),
+        catalog.clone(),
+        state.cache.clone(),
+    ));
+    #[cfg(unix)]
+    actix_web::rt::spawn(crate::srv::reload_on_sighup(reloader.clone()));
+    let peers = config
+        .cache_peers
+        .as_ref()
+        .map(CachePeers::new)
+        .transpose()?;
+    #[cfg(any(feature = "mbtiles", feature = "pmtiles", feature = "cog"))]
+    let overrides = config
+        .admin
+        .as_ref()
+        .map(|cfg| crate::srv::SourceOverrides::load(&cfg.overrides_file))
+        .transpose()?
+        .map(|overrides| Data::new(std::sync::Mutex::new(overrides)));
+    #[cfg(feature = "mbtiles")]
+    let uploads = Data::new(crate::srv::upload::Uploads::default());
+    let rate_limiter = config
+        .rate_limit
+        .as_ref()
+        .map(|cfg| Data::new(RateLimiter::new(cfg, &config)));
+    // Load the certificate before starting, to fail fast if it is not usable
+    let tls = config.tls.as_ref().map(TlsConfig::load).transpose()?;
+
+    let version_header = config.version_header.unwrap_or_default();
+
+    let keep_alive = config.keep_alive.unwrap_or_default();
+    let shutdown_timeout = config.shutdown_timeout.unwrap_or(SHUTDOWN_TIMEOUT_DEFAULT);
+    let shutting_down = Data::new(ShuttingDown::default());
+    // Shared by all workers, so that the concurrent requests for a tile are coalesced regardless of the worker
+    let in_flight = Data::new(InFlightTiles::new());
+    let on_signal = shutting_down.clone();
     let worker_processes = config.worker_processes.unwrap_or_else(num_cpus::get);
-    let listen_addresses = config
-        .listen_addresses
-        .clone()
-        .unwrap_or_else(|| LISTEN_ADDRESSES_DEFAULT.to_string());
+    let listen_addresses = config.get_listen_addresses();
+    let backlog = config.backlog;
+    let max_connections = config.max_connections;
+    let client_request_timeout = config.client_request_timeout;
+    let client_disconnect_timeout = config.client_disconnect_timeout;
 
     let factory = move || {
-        let cors_middleware = Cors::default()
-            .allow_any_origin()
-            .allowed_methods(vec!["GET"]);
+        let cors_middleware = new_cors_middleware(&config.cors);
+        let access_log = config
+            .access_log
+            .clone()
+            .unwrap_or_default()
+            .new_middleware();
 
         let app = App::new()
-            .app_data(Data::new(state.tiles.clone()))
+            .app_data(tiles.clone())
+            .app_data(settings.clone())
+            .app_data(reloader.clone())
+            .app_data(shutting_down.clone())
+            .app_data(in_flight.clone())
             .app_data(Data::new(state.cache.clone()));
 
+        let app = match &peers {
+            Some(peers) => app.app_data(Data::new(peers.clone())),
+            None => app,
+        };
+
+        let app = match &rate_limiter {
+            Some(rate_limiter) => app.app_data(rate_limiter.clone()),
+            None => app,
+        };
+
+        #[cfg(any(feature = "mbtiles", feature = "pmtiles", feature = "cog"))]
+        let app = match &overrides {
+            Some(overrides) => app.app_data(overrides.clone()),
+            None => app,
+        };
+
+        #[cfg(feature = "mbtiles")]
+        let app = app.app_data(uploads.clone());
+
         #[cfg(feature = "sprites")]
         let app = app.app_data(Data::new(state.sprites.clone()));
 
         #[cfg(feature = "fonts")]
         let app = app.app_data(Data::new(state.fonts.clone()));
 
-        app.app_data(Data::new(catalog.clone()))
+        app.app_data(catalog.clone())
             .app_data(Data::new(config.clone()))
-            .wrap(cors_middleware)
+            .wrap(middleware::Condition::new(
+                rate_limiter.is_some(),
+                middleware::from_fn(rate_limit),
+            ))
+            .wrap(middleware::Condition::new(
+                version_header,
+                middleware::DefaultHeaders::new()
+                    .add((VERSION_HEADER, VersionInfo::header_value())),
+            ))
+            .wrap(middleware::Condition::new(
+                cors_middleware.is_some(),
+                cors_middleware.unwrap_or_default(),
+            ))
+            .wrap(middleware::from_fn(close_on_shutdown))
             .wrap(middleware::NormalizePath::new(TrailingSlash::MergeOnly))
-            .wrap(middleware::Logger::default())
+            .wrap(middleware::Condition::new(
+                access_log.is_some(),
+                access_log.unwrap_or_default(),
+            ))
             .configure(|c| router(c, &config))
     };
 
@@ -183,27 +742,216 @@ pub fn new_server(config: SrvConfig, state: ServerState) -> MartinResult<(Server
         return Ok((Box::pin(server), "(aws lambda)".into()));
     }
 
-    let server = HttpServer::new(factory)
-        .bind(listen_addresses.clone())
-        .map_err(|e| BindingError(e, listen_addresses.clone()))?
+    let mut server = HttpServer::new(factory);
+    // The backlog is only applied to the sockets bound after it is set
+    if let Some(backlog) = backlog {
+        server = server.backlog(backlog);
+    }
+    if let Some(max_connections) = max_connections {
+        server = server.max_connections(max_connections);
+    }
+    if let Some(timeout) = client_request_timeout {
+        server = server.client_request_timeout(Duration::from_millis(timeout));
+    }
+    if let Some(timeout) = client_disconnect_timeout {
+        server = server.client_disconnect_timeout(Duration::from_millis(timeout));
+    }
+    for address in &listen_addresses {
+        server = match &tls {
+            Some(tls) => server.bind_rustls_0_23(address, tls.clone()),
+            None => server.bind(address),
+        }
+        .map_err(|e| BindingError(e, address.clone()))?;
+    }
+    let server = server
         .keep_alive(keep_alive)
-        .shutdown_timeout(0)
+        .shutdown_timeout(shutdown_timeout)
+        .disable_signals()
         .workers(worker_processes)
-        .run()
-        .err_into();
-
-    Ok((Box::pin(server), listen_addresses))
+        .run();
+    actix_web::rt::spawn(stop_on_signal(
+        server.handle(),
+        on_signal,
+        worker_processes,
+        shutdown_timeout,
+    ));
+    let server = server.err_into();
+
+    Ok((Box::pin(server), listen_addresses.join(", ")))
 }
 
 #[cfg(test)]
 pub mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test::{TestRequest, call_service, init_service, read_body, read_body_json};
     use async_trait::async_trait;
     use martin_tile_utils::{Encoding, Format, TileCoord, TileInfo};
-    use tilejson::TileJSON;
+    use tilejson::{TileJSON, tilejson};
 
     use super::*;
-    use crate::UrlQuery;
-    use crate::source::{Source, TileData, TileInfoSource};
+    use crate::source::{CatalogSourceEntry, Source, TileData, TileInfoSource};
+    use crate::{MartinError, UrlQuery};
+
+    #[test]
+    #[cfg(any(feature = "mbtiles", feature = "pmtiles", feature = "cog"))]
+    fn source_ids() {
+        assert!(is_valid_source_id("world_cities-2.v1"));
+        for id in ["", ".hidden", "../etc", "a,b", "a/b", "catalog"] {
+            assert!(!is_valid_source_id(id), "{id}");
+        }
+    }
+
+    #[test]
+    fn source_settings() {
+        let settings = SourceSettings {
+            cache_control: HashMap::from([
+                ("a".to_string(), "max-age=60".to_string()),
+                ("b".to_string(), "max-age=60".to_string()),
+                ("c".to_string(), "no-cache".to_string()),
+            ]),
+            missing_tile: HashMap::from([
+                ("a".to_string(), MissingTile::NotFound),
+                ("b".to_string(), MissingTile::NotFound),
+                ("c".to_string(), MissingTile::Blank),
+            ]),
+            query_timeout: HashMap::from([("a".to_string(), 500), ("b".to_string(), 500)]),
+            ..Default::default()
+        };
+        assert_eq!(settings.get_cache_control("a"), Some("max-age=60"));
+        assert_eq!(settings.get_cache_control("a,b"), Some("max-age=60"));
+        assert_eq!(settings.get_cache_control("a,c"), None);
+        assert_eq!(settings.get_cache_control("a,d"), None);
+        assert_eq!(settings.get_cache_control("d"), None);
+        assert_eq!(
+            settings.get_missing_tile("a,b"),
+            Some(MissingTile::NotFound)
+        );
+        assert_eq!(settings.get_missing_tile("c"), Some(MissingTile::Blank));
+        assert_eq!(settings.get_missing_tile("a,c"), None);
+        assert_eq!(settings.get_missing_tile("d"), None);
+        assert_eq!(
+            settings.get_query_timeout("a,b"),
+            Some(Duration::from_millis(500))
+        );
+        assert_eq!(settings.get_query_timeout("a,c"), None);
+    }
+
+    #[actix_rt::test]
+    async fn filter_catalog() {
+        let catalog = Catalog::default();
+        for (id, name, kind) in [
+            ("roads", None, SourceKind::Table),
+            ("buildings", Some("City Buildings"), SourceKind::Table),
+            ("clusters", None, SourceKind::Function),
+            ("world_cities", None, SourceKind::Mbtiles),
+        ] {
+            let entry = CatalogSourceEntry {
+                name: name.map(ToString::to_string),
+                kind: Some(kind),
+                ..Default::default()
+            };
+            catalog.tiles.insert(id.to_string(), entry);
+        }
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(catalog))
+                .app_data(Data::new(SrvConfig::default()))
+                .service(get_catalog),
+        )
+        .await;
+        let get = |path: &str| TestRequest::get().uri(path).to_request();
+
+        for (path, expected) in [
+            (
+                "/catalog",
+                vec!["buildings", "clusters", "roads", "world_cities"],
+            ),
+            ("/catalog?kind=table", vec!["buildings", "roads"]),
+            ("/catalog?kind=mbtiles", vec!["world_cities"]),
+            ("/catalog?kind=pmtiles", vec![]),
+            ("/catalog?q=CIT", vec!["buildings", "world_cities"]),
+            ("/catalog?kind=table&q=cit", vec!["buildings"]),
+        ] {
+            let response = call_service(&app, get(path)).await;
+            assert_eq!(response.status(), StatusCode::OK, "{path}");
+            let body: serde_json::Value = read_body_json(response).await;
+            let tiles = body["tiles"].as_object().unwrap();
+            let mut ids: Vec<&str> = tiles.keys().map(String::as_str).collect();
+            ids.sort_unstable();
+            assert_eq!(ids, expected, "{path}");
+            assert!(tiles.values().all(|v| v.get("kind").is_none()), "{path}");
+        }
+
+        let response = call_service(&app, get("/catalog?kind=geojson")).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn health_check() {
+        let src = |id| TestSource {
+            id,
+            tj: tilejson! { tiles: vec![] },
+            data: Vec::new(),
+        };
+        let sources = Data::new(TileSources::new(vec![vec![Box::new(src("healthy"))]]));
+        let app = init_service(App::new().app_data(sources.clone()).service(get_health)).await;
+        let get = |path| TestRequest::get().uri(path).to_request();
+
+        for path in ["/health", "/health?deep=true"] {
+            let response = call_service(&app, get(path)).await;
+            assert_eq!(response.status(), StatusCode::OK, "{path}");
+            assert_eq!(read_body(response).await, "OK", "{path}");
+        }
+
+        sources.insert(Box::new(UnhealthySource(src("broken"))));
+        let response = call_service(&app, get("/health")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = call_service(&app, get("/health?deep=true")).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            read_body(response).await,
+            "broken: Internal error: database is down"
+        );
+    }
+
+    /// A source whose database cannot be reached
+    #[derive(Debug, Clone)]
+    struct UnhealthySource(TestSource);
+
+    #[async_trait]
+    impl Source for UnhealthySource {
+        fn get_id(&self) -> &str {
+            self.0.id
+        }
+
+        fn get_tilejson(&self) -> &TileJSON {
+            &self.0.tj
+        }
+
+        fn get_tile_info(&self) -> TileInfo {
+            self.0.get_tile_info()
+        }
+
+        fn get_kind(&self) -> SourceKind {
+            self.0.get_kind()
+        }
+
+        fn clone_source(&self) -> TileInfoSource {
+            Box::new(self.clone())
+        }
+
+        async fn check_health(&self) -> MartinResult<()> {
+            Err(MartinError::InternalError("database is down".into()))
+        }
+
+        async fn get_tile(
+            &self,
+            xyz: TileCoord,
+            url_query: Option<&UrlQuery>,
+        ) -> MartinResult<TileData> {
+            self.0.get_tile(xyz, url_query).await
+        }
+    }
 
     #[derive(Debug, Clone)]
     pub struct TestSource {
@@ -226,6 +974,10 @@ pub mod tests {
             TileInfo::new(Format::Mvt, Encoding::Uncompressed)
         }
 
+        fn get_kind(&self) -> SourceKind {
+            SourceKind::Function
+        }
+
         fn clone_source(&self) -> TileInfoSource {
             Box::new(self.clone())
         }
@@ -0,0 +1,86 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use actix_http::ConnectionType;
+use actix_web::Error;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServerHandle, ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::web::Data;
+use log::{info, warn};
+
+pub const SHUTDOWN_TIMEOUT_DEFAULT: u64 = 5;
+
+/// Set once the server has started to shut down
+#[derive(Debug, Default)]
+pub struct ShuttingDown(AtomicBool);
+
+impl ShuttingDown {
+    #[must_use]
+    pub fn is_set(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub fn set(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Middleware closing the keep-alive connections of the requests completed during shutdown,
+/// so that the clients open their next connection to another instance instead of having it reset
+pub async fn close_on_shutdown(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let shutting_down = req.app_data::<Data<ShuttingDown>>().cloned();
+    let mut response = next.call(req).await?;
+    if shutting_down.is_some_and(|v| v.is_set()) {
+        response
+            .response_mut()
+            .head_mut()
+            .set_connection_type(ConnectionType::Close);
+    }
+    Ok(response)
+}
+
+/// Wait for `SIGTERM` or `Ctrl+C`, then stop accepting connections and let the workers finish
+/// their in-flight requests for up to `timeout_secs` seconds before closing the remaining connections
+pub async fn stop_on_signal(
+    server: ServerHandle,
+    shutting_down: Data<ShuttingDown>,
+    workers: usize,
+    timeout_secs: u64,
+) {
+    let signal = match wait_for_signal().await {
+        Ok(v) => v,
+        Err(e) => {
+            warn!(
+                "Unable to listen for shutdown signals, the server must be stopped forcefully: {e}"
+            );
+            return;
+        }
+    };
+    info!(
+        "Received {signal}, shutting down {workers} workers after their in-flight requests, waiting up to {timeout_secs}s"
+    );
+    shutting_down.set();
+    server.stop(true).await;
+    info!("Shutdown complete, {workers} workers have stopped");
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() -> std::io::Result<&'static str> {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    Ok(tokio::select! {
+        _ = terminate.recv() => "SIGTERM",
+        _ = interrupt.recv() => "SIGINT",
+    })
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() -> std::io::Result<&'static str> {
+    tokio::signal::ctrl_c().await?;
+    Ok("Ctrl+C")
+}
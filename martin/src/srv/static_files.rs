@@ -0,0 +1,109 @@
+use std::path::Path;
+
+use actix_files::Files;
+
+use crate::MartinError::{StaticDirError, StaticMountError};
+use crate::MartinResult;
+use crate::srv::RESERVED_KEYWORDS;
+use crate::utils::parse_base_path;
+
+pub const STATIC_MOUNT_DEFAULT: &str = "/static";
+
+/// Check that the static files directory exists, and that its mount path does not shadow the API routes.
+/// Returns the normalized mount path.
+pub fn validate_static_files(dir: &Path, mount: Option<&str>) -> MartinResult<String> {
+    if !dir.is_dir() {
+        return Err(StaticDirError(dir.to_path_buf()));
+    }
+    let mount = mount.unwrap_or(STATIC_MOUNT_DEFAULT);
+    let path = parse_base_path(mount)?;
+    let first = path.split('/').nth(1).unwrap_or_default();
+    if first.is_empty() || RESERVED_KEYWORDS.contains(&first) {
+        return Err(StaticMountError(mount.to_string()));
+    }
+    Ok(path)
+}
+
+/// Serve the files of a directory, e.g. a map viewer, with `index.html` as the directory index.
+/// Paths leading outside of the directory and hidden files are rejected by [`Files`].
+#[must_use]
+pub fn new_static_files(dir: &Path, mount: Option<&str>) -> Files {
+    Files::new(mount.unwrap_or(STATIC_MOUNT_DEFAULT), dir)
+        .index_file("index.html")
+        .redirect_to_slash_directory()
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::App;
+    use actix_web::http::StatusCode;
+    use actix_web::http::header::{CONTENT_TYPE, LOCATION};
+    use actix_web::test::{TestRequest, call_service, init_service, read_body};
+
+    use super::*;
+
+    #[test]
+    fn validate_mount() {
+        let dir = Path::new("../tests/fixtures");
+        assert_eq!(validate_static_files(dir, None).unwrap(), "/static");
+        assert_eq!(validate_static_files(dir, Some("/ui/")).unwrap(), "/ui");
+        assert_eq!(
+            validate_static_files(dir, Some("/maps/viewer")).unwrap(),
+            "/maps/viewer"
+        );
+        for mount in ["/", "ui", "/catalog", "/health/ui", "/_"] {
+            assert!(validate_static_files(dir, Some(mount)).is_err(), "{mount}");
+        }
+        assert!(validate_static_files(Path::new("../tests/no-such-dir"), None).is_err());
+    }
+
+    #[actix_rt::test]
+    async fn serve_static_files() {
+        let dir = std::env::temp_dir().join("martin-static-files");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("js")).unwrap();
+        std::fs::write(dir.join("index.html"), "<html>map</html>").unwrap();
+        std::fs::write(dir.join("js/viewer.js"), "console.log(1);").unwrap();
+        std::fs::write(dir.join("style.json"), "{}").unwrap();
+
+        let app = init_service(App::new().service(new_static_files(&dir, Some("/ui")))).await;
+        let get = async |path: &str| {
+            let req = TestRequest::get().uri(path).to_request();
+            call_service(&app, req).await
+        };
+
+        let response = get("/ui/").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(
+            response.headers()[CONTENT_TYPE]
+                .to_str()
+                .unwrap()
+                .starts_with("text/html")
+        );
+        assert_eq!(read_body(response).await, "<html>map</html>");
+
+        let response = get("/ui").await;
+        assert!(response.status().is_redirection());
+        assert_eq!(response.headers()[LOCATION], "/ui/");
+
+        for (path, content_type) in [
+            ("/ui/js/viewer.js", "javascript"),
+            ("/ui/style.json", "application/json"),
+        ] {
+            let response = get(path).await;
+            assert_eq!(response.status(), StatusCode::OK, "{path}");
+            let header = response.headers()[CONTENT_TYPE].to_str().unwrap();
+            assert!(header.contains(content_type), "{path}: {header}");
+        }
+
+        for path in [
+            "/ui/../martin-static-files/index.html",
+            "/ui/%2e%2e/Cargo.toml",
+        ] {
+            assert!(!get(path).await.status().is_success(), "{path}");
+        }
+        assert_eq!(get("/ui/missing.js").await.status(), StatusCode::NOT_FOUND);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,122 @@
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use actix_web::http::header::CACHE_CONTROL;
+use actix_web::web::Data;
+use actix_web::{HttpResponse, Responder, route};
+use serde::Serialize;
+
+use crate::source::{PoolStatus, SourceKind, TileSources};
+use crate::srv::{ConcurrencyStatus, ReloadStatus, SourceReloader, SourceSettings};
+
+/// Time the server started, see [`init_start_time`]
+static START_TIME: OnceLock<(SystemTime, Instant)> = OnceLock::new();
+
+/// Remember the start of the server, to report its uptime. Only the first call has an effect.
+pub fn init_start_time() {
+    start_time();
+}
+
+fn start_time() -> (SystemTime, Instant) {
+    *START_TIME.get_or_init(|| (SystemTime::now(), Instant::now()))
+}
+
+/// State of the running server, built from the values kept in memory, without querying the sources
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ServerStatus {
+    pub version: &'static str,
+    pub git_hash: &'static str,
+    /// Unix time of the start of the server, in seconds
+    pub start_time: u64,
+    pub uptime_secs: f64,
+    /// Number of sources of each kind, without the aliases
+    pub sources: BTreeMap<SourceKind, usize>,
+    /// Connections of the database pools, by pool ID
+    pub pools: BTreeMap<String, PoolStatus>,
+    /// Requests generating and waiting for the tiles of the sources with `max_concurrent_requests`, by source ID
+    pub concurrency: BTreeMap<String, ConcurrencyStatus>,
+    /// Outcome of the last reload, `null` if the sources were not reloaded since the start
+    pub last_reload: Option<ReloadStatus>,
+}
+
+/// Return the version, uptime, number of sources, and pool usage of the server, e.g. for a dashboard.
+/// Unlike the deep health check, this does not query the sources, so it is cheap to call often.
+#[route("/-/status", method = "GET", method = "HEAD")]
+#[allow(clippy::unused_async)]
+async fn get_status(
+    sources: Data<TileSources>,
+    reloader: Option<Data<SourceReloader>>,
+    settings: Option<Data<SourceSettings>>,
+) -> impl Responder {
+    let (start_time, started) = start_time();
+    let status = ServerStatus {
+        version: env!("CARGO_PKG_VERSION"),
+        git_hash: env!("MARTIN_GIT_HASH"),
+        start_time: start_time
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+        uptime_secs: started.elapsed().as_secs_f64(),
+        sources: sources.count_by_kind(),
+        pools: sources.get_pool_statuses(),
+        concurrency: settings
+            .iter()
+            .flat_map(|v| v.concurrency.iter())
+            .map(|(id, limiter)| (id.clone(), limiter.status()))
+            .collect(),
+        last_reload: reloader.and_then(|v| v.last_status()),
+    };
+    HttpResponse::Ok()
+        .insert_header((CACHE_CONTROL, "no-cache"))
+        .json(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::App;
+    use actix_web::test::{TestRequest, call_and_read_body_json, init_service};
+    use itertools::Itertools as _;
+    use tilejson::tilejson;
+
+    use super::*;
+    use crate::srv::server::tests::TestSource;
+
+    #[actix_rt::test]
+    async fn status() {
+        let src = TestSource {
+            id: "test_source",
+            tj: tilejson! { tiles: vec![] },
+            data: Vec::default(),
+        };
+        let sources = TileSources::new(vec![vec![Box::new(src)]]);
+        let app = init_service(App::new().app_data(Data::new(sources)).service(get_status)).await;
+        let get = || TestRequest::get().uri("/-/status").to_request();
+
+        let first: serde_json::Value = call_and_read_body_json(&app, get()).await;
+        let keys: Vec<_> = first.as_object().unwrap().keys().sorted().collect();
+        assert_eq!(
+            keys,
+            [
+                "concurrency",
+                "git_hash",
+                "last_reload",
+                "pools",
+                "sources",
+                "start_time",
+                "uptime_secs",
+                "version",
+            ]
+        );
+        assert_eq!(first["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(first["sources"], serde_json::json!({"function": 1}));
+        assert_eq!(first["pools"], serde_json::json!({}));
+        assert_eq!(first["concurrency"], serde_json::json!({}));
+        assert!(first["last_reload"].is_null());
+
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        let second: serde_json::Value = call_and_read_body_json(&app, get()).await;
+        assert_eq!(second["start_time"], first["start_time"]);
+        assert!(second["uptime_secs"].as_f64().unwrap() > first["uptime_secs"].as_f64().unwrap());
+    }
+}
@@ -0,0 +1,100 @@
+use serde::{Deserialize, Serialize};
+
+#[cfg(not(feature = "otlp"))]
+use crate::MartinError::OtlpDisabledError;
+use crate::MartinResult;
+
+pub const OTLP_SERVICE_NAME_DEFAULT: &str = "martin";
+
+/// Export of the request spans to an OpenTelemetry collector
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TracingConfig {
+    /// OTLP/HTTP endpoint receiving the spans, e.g. `http://localhost:4318/v1/traces`.
+    /// If not set, the logs are printed as usual and no spans are exported.
+    pub otlp_endpoint: Option<String>,
+    /// Name of the service reported with the spans. Defaults to `martin`.
+    pub service_name: Option<String>,
+}
+
+/// Keeps the span exporter running, and sends the remaining spans when dropped
+#[derive(Default)]
+pub struct TracingGuard {
+    #[cfg(feature = "otlp")]
+    provider: Option<opentelemetry_sdk::trace::SdkTracerProvider>,
+}
+
+#[cfg(feature = "otlp")]
+impl Drop for TracingGuard {
+    fn drop(&mut self) {
+        if let Some(provider) = self.provider.take() {
+            if let Err(e) = provider.shutdown() {
+                eprintln!("Unable to export the remaining spans: {e}");
+            }
+        }
+    }
+}
+
+/// Initialize the logging with the `RUST_LOG` filter, or `default_filter` if it is not set.
+/// Without an OTLP endpoint, this is the usual `env_logger` output. With one, the `log` records become
+/// `tracing` events, and are printed along with the spans exported to the endpoint.
+pub fn init_logging(
+    config: Option<&TracingConfig>,
+    default_filter: &str,
+) -> MartinResult<TracingGuard> {
+    let Some(endpoint) = config.and_then(|v| v.otlp_endpoint.as_deref()) else {
+        let env = env_logger::Env::default().default_filter_or(default_filter);
+        env_logger::Builder::from_env(env).init();
+        return Ok(TracingGuard::default());
+    };
+
+    #[cfg(feature = "otlp")]
+    {
+        let service_name = config
+            .and_then(|v| v.service_name.clone())
+            .unwrap_or_else(|| OTLP_SERVICE_NAME_DEFAULT.to_string());
+        init_otlp(endpoint, service_name, default_filter)
+    }
+    #[cfg(not(feature = "otlp"))]
+    Err(OtlpDisabledError(endpoint.to_string()))
+}
+
+#[cfg(feature = "otlp")]
+fn init_otlp(
+    endpoint: &str,
+    service_name: String,
+    default_filter: &str,
+) -> MartinResult<TracingGuard> {
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_otlp::WithExportConfig as _;
+    use opentelemetry_sdk::Resource;
+    use opentelemetry_sdk::trace::SdkTracerProvider;
+    use tracing_subscriber::EnvFilter;
+    use tracing_subscriber::layer::SubscriberExt as _;
+    use tracing_subscriber::util::SubscriberInitExt as _;
+
+    use crate::MartinError::OtlpError;
+
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint)
+        .build()
+        .map_err(|e| OtlpError(endpoint.to_string(), e.to_string()))?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(Resource::builder().with_service_name(service_name).build())
+        .build();
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(default_filter));
+    // Also installs the bridge of the `log` records into `tracing` events
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer())
+        .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("martin")))
+        .try_init()
+        .map_err(|e| OtlpError(endpoint.to_string(), e.to_string()))?;
+
+    Ok(TracingGuard {
+        provider: Some(provider),
+    })
+}
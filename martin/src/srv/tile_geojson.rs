@@ -0,0 +1,257 @@
+use actix_web::error::{ErrorBadRequest, ErrorInternalServerError};
+use actix_web::web::{Data, Path};
+use actix_web::{HttpRequest, HttpResponse, Result as ActixResult, route};
+use martin_tile_utils::{
+    EARTH_CIRCUMFERENCE, Format, MvtFeature, MvtGeomType, MvtLayer, MvtValue, TileCoord,
+    decode_mvt, webmercator_to_wgs84,
+};
+use serde_json::{Map, Value, json};
+
+use crate::source::TileSources;
+use crate::srv::tile_stats::{DebugTileRequest, decode_tile, new_debug_source};
+use crate::srv::{
+    CachePeers, GEOJSON_MAX_FEATURES_DEFAULT, InFlightTiles, SourceSettings, SrvConfig,
+};
+use crate::utils::OptMainCache;
+
+/// Get an MVT tile like the tile route does, and return its features as a GeoJSON `FeatureCollection`
+/// in longitude and latitude, e.g. to inspect it in a GeoJSON viewer. Only registered with `debug_endpoints: true`.
+#[route("/{source_ids}/{z}/{x}/{y}.geojson", method = "GET", method = "HEAD")]
+#[allow(clippy::too_many_arguments)]
+async fn get_tile_geojson(
+    req: HttpRequest,
+    path: Path<DebugTileRequest>,
+    srv_config: Data<SrvConfig>,
+    sources: Data<TileSources>,
+    cache: Data<OptMainCache>,
+    peers: Option<Data<CachePeers>>,
+    settings: Option<Data<SourceSettings>>,
+    in_flight: Option<Data<InFlightTiles>>,
+) -> ActixResult<HttpResponse> {
+    let (xyz, src) = new_debug_source(
+        &req,
+        &path,
+        &srv_config,
+        &sources,
+        &cache,
+        peers.as_deref(),
+        settings.as_deref(),
+        in_flight.as_deref(),
+    )?;
+    if src.info.format != Format::Mvt {
+        return Err(ErrorBadRequest(format!(
+            "Only MVT tiles can be converted to GeoJSON, the tiles of {} are {}",
+            path.source_ids, src.info.format
+        )));
+    }
+    let data = src.get_tile_data(xyz).await?;
+    let info = src.data_info(&data);
+    let tile = decode_tile(&data, info.encoding)?.ok_or_else(|| {
+        ErrorInternalServerError(format!(
+            "Tiles with {:?} encoding cannot be converted to GeoJSON",
+            info.encoding
+        ))
+    })?;
+    let layers = decode_mvt(&tile).map_err(ErrorInternalServerError)?;
+    let max_features = srv_config
+        .geojson_max_features
+        .unwrap_or(GEOJSON_MAX_FEATURES_DEFAULT);
+    Ok(HttpResponse::Ok()
+        .content_type("application/geo+json")
+        .json(to_geojson(&layers, xyz, max_features)))
+}
+
+/// Convert the layers of a decoded tile to a `FeatureCollection`, with the name of the layer of each feature
+/// in its `layer` property. Only the first `max_features` features are converted, with a `warning` member
+/// on the collection if the others were left out.
+fn to_geojson(layers: &[MvtLayer], xyz: TileCoord, max_features: usize) -> Value {
+    let total: usize = layers.iter().map(|v| v.features.len()).sum();
+    let features: Vec<_> = layers
+        .iter()
+        .flat_map(|layer| layer.features.iter().map(move |v| (layer, v)))
+        .take(max_features)
+        .map(|(layer, feature)| to_feature(layer, feature, xyz))
+        .collect();
+    let mut collection = json!({
+        "type": "FeatureCollection",
+        "features": features,
+    });
+    if total > max_features {
+        collection["warning"] = Value::from(format!(
+            "The tile has {total} features, only the first {max_features} are included"
+        ));
+    }
+    collection
+}
+
+fn to_feature(layer: &MvtLayer, feature: &MvtFeature, xyz: TileCoord) -> Value {
+    let mut properties: Map<_, _> = feature
+        .properties
+        .iter()
+        .map(|(key, value)| (key.clone(), to_json_value(value)))
+        .collect();
+    properties.insert("layer".to_string(), Value::from(layer.name.clone()));
+
+    let project = |point: &[i64; 2]| to_lon_lat(*point, layer.extent, xyz);
+    let line = |part: &Vec<[i64; 2]>| part.iter().map(project).collect::<Vec<_>>();
+    let geometry = match (feature.geom_type, feature.geometry.as_slice()) {
+        (MvtGeomType::Point, [part]) if part.len() == 1 => {
+            Some(("Point", json!(project(&part[0]))))
+        }
+        (MvtGeomType::Point, parts) => {
+            let points: Vec<_> = parts.iter().flatten().map(project).collect();
+            Some(("MultiPoint", json!(points)))
+        }
+        (MvtGeomType::LineString, [part]) => Some(("LineString", json!(line(part)))),
+        (MvtGeomType::LineString, parts) => {
+            let lines: Vec<_> = parts.iter().map(line).collect();
+            Some(("MultiLineString", json!(lines)))
+        }
+        (MvtGeomType::Polygon, parts) => {
+            let polygons: Vec<_> = group_rings(parts)
+                .into_iter()
+                .map(|rings| rings.into_iter().map(line).collect::<Vec<_>>())
+                .collect();
+            if let [polygon] = polygons.as_slice() {
+                Some(("Polygon", json!(polygon)))
+            } else {
+                Some(("MultiPolygon", json!(polygons)))
+            }
+        }
+        (MvtGeomType::Unknown, _) => None,
+    };
+    let geometry = geometry.map_or(
+        Value::Null,
+        |(geom_type, coordinates)| json!({ "type": geom_type, "coordinates": coordinates }),
+    );
+
+    let mut value = json!({
+        "type": "Feature",
+        "geometry": geometry,
+        "properties": properties,
+    });
+    if let Some(id) = feature.id {
+        value["id"] = Value::from(id);
+    }
+    value
+}
+
+/// Group the rings of an MVT polygon into polygons, each starting with an exterior ring followed by its holes.
+/// The exterior rings have a positive area in tile coordinates, where `y` grows downwards.
+fn group_rings(rings: &[Vec<[i64; 2]>]) -> Vec<Vec<&Vec<[i64; 2]>>> {
+    let mut polygons: Vec<Vec<_>> = Vec::new();
+    for ring in rings {
+        let area: i64 = ring
+            .windows(2)
+            .map(|v| v[0][0] * v[1][1] - v[1][0] * v[0][1])
+            .sum();
+        match polygons.last_mut() {
+            Some(polygon) if area <= 0 => polygon.push(ring),
+            _ => polygons.push(vec![ring]),
+        }
+    }
+    polygons
+}
+
+/// Convert tile coordinates to longitude and latitude, using the Web Mercator envelope of the tile
+#[allow(clippy::cast_precision_loss)]
+fn to_lon_lat(point: [i64; 2], extent: u32, xyz: TileCoord) -> [f64; 2] {
+    let tile_length = EARTH_CIRCUMFERENCE / f64::from(1_u32 << xyz.z);
+    let extent = f64::from(extent);
+    let x = (f64::from(xyz.x) + point[0] as f64 / extent) * tile_length - EARTH_CIRCUMFERENCE / 2.0;
+    let y = EARTH_CIRCUMFERENCE / 2.0 - (f64::from(xyz.y) + point[1] as f64 / extent) * tile_length;
+    let (lon, lat) = webmercator_to_wgs84(x, y);
+    [lon, lat]
+}
+
+fn to_json_value(value: &MvtValue) -> Value {
+    match value {
+        MvtValue::String(v) => Value::from(v.clone()),
+        MvtValue::Float(v) => Value::from(f64::from(*v)),
+        MvtValue::Double(v) => Value::from(*v),
+        MvtValue::Int(v) => Value::from(*v),
+        MvtValue::Uint(v) => Value::from(*v),
+        MvtValue::Bool(v) => Value::from(*v),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_coords(value: &Value, expected: &[f64]) {
+        let coords: Vec<f64> = value
+            .as_array()
+            .unwrap()
+            .iter()
+            .flat_map(|v| v.as_array().unwrap().iter().map(|v| v.as_f64().unwrap()))
+            .collect();
+        assert_eq!(coords.len(), expected.len(), "{value}");
+        for (coord, expected) in coords.iter().zip(expected) {
+            assert!((coord - expected).abs() < 1e-9, "{value}");
+        }
+    }
+
+    #[test]
+    fn geojson_fixture() {
+        // Layer "places" with a point at the center of the tile, and a line along its diagonal
+        let tile = std::fs::read("../martin-tile-utils/fixtures/places.mvt").unwrap();
+        let layers = decode_mvt(&tile).unwrap();
+        let value = to_geojson(&layers, TileCoord { z: 0, x: 0, y: 0 }, 10);
+        assert!(value.get("warning").is_none());
+        let features = value["features"].as_array().unwrap();
+        assert_eq!(features.len(), 2);
+
+        assert_eq!(features[0]["id"], 7);
+        assert_eq!(
+            features[0]["properties"],
+            json!({"layer": "places", "name": "center", "rank": -2})
+        );
+        assert_eq!(features[0]["geometry"]["type"], "Point");
+        assert_coords(
+            &json!([features[0]["geometry"]["coordinates"]]),
+            &[0.0, 0.0],
+        );
+
+        assert!(features[1].get("id").is_none());
+        assert_eq!(
+            features[1]["properties"],
+            json!({"layer": "places", "rank": 1.5})
+        );
+        assert_eq!(features[1]["geometry"]["type"], "LineString");
+        assert_coords(
+            &features[1]["geometry"]["coordinates"],
+            &[-180.0, 85.051_128_779_806_6, 180.0, -85.051_128_779_806_6],
+        );
+
+        // The last tile of zoom 1 starts at the center of the world
+        let value = to_geojson(&layers, TileCoord { z: 1, x: 1, y: 1 }, 10);
+        assert_coords(
+            &value["features"][1]["geometry"]["coordinates"],
+            &[0.0, 0.0, 180.0, -85.051_128_779_806_6],
+        );
+    }
+
+    #[test]
+    fn geojson_truncated() {
+        let tile = std::fs::read("../martin-tile-utils/fixtures/places.mvt").unwrap();
+        let layers = decode_mvt(&tile).unwrap();
+        let value = to_geojson(&layers, TileCoord { z: 0, x: 0, y: 0 }, 1);
+        assert_eq!(value["features"].as_array().unwrap().len(), 1);
+        assert_eq!(
+            value["warning"],
+            "The tile has 2 features, only the first 1 are included"
+        );
+    }
+
+    #[test]
+    fn polygon_rings() {
+        let square = |size: i64| vec![[0, 0], [size, 0], [size, size], [0, size], [0, 0]];
+        let hole = vec![[1, 1], [1, 2], [2, 2], [2, 1], [1, 1]];
+        let rings = vec![square(10), hole.clone(), square(5)];
+        assert_eq!(
+            group_rings(&rings),
+            vec![vec![&square(10), &hole], vec![&square(5)]]
+        );
+    }
+}
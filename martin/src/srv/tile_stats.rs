@@ -0,0 +1,164 @@
+use actix_web::error::{ErrorInternalServerError, ErrorNotFound};
+use actix_web::web::{Data, Path};
+use actix_web::{HttpRequest, HttpResponse, Result as ActixResult, route};
+use martin_tile_utils::{
+    Encoding, Format, MvtLayerStats, TileCoord, decode_brotli, decode_gzip, decode_zlib,
+    get_mvt_stats,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::source::TileSources;
+use crate::srv::{
+    CachePeers, DynTileSource, InFlightTiles, MAX_TILE_SIZE_BYTES_DEFAULT, SourceSettings,
+    SrvConfig,
+};
+use crate::utils::OptMainCache;
+
+#[derive(Deserialize)]
+pub(crate) struct DebugTileRequest {
+    source_ids: String,
+    z: u8,
+    x: u32,
+    y: u32,
+}
+
+/// Description of a tile returned by the tile debug endpoint instead of the tile itself
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TileStats {
+    /// Size of the tile in bytes, as returned by the sources
+    pub size: usize,
+    pub format: String,
+    /// `Content-Encoding` of the tile as returned by the sources, `null` if uncompressed
+    pub encoding: Option<String>,
+    /// Whether the tile was found in the main cache
+    pub cached: bool,
+    /// Milliseconds spent in the slowest of the sources, `null` if none of them was queried
+    pub backend_ms: Option<f64>,
+    /// Features and vertices of each layer of an MVT tile, `null` for the other formats
+    pub layers: Option<Vec<LayerStats>>,
+}
+
+/// Number of features and vertices of a layer of an MVT tile
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct LayerStats {
+    pub name: String,
+    pub features: usize,
+    pub vertices: usize,
+}
+
+impl From<MvtLayerStats> for LayerStats {
+    fn from(value: MvtLayerStats) -> Self {
+        Self {
+            name: value.name,
+            features: value.features,
+            vertices: value.vertices,
+        }
+    }
+}
+
+/// Get a tile like the tile route does, but describe it instead of returning it, e.g. to find the layers
+/// making it too large. Only registered with `debug_endpoints: true`.
+#[route("/{source_ids}/{z}/{x}/{y}.json", method = "GET", method = "HEAD")]
+#[allow(clippy::too_many_arguments)]
+async fn get_tile_stats(
+    req: HttpRequest,
+    path: Path<DebugTileRequest>,
+    srv_config: Data<SrvConfig>,
+    sources: Data<TileSources>,
+    cache: Data<OptMainCache>,
+    peers: Option<Data<CachePeers>>,
+    settings: Option<Data<SourceSettings>>,
+    in_flight: Option<Data<InFlightTiles>>,
+) -> ActixResult<HttpResponse> {
+    let (xyz, src) = new_debug_source(
+        &req,
+        &path,
+        &srv_config,
+        &sources,
+        &cache,
+        peers.as_deref(),
+        settings.as_deref(),
+        in_flight.as_deref(),
+    )?;
+    let data = src.get_tile_data(xyz).await?;
+    let info = src.data_info(&data);
+    let (cache_misses, source) = src
+        .timing
+        .lock()
+        .map(|v| (v.cache_misses, v.source))
+        .unwrap_or_default();
+    let layers = if info.format == Format::Mvt {
+        decode_tile(&data, info.encoding)?
+            .map(|v| get_mvt_stats(&v))
+            .transpose()
+            .map_err(ErrorInternalServerError)?
+            .map(|v| v.into_iter().map(LayerStats::from).collect())
+    } else {
+        None
+    };
+    Ok(HttpResponse::Ok().json(TileStats {
+        size: data.len(),
+        format: info.format.to_string(),
+        encoding: info.encoding.content_encoding().map(ToString::to_string),
+        cached: src.cache.is_some() && cache_misses == 0,
+        backend_ms: source.map(|v| v.as_secs_f64() * 1000.0),
+        layers,
+    }))
+}
+
+/// Create the source of a debug endpoint, getting the tiles like the tile route does
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn new_debug_source<'a>(
+    req: &'a HttpRequest,
+    path: &'a DebugTileRequest,
+    srv_config: &SrvConfig,
+    sources: &'a TileSources,
+    cache: &'a OptMainCache,
+    peers: Option<&'a CachePeers>,
+    settings: Option<&'a SourceSettings>,
+    in_flight: Option<&'a InFlightTiles>,
+) -> ActixResult<(TileCoord, DynTileSource<'a>)> {
+    let xyz = TileCoord {
+        z: path.z,
+        x: path.x,
+        y: path.y,
+    };
+    if !xyz.is_valid() {
+        return Err(ErrorNotFound(format!(
+            "Tile {xyz:#} is outside of the world bounds"
+        )));
+    }
+    let src = DynTileSource::new(
+        sources,
+        &path.source_ids,
+        Some(path.z),
+        req.query_string(),
+        None,
+        None,
+        cache.as_ref(),
+    )?
+    .with_peers(peers)
+    .with_bounds_check(path.z, |id| {
+        settings.is_none_or(|v| v.is_bounds_checked(id))
+    })
+    .with_concurrency_limits(|id| settings.and_then(|v| v.get_concurrency_limiter(id)))
+    .with_in_flight_tiles(in_flight)
+    .with_max_tile_size(
+        srv_config
+            .max_tile_size_bytes
+            .unwrap_or(MAX_TILE_SIZE_BYTES_DEFAULT),
+    );
+    Ok((xyz, src))
+}
+
+/// Decompress an MVT tile as returned by the sources, `None` if its encoding cannot be decoded
+pub(crate) fn decode_tile(data: &[u8], encoding: Encoding) -> ActixResult<Option<Vec<u8>>> {
+    Ok(match encoding {
+        _ if data.is_empty() => Some(Vec::new()),
+        Encoding::Gzip => Some(decode_gzip(data)?),
+        Encoding::Zlib => Some(decode_zlib(data)?),
+        Encoding::Brotli => Some(decode_brotli(data)?),
+        Encoding::Uncompressed => Some(data.to_vec()),
+        Encoding::Internal | Encoding::Zstd => None,
+    })
+}
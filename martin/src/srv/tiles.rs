@@ -1,25 +1,77 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
 use actix_http::ContentEncoding;
 use actix_http::header::Quality;
-use actix_web::error::{ErrorBadRequest, ErrorNotAcceptable, ErrorNotFound};
+use actix_web::error::{
+    ErrorBadRequest, ErrorGatewayTimeout, ErrorInternalServerError, ErrorNotAcceptable,
+    ErrorNotFound, InternalError,
+};
+use actix_web::http::Method;
 use actix_web::http::header::{
-    AcceptEncoding, CONTENT_ENCODING, Encoding as HeaderEnc, Preference,
+    AcceptEncoding, CACHE_CONTROL, CONTENT_ENCODING, ETag, Encoding as HeaderEnc, EntityTag,
+    HeaderValue, IfModifiedSince, IfNoneMatch, LastModified, Preference, RETRY_AFTER, VARY,
 };
 use actix_web::web::{Data, Path, Query};
-use actix_web::{HttpMessage, HttpRequest, HttpResponse, Result as ActixResult, route};
+use actix_web::{HttpMessage, HttpRequest, HttpResponse, Result as ActixResult};
+use dashmap::DashSet;
 use futures::future::try_join_all;
-use log::trace;
+use futures::stream::{LocalBoxStream, StreamExt as _};
+use itertools::Itertools as _;
+use log::{error, trace, warn};
 use martin_tile_utils::{
-    Encoding, Format, TileCoord, TileInfo, decode_brotli, decode_gzip, encode_brotli, encode_gzip,
+    Encoding, Format, TileCoord, TileInfo, decode_brotli, decode_gzip, decode_zlib, encode_brotli,
+    encode_gzip, get_layer_names, validate_mvt,
 };
 use serde::Deserialize;
+use tracing::{Span, field};
+use xxhash_rust::xxh3::xxh3_64;
 
-use crate::args::PreferredEncoding;
-use crate::source::{TileInfoSources, TileSources, UrlQuery};
-use crate::srv::SrvConfig;
+use crate::args::{MvtValidation, PreferredEncoding};
+use crate::source::{TileInfoSources, TileSources, UrlQuery, get_last_modified};
 use crate::srv::server::map_internal_error;
+use crate::srv::{
+    COMPRESSION_MIN_SIZE_DEFAULT, CachePeers, ConcurrencyLimiter, InFlightTiles,
+    MAX_TILE_SIZE_BYTES_DEFAULT, MissingTile, MissingTileFile, SourceSettings, SrvConfig,
+    UrlQueryLimits,
+};
 use crate::utils::cache::get_or_insert_cached_value;
 use crate::utils::{CacheKey, CacheValue, MainCache, OptMainCache};
-use crate::{Tile, TileData};
+use crate::{MartinError, MartinResult, Tile, TileData, TileRect};
+
+/// Request header listing experimental features to enable for this request, e.g. `overzoom`.
+/// Only features allowed by the `request_features` config are enabled,
+/// and the enabled ones are returned in the same response header.
+pub const FEATURES_HEADER: &str = "x-martin-features";
+/// Feature of the [`FEATURES_HEADER`] serving the MVT tiles above the `maxzoom` of the sources,
+/// by clipping their tiles at `maxzoom` like the sources configured with `max_overzoom`
+pub const OVERZOOM_FEATURE: &str = "overzoom";
+/// All features that can be enabled with the [`FEATURES_HEADER`]
+pub const REQUEST_FEATURES: &[&str] = &[OVERZOOM_FEATURE];
+
+/// Request header with the milliseconds to wait for the tile before responding with `504 Gateway Timeout`.
+/// It can only shorten the `query_timeout` configured for the sources.
+pub const TIMEOUT_HEADER: &str = "x-martin-timeout";
+
+/// Response header with the time spent in the sources, added with `debug_headers: true`,
+/// e.g. `db;dur=12.3, cache;desc=miss`
+pub const SERVER_TIMING_HEADER: &str = "server-timing";
+
+/// A transparent 1x1 PNG image, returned for missing PNG tiles with `missing_tile: blank`
+static BLANK_PNG: &[u8] = &[
+    0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48, 0x44, 0x52,
+    0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x08, 0x06, 0x00, 0x00, 0x00, 0x1f, 0x15, 0xc4,
+    0x89, 0x00, 0x00, 0x00, 0x0b, 0x49, 0x44, 0x41, 0x54, 0x78, 0xda, 0x63, 0x60, 0x00, 0x02, 0x00,
+    0x00, 0x05, 0x00, 0x01, 0xe9, 0xfa, 0xdc, 0xd8, 0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4e, 0x44,
+    0xae, 0x42, 0x60, 0x82,
+];
+
+/// Combinations of source IDs whose tiles have no duplicate layer names, see [`DynTileSource::check_layer_names`]
+static CHECKED_LAYER_NAMES: LazyLock<DashSet<String>> = LazyLock::new(DashSet::new);
+
+/// The first bytes of gzip-compressed data
+static GZIP_MAGIC: &[u8] = b"\x1f\x8b";
 
 static SUPPORTED_ENC: &[HeaderEnc] = &[
     HeaderEnc::gzip(),
@@ -27,38 +79,245 @@ static SUPPORTED_ENC: &[HeaderEnc] = &[
     HeaderEnc::identity(),
 ];
 
+/// Extensions that may be used for the `{format}` placeholder of the tile route
+static FORMAT_EXTENSIONS: &[&str] = &["gif", "jpg", "jpeg", "json", "pbf", "mvt", "png", "webp"];
+
 #[derive(Deserialize, Clone)]
 pub struct TileRequest {
     source_ids: String,
     z: u8,
     x: u32,
+    y: TileRow,
+    /// Only set if the configured tile route has the `{format}` placeholder
+    format: Option<String>,
+}
+
+/// The `{y}` segment of the tile route, e.g. `21`, or `21@2x` for the high-DPI version of a raster tile
+#[derive(Deserialize, Clone, Copy)]
+#[serde(try_from = "String")]
+struct TileRow {
     y: u32,
+    scale: Option<u8>,
+}
+
+impl TryFrom<String> for TileRow {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let (y, scale) = match value.split_once('@') {
+            Some((y, "2x")) => (y, Some(2)),
+            Some((y, "3x")) => (y, Some(3)),
+            Some(_) => return Err(format!("Unsupported tile scale in {value}")),
+            None => (value.as_str(), None),
+        };
+        let y = y
+            .parse()
+            .map_err(|e| format!("Invalid y coordinate {y}: {e}"))?;
+        Ok(Self { y, scale })
+    }
 }
 
-#[route("/{source_ids}/{z}/{x}/{y}", method = "GET", method = "HEAD")]
-async fn get_tile(
+/// Serve a tile of one or more sources. Tiles are passed through in their stored encoding if the client accepts it,
+/// and are otherwise decompressed and re-compressed here, so this route must not use the `Compress` middleware.
+/// Registered at the configured `tile` route.
+#[tracing::instrument(
+    name = "tile",
+    skip_all,
+    fields(
+        source = %path.source_ids,
+        z = path.z,
+        x = path.x,
+        y = path.y.y,
+        size = field::Empty,
+        backend_ms = field::Empty,
+    ),
+)]
+pub async fn get_tile(
     req: HttpRequest,
     srv_config: Data<SrvConfig>,
     path: Path<TileRequest>,
     sources: Data<TileSources>,
     cache: Data<OptMainCache>,
+    peers: Option<Data<CachePeers>>,
+    settings: Option<Data<SourceSettings>>,
+    in_flight: Option<Data<InFlightTiles>>,
 ) -> ActixResult<HttpResponse> {
+    let xyz = TileCoord {
+        z: path.z,
+        x: path.x,
+        y: path.y.y,
+    };
+    // Negative and non-numeric coordinates are already rejected with 404 by the path extractor
+    if !xyz.is_valid() {
+        return Err(ErrorNotFound(format!(
+            "Tile {xyz:#} is outside of the world bounds"
+        )));
+    }
+
+    // Only the sources with high-DPI tiles are served at the `@2x` and `@3x` URLs
+    if let Some(scale) = path.y.scale {
+        let (all_sources, _, _) = sources.get_sources(&path.source_ids, None)?;
+        if let Some(src) = all_sources.iter().find(|v| v.get_tile_scale() != scale) {
+            return Err(ErrorNotFound(format!(
+                "Source {} has no @{scale}x tiles",
+                src.get_id()
+            )));
+        }
+    }
+
+    let timeout = get_request_timeout(
+        &req,
+        settings
+            .as_ref()
+            .and_then(|v| v.get_query_timeout(&path.source_ids)),
+    )?;
+    let missing_tile = settings
+        .as_ref()
+        .and_then(|v| v.get_missing_tile(&path.source_ids))
+        .or(srv_config.missing_tile)
+        .unwrap_or_default();
+    let features = get_request_features(&req, &srv_config);
+    let overzoomed;
+    let sources = if features.contains(OVERZOOM_FEATURE) {
+        overzoomed = sources.get_overzoomed(&path.source_ids, path.z)?;
+        &overzoomed
+    } else {
+        sources.as_ref()
+    };
     let src = DynTileSource::new(
-        sources.as_ref(),
+        sources,
         &path.source_ids,
         Some(path.z),
         req.query_string(),
         req.get_header::<AcceptEncoding>(),
         srv_config.preferred_encoding,
         cache.as_ref().as_ref(),
-    )?;
-
-    src.get_http_response(TileCoord {
-        z: path.z,
-        x: path.x,
-        y: path.y,
+    )?
+    .with_features(features)
+    .with_peers(peers.as_deref())
+    .with_mvt_validation(srv_config.validate_mvt)
+    .with_if_none_match(req.get_header::<IfNoneMatch>())
+    .with_if_modified_since(req.get_header::<IfModifiedSince>())
+    .with_missing_tile(missing_tile)
+    .with_missing_tile_file(
+        settings
+            .as_ref()
+            .and_then(|v| v.get_missing_tile_file(&path.source_ids)),
+    )
+    .with_server_timing(srv_config.debug_headers.unwrap_or_default())
+    .with_bounds_check(path.z, |id| {
+        settings.as_ref().is_none_or(|v| v.is_bounds_checked(id))
+    })
+    .with_concurrency_limits(|id| {
+        settings
+            .as_ref()
+            .and_then(|v| v.get_concurrency_limiter(id))
     })
-    .await
+    .with_in_flight_tiles(in_flight.as_deref())
+    .with_compression_min_size(
+        srv_config
+            .compression
+            .as_ref()
+            .and_then(|v| v.min_size)
+            .unwrap_or(COMPRESSION_MIN_SIZE_DEFAULT),
+    )
+    .with_max_tile_size(
+        srv_config
+            .max_tile_size_bytes
+            .unwrap_or(MAX_TILE_SIZE_BYTES_DEFAULT),
+    );
+    if let Some(extension) = &path.format {
+        check_format_extension(extension, src.info.format)?;
+    }
+    // The query is only set if one of the sources accepts query parameters
+    if let (Some(query_str), Some(query)) = (src.query_str, &src.query_obj) {
+        srv_config
+            .url_query_limits
+            .as_ref()
+            .unwrap_or(&UrlQueryLimits::default())
+            .check(query_str, query)?;
+    }
+
+    let get_response = async {
+        if req.method() == Method::HEAD {
+            src.get_head_response(xyz).await
+        } else {
+            src.get_http_response(xyz).await
+        }
+    };
+    let mut response = match timeout {
+        // Dropping the tile future on timeout also drops the source queries and their connections
+        Some(timeout) => tokio::time::timeout(timeout, get_response)
+            .await
+            .map_err(|_| {
+                ErrorGatewayTimeout(format!(
+                    "Tile {xyz:#} of {} was not ready within {}ms",
+                    path.source_ids,
+                    timeout.as_millis()
+                ))
+            })??,
+        None => get_response.await?,
+    };
+    if srv_config.request_features.is_some() {
+        // Responses may differ depending on the requested features
+        response
+            .headers_mut()
+            .append(VARY, HeaderValue::from_static(FEATURES_HEADER));
+    }
+    let cache_control = settings
+        .as_ref()
+        .and_then(|v| v.get_cache_control(&path.source_ids));
+    if let Some(value) = cache_control.or(srv_config.cache_control.as_deref()) {
+        let value = HeaderValue::from_str(value).map_err(map_internal_error)?;
+        response.headers_mut().insert(CACHE_CONTROL, value);
+    }
+    // Set last, so that the configured headers of the sources can override the standard ones
+    for (name, value) in settings
+        .iter()
+        .flat_map(|v| v.get_headers(&path.source_ids))
+    {
+        response.headers_mut().insert(name.clone(), value.clone());
+    }
+    Ok(response)
+}
+
+/// Reject the requested tile extension if it does not match the format of the sources,
+/// e.g. `.png` for vector tiles. Both `.pbf` and `.mvt` are accepted for vector tiles, and `.jpg` and `.jpeg` for JPEG.
+fn check_format_extension(extension: &str, format: Format) -> ActixResult<()> {
+    if Format::parse(extension) == Some(format) {
+        return Ok(());
+    }
+    let expected = FORMAT_EXTENSIONS
+        .iter()
+        .filter(|v| Format::parse(v) == Some(format))
+        .map(|v| format!(".{v}"))
+        .join(" or ");
+    Err(ErrorBadRequest(format!(
+        "The tiles are in {format} format, so the extension must be {expected} instead of .{extension}"
+    )))
+}
+
+/// The time to wait for a tile: the `query_timeout` configured for the sources,
+/// shortened by the [`TIMEOUT_HEADER`] of the request
+fn get_request_timeout(
+    req: &HttpRequest,
+    configured: Option<Duration>,
+) -> ActixResult<Option<Duration>> {
+    let Some(value) = req.headers().get(TIMEOUT_HEADER) else {
+        return Ok(configured);
+    };
+    let requested = value
+        .to_str()
+        .ok()
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .filter(|v| *v > 0)
+        .map(Duration::from_millis)
+        .ok_or_else(|| {
+            ErrorBadRequest(format!(
+                "{TIMEOUT_HEADER} header must be a positive number of milliseconds"
+            ))
+        })?;
+    Ok(Some(configured.map_or(requested, |v| v.min(requested))))
 }
 
 pub struct DynTileSource<'a> {
@@ -69,6 +328,66 @@ pub struct DynTileSource<'a> {
     pub accept_enc: Option<AcceptEncoding>,
     pub preferred_enc: Option<PreferredEncoding>,
     pub cache: Option<&'a MainCache>,
+    /// Experimental features enabled for this request
+    pub features: BTreeSet<String>,
+    /// Other instances to fetch cached tiles from before generating them
+    pub peers: Option<&'a CachePeers>,
+    /// Check the produced MVT tiles against the MVT specification
+    pub validate_mvt: Option<MvtValidation>,
+    /// `If-None-Match` request header, to respond with `304 Not Modified` if the tile has not changed
+    pub if_none_match: Option<IfNoneMatch>,
+    /// `If-Modified-Since` request header, only used without `If-None-Match` and for sources that know when they changed
+    pub if_modified_since: Option<IfModifiedSince>,
+    /// Response for the tiles that none of the sources have
+    pub missing_tile: MissingTile,
+    /// Image served instead of the tiles that none of the sources have, taking precedence over `missing_tile`
+    pub missing_tile_file: Option<&'a MissingTileFile>,
+    /// Time spent in the sources, reported in the tile span and in the optional `Server-Timing` header
+    pub timing: Mutex<TileTiming>,
+    /// Add the `Server-Timing` header to the responses
+    pub server_timing: bool,
+    /// Uncompressed tiles smaller than this many bytes are not compressed for the response
+    pub compression_min_size: usize,
+    /// Tiles returned by a source larger than this many bytes are rejected before being compressed or cached
+    pub max_tile_size: usize,
+    /// Tiles covering the bounds of each source, which is not queried for the tiles outside of them.
+    /// `None` for the sources without bounds or with `check_bounds: false`.
+    pub tile_bounds: Vec<Option<TileRect>>,
+    /// Limit of the tiles generated at once by each source, `None` for the unlimited sources
+    pub concurrency: Vec<Option<&'a ConcurrencyLimiter>>,
+    /// Tiles being generated by the concurrent requests, whose result is shared instead of generating them again
+    pub in_flight: Option<&'a InFlightTiles>,
+}
+
+/// Time spent in the sources to get a tile, reported in the [`SERVER_TIMING_HEADER`]
+#[derive(Debug, Default)]
+pub struct TileTiming {
+    /// The longest `get_tile` call of the sources, which are queried concurrently
+    pub source: Option<Duration>,
+    /// Number of sources whose tile was not in the cache
+    pub cache_misses: usize,
+}
+
+/// Check if the data last modified at `last_modified` has not changed since the `If-Modified-Since` request header,
+/// with the one-second precision of HTTP dates
+pub fn is_unmodified_since(last_modified: SystemTime, if_modified_since: &IfModifiedSince) -> bool {
+    let secs = |v: SystemTime| v.duration_since(UNIX_EPOCH).map_or(0, |v| v.as_secs());
+    secs(last_modified) <= secs(SystemTime::from(if_modified_since.0))
+}
+
+/// Get the features requested with the [`FEATURES_HEADER`] that are allowed by the server config
+pub fn get_request_features(req: &HttpRequest, srv_config: &SrvConfig) -> BTreeSet<String> {
+    let Some(allowed) = &srv_config.request_features else {
+        return BTreeSet::new();
+    };
+    req.headers()
+        .get_all(FEATURES_HEADER)
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .filter(|v| allowed.iter().any(|a| a == v))
+        .map(ToString::to_string)
+        .collect()
 }
 
 impl<'a> DynTileSource<'a> {
@@ -83,10 +402,6 @@ impl<'a> DynTileSource<'a> {
     ) -> ActixResult<Self> {
         let (sources, use_url_query, info) = sources.get_sources(source_ids, zoom)?;
 
-        if sources.is_empty() {
-            return Err(ErrorNotFound("No valid sources found"));
-        }
-
         let mut query_obj = None;
         let mut query_str = None;
         if use_url_query && !query.is_empty() {
@@ -102,42 +417,355 @@ impl<'a> DynTileSource<'a> {
             accept_enc,
             preferred_enc,
             cache,
+            features: BTreeSet::new(),
+            peers: None,
+            validate_mvt: None,
+            if_none_match: None,
+            if_modified_since: None,
+            missing_tile: MissingTile::default(),
+            missing_tile_file: None,
+            timing: Mutex::default(),
+            server_timing: false,
+            compression_min_size: 0,
+            max_tile_size: MAX_TILE_SIZE_BYTES_DEFAULT,
+            tile_bounds: Vec::new(),
+            concurrency: Vec::new(),
+            in_flight: None,
         })
     }
 
+    #[must_use]
+    pub fn with_features(mut self, features: BTreeSet<String>) -> Self {
+        self.features = features;
+        self
+    }
+
+    #[must_use]
+    pub fn with_peers(mut self, peers: Option<&'a CachePeers>) -> Self {
+        self.peers = peers;
+        self
+    }
+
+    #[must_use]
+    pub fn with_mvt_validation(mut self, validate_mvt: Option<MvtValidation>) -> Self {
+        self.validate_mvt = validate_mvt;
+        self
+    }
+
+    #[must_use]
+    pub fn with_if_none_match(mut self, if_none_match: Option<IfNoneMatch>) -> Self {
+        self.if_none_match = if_none_match;
+        self
+    }
+
+    #[must_use]
+    pub fn with_if_modified_since(mut self, if_modified_since: Option<IfModifiedSince>) -> Self {
+        self.if_modified_since = if_modified_since;
+        self
+    }
+
+    #[must_use]
+    pub fn with_missing_tile(mut self, missing_tile: MissingTile) -> Self {
+        self.missing_tile = missing_tile;
+        self
+    }
+
+    #[must_use]
+    pub fn with_missing_tile_file(
+        mut self,
+        missing_tile_file: Option<&'a MissingTileFile>,
+    ) -> Self {
+        self.missing_tile_file = missing_tile_file;
+        self
+    }
+
+    #[must_use]
+    pub fn with_server_timing(mut self, enabled: bool) -> Self {
+        self.server_timing = enabled;
+        self
+    }
+
+    #[must_use]
+    pub fn with_compression_min_size(mut self, compression_min_size: usize) -> Self {
+        self.compression_min_size = compression_min_size;
+        self
+    }
+
+    /// Compute the tiles covering the bounds of the sources at `zoom`, once for all tiles of the zoom level.
+    /// The sources for which `is_checked` returns `false` are queried for the tiles outside of their bounds too.
+    #[must_use]
+    pub fn with_bounds_check(mut self, zoom: u8, is_checked: impl Fn(&str) -> bool) -> Self {
+        self.tile_bounds = self
+            .sources
+            .iter()
+            .map(|src| {
+                let bounds = src.get_tilejson().bounds?;
+                // Bounds crossing the antimeridian are not checked
+                (is_checked(src.get_id()) && bounds.left <= bounds.right)
+                    .then(|| TileRect::from_bounds(zoom, &bounds))
+            })
+            .collect();
+        self
+    }
+
+    /// Wait for the concurrency limit of each source returned by `get_limiter` before generating its tiles
+    #[must_use]
+    pub fn with_concurrency_limits(
+        mut self,
+        get_limiter: impl Fn(&str) -> Option<&'a ConcurrencyLimiter>,
+    ) -> Self {
+        self.concurrency = self
+            .sources
+            .iter()
+            .map(|src| get_limiter(src.get_id()))
+            .collect();
+        self
+    }
+
+    /// Share the tiles generated by the concurrent requests for the same tile, source, and query
+    #[must_use]
+    pub fn with_in_flight_tiles(mut self, in_flight: Option<&'a InFlightTiles>) -> Self {
+        self.in_flight = in_flight;
+        self
+    }
+
+    /// Check if the tile is within the bounds of the source at `idx`, if they are checked
+    fn is_in_bounds(&self, idx: usize, xyz: TileCoord) -> bool {
+        self.tile_bounds
+            .get(idx)
+            .copied()
+            .flatten()
+            .is_none_or(|v| v.contains(xyz))
+    }
+
+    #[must_use]
+    pub fn with_max_tile_size(mut self, max_tile_size: usize) -> Self {
+        self.max_tile_size = max_tile_size;
+        self
+    }
+
+    /// Reject the tiles of a source larger than [`Self::max_tile_size`], before they are cached, compressed, or sent
+    fn check_tile_size(&self, id: &str, xyz: TileCoord, tile: TileData) -> MartinResult<TileData> {
+        if tile.len() > self.max_tile_size {
+            return Err(MartinError::TileTooLarge(
+                id.to_string(),
+                xyz,
+                tile.len(),
+                self.max_tile_size,
+            ));
+        }
+        Ok(tile)
+    }
+
+    #[must_use]
+    pub fn has_feature(&self, feature: &str) -> bool {
+        self.features.contains(feature)
+    }
+
     pub async fn get_http_response(&self, xyz: TileCoord) -> ActixResult<HttpResponse> {
-        let tile = self.get_tile_content(xyz).await?;
+        let data = self.get_tile_data(xyz).await?;
+        self.build_response(xyz, data).await
+    }
 
-        Ok(if tile.data.is_empty() {
-            HttpResponse::NoContent().finish()
-        } else {
+    /// Respond to a `HEAD` request with the same status and headers as [`Self::get_http_response`].
+    /// If all sources can tell that they do not have the tile with [`crate::Source::has_tile`], e.g. `MBTiles` sources,
+    /// the missing tile is not fetched. The existing tiles are still fetched for their `ETag` and `Content-Length`.
+    pub async fn get_head_response(&self, xyz: TileCoord) -> ActixResult<HttpResponse> {
+        if self.sources.iter().all(|s| s.can_probe_tiles()) && !self.has_tile(xyz).await? {
+            return self.build_response(xyz, Vec::new()).await;
+        }
+        self.get_http_response(xyz).await
+    }
+
+    async fn build_response(&self, xyz: TileCoord, data: TileData) -> ActixResult<HttpResponse> {
+        let span = Span::current();
+        span.record("size", data.len());
+        if let Some(duration) = self.timing.lock().ok().and_then(|v| v.source) {
+            span.record("backend_ms", duration.as_secs_f64() * 1000.0);
+        }
+
+        let mut body = None;
+        let mut response = if let (true, Some(file)) = (data.is_empty(), self.missing_tile_file) {
             let mut response = HttpResponse::Ok();
-            response.content_type(tile.info.format.content_type());
-            if let Some(val) = tile.info.encoding.content_encoding() {
-                response.insert_header((CONTENT_ENCODING, val));
+            response.content_type(file.format.content_type());
+            body = Some(file.data.clone());
+            response
+        } else if data.is_empty() {
+            match self.missing_tile {
+                MissingTile::NoContent => HttpResponse::NoContent(),
+                // An empty tile stored in the source is not missing
+                MissingTile::NotFound if self.has_tile(xyz).await? => HttpResponse::NoContent(),
+                MissingTile::NotFound => HttpResponse::NotFound(),
+                MissingTile::Blank => match self.info.format {
+                    Format::Mvt | Format::Png => {
+                        let mut response = HttpResponse::Ok();
+                        response.content_type(self.info.format.content_type());
+                        if self.info.format == Format::Png {
+                            body = Some(BLANK_PNG.to_vec());
+                        }
+                        response
+                    }
+                    _ => HttpResponse::NoContent(),
+                },
             }
-            response.body(tile.data)
+        } else {
+            // The tile is hashed before it is re-compressed, so the ETag does not depend on the Accept-Encoding.
+            // It is weak because the bytes of the response still differ between encodings.
+            let etag = EntityTag::new_weak(format!("{:016x}", xxh3_64(&data)));
+            let last_modified = get_last_modified(&self.sources);
+            let mut response = if self.is_not_modified(&etag, last_modified) {
+                HttpResponse::NotModified()
+            } else {
+                let tile = self.recompress(data)?;
+                let mut response = HttpResponse::Ok();
+                response.content_type(tile.info.format.content_type());
+                if let Some(val) = tile.info.encoding.content_encoding() {
+                    response.insert_header((CONTENT_ENCODING, val));
+                }
+                body = Some(tile.data);
+                response
+            };
+            response.insert_header(ETag(etag));
+            if let Some(last_modified) = last_modified {
+                response.insert_header(LastModified(last_modified.into()));
+            }
+            response
+        };
+        // The encoding of the tile depends on the Accept-Encoding request header
+        response.insert_header((VARY, "accept-encoding"));
+        if !self.features.is_empty() {
+            response.insert_header((FEATURES_HEADER, self.features.iter().join(",")));
+        }
+        if let Some(value) = self.server_timing() {
+            response.insert_header((SERVER_TIMING_HEADER, value));
+        }
+
+        Ok(match body {
+            Some(data) => response.body(data),
+            None => response.finish(),
         })
     }
 
+    fn record_timing(&self, record: impl FnOnce(&mut TileTiming)) {
+        record(
+            &mut self
+                .timing
+                .lock()
+                .unwrap_or_else(std::sync::PoisonError::into_inner),
+        )
+    }
+
+    /// The `Server-Timing` header value, with the time spent in the sources and whether the tile was cached
+    fn server_timing(&self) -> Option<String> {
+        if !self.server_timing {
+            return None;
+        }
+        let timing = self.timing.lock().ok()?;
+        let mut metrics = Vec::new();
+        if let Some(duration) = timing.source {
+            metrics.push(format!("db;dur={:.1}", duration.as_secs_f64() * 1000.0));
+        }
+        if self.cache.is_some() && !self.sources.is_empty() {
+            let desc = if timing.cache_misses == 0 {
+                "hit"
+            } else {
+                "miss"
+            };
+            metrics.push(format!("cache;desc={desc}"));
+        }
+        (!metrics.is_empty()).then(|| metrics.join(", "))
+    }
+
+    /// Check if any of the sources has a stored tile at `xyz`, even if it is empty
+    async fn has_tile(&self, xyz: TileCoord) -> ActixResult<bool> {
+        let found = try_join_all(self.sources.iter().enumerate().map(|(idx, s)| async move {
+            if self.is_in_bounds(idx, xyz) {
+                s.has_tile(xyz).await
+            } else {
+                Ok(false)
+            }
+        }))
+        .await
+        .map_err(|e| map_tile_error(&e))?;
+        Ok(found.into_iter().any(|v| v))
+    }
+
+    /// Check if the client already has the tile with this `ETag`, based on the `If-None-Match` request header,
+    /// or if the sources have not changed since the `If-Modified-Since` request header
+    fn is_not_modified(&self, etag: &EntityTag, last_modified: Option<SystemTime>) -> bool {
+        match (&self.if_none_match, &self.if_modified_since, last_modified) {
+            (Some(IfNoneMatch::Any), _, _) => true,
+            (Some(IfNoneMatch::Items(items)), _, _) => items.iter().any(|v| v.weak_eq(etag)),
+            (None, Some(since), Some(last_modified)) => is_unmodified_since(last_modified, since),
+            _ => false,
+        }
+    }
+
     pub async fn get_tile_content(&self, xyz: TileCoord) -> ActixResult<Tile> {
-        let mut tiles = try_join_all(self.sources.iter().map(|s| async {
-            get_or_insert_cached_value!(
-                self.cache,
-                CacheValue::Tile,
-                s.get_tile(xyz, self.query_obj.as_ref()),
-                {
-                    let id = s.get_id().to_string();
-                    if let Some(query_str) = self.query_str {
-                        CacheKey::TileWithQuery(id, xyz, query_str.to_string())
-                    } else {
-                        CacheKey::Tile(id, xyz)
-                    }
+        let data = self.get_tile_data(xyz).await?;
+        if data.is_empty() {
+            Ok(Tile::new(data, self.info))
+        } else {
+            // decide if (re-)encoding of the tile data is needed, and recompress if so
+            self.recompress(data)
+        }
+    }
+
+    /// Get the tile data of all sources, concatenated and validated, but not yet re-compressed
+    pub(crate) async fn get_tile_data(&self, xyz: TileCoord) -> ActixResult<TileData> {
+        let mut tiles = try_join_all(self.sources.iter().enumerate().map(|(idx, s)| async move {
+            // The tiles outside of the bounds are missing without querying the source or the cache
+            if !self.is_in_bounds(idx, xyz) {
+                return Ok(Vec::new());
+            }
+            let key = {
+                let id = s.get_id().to_string();
+                if let Some(query_str) = self.query_str {
+                    CacheKey::TileWithQuery(id, xyz, query_str.to_string())
+                } else {
+                    CacheKey::Tile(id, xyz)
                 }
-            )
+            };
+            let get_tile = async {
+                get_or_insert_cached_value!(
+                    self.cache,
+                    CacheValue::Tile,
+                    async {
+                        self.record_timing(|v| v.cache_misses += 1);
+                        // Tiles generated for a URL query are not shared with the peers
+                        let peer_tile = match (self.peers, self.query_str) {
+                            (Some(peers), None) => peers.fetch_tile(s.get_id(), xyz).await,
+                            _ => None,
+                        };
+                        let tile = match peer_tile {
+                            Some(data) => Ok(data),
+                            None => {
+                                // Only the requests generating the tile wait, not those served from the cache
+                                let _permit = match self.concurrency.get(idx).copied().flatten() {
+                                    Some(limiter) => Some(limiter.acquire(s.get_id()).await?),
+                                    None => None,
+                                };
+                                let start = Instant::now();
+                                let tile = s.get_tile(xyz, self.query_obj.as_ref()).await;
+                                let duration = start.elapsed();
+                                self.record_timing(|v| v.source = v.source.max(Some(duration)));
+                                tile
+                            }
+                        };
+                        // Checked before caching, so that the cache cannot be filled with oversized tiles
+                        tile.and_then(|tile| self.check_tile_size(s.get_id(), xyz, tile))
+                    },
+                    key.clone()
+                )
+            };
+            // The concurrent requests for the same tile wait for the first one to look it up in the cache and generate it
+            match self.in_flight {
+                Some(in_flight) => in_flight.get_or_generate(key, get_tile).await,
+                None => get_tile.await,
+            }
         }))
         .await
-        .map_err(map_internal_error)?;
+        .map_err(|e| map_tile_error(&e))?;
 
         let mut layer_count = 0;
         let mut last_non_empty_layer = 0;
@@ -151,7 +779,7 @@ impl<'a> DynTileSource<'a> {
         // Minor optimization to prevent concatenation if there are less than 2 tiles
         let data = match layer_count {
             1 => tiles.swap_remove(last_non_empty_layer),
-            0 => return Ok(Tile::new(Vec::new(), self.info)),
+            0 => return Ok(Vec::new()),
             _ => {
                 // Make sure tiles can be concatenated, or if not, that there is only one non-empty tile for each zoom level
                 // TODO: can zlib, brotli, or zstd be concatenated?
@@ -165,12 +793,126 @@ impl<'a> DynTileSource<'a> {
                         self.info, xyz.z
                     )))?;
                 }
+                self.check_layer_names(&tiles)?;
+                if self.info.encoding == Encoding::Gzip {
+                    // Gzip members can be concatenated, but not with the uncompressed small tiles of the same sources
+                    for tile in tiles
+                        .iter_mut()
+                        .filter(|v| !v.is_empty() && !v.starts_with(GZIP_MAGIC))
+                    {
+                        *tile = encode_gzip(tile)?;
+                    }
+                }
                 tiles.concat()
             }
         };
 
-        // decide if (re-)encoding of the tile data is needed, and recompress if so
-        self.recompress(data)
+        if let Some(mode) = self.validate_mvt {
+            self.validate_tile(xyz, &data, mode)?;
+        }
+        Ok(data)
+    }
+
+    /// The actual encoding of the tile data. Sources that only compress some of their tiles,
+    /// like `PostgreSQL` sources with `compress_tiles: gzip`, report gzip but return their small tiles uncompressed.
+    pub(crate) fn data_info(&self, data: &[u8]) -> TileInfo {
+        if self.info.encoding == Encoding::Gzip && !data.starts_with(GZIP_MAGIC) {
+            self.info.encoding(Encoding::Uncompressed)
+        } else {
+            self.info
+        }
+    }
+
+    /// Make sure that the tiles of different sources do not have layers with the same name,
+    /// as clients could not tell them apart once the tiles are concatenated.
+    /// Only uncompressed MVT tiles are checked, once for each combination of sources,
+    /// as the sources are expected to have the same layers in all of their tiles.
+    /// Tiles that cannot be decoded have no layer names to compare.
+    fn check_layer_names(&self, tiles: &[TileData]) -> ActixResult<()> {
+        if self.info.format != Format::Mvt || self.info.encoding != Encoding::Uncompressed {
+            return Ok(());
+        }
+        let ids = self.sources.iter().map(|s| s.get_id()).join(",");
+        if CHECKED_LAYER_NAMES.contains(&ids) {
+            return Ok(());
+        }
+        let mut layers: HashMap<String, &str> = HashMap::new();
+        let mut checked = HashSet::new();
+        let mut all_decoded = true;
+        for (src, tile) in self.sources.iter().zip(tiles) {
+            let id = src.get_id();
+            // A source repeated in the combination has the same layers as itself
+            if !checked.insert(id) {
+                continue;
+            }
+            let Ok(names) = get_layer_names(tile) else {
+                all_decoded = false;
+                continue;
+            };
+            all_decoded &= !tile.is_empty();
+            for name in names.into_iter().unique() {
+                if let Some(other) = layers.insert(name.clone(), id) {
+                    return Err(ErrorBadRequest(format!(
+                        "Sources {other} and {id} both have a layer named {name}, and cannot be combined into one tile"
+                    )));
+                }
+            }
+        }
+        // The layers of the empty or invalid tiles are not known yet, so they are checked again with the next tile
+        if all_decoded {
+            CHECKED_LAYER_NAMES.insert(ids);
+        }
+        Ok(())
+    }
+
+    /// Check that an MVT tile conforms to the MVT specification, logging or rejecting it if not.
+    /// Tiles of other formats, and tiles with an encoding that cannot be decoded are not checked.
+    fn validate_tile(&self, xyz: TileCoord, data: &[u8], mode: MvtValidation) -> ActixResult<()> {
+        if self.info.format != Format::Mvt {
+            return Ok(());
+        }
+        let res = match self.data_info(data).encoding {
+            Encoding::Uncompressed => validate_mvt(data),
+            Encoding::Gzip => validate_mvt(&decode_gzip(data)?),
+            Encoding::Zlib => validate_mvt(&decode_zlib(data)?),
+            Encoding::Brotli => validate_mvt(&decode_brotli(data)?),
+            _ => return Ok(()),
+        };
+        if let Err(e) = res {
+            let ids = self.sources.iter().map(|s| s.get_id()).join(",");
+            match mode {
+                MvtValidation::Warn => warn!("Tile {xyz} of {ids} is not a valid MVT: {e}"),
+                MvtValidation::Reject => {
+                    error!("Tile {xyz} of {ids} is not a valid MVT: {e}");
+                    return Err(ErrorInternalServerError(format!(
+                        "Tile {xyz} is not a valid MVT: {e}"
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Generate all tiles of a rectangle with a single query, if there is only one source and it supports it.
+    /// Empty tiles may be omitted from the stream.
+    pub async fn get_tiles_bulk(
+        &self,
+        rect: TileRect,
+    ) -> ActixResult<Option<LocalBoxStream<'_, ActixResult<(TileCoord, Tile)>>>> {
+        let [src] = self.sources.as_slice() else {
+            return Ok(None);
+        };
+        let Some(tiles) = src.get_tiles_bulk(rect).await.map_err(map_internal_error)? else {
+            return Ok(None);
+        };
+        Ok(Some(
+            tiles
+                .map(|res| {
+                    let (xyz, data) = res.map_err(map_internal_error)?;
+                    Ok((xyz, self.recompress(data)?))
+                })
+                .boxed_local(),
+        ))
     }
 
     /// Decide which encoding to use for the uncompressed tile data, based on the client's Accept-Encoding header
@@ -217,13 +959,14 @@ impl<'a> DynTileSource<'a> {
     }
 
     fn recompress(&self, tile: TileData) -> ActixResult<Tile> {
-        let mut tile = Tile::new(tile, self.info);
+        let info = self.data_info(&tile);
+        let mut tile = Tile::new(tile, info);
         if let Some(accept_enc) = &self.accept_enc {
-            if self.info.encoding.is_encoded() {
+            if tile.info.encoding.is_encoded() {
                 // already compressed, see if we can send it as is, or need to re-compress
                 if !accept_enc.iter().any(|e| {
                     if let Preference::Specific(HeaderEnc::Known(enc)) = e.item {
-                        to_encoding(enc) == Some(tile.info.encoding)
+                        to_encoding(enc) == Some(tile.info.encoding) && e.quality > Quality::ZERO
                     } else {
                         false
                     }
@@ -233,7 +976,10 @@ impl<'a> DynTileSource<'a> {
                 }
             }
 
-            if tile.info.encoding == Encoding::Uncompressed {
+            // Compressing small tiles wastes CPU, and often makes them larger
+            if tile.info.encoding == Encoding::Uncompressed
+                && tile.data.len() >= self.compression_min_size
+            {
                 if let Some(enc) = self.decide_encoding(accept_enc)? {
                     // (re-)compress the tile into the preferred encoding
                     tile = encode(tile, enc)?;
@@ -248,6 +994,30 @@ impl<'a> DynTileSource<'a> {
     }
 }
 
+/// Respond with 400 to tile errors caused by an invalid request, with 503 to the requests of busy sources,
+/// and with 500 to all other errors
+fn map_tile_error(e: &MartinError) -> actix_web::Error {
+    match e {
+        // The requests sharing the result of another one respond like it does
+        MartinError::CoalescedTileError(e) => map_tile_error(e),
+        MartinError::SourceBusyError(_, retry_after) => {
+            let response = HttpResponse::ServiceUnavailable()
+                .insert_header((RETRY_AFTER, *retry_after))
+                .body(e.to_string());
+            InternalError::from_response(e.to_string(), response).into()
+        }
+        #[cfg(feature = "mbtiles")]
+        MartinError::MbtilesError(e @ mbtiles::MbtError::InvalidTileCoordinate(..)) => {
+            ErrorBadRequest(e.to_string())
+        }
+        #[cfg(feature = "postgres")]
+        MartinError::PostgresError(e) if e.is_query_canceled() => {
+            ErrorGatewayTimeout(e.to_string())
+        }
+        e => map_internal_error(e),
+    }
+}
+
 fn encode(tile: Tile, enc: ContentEncoding) -> ActixResult<Tile> {
     Ok(match enc {
         ContentEncoding::Brotli => Tile::new(
@@ -269,6 +1039,10 @@ fn decode(tile: Tile) -> ActixResult<Tile> {
                 decode_gzip(&tile.data)?,
                 info.encoding(Encoding::Uncompressed),
             ),
+            Encoding::Zlib => Tile::new(
+                decode_zlib(&tile.data)?,
+                info.encoding(Encoding::Uncompressed),
+            ),
             Encoding::Brotli => Tile::new(
                 decode_brotli(&tile.data)?,
                 info.encoding(Encoding::Uncompressed),
@@ -287,18 +1061,26 @@ pub fn to_encoding(val: ContentEncoding) -> Option<Encoding> {
         ContentEncoding::Identity => Encoding::Uncompressed,
         ContentEncoding::Gzip => Encoding::Gzip,
         ContentEncoding::Brotli => Encoding::Brotli,
-        // TODO: Deflate => Encoding::Zstd or Encoding::Zlib ?
+        // HTTP `deflate` is the zlib format
+        ContentEncoding::Deflate => Encoding::Zlib,
         _ => None?,
     })
 }
 
 #[cfg(test)]
 mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test::{TestRequest, call_service, init_service, read_body};
+    use actix_web::{App, web};
+    use async_trait::async_trait;
     use rstest::rstest;
-    use tilejson::tilejson;
+    use tilejson::{TileJSON, tilejson};
 
     use super::*;
+    use crate::source::{Source, SourceKind, TileInfoSource};
+    use crate::srv::ConcurrencyLimit;
     use crate::srv::server::tests::TestSource;
+    use crate::{MartinResult, UrlQuery};
 
     #[actix_rt::test]
     async fn test_deleteme() {
@@ -345,6 +1127,50 @@ mod tests {
         assert_eq!(tile.info.encoding, expected_enc);
     }
 
+    #[rstest]
+    #[trace]
+    #[case::gzip(Some("gzip"), Some("gzip"))]
+    #[case::any_gzip(Some("gzip, deflate, br, zstd"), Some("gzip"))]
+    #[case::gzip_not_preferred(Some("br;q=1, gzip;q=0.5"), Some("gzip"))]
+    #[case::brotli(Some("br"), Some("br"))]
+    #[case::gzip_refused(Some("gzip;q=0, br"), Some("br"))]
+    #[case::identity(Some("identity"), None)]
+    #[case::no_header(None, None)]
+    #[actix_rt::test]
+    async fn test_stored_gzip_tile(
+        #[case] accept_enc: Option<&'static str>,
+        #[case] expected_enc: Option<&'static str>,
+    ) {
+        let data = b"stored tile".to_vec();
+        let sources = TileSources::new(vec![vec![Box::new(TestSource {
+            id: "test_source",
+            tj: tilejson! { tiles: vec![] },
+            data: encode_gzip(&data).unwrap(),
+        })]]);
+        let accept_enc = accept_enc
+            .map(|v| AcceptEncoding(v.split(',').map(|s| s.trim().parse().unwrap()).collect()));
+        let mut src =
+            DynTileSource::new(&sources, "test_source", None, "", accept_enc, None, None).unwrap();
+        src.info = TileInfo::new(Format::Mvt, Encoding::Gzip);
+
+        let xyz = TileCoord { z: 0, x: 0, y: 0 };
+        let response = src.get_http_response(xyz).await.unwrap();
+        let headers = response.headers().clone();
+        assert_eq!(headers.get(VARY).unwrap(), "accept-encoding");
+        let encoding = headers.get(CONTENT_ENCODING).map(|v| v.to_str().unwrap());
+        assert_eq!(encoding, expected_enc);
+
+        let body = actix_web::body::to_bytes(response.into_body())
+            .await
+            .unwrap();
+        let body = match encoding {
+            Some("gzip") => decode_gzip(&body).unwrap(),
+            Some("br") => decode_brotli(&body).unwrap(),
+            _ => body.to_vec(),
+        };
+        assert_eq!(body, data);
+    }
+
     #[actix_rt::test]
     async fn test_tile_content() {
         let non_empty_source = TestSource {
@@ -377,4 +1203,811 @@ mod tests {
             assert_eq!(expected, &src.get_tile_content(xyz).await.unwrap().data);
         }
     }
+
+    #[test]
+    fn test_request_features() {
+        let req = TestRequest::default()
+            .insert_header((FEATURES_HEADER, "overzoom , unknown"))
+            .to_http_request();
+
+        let cfg = SrvConfig::default();
+        assert!(get_request_features(&req, &cfg).is_empty());
+
+        let cfg = SrvConfig {
+            request_features: Some(vec!["overzoom".to_string()]),
+            ..Default::default()
+        };
+        assert_eq!(
+            get_request_features(&req, &cfg),
+            BTreeSet::from(["overzoom".to_string()])
+        );
+
+        let req = TestRequest::default().to_http_request();
+        assert!(get_request_features(&req, &cfg).is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn test_overzoom_feature() {
+        let mut tj = tilejson! { tiles: vec![] };
+        tj.maxzoom = Some(6);
+        let src = TestSource {
+            id: "src",
+            tj,
+            // Layer "test" with a point at (3172,1124)
+            data: vec![
+                26, 24, 120, 2, 10, 4, 116, 101, 115, 116, 18, 11, 8, 1, 24, 1, 34, 5, 9, 200, 49,
+                200, 17, 40, 128, 32,
+            ],
+        };
+        let cfg = SrvConfig {
+            request_features: Some(vec![OVERZOOM_FEATURE.to_string()]),
+            ..Default::default()
+        };
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(cfg))
+                .app_data(Data::new(TileSources::new(vec![vec![Box::new(src)]])))
+                .app_data(Data::new(crate::NO_MAIN_CACHE))
+                .route("/{source_ids}/{z}/{x}/{y}", web::route().to(get_tile)),
+        )
+        .await;
+        let request = |path: &str, features: &str| {
+            TestRequest::get()
+                .uri(path)
+                .insert_header((FEATURES_HEADER, features))
+                .to_request()
+        };
+
+        // The point is in the tile at column 3 and row 1 of the 4x4 tiles covering 6/10/21 at zoom 8
+        let response = call_service(&app, request("/src/8/43/85", "overzoom")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers().get(FEATURES_HEADER).unwrap(), "overzoom");
+        let body = read_body(response).await;
+        assert_eq!(get_layer_names(&body), Ok(vec!["test".to_string()]));
+
+        let response = call_service(&app, request("/src/8/40/84", "overzoom")).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        // Without the feature, the source has no tiles above its maxzoom
+        let response = call_service(&app, request("/src/8/43/85", "unknown")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_rt::test]
+    async fn test_duplicate_layer_names() {
+        // Layer "test" with a point
+        let tile = vec![
+            26, 24, 120, 2, 10, 4, 116, 101, 115, 116, 18, 11, 8, 1, 24, 1, 34, 5, 9, 200, 49, 200,
+            17, 40, 128, 32,
+        ];
+        let source = |id| TestSource {
+            id,
+            tj: tilejson! { tiles: vec![] },
+            data: tile.clone(),
+        };
+        let sources = TileSources::new(vec![vec![Box::new(source("a")), Box::new(source("b"))]]);
+        let xyz = TileCoord { z: 0, x: 0, y: 0 };
+        let get = async |ids| {
+            DynTileSource::new(&sources, ids, None, "", None, None, None)
+                .unwrap()
+                .get_tile_content(xyz)
+                .await
+        };
+        assert_eq!(get("a,a").await.unwrap().data, tile.repeat(2));
+        let err = get("a,b").await.unwrap_err().to_string();
+        assert!(err.contains("layer named test"), "{err}");
+    }
+
+    #[test]
+    fn format_extension() {
+        assert!(check_format_extension("pbf", Format::Mvt).is_ok());
+        assert!(check_format_extension("mvt", Format::Mvt).is_ok());
+        assert!(check_format_extension("jpg", Format::Jpeg).is_ok());
+        assert!(check_format_extension("JPEG", Format::Jpeg).is_ok());
+        assert!(check_format_extension("webp", Format::Webp).is_ok());
+        let err = check_format_extension("png", Format::Mvt).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The tiles are in mvt format, so the extension must be .pbf or .mvt instead of .png"
+        );
+        assert!(check_format_extension("jpg", Format::Png).is_err());
+        assert!(check_format_extension("tiff", Format::Png).is_err());
+    }
+
+    #[test]
+    fn test_request_timeout() {
+        let configured = Some(Duration::from_millis(500));
+        let req = |value: &str| {
+            TestRequest::default()
+                .insert_header((TIMEOUT_HEADER, value))
+                .to_http_request()
+        };
+        let no_header = TestRequest::default().to_http_request();
+        assert_eq!(get_request_timeout(&no_header, None).unwrap(), None);
+        assert_eq!(
+            get_request_timeout(&no_header, configured).unwrap(),
+            configured
+        );
+        assert_eq!(
+            get_request_timeout(&req("100"), configured).unwrap(),
+            Some(Duration::from_millis(100))
+        );
+        // The header cannot extend the configured timeout
+        assert_eq!(
+            get_request_timeout(&req("2000"), configured).unwrap(),
+            configured
+        );
+        assert_eq!(
+            get_request_timeout(&req("2000"), None).unwrap(),
+            Some(Duration::from_millis(2000))
+        );
+        for value in ["0", "-5", "1.5", "soon"] {
+            assert!(
+                get_request_timeout(&req(value), configured).is_err(),
+                "{value}"
+            );
+        }
+    }
+
+    /// A source that takes half a second to generate each tile
+    #[derive(Debug, Clone)]
+    struct SlowSource(TestSource);
+
+    #[async_trait]
+    impl Source for SlowSource {
+        fn get_id(&self) -> &str {
+            self.0.id
+        }
+
+        fn get_tilejson(&self) -> &TileJSON {
+            &self.0.tj
+        }
+
+        fn get_tile_info(&self) -> TileInfo {
+            self.0.get_tile_info()
+        }
+
+        fn get_kind(&self) -> SourceKind {
+            self.0.get_kind()
+        }
+
+        fn clone_source(&self) -> TileInfoSource {
+            Box::new(self.clone())
+        }
+
+        async fn get_tile(
+            &self,
+            xyz: TileCoord,
+            url_query: Option<&UrlQuery>,
+        ) -> MartinResult<TileData> {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            self.0.get_tile(xyz, url_query).await
+        }
+    }
+
+    #[rstest]
+    #[case::no_timeout(None, None, StatusCode::OK)]
+    #[case::configured(Some(100), None, StatusCode::GATEWAY_TIMEOUT)]
+    #[case::configured_long(Some(5000), None, StatusCode::OK)]
+    #[case::header(None, Some("100"), StatusCode::GATEWAY_TIMEOUT)]
+    #[case::header_shortens(Some(5000), Some("100"), StatusCode::GATEWAY_TIMEOUT)]
+    #[case::header_clamped(Some(100), Some("5000"), StatusCode::GATEWAY_TIMEOUT)]
+    #[case::bad_header(None, Some("soon"), StatusCode::BAD_REQUEST)]
+    #[actix_rt::test]
+    async fn tile_timeout(
+        #[case] query_timeout: Option<u64>,
+        #[case] header: Option<&str>,
+        #[case] status: StatusCode,
+    ) {
+        let src = SlowSource(TestSource {
+            id: "slow",
+            tj: tilejson! { tiles: vec![] },
+            data: vec![1_u8, 2, 3],
+        });
+        let settings = SourceSettings {
+            query_timeout: query_timeout
+                .map(|v| HashMap::from([("slow".to_string(), v)]))
+                .unwrap_or_default(),
+            ..Default::default()
+        };
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(SrvConfig::default()))
+                .app_data(Data::new(TileSources::new(vec![vec![Box::new(src)]])))
+                .app_data(Data::new(crate::NO_MAIN_CACHE))
+                .app_data(Data::new(settings))
+                .route("/{source_ids}/{z}/{x}/{y}", web::get().to(get_tile)),
+        )
+        .await;
+        let mut req = TestRequest::get().uri("/slow/0/0/0");
+        if let Some(value) = header {
+            req = req.insert_header((TIMEOUT_HEADER, value));
+        }
+        let response = call_service(&app, req.to_request()).await;
+        assert_eq!(response.status(), status);
+    }
+
+    #[actix_rt::test]
+    async fn tile_too_large() {
+        let src = |id, data| -> TileInfoSource {
+            Box::new(TestSource {
+                id,
+                tj: tilejson! { tiles: vec![] },
+                data,
+            })
+        };
+        let cache = MainCache::new(100);
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(SrvConfig {
+                    max_tile_size_bytes: Some(1024),
+                    ..Default::default()
+                }))
+                .app_data(Data::new(TileSources::new(vec![vec![
+                    src("large", vec![0_u8; 2048]),
+                    src("small", vec![1_u8, 2, 3]),
+                ]])))
+                .app_data(Data::new(Some(cache.clone())))
+                .route("/{source_ids}/{z}/{x}/{y}", web::get().to(get_tile)),
+        )
+        .await;
+        let get =
+            |path: &'static str| call_service(&app, TestRequest::get().uri(path).to_request());
+
+        let response = get("/large/3/1/2").await;
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = actix_web::test::read_body(response).await;
+        assert_eq!(
+            body.as_ref(),
+            b"Tile 3/1/2 of source 'large' is 2048 bytes, more than the max_tile_size_bytes of 1024 bytes"
+        );
+        // The oversized tile is not cached
+        let xyz = TileCoord { z: 3, x: 1, y: 2 };
+        assert!(
+            cache
+                .get(&CacheKey::Tile("large".to_string(), xyz))
+                .await
+                .is_none()
+        );
+
+        let response = get("/small/3/1/2").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        // The composite tile fails as soon as one of its sources returns an oversized tile
+        let response = get("/small,large/3/1/2").await;
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[rstest]
+    #[case::unlimited(None, StatusCode::OK, false)]
+    #[case::queued(Some((None, None)), StatusCode::OK, true)]
+    #[case::queue_full(Some((Some(0), None)), StatusCode::SERVICE_UNAVAILABLE, false)]
+    #[case::queue_timeout(Some((None, Some(100))), StatusCode::SERVICE_UNAVAILABLE, false)]
+    #[actix_rt::test]
+    async fn tile_concurrency_limit(
+        #[case] queue: Option<(Option<usize>, Option<u64>)>,
+        #[case] second_status: StatusCode,
+        #[case] serialized: bool,
+    ) {
+        let src = SlowSource(TestSource {
+            id: "slow",
+            tj: tilejson! { tiles: vec![] },
+            data: vec![1_u8, 2, 3],
+        });
+        let mut settings = SourceSettings::default();
+        if let Some((max_queued_requests, queue_timeout)) = queue {
+            let limit = ConcurrencyLimit {
+                max_concurrent_requests: 1,
+                max_queued_requests,
+                queue_timeout,
+            };
+            settings
+                .insert_concurrency_limit("slow".to_string(), limit)
+                .unwrap();
+        }
+        let settings = Data::new(settings);
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(SrvConfig::default()))
+                .app_data(Data::new(TileSources::new(vec![vec![Box::new(src)]])))
+                .app_data(Data::new(crate::NO_MAIN_CACHE))
+                .app_data(settings.clone())
+                .route("/{source_ids}/{z}/{x}/{y}", web::get().to(get_tile)),
+        )
+        .await;
+
+        let start = Instant::now();
+        let first = call_service(&app, TestRequest::get().uri("/slow/0/0/0").to_request());
+        let second = async {
+            // Make sure the first request is generating its tile before the second one is sent
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            call_service(&app, TestRequest::get().uri("/slow/1/0/0").to_request()).await
+        };
+        let (first, second) = futures::join!(first, second);
+        let elapsed = start.elapsed();
+
+        assert_eq!(first.status(), StatusCode::OK);
+        assert_eq!(second.status(), second_status);
+        if second_status == StatusCode::SERVICE_UNAVAILABLE {
+            assert_eq!(second.headers()[RETRY_AFTER], "1");
+        }
+        // Each tile takes half a second, so the serialized requests take at least a second in total
+        assert_eq!(elapsed >= Duration::from_millis(1000), serialized);
+
+        if let Some(limiter) = settings.get_concurrency_limiter("slow") {
+            let status = limiter.status();
+            assert_eq!((status.in_flight, status.queued), (0, 0));
+        }
+    }
+
+    #[actix_rt::test]
+    async fn tile_coalesced_requests() {
+        let count = std::sync::Arc::default();
+        let src = CountingSource(
+            TestSource {
+                id: "src",
+                tj: tilejson! { tiles: vec![] },
+                data: vec![1_u8, 2, 3],
+            },
+            std::sync::Arc::clone(&count),
+            Duration::from_millis(200),
+        );
+        let in_flight = Data::new(InFlightTiles::new());
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(SrvConfig::default()))
+                .app_data(Data::new(TileSources::new(vec![vec![Box::new(src)]])))
+                .app_data(Data::new(crate::NO_MAIN_CACHE))
+                .app_data(in_flight.clone())
+                .route("/{source_ids}/{z}/{x}/{y}", web::get().to(get_tile)),
+        )
+        .await;
+        let get =
+            |path: &'static str| call_service(&app, TestRequest::get().uri(path).to_request());
+
+        let responses = futures::future::join_all((0..5).map(|_| get("/src/0/0/0"))).await;
+        for response in responses {
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(
+                actix_web::test::read_body(response).await.as_ref(),
+                &[1_u8, 2, 3]
+            );
+        }
+        // The concurrent requests for the same tile share a single call to the source
+        assert_eq!(count.load(std::sync::atomic::Ordering::Relaxed), 1);
+
+        // Other tiles are generated separately, and the next requests are not served the previous result
+        let (first, second) = futures::join!(get("/src/1/0/0"), get("/src/0/0/0"));
+        assert_eq!(first.status(), StatusCode::OK);
+        assert_eq!(second.status(), StatusCode::OK);
+        assert_eq!(count.load(std::sync::atomic::Ordering::Relaxed), 3);
+        assert!(in_flight.is_empty());
+    }
+
+    #[test]
+    fn tile_row() {
+        let row = |v: &str| TileRow::try_from(v.to_string()).map(|v| (v.y, v.scale));
+        assert_eq!(row("21"), Ok((21, None)));
+        assert_eq!(row("21@2x"), Ok((21, Some(2))));
+        assert_eq!(row("0@3x"), Ok((0, Some(3))));
+        for value in ["21@1x", "21@4x", "21@2", "@2x", "-1", "a@2x"] {
+            assert!(row(value).is_err(), "{value}");
+        }
+    }
+
+    /// A source with 512px tiles
+    #[derive(Debug, Clone)]
+    struct HidpiSource(TestSource);
+
+    #[async_trait]
+    impl Source for HidpiSource {
+        fn get_id(&self) -> &str {
+            self.0.id
+        }
+
+        fn get_tilejson(&self) -> &TileJSON {
+            &self.0.tj
+        }
+
+        fn get_tile_info(&self) -> TileInfo {
+            self.0.get_tile_info()
+        }
+
+        fn get_kind(&self) -> SourceKind {
+            self.0.get_kind()
+        }
+
+        fn get_tile_scale(&self) -> u8 {
+            2
+        }
+
+        fn clone_source(&self) -> TileInfoSource {
+            Box::new(self.clone())
+        }
+
+        async fn get_tile(
+            &self,
+            xyz: TileCoord,
+            url_query: Option<&UrlQuery>,
+        ) -> MartinResult<TileData> {
+            self.0.get_tile(xyz, url_query).await
+        }
+    }
+
+    #[rstest]
+    #[case::plain("/src/0/0/0", StatusCode::OK)]
+    #[case::no_hidpi("/src/0/0/0@2x", StatusCode::NOT_FOUND)]
+    #[case::hidpi_plain("/hidpi/0/0/0", StatusCode::OK)]
+    #[case::hidpi("/hidpi/0/0/0@2x", StatusCode::OK)]
+    #[case::hidpi_other_scale("/hidpi/0/0/0@3x", StatusCode::NOT_FOUND)]
+    #[case::composite("/hidpi,src/0/0/0@2x", StatusCode::NOT_FOUND)]
+    #[case::bad_scale("/hidpi/0/0/0@4x", StatusCode::NOT_FOUND)]
+    #[actix_rt::test]
+    async fn tile_scale(#[case] uri: &str, #[case] status: StatusCode) {
+        let src = |id| TestSource {
+            id,
+            tj: tilejson! { tiles: vec![] },
+            data: vec![1_u8, 2, 3],
+        };
+        let sources: Vec<TileInfoSource> =
+            vec![Box::new(src("src")), Box::new(HidpiSource(src("hidpi")))];
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(SrvConfig::default()))
+                .app_data(Data::new(TileSources::new(vec![sources])))
+                .app_data(Data::new(crate::NO_MAIN_CACHE))
+                .route("/{source_ids}/{z}/{x}/{y}", web::get().to(get_tile)),
+        )
+        .await;
+        let response = call_service(&app, TestRequest::get().uri(uri).to_request()).await;
+        assert_eq!(response.status(), status, "{uri}");
+    }
+
+    #[rstest]
+    #[case::disabled(None, false)]
+    #[case::no_cache(Some(true), false)]
+    #[case::cache(Some(true), true)]
+    #[actix_rt::test]
+    async fn server_timing(#[case] debug_headers: Option<bool>, #[case] use_cache: bool) {
+        let src = TestSource {
+            id: "src",
+            tj: tilejson! { tiles: vec![] },
+            data: vec![1_u8, 2, 3],
+        };
+        let cache: OptMainCache = use_cache.then(|| MainCache::new(100));
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(SrvConfig {
+                    debug_headers,
+                    ..Default::default()
+                }))
+                .app_data(Data::new(TileSources::new(vec![vec![Box::new(src)]])))
+                .app_data(Data::new(cache))
+                .route("/{source_ids}/{z}/{x}/{y}", web::get().to(get_tile)),
+        )
+        .await;
+        let get_timing = async || {
+            let req = TestRequest::get().uri("/src/0/0/0").to_request();
+            let response = call_service(&app, req).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            response
+                .headers()
+                .get(SERVER_TIMING_HEADER)
+                .map(|v| v.to_str().unwrap().to_string())
+        };
+
+        let timing = get_timing().await;
+        if debug_headers.is_none() {
+            assert_eq!(timing, None);
+            return;
+        }
+        let timing = timing.unwrap();
+        let duration = timing.strip_prefix("db;dur=").unwrap();
+        let duration = duration.split(',').next().unwrap();
+        assert!(duration.parse::<f64>().is_ok(), "{timing}");
+        if use_cache {
+            assert!(timing.ends_with(", cache;desc=miss"), "{timing}");
+            // The source is not queried for a cached tile
+            assert_eq!(get_timing().await.unwrap(), "cache;desc=hit");
+        } else {
+            assert!(!timing.contains("cache"), "{timing}");
+        }
+    }
+
+    #[test]
+    fn composite_zoom_range() {
+        let src = |id, minzoom, maxzoom| -> TileInfoSource {
+            Box::new(TestSource {
+                id,
+                tj: tilejson! { tiles: vec![], minzoom: minzoom, maxzoom: maxzoom },
+                data: Vec::default(),
+            })
+        };
+        let sources = TileSources::new(vec![vec![src("a", 0, 5), src("b", 10, 14)]]);
+        let ids = |zoom| {
+            sources.get_sources("a,b", Some(zoom)).map(|(sources, ..)| {
+                sources
+                    .iter()
+                    .map(|s| s.get_id().to_string())
+                    .collect::<Vec<_>>()
+            })
+        };
+        assert_eq!(ids(3).unwrap(), vec!["a"]);
+        assert_eq!(ids(12).unwrap(), vec!["b"]);
+        // Zoom levels between the ranges of the sources are valid, but have no sources
+        assert!(ids(7).unwrap().is_empty());
+        assert!(ids(15).is_err());
+    }
+
+    #[rstest]
+    #[case::zero("/src/0/0/0", StatusCode::NO_CONTENT)]
+    #[case::max_zoom("/src/30/1073741823/1073741823", StatusCode::NO_CONTENT)]
+    #[case::x_outside_zoom_0("/src/0/1/0", StatusCode::NOT_FOUND)]
+    #[case::y_outside_zoom_0("/src/0/0/1", StatusCode::NOT_FOUND)]
+    #[case::x_outside("/src/3/999/5", StatusCode::NOT_FOUND)]
+    #[case::y_outside("/src/3/0/8", StatusCode::NOT_FOUND)]
+    #[case::x_outside_max_zoom("/src/30/1073741824/0", StatusCode::NOT_FOUND)]
+    #[case::zoom_too_high("/src/31/0/0", StatusCode::NOT_FOUND)]
+    #[case::negative_y("/src/3/1/-5", StatusCode::NOT_FOUND)]
+    #[case::negative_zoom("/src/-1/0/0", StatusCode::NOT_FOUND)]
+    #[actix_rt::test]
+    async fn tile_coordinates(#[case] path: &str, #[case] status: StatusCode) {
+        let src = TestSource {
+            id: "src",
+            tj: tilejson! { tiles: vec![] },
+            data: Vec::default(),
+        };
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(SrvConfig::default()))
+                .app_data(Data::new(TileSources::new(vec![vec![Box::new(src)]])))
+                .app_data(Data::new(crate::NO_MAIN_CACHE))
+                .route("/{source_ids}/{z}/{x}/{y}", web::get().to(get_tile)),
+        )
+        .await;
+        let response = call_service(&app, TestRequest::get().uri(path).to_request()).await;
+        assert_eq!(response.status(), status);
+    }
+
+    /// A source counting the tiles it is queried for, like the connections it would acquire from its pool,
+    /// and taking the given time to generate each of them
+    #[derive(Debug, Clone)]
+    struct CountingSource(
+        TestSource,
+        std::sync::Arc<std::sync::atomic::AtomicUsize>,
+        Duration,
+    );
+
+    #[async_trait]
+    impl Source for CountingSource {
+        fn get_id(&self) -> &str {
+            self.0.id
+        }
+
+        fn get_tilejson(&self) -> &TileJSON {
+            &self.0.tj
+        }
+
+        fn get_tile_info(&self) -> TileInfo {
+            self.0.get_tile_info()
+        }
+
+        fn get_kind(&self) -> SourceKind {
+            self.0.get_kind()
+        }
+
+        fn clone_source(&self) -> TileInfoSource {
+            Box::new(self.clone())
+        }
+
+        async fn get_tile(
+            &self,
+            xyz: TileCoord,
+            url_query: Option<&UrlQuery>,
+        ) -> MartinResult<TileData> {
+            self.1.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            tokio::time::sleep(self.2).await;
+            self.0.get_tile(xyz, url_query).await
+        }
+    }
+
+    /// A source that can tell which tiles it has without fetching them, like an `MBTiles` source.
+    /// It only has the tile 0/0/0.
+    #[derive(Debug, Clone)]
+    struct ProbingSource(CountingSource);
+
+    #[async_trait]
+    impl Source for ProbingSource {
+        fn get_id(&self) -> &str {
+            self.0.get_id()
+        }
+
+        fn get_tilejson(&self) -> &TileJSON {
+            self.0.get_tilejson()
+        }
+
+        fn get_tile_info(&self) -> TileInfo {
+            self.0.get_tile_info()
+        }
+
+        fn get_kind(&self) -> SourceKind {
+            self.0.get_kind()
+        }
+
+        fn clone_source(&self) -> TileInfoSource {
+            Box::new(self.clone())
+        }
+
+        async fn get_tile(
+            &self,
+            xyz: TileCoord,
+            url_query: Option<&UrlQuery>,
+        ) -> MartinResult<TileData> {
+            let tile = self.0.get_tile(xyz, url_query).await?;
+            Ok(if xyz.z == 0 { tile } else { Vec::new() })
+        }
+
+        fn can_probe_tiles(&self) -> bool {
+            true
+        }
+
+        async fn has_tile(&self, xyz: TileCoord) -> MartinResult<bool> {
+            Ok(xyz.z == 0)
+        }
+    }
+
+    #[actix_rt::test]
+    async fn head_probes_missing_tiles() {
+        let count = std::sync::Arc::default();
+        let src = ProbingSource(CountingSource(
+            TestSource {
+                id: "src",
+                tj: tilejson! { tiles: vec![] },
+                data: vec![1_u8, 2, 3],
+            },
+            std::sync::Arc::clone(&count),
+            Duration::ZERO,
+        ));
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(SrvConfig::default()))
+                .app_data(Data::new(TileSources::new(vec![vec![Box::new(src)]])))
+                .app_data(Data::new(crate::NO_MAIN_CACHE))
+                .route("/{source_ids}/{z}/{x}/{y}", web::route().to(get_tile)),
+        )
+        .await;
+        for (method, path, status, queries) in [
+            (Method::HEAD, "/src/0/0/0", StatusCode::OK, 1),
+            (Method::HEAD, "/src/1/0/0", StatusCode::NO_CONTENT, 0),
+            (Method::GET, "/src/1/0/0", StatusCode::NO_CONTENT, 1),
+        ] {
+            count.store(0, std::sync::atomic::Ordering::Relaxed);
+            let req = TestRequest::default().method(method.clone()).uri(path);
+            let response = call_service(&app, req.to_request()).await;
+            assert_eq!(response.status(), status, "{method} {path}");
+            let count = count.load(std::sync::atomic::Ordering::Relaxed);
+            assert_eq!(count, queries, "{method} {path}");
+        }
+    }
+
+    #[rstest]
+    #[case::inside("/src/6/10/21", None, StatusCode::OK, 1)]
+    #[case::outside("/src/6/0/0", None, StatusCode::NO_CONTENT, 0)]
+    #[case::outside_not_found("/src/6/0/0", Some(MissingTile::NotFound), StatusCode::NOT_FOUND, 0)]
+    #[case::zoom_0("/src/0/0/0", None, StatusCode::OK, 1)]
+    #[actix_rt::test]
+    async fn tile_outside_bounds(
+        #[case] path: &str,
+        #[case] missing_tile: Option<MissingTile>,
+        #[case] status: StatusCode,
+        #[case] queries: usize,
+    ) {
+        for check_bounds in [None, Some(false)] {
+            let count = std::sync::Arc::default();
+            let mut tj = tilejson! { tiles: vec![] };
+            // The bounds of the world_cities.mbtiles test file
+            tj.bounds = Some(tilejson::Bounds::new(-123.1, -37.8, 174.7, 59.3));
+            let src = CountingSource(
+                TestSource {
+                    id: "src",
+                    tj,
+                    data: vec![1_u8, 2, 3],
+                },
+                std::sync::Arc::clone(&count),
+                Duration::ZERO,
+            );
+            let settings = SourceSettings {
+                check_bounds: check_bounds
+                    .map(|v| HashMap::from([("src".to_string(), v)]))
+                    .unwrap_or_default(),
+                ..Default::default()
+            };
+            let app = init_service(
+                App::new()
+                    .app_data(Data::new(SrvConfig {
+                        missing_tile,
+                        ..Default::default()
+                    }))
+                    .app_data(Data::new(TileSources::new(vec![vec![Box::new(src)]])))
+                    .app_data(Data::new(crate::NO_MAIN_CACHE))
+                    .app_data(Data::new(settings))
+                    .route("/{source_ids}/{z}/{x}/{y}", web::get().to(get_tile)),
+            )
+            .await;
+            let response = call_service(&app, TestRequest::get().uri(path).to_request()).await;
+            let count = count.load(std::sync::atomic::Ordering::Relaxed);
+            if check_bounds.is_none() {
+                assert_eq!(response.status(), status, "{path}");
+                assert_eq!(count, queries, "{path}");
+            } else {
+                // The source is always queried if its bounds are not checked
+                assert_eq!(response.status(), StatusCode::OK, "{path}");
+                assert_eq!(count, 1, "{path}");
+            }
+        }
+    }
+
+    /// Collects the fields of the spans when they are created or recorded, like an OpenTelemetry exporter would
+    #[derive(Clone, Default)]
+    struct SpanFields(std::sync::Arc<Mutex<HashMap<String, String>>>);
+
+    impl tracing::field::Visit for SpanFields {
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            let mut fields = self.0.lock().unwrap();
+            fields.insert(field.name().to_string(), format!("{value:?}"));
+        }
+    }
+
+    impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for SpanFields {
+        fn on_new_span(
+            &self,
+            attrs: &tracing::span::Attributes<'_>,
+            _id: &tracing::span::Id,
+            _ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            attrs.record(&mut self.clone());
+        }
+
+        fn on_record(
+            &self,
+            _id: &tracing::span::Id,
+            values: &tracing::span::Record<'_>,
+            _ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            values.record(&mut self.clone());
+        }
+    }
+
+    #[actix_rt::test]
+    async fn tile_span_fields() {
+        use tracing_subscriber::layer::SubscriberExt as _;
+
+        let fields = SpanFields::default();
+        let subscriber = tracing_subscriber::registry().with(fields.clone());
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let src = TestSource {
+            id: "test_source",
+            tj: tilejson! { tiles: vec![] },
+            data: vec![1_u8, 2, 3],
+        };
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(SrvConfig::default()))
+                .app_data(Data::new(TileSources::new(vec![vec![Box::new(src)]])))
+                .app_data(Data::new(crate::NO_MAIN_CACHE))
+                .route("/{source_ids}/{z}/{x}/{y}", web::get().to(get_tile)),
+        )
+        .await;
+        let req = TestRequest::get().uri("/test_source/6/10/21").to_request();
+        assert_eq!(call_service(&app, req).await.status(), StatusCode::OK);
+
+        let fields = fields.0.lock().unwrap();
+        assert_eq!(fields["source"], "test_source");
+        assert_eq!(fields["z"], "6");
+        assert_eq!(fields["x"], "10");
+        assert_eq!(fields["y"], "21");
+        assert_eq!(fields["size"], "3");
+        let backend_ms: f64 = fields["backend_ms"].parse().unwrap();
+        assert!(backend_ms >= 0.0);
+    }
 }
@@ -1,64 +1,268 @@
+use std::collections::HashMap;
 use std::string::ToString;
 
-use actix_web::error::ErrorBadRequest;
-use actix_web::http::Uri;
-use actix_web::web::{Data, Path};
-use actix_web::{HttpRequest, HttpResponse, Result as ActixResult, middleware, route};
+use actix_web::error::{ErrorBadRequest, ErrorInternalServerError};
+use actix_web::http::header::{
+    CACHE_CONTROL, ETag, EntityTag, HOST, IfModifiedSince, IfNoneMatch, LastModified,
+};
+use actix_web::http::{StatusCode, Uri};
+use actix_web::web::{Bytes, Data, Path};
+use actix_web::{
+    HttpMessage as _, HttpRequest, HttpResponse, HttpResponseBuilder, Result as ActixResult, mime,
+};
 use itertools::Itertools as _;
-use serde::Deserialize;
+use martin_tile_utils::Format;
+use serde::{Deserialize, Serialize};
 use tilejson::{TileJSON, tilejson};
+use xxhash_rust::xxh3::xxh3_64;
 
-use crate::source::{TileInfoSource, TileSources};
-use crate::srv::SrvConfig;
+use crate::source::{TileInfoSource, TileSources, get_last_modified};
+use crate::srv::tiles::is_unmodified_since;
+use crate::srv::{SourceSettings, SrvConfig};
+use crate::utils::parse_base_path;
 
 #[derive(Deserialize)]
 pub struct SourceIDsRequest {
     pub source_ids: String,
 }
 
-#[route(
-    "/{source_ids}",
-    method = "GET",
-    method = "HEAD",
-    wrap = "middleware::Compress::default()"
-)]
+/// Serve the `TileJSON` of one or more sources, registered at the configured `tilejson` route
 #[allow(clippy::unused_async)]
-async fn get_source_info(
+#[tracing::instrument(name = "tilejson", skip_all, fields(source = %path.source_ids))]
+pub async fn get_source_info(
     req: HttpRequest,
     path: Path<SourceIDsRequest>,
     sources: Data<TileSources>,
     srv_config: Data<SrvConfig>,
+    settings: Option<Data<SourceSettings>>,
 ) -> ActixResult<HttpResponse> {
     let sources = sources.get_sources(&path.source_ids, None)?.0;
+    check_vector_layers(&sources)?;
 
-    let tiles_path = if let Some(base_path) = &srv_config.base_path {
-        format!("{base_path}/{}", path.source_ids)
+    let trust_proxy_headers = srv_config.trust_proxy_headers.unwrap_or(true);
+    let format = sources[0].get_tile_info().format.metadata_format_value();
+    let custom_path = srv_config
+        .routes
+        .as_ref()
+        .and_then(|v| v.tile_path(&path.source_ids, format));
+    let tiles_path = if let Some(tile_path) = custom_path {
+        // The tiles are not below the TileJSON path, so only the prefix can be kept
+        let prefix = srv_config
+            .base_path
+            .clone()
+            .or_else(|| get_forwarded_prefix(&req, trust_proxy_headers));
+        format!("{}{tile_path}", prefix.unwrap_or_default())
+    } else if let Some(base_path) = &srv_config.base_path {
+        format!("{base_path}/{}/{{z}}/{{x}}/{{y}}", path.source_ids)
     } else {
         req.headers()
             .get("x-rewrite-url")
             .and_then(|v| v.to_str().ok())
             .and_then(|v| v.parse::<Uri>().ok())
-            .map_or_else(|| req.path().to_string(), |v| v.path().to_string())
+            .map_or_else(
+                || {
+                    let prefix = get_forwarded_prefix(&req, trust_proxy_headers);
+                    format!("{}{}", prefix.unwrap_or_default(), req.path())
+                },
+                |v| v.path().to_string(),
+            )
+            + "/{z}/{x}/{y}"
     };
 
     let query_string = req.query_string();
     let path_and_query = if query_string.is_empty() {
-        format!("{tiles_path}/{{z}}/{{x}}/{{y}}")
+        tiles_path
     } else {
-        format!("{tiles_path}/{{z}}/{{x}}/{{y}}?{query_string}")
+        format!("{tiles_path}?{query_string}")
     };
 
     // Construct a tiles URL from the request info, including the query string if present.
-    let info = req.connection_info();
-    let tiles_url = Uri::builder()
-        .scheme(info.scheme())
-        .authority(info.host())
+    let tiles_url = get_public_url(&req, trust_proxy_headers, path_and_query)?;
+
+    let mut response = HttpResponse::Ok();
+    let mut is_unmodified = false;
+    if let Some(last_modified) = get_last_modified(&sources) {
+        response.insert_header(LastModified(last_modified.into()));
+        // `If-Modified-Since` is ignored if the request has `If-None-Match`
+        is_unmodified = req.get_header::<IfNoneMatch>().is_none()
+            && req
+                .get_header::<IfModifiedSince>()
+                .is_some_and(|v| is_unmodified_since(last_modified, &v));
+    }
+    let cache_control = settings
+        .as_ref()
+        .and_then(|v| v.get_cache_control(&path.source_ids));
+    if let Some(value) = cache_control.or(srv_config.cache_control.as_deref()) {
+        response.insert_header((CACHE_CONTROL, value));
+    }
+    for (name, value) in settings
+        .iter()
+        .flat_map(|v| v.get_headers(&path.source_ids))
+    {
+        response.insert_header((name.clone(), value.clone()));
+    }
+    if is_unmodified {
+        return Ok(response.status(StatusCode::NOT_MODIFIED).finish());
+    }
+    json_with_etag(&req, response, &merge_tilejson(&sources, tiles_url))
+}
+
+/// Respond with the serialized JSON and a weak `ETag` of it, or with `304 Not Modified`
+/// if the `If-None-Match` request header already has that `ETag`.
+/// The `ETag` is computed from the whole body, so it includes the URLs built from the request,
+/// and changes whenever the sources are reloaded with a different `TileJSON`.
+pub fn json_with_etag(
+    req: &HttpRequest,
+    response: HttpResponseBuilder,
+    value: &impl Serialize,
+) -> ActixResult<HttpResponse> {
+    Ok(EtaggedJson::new(value)?.respond_to(req, response))
+}
+
+/// Serialized JSON with its weak `ETag`, computed once to be served many times, see [`json_with_etag`]
+#[derive(Clone, Debug)]
+pub struct EtaggedJson {
+    pub body: Bytes,
+    pub etag: EntityTag,
+}
+
+impl EtaggedJson {
+    pub fn new(value: &impl Serialize) -> ActixResult<Self> {
+        let body = serde_json::to_vec(value).map_err(ErrorInternalServerError)?;
+        let etag = EntityTag::new_weak(format!("{:016x}", xxh3_64(&body)));
+        Ok(Self {
+            body: body.into(),
+            etag,
+        })
+    }
+
+    /// Respond with the JSON, or with `304 Not Modified` if the `If-None-Match` request header already has its `ETag`
+    #[must_use]
+    pub fn respond_to(self, req: &HttpRequest, mut response: HttpResponseBuilder) -> HttpResponse {
+        let is_cached = match req.get_header::<IfNoneMatch>() {
+            Some(IfNoneMatch::Any) => true,
+            Some(IfNoneMatch::Items(items)) => items.iter().any(|v| v.weak_eq(&self.etag)),
+            None => false,
+        };
+        response.insert_header(ETag(self.etag));
+        if is_cached {
+            return response.status(StatusCode::NOT_MODIFIED).finish();
+        }
+        response
+            .content_type(mime::APPLICATION_JSON)
+            .body(self.body)
+    }
+}
+
+/// Public URL of the `TileJSON` of the sources, built like the tile URLs of the `TileJSON` itself
+pub fn get_tilejson_url(
+    req: &HttpRequest,
+    srv_config: &SrvConfig,
+    source_ids: &str,
+) -> ActixResult<String> {
+    let trust_proxy_headers = srv_config.trust_proxy_headers.unwrap_or(true);
+    let prefix = srv_config
+        .base_path
+        .clone()
+        .or_else(|| get_forwarded_prefix(req, trust_proxy_headers));
+    let tilejson_path = srv_config
+        .routes
+        .clone()
+        .unwrap_or_default()
+        .tilejson_path(source_ids);
+    get_public_url(
+        req,
+        trust_proxy_headers,
+        format!("{}{tilejson_path}", prefix.unwrap_or_default()),
+    )
+}
+
+/// Public URL template of the tiles of the sources, with the `{z}`, `{x}` and `{y}` placeholders,
+/// built like the tile URLs of the `TileJSON`
+pub fn get_tiles_url(
+    req: &HttpRequest,
+    srv_config: &SrvConfig,
+    source_ids: &str,
+    format: Format,
+) -> ActixResult<String> {
+    let trust_proxy_headers = srv_config.trust_proxy_headers.unwrap_or(true);
+    let prefix = srv_config
+        .base_path
+        .clone()
+        .or_else(|| get_forwarded_prefix(req, trust_proxy_headers));
+    let tile_path = srv_config
+        .routes
+        .as_ref()
+        .and_then(|v| v.tile_path(source_ids, format.metadata_format_value()))
+        .unwrap_or_else(|| format!("/{source_ids}/{{z}}/{{x}}/{{y}}"));
+    get_public_url(
+        req,
+        trust_proxy_headers,
+        format!("{}{tile_path}", prefix.unwrap_or_default()),
+    )
+}
+
+/// Absolute URL of a path on this server, as seen by the client
+fn get_public_url(
+    req: &HttpRequest,
+    trust_proxy_headers: bool,
+    path_and_query: String,
+) -> ActixResult<String> {
+    let (scheme, host) = get_public_origin(req, trust_proxy_headers);
+    Uri::builder()
+        .scheme(scheme.as_str())
+        .authority(host.as_str())
         .path_and_query(path_and_query)
         .build()
-        .map(|tiles_url| tiles_url.to_string())
-        .map_err(|e| ErrorBadRequest(format!("Can't build tiles URL: {e}")))?;
+        .map(|url| url.to_string())
+        .map_err(|e| ErrorBadRequest(format!("Can't build URL: {e}")))
+}
+
+/// Scheme and host the client used to reach the server. If the proxy headers are trusted,
+/// they are taken from the `Forwarded` or `X-Forwarded-Proto` and `X-Forwarded-Host` headers if present.
+fn get_public_origin(req: &HttpRequest, trust_proxy_headers: bool) -> (String, String) {
+    if trust_proxy_headers {
+        let info = req.connection_info();
+        return (info.scheme().to_string(), info.host().to_string());
+    }
+    let scheme = if req.app_config().secure() {
+        "https"
+    } else {
+        "http"
+    };
+    let host = req
+        .headers()
+        .get(HOST)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_else(|| req.app_config().host());
+    (scheme.to_string(), host.to_string())
+}
 
-    Ok(HttpResponse::Ok().json(merge_tilejson(&sources, tiles_url)))
+/// Path prefix the reverse proxy removed from the request URL, from the `X-Forwarded-Prefix` header
+fn get_forwarded_prefix(req: &HttpRequest, trust_proxy_headers: bool) -> Option<String> {
+    if !trust_proxy_headers {
+        return None;
+    }
+    let prefix = req.headers().get("x-forwarded-prefix")?.to_str().ok()?;
+    parse_base_path(prefix).ok()
+}
+
+/// Make sure that the sources of a composite source do not have layers with the same ID,
+/// as their tiles could not be combined
+fn check_vector_layers(sources: &[TileInfoSource]) -> ActixResult<()> {
+    let mut layers: HashMap<&str, &str> = HashMap::new();
+    for src in sources {
+        let id = src.get_id();
+        let vector_layers = src.get_tilejson().vector_layers.iter().flatten();
+        for name in vector_layers.map(|v| v.id.as_str()).unique() {
+            if let Some(other) = layers.insert(name, id) {
+                return Err(ErrorBadRequest(format!(
+                    "Sources {other} and {id} both have a layer named {name}, and cannot be combined into one tile"
+                )));
+            }
+        }
+    }
+    Ok(())
 }
 
 #[must_use]
@@ -158,6 +362,9 @@ pub fn merge_tilejson(sources: &[TileInfoSource], tiles_url: String) -> TileJSON
 pub mod tests {
     use std::collections::BTreeMap;
 
+    use actix_web::test::{TestRequest, call_and_read_body_json, init_service};
+    use actix_web::{App, web};
+    use rstest::rstest;
     use tilejson::{Bounds, VectorLayer};
 
     use super::*;
@@ -230,4 +437,134 @@ pub mod tests {
             ])
         );
     }
+
+    #[test]
+    fn composite_tilejson() {
+        let src = |id, name: &str, minzoom, maxzoom, bounds| TestSource {
+            id,
+            tj: tilejson! {
+                tiles: vec![],
+                name: name.to_string(),
+                minzoom: minzoom,
+                maxzoom: maxzoom,
+                bounds: bounds,
+                vector_layers: vec![VectorLayer::new(
+                    name.to_string(),
+                    BTreeMap::from([("name".to_string(), "String".to_string())]),
+                )],
+            },
+            data: Vec::default(),
+        };
+        let sources: Vec<TileInfoSource> = vec![
+            Box::new(src(
+                "a",
+                "points",
+                5,
+                10,
+                Bounds::new(-10.0, -20.0, 10.0, 20.0),
+            )),
+            Box::new(src(
+                "b",
+                "lines",
+                7,
+                12,
+                Bounds::new(-20.0, -5.0, 5.0, 50.0),
+            )),
+        ];
+        assert!(check_vector_layers(&sources).is_ok());
+        let tj = merge_tilejson(
+            &sources,
+            "http://localhost:3000/a,b/{z}/{x}/{y}".to_string(),
+        );
+        insta::assert_yaml_snapshot!(tj, @r#"
+        tilejson: 3.0.0
+        tiles:
+          - "http://localhost:3000/a,b/{z}/{x}/{y}"
+        vector_layers:
+          - id: points
+            fields:
+              name: String
+          - id: lines
+            fields:
+              name: String
+        bounds:
+          - -20
+          - -20
+          - 10
+          - 50
+        maxzoom: 12
+        minzoom: 5
+        name: "points,lines"
+        "#);
+
+        let duplicate: Vec<TileInfoSource> = vec![
+            Box::new(src(
+                "a",
+                "points",
+                5,
+                10,
+                Bounds::new(-10.0, -20.0, 10.0, 20.0),
+            )),
+            Box::new(src(
+                "c",
+                "points",
+                0,
+                14,
+                Bounds::new(-20.0, -5.0, 5.0, 50.0),
+            )),
+        ];
+        assert!(check_vector_layers(&duplicate).is_err());
+    }
+
+    #[rstest]
+    #[case::direct(&[], true, "http://localhost:8080/src/{z}/{x}/{y}")]
+    #[case::forwarded(
+        &[("forwarded", "proto=https;host=example.com")],
+        true,
+        "https://example.com/src/{z}/{x}/{y}"
+    )]
+    #[case::x_forwarded(
+        &[("x-forwarded-proto", "https"), ("x-forwarded-host", "example.com"), ("x-forwarded-prefix", "/tiles/")],
+        true,
+        "https://example.com/tiles/src/{z}/{x}/{y}"
+    )]
+    #[case::x_rewrite_url(
+        &[("x-forwarded-host", "example.com"), ("x-forwarded-prefix", "/tiles"), ("x-rewrite-url", "/maps/src")],
+        true,
+        "http://example.com/maps/src/{z}/{x}/{y}"
+    )]
+    #[case::untrusted(
+        &[("forwarded", "proto=https;host=example.com"), ("x-forwarded-host", "example.com"), ("x-forwarded-prefix", "/tiles")],
+        false,
+        "http://localhost:8080/src/{z}/{x}/{y}"
+    )]
+    #[actix_rt::test]
+    async fn tiles_url_proxy_headers(
+        #[case] headers: &[(&str, &str)],
+        #[case] trust_proxy_headers: bool,
+        #[case] expected: &str,
+    ) {
+        let src = TestSource {
+            id: "src",
+            tj: tilejson! { tiles: vec![] },
+            data: Vec::default(),
+        };
+        let config = SrvConfig {
+            trust_proxy_headers: Some(trust_proxy_headers),
+            ..Default::default()
+        };
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(config))
+                .app_data(Data::new(TileSources::new(vec![vec![Box::new(src)]])))
+                .route("/{source_ids}", web::get().to(get_source_info)),
+        )
+        .await;
+        let mut req = TestRequest::get().uri("/src");
+        for header in headers {
+            req = req.insert_header(*header);
+        }
+        let tj: TileJSON = call_and_read_body_json(&app, req.to_request()).await;
+        assert_eq!(tj.tiles, vec![expected.to_string()]);
+    }
 }
@@ -0,0 +1,83 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::PathBuf;
+
+use rustls::ServerConfig;
+use serde::{Deserialize, Serialize};
+
+use crate::MartinError::{TlsCertError, TlsConfigError, TlsKeyError};
+use crate::MartinResult;
+use crate::utils::init_aws_lc_tls;
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TlsConfig {
+    /// PEM file with the certificate chain, starting with the server certificate
+    pub cert_file: PathBuf,
+    /// PEM file with the private key of the server certificate
+    pub key_file: PathBuf,
+}
+
+impl TlsConfig {
+    /// Load the certificate chain and the private key, and check that they match
+    pub fn load(&self) -> MartinResult<ServerConfig> {
+        init_aws_lc_tls()?;
+
+        let file =
+            File::open(&self.cert_file).map_err(|e| TlsCertError(e, self.cert_file.clone()))?;
+        let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| TlsCertError(e, self.cert_file.clone()))?;
+        if certs.is_empty() {
+            return Err(TlsCertError(
+                std::io::Error::other("no certificates found"),
+                self.cert_file.clone(),
+            ));
+        }
+
+        let file = File::open(&self.key_file).map_err(|e| TlsKeyError(e, self.key_file.clone()))?;
+        let key = rustls_pemfile::private_key(&mut BufReader::new(file))
+            .map_err(|e| TlsKeyError(e, self.key_file.clone()))?
+            .ok_or_else(|| {
+                TlsKeyError(
+                    std::io::Error::other("no private key found"),
+                    self.key_file.clone(),
+                )
+            })?;
+
+        ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .map_err(|e| TlsConfigError(e, self.cert_file.clone(), self.key_file.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_tls_config() {
+        let dir = PathBuf::from("../tests/fixtures/tls");
+        let cfg = |cert: &str, key: &str| TlsConfig {
+            cert_file: dir.join(cert),
+            key_file: dir.join(key),
+        };
+        assert!(cfg("cert.pem", "key.pem").load().is_ok());
+        assert!(matches!(
+            cfg("missing.pem", "key.pem").load(),
+            Err(TlsCertError(..))
+        ));
+        assert!(matches!(
+            cfg("key.pem", "key.pem").load(),
+            Err(TlsCertError(..))
+        ));
+        assert!(matches!(
+            cfg("cert.pem", "cert.pem").load(),
+            Err(TlsKeyError(..))
+        ));
+        assert!(matches!(
+            cfg("cert.pem", "other-key.pem").load(),
+            Err(TlsConfigError(..))
+        ));
+    }
+}
@@ -0,0 +1,349 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use actix_web::error::{ErrorBadRequest, ErrorConflict, ErrorNotFound, ErrorPayloadTooLarge};
+use actix_web::web::{Data, Path as UrlPath, Payload};
+use actix_web::{HttpRequest, HttpResponse, Result as ActixResult, route};
+use futures::StreamExt as _;
+use log::{info, warn};
+use mbtiles::{AggHashType, IntegrityCheckType, Mbtiles};
+use serde::{Deserialize, Serialize};
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt as _;
+
+use crate::file_config::SourceConfigExtras as _;
+use crate::mbtiles::MbtConfig;
+use crate::source::{CatalogSourceEntry, TileSources};
+use crate::srv::admin::{check_token, register_pending};
+use crate::srv::server::{Catalog, is_valid_source_id, map_internal_error};
+use crate::srv::{AdminSource, SourceOverrides, SrvConfig};
+
+pub const UPLOAD_MAX_SIZE_MB_DEFAULT: u64 = 1024;
+
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct UploadConfig {
+    /// Directory to store the uploaded `MBTiles` files in
+    pub directory: PathBuf,
+    /// Maximum size of an uploaded file in megabytes
+    pub max_size_mb: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub struct UploadRequest {
+    source_id: String,
+}
+
+/// IDs of the sources being uploaded, shared by all workers to reject concurrent uploads of the same source
+#[derive(Debug, Default)]
+pub(crate) struct Uploads(Mutex<HashSet<String>>);
+
+impl Uploads {
+    /// Mark the source as being uploaded to the `tmp` file until the returned guard is dropped,
+    /// or return `None` if it is already being uploaded
+    fn start(&self, id: &str, tmp: PathBuf) -> Option<UploadGuard<'_>> {
+        let mut ids = self.0.lock().ok()?;
+        ids.insert(id.to_string()).then(|| UploadGuard {
+            uploads: self,
+            id: id.to_string(),
+            tmp,
+        })
+    }
+}
+
+/// Ends the upload of a source when dropped, removing its temporary file if it was not published
+struct UploadGuard<'a> {
+    uploads: &'a Uploads,
+    id: String,
+    tmp: PathBuf,
+}
+
+impl Drop for UploadGuard<'_> {
+    fn drop(&mut self) {
+        // The file is already gone if it was renamed to the published file
+        let _ = std::fs::remove_file(&self.tmp);
+        if let Ok(mut ids) = self.uploads.0.lock() {
+            ids.remove(&self.id);
+        }
+    }
+}
+
+/// Store an uploaded `MBTiles` file in the upload directory, validate it, and publish it as a new source.
+/// The file is first written to a temporary `{source_id}.mbtiles.upload` file, which is removed if the upload fails,
+/// and concurrent uploads of the same source are rejected. A `{source_id}.mbtiles` file left over from a removed source
+/// is replaced, but restored if the uploaded file cannot be published.
+/// The source is stored in the admin overrides file, so that it is published again on reload and restart.
+#[route("/admin/sources/{source_id}/upload", method = "PUT")]
+#[allow(clippy::too_many_arguments)]
+async fn put_source_upload(
+    req: HttpRequest,
+    path: UrlPath<UploadRequest>,
+    mut payload: Payload,
+    config: Data<SrvConfig>,
+    uploads: Data<Uploads>,
+    overrides: Data<Mutex<SourceOverrides>>,
+    sources: Data<TileSources>,
+    catalog: Data<Catalog>,
+) -> ActixResult<HttpResponse> {
+    let admin = check_token(&req, &config)?;
+    let Some(cfg) = &config.upload else {
+        return Err(ErrorNotFound("Uploads are not enabled"));
+    };
+
+    let id = &path.source_id;
+    if !is_valid_source_id(id) {
+        return Err(ErrorBadRequest(format!("Invalid source ID {id}")));
+    }
+    if sources.get_source(id).is_ok() {
+        return Err(ErrorConflict(format!("Source {id} already exists")));
+    }
+
+    let tmp = cfg.directory.join(format!("{id}.mbtiles.upload"));
+    let Some(_upload) = uploads.start(id, tmp.clone()) else {
+        return Err(ErrorConflict(format!(
+            "Source {id} is already being uploaded"
+        )));
+    };
+    // A file left over from an interrupted upload is overwritten
+    let file = File::create(&tmp).await.map_err(map_internal_error)?;
+    let max_size = cfg.max_size_mb.unwrap_or(UPLOAD_MAX_SIZE_MB_DEFAULT) * 1024 * 1024;
+    receive_file(file, &mut payload, max_size).await?;
+    let validation = match Mbtiles::new(&tmp) {
+        Ok(mbt) => mbt
+            .open_and_validate(IntegrityCheckType::Quick, AggHashType::Off)
+            .await
+            .map(|_| ()),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = validation {
+        return Err(ErrorBadRequest(format!(
+            "Uploaded file is not a valid MBTiles file: {e}"
+        )));
+    }
+
+    let dst = cfg.directory.join(format!("{id}.mbtiles"));
+    let backup = cfg.directory.join(format!("{id}.mbtiles.bak"));
+    let replaced = tokio::fs::try_exists(&dst)
+        .await
+        .map_err(map_internal_error)?;
+    if replaced {
+        warn!(
+            "Replacing {} left over from a removed source with the uploaded file",
+            dst.display()
+        );
+        tokio::fs::rename(&dst, &backup)
+            .await
+            .map_err(map_internal_error)?;
+    }
+    let published = match tokio::fs::rename(&tmp, &dst).await {
+        Ok(()) => {
+            publish(
+                id,
+                &dst,
+                &overrides,
+                &admin.overrides_file,
+                &sources,
+                &catalog,
+            )
+            .await
+        }
+        Err(e) => Err(map_internal_error(e)),
+    };
+    match published {
+        Ok(entry) => {
+            if replaced {
+                let _ = tokio::fs::remove_file(&backup).await;
+            }
+            info!("Published uploaded source {id} from {}", dst.display());
+            Ok(HttpResponse::Created().json(entry))
+        }
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&dst).await;
+            if replaced {
+                if let Err(e) = tokio::fs::rename(&backup, &dst).await {
+                    warn!("Unable to restore {}: {e}", dst.display());
+                }
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Publish the uploaded file as a source, storing it in the overrides file first.
+/// Nothing is changed if the source cannot be created or the overrides cannot be saved.
+async fn publish(
+    id: &str,
+    dst: &Path,
+    overrides: &Mutex<SourceOverrides>,
+    overrides_file: &Path,
+    sources: &TileSources,
+    catalog: &Catalog,
+) -> ActixResult<CatalogSourceEntry> {
+    let src = MbtConfig::default()
+        .new_sources(id.to_string(), dst.to_path_buf())
+        .await
+        .map_err(map_internal_error)?;
+    {
+        let mut overrides = overrides.lock().map_err(map_internal_error)?;
+        let mut updated = overrides.clone();
+        updated.removed.remove(id);
+        updated.sources.insert(
+            id.to_string(),
+            AdminSource::Mbtiles {
+                path: dst.to_path_buf(),
+            },
+        );
+        updated.save(overrides_file).map_err(map_internal_error)?;
+        *overrides = updated;
+    }
+    let entry = src.get_catalog_entry();
+    catalog.tiles.insert(id.to_string(), entry.clone());
+    catalog.invalidate_json();
+    sources.insert(src);
+    register_pending(sources, catalog);
+    Ok(entry)
+}
+
+/// Write the request body to the file, failing once it exceeds `max_size` bytes
+async fn receive_file(mut file: File, payload: &mut Payload, max_size: u64) -> ActixResult<()> {
+    let mut size = 0_u64;
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk?;
+        size += chunk.len() as u64;
+        if size > max_size {
+            return Err(ErrorPayloadTooLarge(format!(
+                "Uploaded file exceeds the maximum size of {max_size} bytes"
+            )));
+        }
+        file.write_all(&chunk).await.map_err(map_internal_error)?;
+    }
+    file.sync_all().await.map_err(map_internal_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::App;
+    use actix_web::dev::{Service, ServiceResponse};
+    use actix_web::http::StatusCode;
+    use actix_web::http::header::AUTHORIZATION;
+    use actix_web::test::{TestRequest, call_service, init_service};
+
+    use super::*;
+    use crate::srv::AdminConfig;
+
+    async fn new_app(
+        dir: &Path,
+        overrides_file: PathBuf,
+        uploads: Data<Uploads>,
+        sources: Data<TileSources>,
+    ) -> impl Service<actix_http::Request, Response = ServiceResponse, Error = actix_web::Error>
+    {
+        let config = SrvConfig {
+            upload: Some(UploadConfig {
+                directory: dir.to_path_buf(),
+                max_size_mb: None,
+            }),
+            admin: Some(AdminConfig {
+                token: "secret".to_string(),
+                overrides_file,
+            }),
+            ..Default::default()
+        };
+        init_service(
+            App::new()
+                .app_data(Data::new(config))
+                .app_data(uploads)
+                .app_data(Data::new(Mutex::new(SourceOverrides::default())))
+                .app_data(sources)
+                .app_data(Data::new(Catalog::default()))
+                .service(put_source_upload),
+        )
+        .await
+    }
+
+    fn upload(token: &str, body: Vec<u8>) -> actix_http::Request {
+        TestRequest::put()
+            .uri("/admin/sources/cities/upload")
+            .insert_header((AUTHORIZATION, format!("Bearer {token}")))
+            .set_payload(body)
+            .to_request()
+    }
+
+    #[actix_rt::test]
+    async fn upload_mbtiles() {
+        let dir = tempfile::tempdir().unwrap();
+        let overrides_file = dir.path().join("overrides.yaml");
+        let uploads = Data::new(Uploads::default());
+        let sources = Data::new(TileSources::default());
+        let app = new_app(
+            dir.path(),
+            overrides_file.clone(),
+            uploads.clone(),
+            sources.clone(),
+        )
+        .await;
+        let data = std::fs::read("../tests/fixtures/mbtiles/world_cities.mbtiles").unwrap();
+
+        let response = call_service(&app, upload("wrong", data.clone())).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = call_service(&app, upload("secret", b"not mbtiles".to_vec())).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(!dir.path().join("cities.mbtiles.upload").exists());
+
+        // Concurrent uploads of the same source are rejected
+        let tmp = dir.path().join("cities.mbtiles.upload");
+        let in_progress = uploads.start("cities", tmp).unwrap();
+        let response = call_service(&app, upload("secret", data.clone())).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        drop(in_progress);
+
+        let response = call_service(&app, upload("secret", data.clone())).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert!(sources.get_source("cities").is_ok());
+
+        let response = call_service(&app, upload("secret", data.clone())).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        // The uploaded source is published again after a restart
+        let restarted = TileSources::default();
+        SourceOverrides::load(&overrides_file)
+            .unwrap()
+            .apply(&restarted)
+            .await
+            .unwrap();
+        assert!(restarted.get_source("cities").is_ok());
+
+        // A source removed with the admin API can be uploaded again
+        sources.remove("cities");
+        let response = call_service(&app, upload("secret", data)).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert!(!dir.path().join("cities.mbtiles.bak").exists());
+    }
+
+    #[actix_rt::test]
+    async fn upload_rollback() {
+        let dir = tempfile::tempdir().unwrap();
+        // The overrides cannot be saved in a missing directory
+        let overrides_file = dir.path().join("missing").join("overrides.yaml");
+        let sources = Data::new(TileSources::default());
+        let app = new_app(
+            dir.path(),
+            overrides_file,
+            Data::new(Uploads::default()),
+            sources.clone(),
+        )
+        .await;
+        let dst = dir.path().join("cities.mbtiles");
+        std::fs::write(&dst, b"left over").unwrap();
+        let data = std::fs::read("../tests/fixtures/mbtiles/world_cities.mbtiles").unwrap();
+
+        let response = call_service(&app, upload("secret", data)).await;
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(sources.get_source("cities").is_err());
+        assert_eq!(std::fs::read(&dst).unwrap(), b"left over");
+        assert!(!dir.path().join("cities.mbtiles.upload").exists());
+        assert!(!dir.path().join("cities.mbtiles.bak").exists());
+    }
+}
@@ -0,0 +1,111 @@
+use actix_web::Result as ActixResult;
+use actix_web::error::ErrorBadRequest;
+use serde::{Deserialize, Serialize};
+
+use crate::source::UrlQuery;
+
+pub const URL_QUERY_MAX_SIZE_DEFAULT: usize = 2048;
+pub const URL_QUERY_MAX_PARAMS_DEFAULT: usize = 16;
+pub const URL_QUERY_MAX_KEY_LENGTH_DEFAULT: usize = 64;
+pub const URL_QUERY_MAX_VALUE_LENGTH_DEFAULT: usize = 512;
+
+/// Limits of the URL query passed to the function sources that accept query parameters
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct UrlQueryLimits {
+    /// Maximum size of the whole query string in bytes. Defaults to `2048`.
+    pub max_size: Option<usize>,
+    /// Maximum number of parameters. Defaults to `16`.
+    pub max_params: Option<usize>,
+    /// Maximum length of a parameter name. Defaults to `64`.
+    pub max_key_length: Option<usize>,
+    /// Maximum length of a decoded parameter value. Defaults to `512`.
+    pub max_value_length: Option<usize>,
+}
+
+impl UrlQueryLimits {
+    /// Reject the query with `400 Bad Request` if it is over a limit,
+    /// or if a parameter name is not an identifier like `token` or `min_zoom`
+    pub fn check(&self, query_str: &str, query: &UrlQuery) -> ActixResult<()> {
+        let max_size = self.max_size.unwrap_or(URL_QUERY_MAX_SIZE_DEFAULT);
+        if query_str.len() > max_size {
+            return Err(ErrorBadRequest(format!(
+                "URL query is longer than {max_size} bytes"
+            )));
+        }
+        let max_params = self.max_params.unwrap_or(URL_QUERY_MAX_PARAMS_DEFAULT);
+        if query.len() > max_params {
+            return Err(ErrorBadRequest(format!(
+                "URL query has more than {max_params} parameters"
+            )));
+        }
+        let max_key = self
+            .max_key_length
+            .unwrap_or(URL_QUERY_MAX_KEY_LENGTH_DEFAULT);
+        let max_value = self
+            .max_value_length
+            .unwrap_or(URL_QUERY_MAX_VALUE_LENGTH_DEFAULT);
+        for (key, value) in query {
+            if !is_identifier(key) {
+                return Err(ErrorBadRequest(format!(
+                    "URL query parameter name {key:?} must only contain letters, digits, and underscores, and must not begin with a digit"
+                )));
+            }
+            if key.len() > max_key {
+                return Err(ErrorBadRequest(format!(
+                    "URL query parameter name {key} is longer than {max_key} characters"
+                )));
+            }
+            if value.len() > max_value {
+                return Err(ErrorBadRequest(format!(
+                    "URL query parameter {key} is longer than {max_value} bytes"
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_identifier(key: &str) -> bool {
+    key.chars()
+        .next()
+        .is_some_and(|v| v.is_ascii_alphabetic() || v == '_')
+        && key.chars().all(|v| v.is_ascii_alphanumeric() || v == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::web::Query;
+
+    use super::*;
+
+    fn check(limits: &UrlQueryLimits, query_str: &str) -> ActixResult<()> {
+        let query = Query::<UrlQuery>::from_query(query_str)
+            .unwrap()
+            .into_inner();
+        limits.check(query_str, &query)
+    }
+
+    #[test]
+    fn url_query_limits() {
+        let limits = UrlQueryLimits::default();
+        assert!(check(&limits, "token=martin&min_zoom=5&_x1=%20a").is_ok());
+        for query in ["bad-key=1", "1st=a", "a%20b=1", "=1", "%C3%A9=1"] {
+            assert!(check(&limits, query).is_err(), "{query}");
+        }
+        let long_value = format!("token={}", "x".repeat(513));
+        assert!(check(&limits, &long_value).is_err());
+        let long_key = format!("{}=1", "k".repeat(65));
+        assert!(check(&limits, &long_key).is_err());
+        let many = (0..17).map(|i| format!("p{i}=1")).collect::<Vec<_>>();
+        assert!(check(&limits, &many.join("&")).is_err());
+        assert!(check(&limits, &many[..16].join("&")).is_ok());
+
+        let limits = UrlQueryLimits {
+            max_size: Some(9),
+            ..Default::default()
+        };
+        assert!(check(&limits, "token=abc").is_ok());
+        assert!(check(&limits, "token=abcd").is_err());
+    }
+}
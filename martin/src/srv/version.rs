@@ -0,0 +1,79 @@
+use actix_web::http::header::CACHE_CONTROL;
+use actix_web::{HttpResponse, Responder, route};
+use serde::Serialize;
+
+/// Response header with the version of the server, added to all responses if enabled with `version_header`
+pub const VERSION_HEADER: &str = "x-martin-version";
+
+/// Build information of the running server, see `build.rs`
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct VersionInfo {
+    pub version: &'static str,
+    pub git_hash: &'static str,
+    pub build_date: &'static str,
+    /// Cargo features enabled at compile time
+    pub features: Vec<&'static str>,
+}
+
+impl VersionInfo {
+    #[must_use]
+    pub fn new() -> Self {
+        let features = [
+            ("cog", cfg!(feature = "cog")),
+            ("fonts", cfg!(feature = "fonts")),
+            ("lambda", cfg!(feature = "lambda")),
+            ("mbtiles", cfg!(feature = "mbtiles")),
+            ("pmtiles", cfg!(feature = "pmtiles")),
+            ("postgres", cfg!(feature = "postgres")),
+            ("sprites", cfg!(feature = "sprites")),
+            ("webui", cfg!(feature = "webui")),
+        ];
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            git_hash: env!("MARTIN_GIT_HASH"),
+            build_date: env!("MARTIN_BUILD_DATE"),
+            features: features
+                .into_iter()
+                .filter_map(|(name, enabled)| enabled.then_some(name))
+                .collect(),
+        }
+    }
+
+    /// Value of the [`VERSION_HEADER`], e.g. `0.15.0+1a2b3c4d5e6f`
+    #[must_use]
+    pub fn header_value() -> String {
+        format!("{}+{}", env!("CARGO_PKG_VERSION"), env!("MARTIN_GIT_HASH"))
+    }
+}
+
+impl Default for VersionInfo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Return the version, git commit, build date, and enabled features of the server
+#[route("/version", method = "GET", method = "HEAD")]
+#[allow(clippy::unused_async)]
+async fn get_version() -> impl Responder {
+    HttpResponse::Ok()
+        .insert_header((CACHE_CONTROL, "no-cache"))
+        .json(VersionInfo::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::App;
+    use actix_web::test::{TestRequest, call_and_read_body_json, init_service};
+
+    use super::*;
+
+    #[actix_rt::test]
+    async fn version() {
+        let app = init_service(App::new().service(get_version)).await;
+        let req = TestRequest::get().uri("/version").to_request();
+        let body: serde_json::Value = call_and_read_body_json(&app, req).await;
+        assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(body, serde_json::to_value(VersionInfo::new()).unwrap());
+    }
+}
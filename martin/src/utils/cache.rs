@@ -7,7 +7,7 @@ pub type MainCache = Cache<CacheKey, CacheValue>;
 pub type OptMainCache = Option<MainCache>;
 pub const NO_MAIN_CACHE: OptMainCache = None;
 
-#[derive(Debug, Hash, PartialEq, Eq)]
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub enum CacheKey {
     /// (`pmtiles_id`, `offset`)
     PmtDirectory(usize, usize),
@@ -2,6 +2,9 @@ use std::error::Error;
 use std::fmt::Write as _;
 use std::io;
 use std::path::PathBuf;
+use std::sync::Arc;
+
+use martin_tile_utils::{MvtError, TileCoord};
 
 /// A convenience [`Result`] for Martin crate.
 pub type MartinResult<T> = Result<T, MartinError>;
@@ -37,6 +40,83 @@ pub enum MartinError {
     #[error("Base path must be a valid URL path, and must begin with a '/' symbol, but is '{0}'")]
     BasePathError(String),
 
+    #[error("Cache peer must be an http or https URL, but is '{0}'")]
+    PeerUrlError(String),
+
+    #[error(
+        "CORS origin must be a scheme and host with an optional port, e.g. https://maps.example.com, but is '{0}'"
+    )]
+    CorsOriginError(String),
+
+    #[error("CORS allow_credentials requires a list of allowed origins")]
+    CorsCredentialsError,
+
+    #[error("CORS allowed method '{0}' is not a valid HTTP method")]
+    CorsMethodError(String),
+
+    #[error("CORS allowed header '{0}' is not a valid HTTP header name")]
+    CorsHeaderError(String),
+
+    #[error("Rate limit requests, period_secs, and burst must be greater than zero")]
+    RateLimitError,
+
+    #[error(
+        "Exporting the spans to {0} requires Martin to be built with the otlp feature, e.g. cargo build --features otlp"
+    )]
+    OtlpDisabledError(String),
+
+    #[error("Unable to export the spans to {0}: {1}")]
+    OtlpError(String, String),
+
+    #[error("Source '{0}' has too many concurrent requests, retry after {1} seconds")]
+    SourceBusyError(String, u64),
+
+    /// Error of a tile generated once for several concurrent requests, see [`crate::srv::InFlightTiles`]
+    #[error(transparent)]
+    CoalescedTileError(Arc<MartinError>),
+
+    #[error("The max_concurrent_requests of source '{0}' must be greater than zero")]
+    ConcurrencyLimitError(String),
+
+    #[error("The {0} setting must be between 1 and {1}, but is {2}")]
+    SrvValueError(&'static str, u64, u64),
+
+    #[error("The {0} setting must not be empty")]
+    EmptyTokenError(&'static str),
+
+    #[error("Unknown feature {0} in request_features, the supported features are: {1}")]
+    UnknownRequestFeature(String, String),
+
+    #[error(
+        "The {0} setting requires the admin setting, which provides the token and stores the sources"
+    )]
+    AdminRequiredError(&'static str),
+
+    #[error("Static files directory {} does not exist or is not a directory", .0.display())]
+    StaticDirError(PathBuf),
+
+    #[error(
+        "Static files mount path must begin with a '/' symbol, and must not be '/' or begin with a reserved path like /catalog, but is '{0}'"
+    )]
+    StaticMountError(String),
+
+    #[error(
+        "The {0} route must begin with a '/' symbol and contain the {1} placeholders exactly once, but is '{2}'"
+    )]
+    RouteTemplateError(&'static str, String, String),
+
+    #[error("Unable to create HTTP client for cache peers: {0}")]
+    PeerClientError(reqwest::Error),
+
+    #[error("Unable to load TLS certificate file {1}: {0}")]
+    TlsCertError(io::Error, PathBuf),
+
+    #[error("Unable to load TLS private key file {1}: {0}")]
+    TlsKeyError(io::Error, PathBuf),
+
+    #[error("Unable to use TLS certificate file {1} with private key file {2}: {0}")]
+    TlsConfigError(rustls::Error, PathBuf, PathBuf),
+
     #[error("Unable to load config file {1}: {0}")]
     ConfigLoadError(io::Error, PathBuf),
 
@@ -46,6 +126,59 @@ pub enum MartinError {
     #[error("Unable to write config file {1}: {0}")]
     ConfigWriteError(io::Error, PathBuf),
 
+    #[error("Unable to parse source overrides file {1}: {0}")]
+    OverridesParseError(serde_yaml::Error, PathBuf),
+
+    #[error("Unable to serialize source overrides for {1}: {0}")]
+    OverridesSerializeError(serde_yaml::Error, PathBuf),
+
+    #[error("Unable to read the missing_tile_file {2} of source {1}: {0}")]
+    MissingTileFileError(io::Error, String, PathBuf),
+
+    #[error(
+        "The missing_tile_file {1} of source {0} is not in a known tile format, e.g. PNG or JPEG"
+    )]
+    MissingTileFormatError(String, PathBuf),
+
+    #[error("Header '{1}' of source {0} is not a valid header, or cannot be changed")]
+    SourceHeaderError(String, String),
+
+    #[error("Source alias '{0}' is a reserved keyword, and cannot be used as a source ID")]
+    AliasReservedError(String),
+
+    #[error("Source alias '{0}' is already the ID of a source or another alias")]
+    AliasConflictError(String),
+
+    #[error("Source alias '{0}' points to '{1}', which is not an existing source ID")]
+    AliasTargetError(String, String),
+
+    #[error("Fallback source '{0}' is a reserved keyword, or is already the ID of a source")]
+    FallbackConflictError(String),
+
+    #[error("Fallback source '{0}' includes '{1}', which is an alias instead of a source ID")]
+    FallbackMemberError(String, String),
+
+    #[error("Fallback source '{0}' includes itself, directly or through other fallback sources")]
+    FallbackCycleError(String),
+
+    #[error(
+        "Fallback source '{0}' must include one or more sources, all with the same format and encoding"
+    )]
+    FallbackFormatError(String),
+
+    #[error(
+        "Source '{0}' has max_overzoom set, but only MVT tiles that are uncompressed or compressed with gzip or brotli can be overzoomed"
+    )]
+    OverzoomFormatError(String),
+
+    #[error("Unable to overzoom tile {1} of source {0}: {2}")]
+    OverzoomTileError(String, TileCoord, MvtError),
+
+    #[error(
+        "Tile {1:#} of source '{0}' is {2} bytes, more than the max_tile_size_bytes of {3} bytes"
+    )]
+    TileTooLarge(String, TileCoord, usize, usize),
+
     #[error(
         "No tile sources found. Set sources by giving a database connection string on command line, env variable, or a config file."
     )]
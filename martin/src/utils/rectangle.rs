@@ -1,4 +1,6 @@
+use martin_tile_utils::{TileCoord, bbox_to_xyz};
 use serde::Serialize;
+use tilejson::Bounds;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct TileRect {
@@ -35,6 +37,21 @@ impl TileRect {
         }
     }
 
+    /// The tiles of a zoom level covering the bounds, which must not cross the antimeridian
+    #[must_use]
+    pub fn from_bounds(zoom: u8, bounds: &Bounds) -> Self {
+        let (min_x, min_y, max_x, max_y) =
+            bbox_to_xyz(bounds.left, bounds.bottom, bounds.right, bounds.top, zoom);
+        Self::new(zoom, min_x, min_y, max_x, max_y)
+    }
+
+    #[must_use]
+    pub fn contains(&self, xyz: TileCoord) -> bool {
+        self.zoom == xyz.z
+            && (self.min_x..=self.max_x).contains(&xyz.x)
+            && (self.min_y..=self.max_y).contains(&xyz.y)
+    }
+
     #[must_use]
     pub fn is_overlapping(&self, other: &Self) -> bool {
         self.zoom == other.zoom
@@ -117,6 +134,20 @@ mod tests {
         assert_eq!(15, TileRect::new(0, 2, 3, 4, 7).size());
     }
 
+    #[test]
+    fn test_from_bounds() {
+        let bounds = Bounds::new(-123.1, -37.8, 174.7, 59.3);
+        let rect = TileRect::from_bounds(6, &bounds);
+        assert_eq!(rect, TileRect::new(6, 10, 18, 63, 39));
+        assert!(rect.contains(TileCoord { z: 6, x: 10, y: 21 }));
+        assert!(!rect.contains(TileCoord { z: 6, x: 0, y: 0 }));
+        assert!(!rect.contains(TileCoord { z: 5, x: 10, y: 21 }));
+        assert_eq!(
+            TileRect::from_bounds(0, &Bounds::MAX_TILED),
+            TileRect::new(0, 0, 0, 0, 0)
+        );
+    }
+
     #[test]
     fn test_tile_range_is_overlapping() {
         let r1 = TileRect::new(0, 0, 0, 0, 0);
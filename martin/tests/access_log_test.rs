@@ -0,0 +1,80 @@
+#![cfg(feature = "mbtiles")]
+
+use std::sync::Mutex;
+
+use actix_web::test::{TestRequest, call_service, init_service};
+use indoc::indoc;
+use log::{LevelFilter, Log, Metadata, Record};
+
+pub mod utils;
+pub use utils::*;
+
+/// Keeps the messages logged with the `martin::access` target, instead of printing them
+struct CaptureLogger(Mutex<Vec<String>>);
+
+impl Log for CaptureLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.target() == "martin::access"
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            self.0.lock().unwrap().push(record.args().to_string());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: CaptureLogger = CaptureLogger(Mutex::new(Vec::new()));
+
+macro_rules! create_app {
+    ($sources:expr) => {{
+        let (state, cfg) = mock_sources(mock_cfg($sources)).await;
+        let access_log = cfg.srv.access_log.clone().unwrap_or_default();
+        init_service(
+            ::actix_web::App::new()
+                .app_data(actix_web::web::Data::new(
+                    ::martin::srv::Catalog::new(&state).unwrap(),
+                ))
+                .app_data(actix_web::web::Data::new(::martin::NO_MAIN_CACHE))
+                .app_data(actix_web::web::Data::new(state.tiles))
+                .app_data(actix_web::web::Data::new(state.settings))
+                .app_data(actix_web::web::Data::new(cfg.srv.clone()))
+                .wrap(access_log.new_middleware().unwrap())
+                .configure(|c| ::martin::srv::router(c, &cfg.srv)),
+        )
+        .await
+    }};
+}
+
+#[actix_rt::test]
+async fn access_log_format_and_exclusion() {
+    log::set_logger(&LOGGER).unwrap();
+    log::set_max_level(LevelFilter::Info);
+
+    let app = create_app! { indoc! {r#"
+        access_log:
+            format: '"%r" %s %{source}xo %D'
+            target: martin::access
+            exclude_paths: [/health]
+        mbtiles:
+            sources:
+                m_mvt: ../tests/fixtures/mbtiles/world_cities.mbtiles
+    "#} };
+
+    for path in ["/health", "/m_mvt/6/10/21", "/health", "/catalog"] {
+        let response = call_service(&app, TestRequest::get().uri(path).to_request()).await;
+        assert!(response.status().is_success(), "{path}");
+        // The line is logged when the response body is dropped
+        drop(response);
+    }
+
+    let lines = LOGGER.0.lock().unwrap().clone();
+    assert_eq!(lines.len(), 2, "{lines:?}");
+    assert!(lines[0].starts_with(r#""GET /m_mvt/6/10/21 HTTP/1.1" 200 m_mvt "#));
+    assert!(lines[1].starts_with(r#""GET /catalog HTTP/1.1" 200 - "#));
+    // The response time in milliseconds
+    let duration = lines[0].rsplit(' ').next().unwrap();
+    assert!(duration.parse::<f64>().is_ok(), "{duration}");
+}
@@ -0,0 +1,109 @@
+#![cfg(feature = "mbtiles")]
+
+use std::net::TcpListener;
+
+use ctor::ctor;
+use indoc::indoc;
+use martin::srv::{SrvConfig, new_server};
+
+pub mod utils;
+pub use utils::*;
+
+#[ctor]
+fn init() {
+    let _ = env_logger::builder().is_test(true).try_init();
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+#[actix_rt::test]
+async fn multiple_listen_addresses() {
+    let ports = [free_port(), free_port()];
+    let state = mock_sources(mock_cfg(indoc! {"
+        mbtiles:
+            sources:
+                m_mvt: ../tests/fixtures/mbtiles/world_cities.mbtiles
+    "}))
+    .await
+    .0;
+    let config = SrvConfig {
+        listen_addresses: Some(format!("127.0.0.1:{},127.0.0.1:{}", ports[0], ports[1])),
+        worker_processes: Some(1),
+        ..Default::default()
+    };
+    let (server, listen_addresses) = new_server(config, state).unwrap();
+    assert_eq!(
+        listen_addresses,
+        format!("127.0.0.1:{}, 127.0.0.1:{}", ports[0], ports[1])
+    );
+    let server = actix_rt::spawn(server);
+
+    for port in ports {
+        let response = reqwest::get(format!("http://127.0.0.1:{port}/health"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK, "{port}");
+        assert_eq!(response.text().await.unwrap(), "OK");
+    }
+
+    server.abort();
+}
+
+#[actix_rt::test]
+async fn server_tuning() {
+    let port = free_port();
+    let state = mock_sources(mock_cfg(indoc! {"
+        mbtiles:
+            sources:
+                m_mvt: ../tests/fixtures/mbtiles/world_cities.mbtiles
+    "}))
+    .await
+    .0;
+    let config = SrvConfig {
+        listen_addresses: Some(format!("127.0.0.1:{port}")),
+        worker_processes: Some(1),
+        backlog: Some(16),
+        max_connections: Some(8),
+        client_request_timeout: Some(2000),
+        client_disconnect_timeout: Some(500),
+        ..Default::default()
+    };
+    config.validate().unwrap();
+    let (server, _) = new_server(config, state).unwrap();
+    let server = actix_rt::spawn(server);
+
+    let response = reqwest::get(format!("http://127.0.0.1:{port}/m_mvt/0/0/0"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+    server.abort();
+}
+
+#[actix_rt::test]
+async fn listen_address_in_use() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let used = listener.local_addr().unwrap().to_string();
+    let state = mock_sources(mock_cfg(indoc! {"
+        mbtiles:
+            sources:
+                m_mvt: ../tests/fixtures/mbtiles/world_cities.mbtiles
+    "}))
+    .await
+    .0;
+    let config = SrvConfig {
+        listen_addresses: Some(format!("127.0.0.1:{}, {used}", free_port())),
+        worker_processes: Some(1),
+        ..Default::default()
+    };
+    let Err(error) = new_server(config, state) else {
+        panic!("binding to {used} should fail");
+    };
+    assert!(error.to_string().contains(&used), "{error}");
+}
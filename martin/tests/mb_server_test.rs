@@ -1,9 +1,12 @@
-use actix_web::http::header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE};
+use actix_web::http::header::{
+    ACCEPT, ACCEPT_ENCODING, CACHE_CONTROL, CONTENT_ENCODING, CONTENT_TYPE, ETAG,
+    IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED,
+};
+use actix_web::http::{Method, StatusCode};
 use actix_web::test::{TestRequest, call_service, read_body, read_body_json};
 use ctor::ctor;
 use indoc::indoc;
 use insta::assert_yaml_snapshot;
-use martin::srv::SrvConfig;
 use martin_tile_utils::{decode_brotli, decode_gzip};
 use tilejson::TileJSON;
 
@@ -17,7 +20,7 @@ fn init() {
 
 macro_rules! create_app {
     ($sources:expr) => {{
-        let state = mock_sources(mock_cfg($sources)).await.0;
+        let (state, cfg) = mock_sources(mock_cfg($sources)).await;
         ::actix_web::test::init_service(
             ::actix_web::App::new()
                 .app_data(actix_web::web::Data::new(
@@ -25,8 +28,9 @@ macro_rules! create_app {
                 ))
                 .app_data(actix_web::web::Data::new(::martin::NO_MAIN_CACHE))
                 .app_data(actix_web::web::Data::new(state.tiles))
-                .app_data(actix_web::web::Data::new(SrvConfig::default()))
-                .configure(|c| ::martin::srv::router(c, &SrvConfig::default())),
+                .app_data(actix_web::web::Data::new(state.settings))
+                .app_data(actix_web::web::Data::new(cfg.srv.clone()))
+                .configure(|c| ::martin::srv::router(c, &cfg.srv)),
         )
         .await
     }};
@@ -53,26 +57,34 @@ async fn mbt_get_catalog() {
     let response = call_service(&app, req).await;
     let response = assert_response(response).await;
     let body: serde_json::Value = read_body_json(response).await;
-    assert_yaml_snapshot!(body, @r"
+    assert_yaml_snapshot!(body, @r#"
     fonts: {}
     sprites: {}
     tiles:
       m_json:
         content_type: application/json
         name: Dummy json data
+        tilejson_url: "http://localhost:8080/m_json"
+        tiles: "http://localhost:8080/m_json/{z}/{x}/{y}"
       m_mvt:
         content_encoding: gzip
         content_type: application/x-protobuf
         description: Major cities from Natural Earth data
         name: Major cities from Natural Earth data
+        tilejson_url: "http://localhost:8080/m_mvt"
+        tiles: "http://localhost:8080/m_mvt/{z}/{x}/{y}"
       m_raw_mvt:
         content_type: application/x-protobuf
         description: Major cities from Natural Earth data
         name: Major cities from Natural Earth data
+        tilejson_url: "http://localhost:8080/m_raw_mvt"
+        tiles: "http://localhost:8080/m_raw_mvt/{z}/{x}/{y}"
       m_webp:
         content_type: image/webp
         name: ne2sr
-    ");
+        tilejson_url: "http://localhost:8080/m_webp"
+        tiles: "http://localhost:8080/m_webp/{z}/{x}/{y}"
+    "#);
 }
 
 #[actix_rt::test]
@@ -84,26 +96,75 @@ async fn mbt_get_catalog_gzip() {
     let response = assert_response(response).await;
     let body = decode_gzip(&read_body(response).await).unwrap();
     let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
-    assert_yaml_snapshot!(body, @r"
+    assert_yaml_snapshot!(body, @r#"
     fonts: {}
     sprites: {}
     tiles:
       m_json:
         content_type: application/json
         name: Dummy json data
+        tilejson_url: "http://localhost:8080/m_json"
+        tiles: "http://localhost:8080/m_json/{z}/{x}/{y}"
       m_mvt:
         content_encoding: gzip
         content_type: application/x-protobuf
         description: Major cities from Natural Earth data
         name: Major cities from Natural Earth data
+        tilejson_url: "http://localhost:8080/m_mvt"
+        tiles: "http://localhost:8080/m_mvt/{z}/{x}/{y}"
       m_raw_mvt:
         content_type: application/x-protobuf
         description: Major cities from Natural Earth data
         name: Major cities from Natural Earth data
+        tilejson_url: "http://localhost:8080/m_raw_mvt"
+        tiles: "http://localhost:8080/m_raw_mvt/{z}/{x}/{y}"
       m_webp:
         content_type: image/webp
         name: ne2sr
-    ");
+        tilejson_url: "http://localhost:8080/m_webp"
+        tiles: "http://localhost:8080/m_webp/{z}/{x}/{y}"
+    "#);
+}
+
+#[actix_rt::test]
+async fn mbt_get_catalog_urls() {
+    let app = create_app! { indoc! {"
+        base_path: /tiles
+        routes:
+            tile: /{source}/{z}/{x}/{y}.{format}
+        mbtiles:
+            sources:
+                m_json: ../tests/fixtures/mbtiles/json.mbtiles
+                m_mvt: ../tests/fixtures/mbtiles/world_cities.mbtiles
+                m_webp: ../tests/fixtures/mbtiles/webp.mbtiles
+    "} };
+
+    let req = test_get("/tiles/catalog")
+        .insert_header(("x-forwarded-proto", "https"))
+        .insert_header(("x-forwarded-host", "maps.example.com"))
+        .to_request();
+    let response = call_service(&app, req).await;
+    let response = assert_response(response).await;
+    let body: serde_json::Value = read_body_json(response).await;
+    assert_yaml_snapshot!(body["tiles"], @r#"
+    m_json:
+      content_type: application/json
+      name: Dummy json data
+      tilejson_url: "https://maps.example.com/tiles/m_json"
+      tiles: "https://maps.example.com/tiles/m_json/{z}/{x}/{y}.json"
+    m_mvt:
+      content_encoding: gzip
+      content_type: application/x-protobuf
+      description: Major cities from Natural Earth data
+      name: Major cities from Natural Earth data
+      tilejson_url: "https://maps.example.com/tiles/m_mvt"
+      tiles: "https://maps.example.com/tiles/m_mvt/{z}/{x}/{y}.pbf"
+    m_webp:
+      content_type: image/webp
+      name: ne2sr
+      tilejson_url: "https://maps.example.com/tiles/m_webp"
+      tiles: "https://maps.example.com/tiles/m_webp/{z}/{x}/{y}.webp"
+    "#);
 }
 
 #[actix_rt::test]
@@ -117,6 +178,191 @@ async fn mbt_get_tilejson() {
     assert!(headers.get(CONTENT_ENCODING).is_none());
     let body: TileJSON = read_body_json(response).await;
     assert_eq!(body.maxzoom, Some(6));
+    // Extra keys of the `json` metadata are passed through
+    assert_eq!(body.other["tilestats"]["layerCount"], 1);
+}
+
+#[actix_rt::test]
+async fn mbt_get_tilejson_raster() {
+    let app = create_app! { "
+mbtiles:
+    sources:
+        m_geo: ../tests/fixtures/mbtiles/geography-class-jpg.mbtiles
+" };
+    let req = test_get("/m_geo").to_request();
+    let response = call_service(&app, req).await;
+    let response = assert_response(response).await;
+    let mut body: TileJSON = read_body_json(response).await;
+    // The legend and the template are long HTML fragments
+    assert!(body.legend.take().unwrap().starts_with("<div style="));
+    assert!(
+        body.template
+            .take()
+            .unwrap()
+            .starts_with("{{#__location__}}")
+    );
+    assert_yaml_snapshot!(body, @r#"
+    tilejson: 3.0.0
+    tiles:
+      - "http://localhost:8080/m_geo/{z}/{x}/{y}"
+    bounds:
+      - -180
+      - -85.0511
+      - 180
+      - 85.0511
+    description: "One of the example maps that comes with TileMill - a bright & colorful world map that blends retro and high-tech with its folded paper texture and interactive flag tooltips. "
+    maxzoom: 1
+    minzoom: 0
+    name: Geography Class
+    version: 1.0.0
+    "#);
+}
+
+#[actix_rt::test]
+async fn mbt_get_with_base_path() {
+    let app = create_app! { indoc! {"
+        base_path: /tiles/
+        mbtiles:
+            sources:
+                m_mvt: ../tests/fixtures/mbtiles/world_cities.mbtiles
+    "} };
+    let req = test_get("/tiles/m_mvt")
+        .insert_header(("x-rewrite-url", "/other/m_mvt"))
+        .to_request();
+    let response = call_service(&app, req).await;
+    let response = assert_response(response).await;
+    let body: TileJSON = read_body_json(response).await;
+    assert_eq!(
+        body.tiles,
+        &["http://localhost:8080/tiles/m_mvt/{z}/{x}/{y}"]
+    );
+
+    for path in ["/tiles/catalog", "/tiles/m_mvt/0/0/0"] {
+        let response = call_service(&app, test_get(path).to_request()).await;
+        assert_response(response).await;
+    }
+    for path in ["/catalog", "/m_mvt", "/m_mvt/0/0/0"] {
+        let response = call_service(&app, test_get(path).to_request()).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND, "{path}");
+    }
+}
+
+#[actix_rt::test]
+async fn mbt_get_custom_routes() {
+    let app = create_app! { indoc! {"
+        routes:
+            tile: /tiles/{source}/{z}/{x}/{y}.pbf
+            tilejson: /tilejson/{source}.json
+        mbtiles:
+            sources:
+                m_mvt: ../tests/fixtures/mbtiles/world_cities.mbtiles
+    "} };
+    let req = test_get("/tilejson/m_mvt.json").to_request();
+    let response = assert_response(call_service(&app, req).await).await;
+    let body: TileJSON = read_body_json(response).await;
+    assert_eq!(
+        body.tiles,
+        vec!["http://localhost:8080/tiles/m_mvt/{z}/{x}/{y}.pbf"]
+    );
+
+    let req = test_get("/tiles/m_mvt/0/0/0.pbf").to_request();
+    let response = assert_response(call_service(&app, req).await).await;
+    assert_eq!(
+        response.headers().get(CONTENT_TYPE).unwrap(),
+        "application/x-protobuf"
+    );
+    assert_eq!(read_body(response).await.len(), 1828);
+
+    for path in ["/m_mvt", "/m_mvt/0/0/0", "/tiles/m_mvt/0/0/0"] {
+        let response = call_service(&app, test_get(path).to_request()).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND, "{path}");
+    }
+}
+
+#[actix_rt::test]
+async fn mbt_get_tile_format_extension() {
+    let app = create_app! { indoc! {"
+        routes:
+            tile: /{source}/{z}/{x}/{y}.{format}
+        mbtiles:
+            sources:
+                m_mvt: ../tests/fixtures/mbtiles/world_cities.mbtiles
+                m_jpg: ../tests/fixtures/mbtiles/geography-class-jpg.mbtiles
+    "} };
+    for path in [
+        "/m_mvt/0/0/0.pbf",
+        "/m_mvt/0/0/0.mvt",
+        "/m_jpg/0/0/0.jpg",
+        "/m_jpg/0/0/0.jpeg",
+    ] {
+        let response = call_service(&app, test_get(path).to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK, "{path}");
+    }
+
+    let response = call_service(&app, test_get("/m_mvt/0/0/0.png").to_request()).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body = String::from_utf8(read_body(response).await.to_vec()).unwrap();
+    assert_eq!(
+        body,
+        "The tiles are in mvt format, so the extension must be .pbf or .mvt instead of .png"
+    );
+
+    let response = call_service(&app, test_get("/m_jpg/0/0/0.png").to_request()).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body = String::from_utf8(read_body(response).await.to_vec()).unwrap();
+    assert!(body.contains(".jpg or .jpeg"), "{body}");
+}
+
+#[actix_rt::test]
+async fn mbt_get_tile_scale() {
+    let app = create_app! { indoc! {"
+        routes:
+            tile: /{source}/{z}/{x}/{y}.{format}
+        mbtiles:
+            sources:
+                m_jpg: ../tests/fixtures/mbtiles/geography-class-jpg.mbtiles
+    "} };
+    let response = call_service(&app, test_get("/m_jpg/0/0/0.jpg").to_request()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // The file has no `scale` metadata value, so it has no high-DPI tiles
+    for path in ["/m_jpg/0/0/0@2x.jpg", "/m_jpg/0/0/0@3x.jpg"] {
+        let response = call_service(&app, test_get(path).to_request()).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND, "{path}");
+    }
+    let response = call_service(&app, test_get("/m_jpg").to_request()).await;
+    let body: serde_json::Value = read_body_json(response).await;
+    assert!(body.get("tileSize").is_none());
+}
+
+#[actix_rt::test]
+async fn mbt_get_tilejson_zoom_stats() {
+    let app = create_app! { "
+mbtiles:
+    zoom_stats: true
+    sources:
+        m_mvt: ../tests/fixtures/mbtiles/world_cities.mbtiles
+" };
+    let req = test_get("/m_mvt").to_request();
+    let response = call_service(&app, req).await;
+    let response = assert_response(response).await;
+    let body: TileJSON = read_body_json(response).await;
+    assert_yaml_snapshot!(body.other.get("x-martin-zoom-stats").unwrap(), @r"
+    - tile_count: 1
+      zoom: 0
+    - tile_count: 4
+      zoom: 1
+    - tile_count: 7
+      zoom: 2
+    - tile_count: 17
+      zoom: 3
+    - tile_count: 38
+      zoom: 4
+    - tile_count: 57
+      zoom: 5
+    - tile_count: 72
+      zoom: 6
+    ");
 }
 
 #[actix_rt::test]
@@ -160,6 +406,23 @@ async fn mbt_get_raster_gzip() {
     assert_eq!(body.len(), 11586);
 }
 
+#[actix_rt::test]
+async fn mbt_get_jpeg() {
+    let app = create_app! { indoc! {"
+        mbtiles:
+            sources:
+                m_jpg: ../tests/fixtures/mbtiles/geography-class-jpg.mbtiles
+    "} };
+    let accept = (ACCEPT_ENCODING, "gzip, deflate, br");
+    let req = test_get("/m_jpg/0/0/0").insert_header(accept).to_request();
+    let response = call_service(&app, req).await;
+    let response = assert_response(response).await;
+    assert_eq!(response.headers().get(CONTENT_TYPE).unwrap(), "image/jpeg");
+    assert!(response.headers().get(CONTENT_ENCODING).is_none());
+    let body = read_body(response).await;
+    assert!(body.starts_with(b"\xFF\xD8\xFF"));
+}
+
 #[actix_rt::test]
 async fn mbt_get_mvt() {
     let app = create_app! { CONFIG };
@@ -175,6 +438,659 @@ async fn mbt_get_mvt() {
     assert_eq!(body.len(), 1828);
 }
 
+#[actix_rt::test]
+async fn mbt_get_mvt_etag() {
+    let app = create_app! { CONFIG };
+    let req = test_get("/m_mvt/0/0/0").to_request();
+    let response = assert_response(call_service(&app, req).await).await;
+    let etag = response.headers().get(ETAG).unwrap().clone();
+    assert!(etag.to_str().unwrap().starts_with("W/"));
+
+    // The ETag does not depend on the encoding of the response
+    let accept = (ACCEPT_ENCODING, "gzip");
+    let req = test_get("/m_mvt/0/0/0").insert_header(accept).to_request();
+    let response = assert_response(call_service(&app, req).await).await;
+    assert_eq!(response.headers().get(CONTENT_ENCODING).unwrap(), "gzip");
+    assert_eq!(response.headers().get(ETAG).unwrap(), etag);
+
+    let req = test_get("/m_mvt/0/0/0")
+        .insert_header((IF_NONE_MATCH, etag.clone()))
+        .to_request();
+    let response = call_service(&app, req).await;
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(response.headers().get(ETAG).unwrap(), etag);
+    assert!(read_body(response).await.is_empty());
+
+    let req = test_get("/m_mvt/0/0/0")
+        .insert_header((IF_NONE_MATCH, "W/\"0000000000000000\""))
+        .to_request();
+    let response = assert_response(call_service(&app, req).await).await;
+    assert_eq!(response.headers().get(ETAG).unwrap(), etag);
+}
+
+#[actix_rt::test]
+async fn mbt_get_tilejson_and_catalog_etag() {
+    let app = create_app! { CONFIG };
+    for path in ["/m_mvt", "/m_mvt,m_raw_mvt", "/catalog"] {
+        let response = assert_response(call_service(&app, test_get(path).to_request()).await).await;
+        let etag = response.headers().get(ETAG).unwrap().clone();
+        assert!(etag.to_str().unwrap().starts_with("W/"), "{path}");
+
+        let req = test_get(path).insert_header((IF_NONE_MATCH, etag.clone()));
+        let response = call_service(&app, req.to_request()).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED, "{path}");
+        assert_eq!(response.headers().get(ETAG).unwrap(), etag);
+        assert!(read_body(response).await.is_empty());
+
+        let req = test_get(path).insert_header((IF_NONE_MATCH, "W/\"0000000000000000\""));
+        let response = assert_response(call_service(&app, req.to_request()).await).await;
+        assert_eq!(response.headers().get(ETAG).unwrap(), etag);
+
+        // The tile URLs in the body depend on the host, and so does the ETag
+        let req = test_get(path)
+            .insert_header(("host", "tiles.example.com"))
+            .insert_header((IF_NONE_MATCH, etag.clone()));
+        let response = assert_response(call_service(&app, req.to_request()).await).await;
+        assert_ne!(response.headers().get(ETAG).unwrap(), etag, "{path}");
+    }
+}
+
+#[actix_rt::test]
+async fn mbt_last_modified() {
+    let dir = std::env::temp_dir().join("martin-mbt-last-modified");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("cities.mbtiles");
+    std::fs::copy("../tests/fixtures/mbtiles/world_cities.mbtiles", &path).unwrap();
+    let set_mtime = |secs: u64| {
+        let time = std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs);
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(time)
+            .unwrap();
+    };
+    let config = format!("mbtiles:\n  sources:\n    cities: {}", path.display());
+
+    set_mtime(1_700_000_000);
+    let app = create_app! { &config };
+    let old_date = "Tue, 14 Nov 2023 22:13:20 GMT";
+    for path in ["/cities", "/cities/0/0/0"] {
+        let response = assert_response(call_service(&app, test_get(path).to_request()).await).await;
+        assert_eq!(response.headers().get(LAST_MODIFIED).unwrap(), old_date);
+
+        let req = test_get(path).insert_header((IF_MODIFIED_SINCE, old_date));
+        let response = call_service(&app, req.to_request()).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED, "{path}");
+        assert!(read_body(response).await.is_empty());
+
+        let req =
+            test_get(path).insert_header((IF_MODIFIED_SINCE, "Tue, 14 Nov 2023 22:13:19 GMT"));
+        assert_response(call_service(&app, req.to_request()).await).await;
+
+        // If-None-Match takes precedence over If-Modified-Since
+        let req = test_get(path)
+            .insert_header((IF_MODIFIED_SINCE, old_date))
+            .insert_header((IF_NONE_MATCH, "W/\"0000000000000000\""));
+        assert_response(call_service(&app, req.to_request()).await).await;
+    }
+
+    // The modification time is read again when the source is opened again
+    set_mtime(1_800_000_000);
+    let app = create_app! { &config };
+    for path in ["/cities", "/cities/0/0/0"] {
+        let req = test_get(path).insert_header((IF_MODIFIED_SINCE, old_date));
+        let response = assert_response(call_service(&app, req.to_request()).await).await;
+        assert_eq!(
+            response.headers().get(LAST_MODIFIED).unwrap(),
+            "Fri, 15 Jan 2027 08:00:00 GMT"
+        );
+    }
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[actix_rt::test]
+async fn mbt_head_matches_get() {
+    let app = create_app! { CONFIG };
+    // Tile 6/0/0 is outside of the world_cities coverage
+    for path in ["/m_mvt", "/m_mvt/0/0/0", "/m_mvt/6/0/0", "/m_mvt/1/2/0"] {
+        for accept in ["identity", "gzip"] {
+            let get = test_get(path).insert_header((ACCEPT_ENCODING, accept));
+            let head = TestRequest::default()
+                .method(Method::HEAD)
+                .uri(path)
+                .insert_header((ACCEPT_ENCODING, accept));
+            let get = call_service(&app, get.to_request()).await;
+            let head = call_service(&app, head.to_request()).await;
+            assert_eq!(head.status(), get.status(), "{path} {accept}");
+            for name in [CONTENT_TYPE, CONTENT_ENCODING, ETAG] {
+                assert_eq!(
+                    head.headers().get(&name),
+                    get.headers().get(&name),
+                    "{path} {accept} {name}"
+                );
+            }
+            // The test service keeps the body of HEAD responses, which the server only uses for the Content-Length
+            let get_len = read_body(get).await.len();
+            let head_len = read_body(head).await.len();
+            assert_eq!(head_len, get_len, "{path} {accept}");
+        }
+    }
+}
+
+#[actix_rt::test]
+async fn mbt_get_cache_control() {
+    let app = create_app! { indoc! {"
+        mbtiles:
+            sources:
+                m_cached:
+                    path: ../tests/fixtures/mbtiles/world_cities.mbtiles
+                    cache_control: public, max-age=86400
+                m_mvt: ../tests/fixtures/mbtiles/world_cities.mbtiles
+    "} };
+    for path in ["/m_cached", "/m_cached/0/0/0"] {
+        let response = call_service(&app, test_get(path).to_request()).await;
+        let response = assert_response(response).await;
+        assert_eq!(
+            response.headers().get(CACHE_CONTROL).unwrap(),
+            "public, max-age=86400",
+            "{path}"
+        );
+    }
+    for path in ["/m_mvt", "/m_mvt/0/0/0", "/m_cached,m_mvt/0/0/0"] {
+        let response = call_service(&app, test_get(path).to_request()).await;
+        let response = assert_response(response).await;
+        assert!(response.headers().get(CACHE_CONTROL).is_none(), "{path}");
+    }
+}
+
+#[actix_rt::test]
+async fn mbt_get_custom_headers() {
+    let app = create_app! { indoc! {"
+        mbtiles:
+            sources:
+                m_attributed:
+                    path: ../tests/fixtures/mbtiles/world_cities.mbtiles
+                    cache_control: public, max-age=86400
+                    headers:
+                        X-Attribution: Natural Earth
+                        Cache-Control: no-store
+                m_mvt: ../tests/fixtures/mbtiles/world_cities.mbtiles
+    "} };
+    for path in [
+        "/m_attributed",
+        "/m_attributed/6/10/21",
+        "/m_attributed,m_mvt/6/0/0",
+    ] {
+        let response = call_service(&app, test_get(path).to_request()).await;
+        let response = assert_response(response).await;
+        let headers = response.headers();
+        assert_eq!(
+            headers.get("x-attribution").unwrap(),
+            "Natural Earth",
+            "{path}"
+        );
+        assert_eq!(headers.get(CACHE_CONTROL).unwrap(), "no-store", "{path}");
+    }
+    for path in ["/m_mvt", "/m_mvt/6/10/21"] {
+        let response = call_service(&app, test_get(path).to_request()).await;
+        let response = assert_response(response).await;
+        assert!(response.headers().get("x-attribution").is_none(), "{path}");
+    }
+
+    for header in ["Content-Length", "transfer-encoding", "Bad Header"] {
+        let mut cfg = mock_cfg(&format!(
+            "
+mbtiles:
+    sources:
+        m_mvt:
+            path: ../tests/fixtures/mbtiles/world_cities.mbtiles
+            headers:
+                {header}: '1'
+"
+        ));
+        let err = cfg.resolve().await.err().unwrap().to_string();
+        assert!(err.contains(&format!("'{header}'")), "{err}");
+    }
+}
+
+#[actix_rt::test]
+async fn mbt_get_alias() {
+    let app = create_app! { indoc! {"
+        mbtiles:
+            sources:
+                m_mvt:
+                    path: ../tests/fixtures/mbtiles/world_cities.mbtiles
+                    cache_control: public, max-age=86400
+                m_webp: ../tests/fixtures/mbtiles/webp.mbtiles
+        aliases:
+            cities_v2: m_mvt
+    "} };
+
+    let response = call_service(&app, test_get("/cities_v2/6/10/21").to_request()).await;
+    let response = assert_response(response).await;
+    assert_eq!(
+        response.headers().get(CACHE_CONTROL).unwrap(),
+        "public, max-age=86400"
+    );
+    let alias_tile = read_body(response).await;
+    let response = call_service(&app, test_get("/m_mvt/6/10/21").to_request()).await;
+    let response = assert_response(response).await;
+    assert_eq!(alias_tile, read_body(response).await);
+
+    let response = call_service(&app, test_get("/cities_v2").to_request()).await;
+    let response = assert_response(response).await;
+    let body: TileJSON = read_body_json(response).await;
+    assert_eq!(body.tiles, &["http://localhost:8080/cities_v2/{z}/{x}/{y}"]);
+
+    let response = call_service(&app, test_get("/catalog").to_request()).await;
+    let response = assert_response(response).await;
+    let body: serde_json::Value = read_body_json(response).await;
+    assert_eq!(body["tiles"]["cities_v2"]["alias_of"], "m_mvt");
+    assert!(body["tiles"]["m_mvt"].get("alias_of").is_none());
+}
+
+#[actix_rt::test]
+async fn mbt_alias_errors() {
+    for (alias, target) in [("catalog", "m_mvt"), ("m_webp", "m_mvt"), ("v2", "missing")] {
+        let mut cfg = mock_cfg(&format!(
+            "
+mbtiles:
+    sources:
+        m_mvt: ../tests/fixtures/mbtiles/world_cities.mbtiles
+        m_webp: ../tests/fixtures/mbtiles/webp.mbtiles
+aliases:
+    {alias}: {target}
+"
+        ));
+        let err = cfg.resolve().await.err().unwrap().to_string();
+        assert!(err.contains(&format!("'{alias}'")), "{err}");
+    }
+}
+
+#[actix_rt::test]
+async fn mbt_get_fallback() {
+    // Split the world cities into a detailed western file, and an eastern one without the zoom 6 tiles
+    let dir = std::env::temp_dir().join("martin-mbt-fallback");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    for (name, deleted, bounds, maxzoom) in [
+        (
+            "west",
+            "tile_column * 2 >= (1 << zoom_level)",
+            "-123.123590,-37.818085,0,59.352706",
+            6,
+        ),
+        (
+            "east",
+            "tile_column * 2 < (1 << zoom_level) OR zoom_level = 6",
+            "0,-37.818085,174.763027,59.352706",
+            5,
+        ),
+    ] {
+        let path = dir.join(format!("{name}.mbtiles"));
+        std::fs::copy("../tests/fixtures/mbtiles/world_cities.mbtiles", &path).unwrap();
+        let mbt = mbtiles::Mbtiles::new(&path).unwrap();
+        let mut conn = mbt.open().await.unwrap();
+        mbtiles::sqlx::query(&format!("DELETE FROM tiles WHERE {deleted}"))
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        mbt.set_metadata_value(&mut conn, "bounds", bounds)
+            .await
+            .unwrap();
+        mbt.set_metadata_value(&mut conn, "maxzoom", maxzoom)
+            .await
+            .unwrap();
+    }
+    let app = create_app! { &format!(
+        "
+mbtiles:
+    sources:
+        west: {0}/west.mbtiles
+        east: {0}/east.mbtiles
+fallbacks:
+    cities: [west, east]
+",
+        dir.display()
+    ) };
+
+    for (tile, member) in [("6/10/25", "west"), ("5/16/15", "east")] {
+        let path = format!("/cities/{tile}");
+        let response = call_service(&app, test_get(&path).to_request()).await;
+        let body = read_body(assert_response(response).await).await;
+        assert!(!body.is_empty(), "{path}");
+        let path = format!("/{member}/{tile}");
+        let response = call_service(&app, test_get(&path).to_request()).await;
+        assert_eq!(body, read_body(assert_response(response).await).await);
+    }
+    // Neither file has this tile
+    let response = call_service(&app, test_get("/cities/6/0/0").to_request()).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let response = call_service(&app, test_get("/cities").to_request()).await;
+    let tj: TileJSON = read_body_json(assert_response(response).await).await;
+    assert_eq!(
+        tj.bounds.unwrap().to_string(),
+        "-123.12359,-37.818085,174.763027,59.352706"
+    );
+    assert_eq!((tj.minzoom, tj.maxzoom), (Some(0), Some(6)));
+    assert_eq!(tj.vector_layers.unwrap().len(), 1);
+
+    // A fallback source is registered after the fallback sources it includes,
+    // and is pending until all of its members are available
+    let app = create_app! { &format!(
+        "
+mbtiles:
+    sources:
+        west: {0}/west.mbtiles
+        east: {0}/east.mbtiles
+fallbacks:
+    all: [cities, missing]
+    both: [cities]
+    cities: [west, east]
+",
+        dir.display()
+    ) };
+    let response = call_service(&app, test_get("/both/6/10/25").to_request()).await;
+    assert!(!read_body(assert_response(response).await).await.is_empty());
+    let response = call_service(&app, test_get("/all/6/10/25").to_request()).await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    let response = call_service(&app, test_get("/catalog").to_request()).await;
+    let body: serde_json::Value = read_body_json(assert_response(response).await).await;
+    assert_eq!(
+        body["tiles"]["all"]["pending"],
+        serde_json::json!(["missing"])
+    );
+    assert!(body["tiles"]["all"].get("tiles").is_none());
+    assert!(body["tiles"]["both"].get("pending").is_none());
+
+    for (id, members) in [
+        ("catalog", "[west]"),
+        ("east", "[west]"),
+        ("cities", "[cities]"),
+        ("cities", "[]"),
+    ] {
+        let mut cfg = mock_cfg(&format!(
+            "
+mbtiles:
+    sources:
+        west: {0}/west.mbtiles
+        east: {0}/east.mbtiles
+fallbacks:
+    {id}: {members}
+",
+            dir.display()
+        ));
+        let err = cfg.resolve().await.err().unwrap().to_string();
+        assert!(err.contains(&format!("'{id}'")), "{err}");
+    }
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[actix_rt::test]
+async fn mbt_get_missing_tile() {
+    let app = create_app! { indoc! {"
+        mbtiles:
+            sources:
+                m_not_found:
+                    path: ../tests/fixtures/mbtiles/world_cities.mbtiles
+                    missing_tile: 404
+                m_blank:
+                    path: ../tests/fixtures/mbtiles/world_cities.mbtiles
+                    missing_tile: blank
+                m_mvt: ../tests/fixtures/mbtiles/world_cities.mbtiles
+    "} };
+    // Tile 6/0/0 is outside of the world_cities coverage
+    for (path, status) in [
+        ("/m_not_found/6/0/0", StatusCode::NOT_FOUND),
+        ("/m_not_found/0/0/0", StatusCode::OK),
+        ("/m_blank/6/0/0", StatusCode::OK),
+        ("/m_mvt/6/0/0", StatusCode::NO_CONTENT),
+        ("/m_not_found,m_mvt/6/0/0", StatusCode::NO_CONTENT),
+    ] {
+        let response = call_service(&app, test_get(path).to_request()).await;
+        assert_eq!(response.status(), status, "{path}");
+    }
+
+    let response = call_service(&app, test_get("/m_blank/6/0/0").to_request()).await;
+    assert_eq!(
+        response.headers().get(CONTENT_TYPE).unwrap(),
+        "application/x-protobuf"
+    );
+    assert!(response.headers().get(CONTENT_ENCODING).is_none());
+    assert!(read_body(response).await.is_empty());
+}
+
+#[actix_rt::test]
+async fn mbt_get_missing_tile_file() {
+    let app = create_app! { indoc! {"
+        mbtiles:
+            sources:
+                m_png:
+                    path: ../tests/fixtures/mbtiles/geography-class-png.mbtiles
+                    missing_tile: 404
+                    missing_tile_file: ../tests/fixtures/cog/expected/not_padded.png
+                m_png_plain: ../tests/fixtures/mbtiles/geography-class-png.mbtiles
+    "} };
+    let blank = std::fs::read("../tests/fixtures/cog/expected/not_padded.png").unwrap();
+
+    // geography-class-png only has tiles up to zoom 1
+    let response = call_service(&app, test_get("/m_png/5/0/0").to_request()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers().get(CONTENT_TYPE).unwrap(), "image/png");
+    assert_eq!(read_body(response).await, blank);
+
+    let response = call_service(&app, test_get("/m_png/0/0/0").to_request()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_ne!(read_body(response).await, blank);
+
+    let response = call_service(&app, test_get("/m_png_plain/5/0/0").to_request()).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+}
+
+#[actix_rt::test]
+async fn mbt_get_tile_stats() {
+    let app = create_app! { indoc! {"
+        debug_endpoints: true
+        mbtiles:
+            sources:
+                m_mvt: ../tests/fixtures/mbtiles/world_cities.mbtiles
+                m_webp: ../tests/fixtures/mbtiles/webp.mbtiles
+    "} };
+
+    let response = call_service(&app, test_get("/m_mvt/0/0/0.json").to_request()).await;
+    let response = assert_response(response).await;
+    let body: serde_json::Value = read_body_json(response).await;
+    assert_eq!(body["size"], 1107);
+    assert_eq!(body["format"], "mvt");
+    assert_eq!(body["encoding"], "gzip");
+    assert_eq!(body["cached"], false);
+    assert!(body["backend_ms"].is_f64());
+    assert_eq!(
+        body["layers"],
+        serde_json::json!([{"name": "cities", "features": 68, "vertices": 69}])
+    );
+
+    // Raster tiles are not decoded
+    let response = call_service(&app, test_get("/m_webp/0/0/0.json").to_request()).await;
+    let response = assert_response(response).await;
+    let body: serde_json::Value = read_body_json(response).await;
+    assert_eq!(body["format"], "webp");
+    assert!(body["layers"].is_null());
+
+    // The tile route still serves the tiles
+    let response = call_service(&app, test_get("/m_mvt/0/0/0").to_request()).await;
+    assert_response(response).await;
+}
+
+#[actix_rt::test]
+async fn mbt_get_tile_geojson() {
+    let app = create_app! { indoc! {"
+        debug_endpoints: true
+        geojson_max_features: 10
+        mbtiles:
+            sources:
+                m_mvt: ../tests/fixtures/mbtiles/world_cities.mbtiles
+                m_webp: ../tests/fixtures/mbtiles/webp.mbtiles
+    "} };
+
+    let response = call_service(&app, test_get("/m_mvt/0/0/0.geojson").to_request()).await;
+    let response = assert_response(response).await;
+    assert_eq!(
+        response.headers().get(CONTENT_TYPE).unwrap(),
+        "application/geo+json"
+    );
+    let body: serde_json::Value = read_body_json(response).await;
+    assert_eq!(body["type"], "FeatureCollection");
+    assert_eq!(
+        body["warning"],
+        "The tile has 68 features, only the first 10 are included"
+    );
+    let features = body["features"].as_array().unwrap();
+    assert_eq!(features.len(), 10);
+    for feature in features {
+        assert_eq!(feature["properties"]["layer"], "cities");
+        assert_eq!(feature["geometry"]["type"], "Point");
+        let coords = feature["geometry"]["coordinates"].as_array().unwrap();
+        assert!(coords[0].as_f64().unwrap().abs() <= 180.0);
+        assert!(coords[1].as_f64().unwrap().abs() <= 85.06);
+    }
+
+    // Raster tiles cannot be converted
+    let response = call_service(&app, test_get("/m_webp/0/0/0.geojson").to_request()).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_rt::test]
+async fn mbt_tile_stats_disabled() {
+    let app = create_app! { CONFIG };
+    for path in ["/m_mvt/0/0/0.json", "/m_mvt/0/0/0.geojson"] {
+        let response = call_service(&app, test_get(path).to_request()).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND, "{path}");
+    }
+}
+
+#[actix_rt::test]
+async fn mbt_get_hidden() {
+    let app = create_app! { indoc! {"
+        mbtiles:
+            sources:
+                m_hidden:
+                    path: ../tests/fixtures/mbtiles/world_cities.mbtiles
+                    hidden: true
+                m_listed: ../tests/fixtures/mbtiles/world_cities.mbtiles
+    "} };
+
+    let response = call_service(&app, test_get("/catalog").to_request()).await;
+    let response = assert_response(response).await;
+    let body: serde_json::Value = read_body_json(response).await;
+    let ids: Vec<_> = body["tiles"].as_object().unwrap().keys().collect();
+    assert_eq!(ids, ["m_listed"]);
+
+    for path in ["/m_hidden", "/m_hidden/0/0/0"] {
+        let response = call_service(&app, test_get(path).to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK, "{path}");
+    }
+}
+
+#[actix_rt::test]
+async fn mbt_missing_tile_file_errors() {
+    for file in [
+        "../tests/fixtures/no-such-file.png",
+        "../tests/fixtures/mbtiles/json.mbtiles",
+    ] {
+        let mut cfg = mock_cfg(&format!(
+            "
+mbtiles:
+    sources:
+        m_png:
+            path: ../tests/fixtures/mbtiles/geography-class-png.mbtiles
+            missing_tile_file: {file}
+"
+        ));
+        let err = cfg.resolve().await.err().unwrap().to_string();
+        assert!(err.contains(file), "{err}");
+    }
+}
+
+#[actix_rt::test]
+async fn mbt_get_tile_above_maxzoom() {
+    let app = create_app! { indoc! {"
+        mbtiles:
+            sources:
+                m_not_found:
+                    path: ../tests/fixtures/mbtiles/world_cities.mbtiles
+                    missing_tile: 404
+                m_mvt: ../tests/fixtures/mbtiles/world_cities.mbtiles
+    "} };
+    // world_cities has tiles up to zoom 6, so zoom 7 tiles are missing without querying the file
+    for (path, status) in [
+        ("/m_mvt/7/0/0", StatusCode::NO_CONTENT),
+        ("/m_mvt/19/0/0", StatusCode::NO_CONTENT),
+        ("/m_not_found/7/0/0", StatusCode::NOT_FOUND),
+        ("/m_mvt/6/10/21", StatusCode::OK),
+    ] {
+        let response = call_service(&app, test_get(path).to_request()).await;
+        assert_eq!(response.status(), status, "{path}");
+    }
+}
+
+#[actix_rt::test]
+async fn mbt_get_overzoom() {
+    let app = create_app! { indoc! {"
+        mbtiles:
+            sources:
+                m_overzoom:
+                    path: ../tests/fixtures/mbtiles/world_cities.mbtiles
+                    max_overzoom: 2
+    "} };
+    let response = call_service(&app, test_get("/m_overzoom").to_request()).await;
+    let body: TileJSON = read_body_json(assert_response(response).await).await;
+    assert_eq!(body.maxzoom, Some(8));
+
+    // The 16 tiles covering 6/10/21 at zoom 8 are cut from it, and the ones without any features are missing
+    let mut found = 0;
+    for x in 40..44 {
+        for y in 84..88 {
+            let path = format!("/m_overzoom/8/{x}/{y}");
+            let response = call_service(&app, test_get(&path).to_request()).await;
+            match response.status() {
+                StatusCode::OK => found += 1,
+                StatusCode::NO_CONTENT => {}
+                status => panic!("{path}: {status}"),
+            }
+        }
+    }
+    assert!(found > 0);
+
+    let response = call_service(&app, test_get("/m_overzoom/9/80/168").to_request()).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let mut cfg = mock_cfg(indoc! {"
+        mbtiles:
+            sources:
+                m_png:
+                    path: ../tests/fixtures/mbtiles/geography-class-png.mbtiles
+                    max_overzoom: 2
+    "});
+    let err = cfg.resolve().await.err().unwrap().to_string();
+    assert!(err.contains("'m_png'"), "{err}");
+}
+
+#[actix_rt::test]
+async fn mbt_get_mvt_out_of_range() {
+    let app = create_app! { CONFIG };
+    for path in ["/m_mvt/1/2/0", "/m_mvt/1/0/2"] {
+        let response = call_service(&app, test_get(path).to_request()).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND, "{path}");
+    }
+}
+
 /// get an MVT tile with accepted gzip enc
 #[actix_rt::test]
 async fn mbt_get_mvt_gzip() {
@@ -300,9 +1216,72 @@ async fn mbt_get_json_gzip() {
         response.headers().get(CONTENT_TYPE).unwrap(),
         "application/json"
     );
+    // The tile is smaller than the default `compression.min_size`, so it is sent uncompressed
+    assert!(response.headers().get(CONTENT_ENCODING).is_none());
+    let body = read_body(response).await;
+    assert_eq!(body.len(), 13);
+}
+
+#[actix_rt::test]
+async fn mbt_get_compression_min_size() {
+    let app = create_app! { indoc! {"
+        compression:
+            min_size: 0
+        mbtiles:
+            sources:
+                m_json: ../tests/fixtures/mbtiles/json.mbtiles
+    "} };
+    let accept = (ACCEPT_ENCODING, "gzip");
+    let req = test_get("/m_json/0/0/0").insert_header(accept).to_request();
+    let response = call_service(&app, req).await;
+    let response = assert_response(response).await;
     assert_eq!(response.headers().get(CONTENT_ENCODING).unwrap(), "gzip");
     let body = read_body(response).await;
     assert_eq!(body.len(), 33); // this number could change if compression gets more optimized
     let body = decode_gzip(&body).unwrap();
     assert_eq!(body.len(), 13);
+
+    let app = create_app! { indoc! {"
+        compression:
+            min_size: 2000
+        mbtiles:
+            sources:
+                m_mvt: ../tests/fixtures/mbtiles/world_cities.mbtiles
+                m_raw_mvt: ../tests/fixtures/mbtiles/uncompressed_mvt.mbtiles
+    "} };
+    for (path, encoding) in [("/m_raw_mvt/0/0/0", None), ("/m_mvt/0/0/0", Some("gzip"))] {
+        let accept = (ACCEPT_ENCODING, "gzip");
+        let req = test_get(path).insert_header(accept).to_request();
+        let response = call_service(&app, req).await;
+        let response = assert_response(response).await;
+        // Tiles stored compressed are sent as is, whatever their size
+        let header = response.headers().get(CONTENT_ENCODING);
+        assert_eq!(header.map(|v| v.to_str().unwrap()), encoding, "{path}");
+    }
+}
+
+#[actix_rt::test]
+async fn mbt_get_legend() {
+    let app = create_app! { "
+mbtiles:
+    sources:
+        m_geo: ../tests/fixtures/mbtiles/geography-class-jpg.mbtiles
+        m_mvt: ../tests/fixtures/mbtiles/world_cities.mbtiles
+" };
+
+    let req = test_get("/m_geo/legend")
+        .insert_header((ACCEPT, "text/html"))
+        .to_request();
+    let response = call_service(&app, req).await;
+    let response = assert_response(response).await;
+    assert_eq!(
+        response.headers().get(CONTENT_TYPE).unwrap(),
+        "text/html; charset=utf-8"
+    );
+    let body = read_body(response).await;
+    assert!(body.starts_with(b"<div style="));
+
+    let req = test_get("/m_mvt/legend").to_request();
+    let response = call_service(&app, req).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
 }
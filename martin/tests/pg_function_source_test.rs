@@ -44,6 +44,26 @@ async fn function_source_tile() {
     assert!(!tile.is_empty());
 }
 
+#[actix_rt::test]
+async fn function_source_patterns() {
+    let cfg = mock_pgcfg(indoc! {"
+        connection_string: $DATABASE_URL
+        auto_publish:
+          tables: false
+          functions:
+            from_schemas: [publ?c, Mixed*]
+            ignore: ['function_z*', '*_row*', '?function.*']
+    "});
+    let sources = mock_sources(cfg).await.0.tiles;
+    insta::with_settings!({sort_maps => true}, {
+    assert_yaml_snapshot!(sources.get_catalog(), @r"
+    function_null:
+      content_type: application/x-protobuf
+      description: public.function_null
+    ");
+    });
+}
+
 #[actix_rt::test]
 async fn function_source_schemas() {
     let cfg = mock_pgcfg(indoc! {"
@@ -2,12 +2,16 @@
 
 use actix_http::Request;
 use actix_web::http::StatusCode;
+use actix_web::http::header::{
+    ACCEPT_ENCODING, CACHE_CONTROL, CONTENT_ENCODING, ETAG, IF_NONE_MATCH,
+};
 use actix_web::test::{TestRequest, call_and_read_body_json, call_service, read_body};
 use ctor::ctor;
 use indoc::indoc;
 use insta::assert_yaml_snapshot;
 use martin::OptOneMany;
 use martin::srv::SrvConfig;
+use martin_tile_utils::decode_gzip;
 use tilejson::TileJSON;
 
 pub mod utils;
@@ -29,6 +33,7 @@ macro_rules! create_app {
                 ))
                 .app_data(actix_web::web::Data::new(::martin::NO_MAIN_CACHE))
                 .app_data(actix_web::web::Data::new(state.tiles))
+                .app_data(actix_web::web::Data::new(state.settings))
                 .app_data(actix_web::web::Data::new(SrvConfig::default()))
                 .configure(|c| ::martin::srv::router(c, &SrvConfig::default())),
         )
@@ -59,76 +64,139 @@ postgres:
       "-function.withweired---_-characters":
         content_type: application/x-protobuf
         description: a function source with special characters
+        tilejson_url: "http://localhost:8080/-function.withweired---_-characters"
+        tiles: "http://localhost:8080/-function.withweired---_-characters/{z}/{x}/{y}"
       ".-Points-----------quote":
         content_type: application/x-protobuf
         description: Escaping test table
+        tilejson_url: "http://localhost:8080/.-Points-----------quote"
+        tiles: "http://localhost:8080/.-Points-----------quote/{z}/{x}/{y}"
       MixPoints:
         content_type: application/x-protobuf
         description: a description from comment on table
+        tilejson_url: "http://localhost:8080/MixPoints"
+        tiles: "http://localhost:8080/MixPoints/{z}/{x}/{y}"
+      My-Table:
+        content_type: application/x-protobuf
+        description: Hyphen-Schema.My-Table.Geom-Col
+        tilejson_url: "http://localhost:8080/My-Table"
+        tiles: "http://localhost:8080/My-Table/{z}/{x}/{y}"
       auto_table:
         content_type: application/x-protobuf
         description: autodetect.auto_table.geom
+        tilejson_url: "http://localhost:8080/auto_table"
+        tiles: "http://localhost:8080/auto_table/{z}/{x}/{y}"
       bigint_table:
         content_type: application/x-protobuf
         description: autodetect.bigint_table.geom
+        tilejson_url: "http://localhost:8080/bigint_table"
+        tiles: "http://localhost:8080/bigint_table/{z}/{x}/{y}"
       function_Mixed_Name:
         content_type: application/x-protobuf
         description: a function source with MixedCase name
+        tilejson_url: "http://localhost:8080/function_Mixed_Name"
+        tiles: "http://localhost:8080/function_Mixed_Name/{z}/{x}/{y}"
       function_null:
         content_type: application/x-protobuf
         description: public.function_null
+        tilejson_url: "http://localhost:8080/function_null"
+        tiles: "http://localhost:8080/function_null/{z}/{x}/{y}"
       function_null_row:
         content_type: application/x-protobuf
         description: public.function_null_row
+        tilejson_url: "http://localhost:8080/function_null_row"
+        tiles: "http://localhost:8080/function_null_row/{z}/{x}/{y}"
       function_null_row2:
         content_type: application/x-protobuf
         description: public.function_null_row2
+        tilejson_url: "http://localhost:8080/function_null_row2"
+        tiles: "http://localhost:8080/function_null_row2/{z}/{x}/{y}"
       function_zoom_xy:
         content_type: application/x-protobuf
         description: public.function_zoom_xy
+        tilejson_url: "http://localhost:8080/function_zoom_xy"
+        tiles: "http://localhost:8080/function_zoom_xy/{z}/{x}/{y}"
       function_zxy:
         content_type: application/x-protobuf
         description: public.function_zxy
+        tilejson_url: "http://localhost:8080/function_zxy"
+        tiles: "http://localhost:8080/function_zxy/{z}/{x}/{y}"
       function_zxy2:
         content_type: application/x-protobuf
         description: public.function_zxy2
+        tilejson_url: "http://localhost:8080/function_zxy2"
+        tiles: "http://localhost:8080/function_zxy2/{z}/{x}/{y}"
       function_zxy_query:
         content_type: application/x-protobuf
+        supports_url_query: true
+        tilejson_url: "http://localhost:8080/function_zxy_query"
+        tiles: "http://localhost:8080/function_zxy_query/{z}/{x}/{y}"
       function_zxy_query_jsonb:
         content_type: application/x-protobuf
         description: public.function_zxy_query_jsonb
+        supports_url_query: true
+        tilejson_url: "http://localhost:8080/function_zxy_query_jsonb"
+        tiles: "http://localhost:8080/function_zxy_query_jsonb/{z}/{x}/{y}"
       function_zxy_query_test:
         content_type: application/x-protobuf
         description: public.function_zxy_query_test
+        supports_url_query: true
+        tilejson_url: "http://localhost:8080/function_zxy_query_test"
+        tiles: "http://localhost:8080/function_zxy_query_test/{z}/{x}/{y}"
       function_zxy_row:
         content_type: application/x-protobuf
         description: public.function_zxy_row
+        tilejson_url: "http://localhost:8080/function_zxy_row"
+        tiles: "http://localhost:8080/function_zxy_row/{z}/{x}/{y}"
       function_zxy_row_key:
         content_type: application/x-protobuf
         description: public.function_zxy_row_key
+        tilejson_url: "http://localhost:8080/function_zxy_row_key"
+        tiles: "http://localhost:8080/function_zxy_row_key/{z}/{x}/{y}"
       points1:
         content_type: application/x-protobuf
         description: public.points1.geom
+        tilejson_url: "http://localhost:8080/points1"
+        tiles: "http://localhost:8080/points1/{z}/{x}/{y}"
+      points1_mvw:
+        content_type: application/x-protobuf
+        description: description of a materialized view from SQL comment
+        tilejson_url: "http://localhost:8080/points1_mvw"
+        tiles: "http://localhost:8080/points1_mvw/{z}/{x}/{y}"
       points1_vw:
         attribution: some attribution from SQL comment
         content_type: application/x-protobuf
         description: description from SQL comment
+        tilejson_url: "http://localhost:8080/points1_vw"
+        tiles: "http://localhost:8080/points1_vw/{z}/{x}/{y}"
       points2:
         content_type: application/x-protobuf
         description: public.points2.geom
+        tilejson_url: "http://localhost:8080/points2"
+        tiles: "http://localhost:8080/points2/{z}/{x}/{y}"
       points3857:
         content_type: application/x-protobuf
         description: public.points3857.geom
+        tilejson_url: "http://localhost:8080/points3857"
+        tiles: "http://localhost:8080/points3857/{z}/{x}/{y}"
       table_source:
         content_type: application/x-protobuf
+        tilejson_url: "http://localhost:8080/table_source"
+        tiles: "http://localhost:8080/table_source/{z}/{x}/{y}"
       table_source_geog:
         content_type: application/x-protobuf
+        tilejson_url: "http://localhost:8080/table_source_geog"
+        tiles: "http://localhost:8080/table_source_geog/{z}/{x}/{y}"
       table_source_multiple_geom:
         content_type: application/x-protobuf
         description: public.table_source_multiple_geom.geom1
+        tilejson_url: "http://localhost:8080/table_source_multiple_geom"
+        tiles: "http://localhost:8080/table_source_multiple_geom/{z}/{x}/{y}"
       table_source_multiple_geom.1:
         content_type: application/x-protobuf
         description: public.table_source_multiple_geom.geom2
+        tilejson_url: "http://localhost:8080/table_source_multiple_geom.1"
+        tiles: "http://localhost:8080/table_source_multiple_geom.1/{z}/{x}/{y}"
     "#);
 }
 
@@ -707,6 +775,48 @@ postgres:
     assert_response(response).await;
 }
 
+#[actix_rt::test]
+async fn pg_get_composite_table_and_function_tile() {
+    let app = create_app! { "
+postgres:
+  connection_string: $DATABASE_URL
+  tables:
+    table_source:
+      schema: public
+      table: table_source
+      srid: 4326
+      geometry_column: geom
+      bounds: [-180.0, -90.0, 180.0, 90.0]
+      geometry_type: GEOMETRY
+      properties:
+        gid: int4
+  functions:
+    function_zxy_query:
+      schema: public
+      function: function_zxy_query
+    function_zxy_query_copy:
+      schema: public
+      function: function_zxy_query
+"};
+
+    let response = call_service(&app, test_get("/table_source/0/0/0")).await;
+    let table_tile = read_body(assert_response(response).await).await;
+    let response = call_service(&app, test_get("/function_zxy_query/0/0/0")).await;
+    let function_tile = read_body(assert_response(response).await).await;
+    assert!(!table_tile.is_empty());
+    assert!(!function_tile.is_empty());
+
+    let req = test_get("/table_source,function_zxy_query/0/0/0?token=martin");
+    let response = call_service(&app, req).await;
+    let body = read_body(assert_response(response).await).await;
+    assert_eq!(body, [table_tile, function_tile].concat());
+
+    // Both function sources produce a layer with the same name
+    let req = test_get("/function_zxy_query,function_zxy_query_copy/0/0/0");
+    let response = call_service(&app, req).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
 #[actix_rt::test]
 async fn pg_get_composite_source_tile_minmax_zoom_ok() {
     let app = create_app! { "
@@ -887,6 +997,121 @@ postgres:
     assert_response(response).await;
 }
 
+#[actix_rt::test]
+async fn pg_get_function_source_tile_etag() {
+    let app = create_app! { "
+postgres:
+  connection_string: $DATABASE_URL
+"};
+
+    let response = call_service(&app, test_get("/function_zxy_query/0/0/0")).await;
+    let response = assert_response(response).await;
+    let etag = response.headers().get(ETAG).unwrap().clone();
+    assert!(etag.to_str().unwrap().starts_with("W/"));
+
+    let req = TestRequest::get()
+        .uri("/function_zxy_query/0/0/0")
+        .insert_header((IF_NONE_MATCH, etag.clone()))
+        .to_request();
+    let response = call_service(&app, req).await;
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(response.headers().get(ETAG).unwrap(), etag);
+    assert!(read_body(response).await.is_empty());
+}
+
+#[actix_rt::test]
+async fn pg_get_cache_control() {
+    let app = create_app! { "
+postgres:
+  connection_string: $DATABASE_URL
+  tables:
+    points1:
+      schema: public
+      table: points1
+      geometry_column: geom
+      srid: 4326
+      cache_control: max-age=3600
+  functions:
+    function_zxy_query:
+      schema: public
+      function: function_zxy_query
+"};
+
+    for path in ["/points1", "/points1/0/0/0"] {
+        let response = call_service(&app, test_get(path)).await;
+        let response = assert_response(response).await;
+        assert_eq!(
+            response.headers().get(CACHE_CONTROL).unwrap(),
+            "max-age=3600",
+            "{path}"
+        );
+    }
+    let response = call_service(&app, test_get("/function_zxy_query/0/0/0")).await;
+    let response = assert_response(response).await;
+    assert!(response.headers().get(CACHE_CONTROL).is_none());
+}
+
+#[actix_rt::test]
+async fn pg_get_compressed_tiles() {
+    let app = create_app! { "
+postgres:
+  connection_string: $DATABASE_URL
+  compress_min_size: 1
+  tables:
+    points1:
+      schema: public
+      table: points1
+      geometry_column: geom
+      srid: 4326
+      compress_tiles: gzip
+"};
+    let get = |encoding: &'static str| {
+        TestRequest::get()
+            .uri("/points1/0/0/0")
+            .insert_header((ACCEPT_ENCODING, encoding))
+            .to_request()
+    };
+
+    let response = assert_response(call_service(&app, get("gzip")).await).await;
+    assert_eq!(response.headers().get(CONTENT_ENCODING).unwrap(), "gzip");
+    let body = decode_gzip(&read_body(response).await).unwrap();
+    assert!(!body.is_empty());
+
+    // Clients without gzip support get the decompressed tile
+    for encoding in ["identity", "br;q=0, gzip;q=0, identity"] {
+        let response = assert_response(call_service(&app, get(encoding)).await).await;
+        assert!(
+            response.headers().get(CONTENT_ENCODING).is_none(),
+            "{encoding}"
+        );
+        assert_eq!(read_body(response).await, body, "{encoding}");
+    }
+    let response = call_service(&app, test_get("/points1/0/0/0")).await;
+    let response = assert_response(response).await;
+    assert!(response.headers().get(CONTENT_ENCODING).is_none());
+    assert_eq!(read_body(response).await, body);
+
+    // Tiles under the size threshold are stored uncompressed, but still served with the accepted encoding
+    let app = create_app! { "
+postgres:
+  connection_string: $DATABASE_URL
+  compress_min_size: 1000000
+  tables:
+    points1:
+      schema: public
+      table: points1
+      geometry_column: geom
+      srid: 4326
+      compress_tiles: gzip
+"};
+    let response = assert_response(call_service(&app, get("gzip")).await).await;
+    assert_eq!(response.headers().get(CONTENT_ENCODING).unwrap(), "gzip");
+    assert_eq!(decode_gzip(&read_body(response).await).unwrap(), body);
+    let response = assert_response(call_service(&app, get("identity")).await).await;
+    assert!(response.headers().get(CONTENT_ENCODING).is_none());
+    assert_eq!(read_body(response).await, body);
+}
+
 #[actix_rt::test]
 async fn pg_get_function_source_tile_minmax_zoom_ok() {
     let app = create_app! {"
@@ -959,6 +1184,13 @@ postgres:
     let req = test_get("/function_zxy_query_test/0/0/0?token=martin");
     let response = call_service(&app, req).await;
     assert_response(response).await;
+
+    let long_token = "x".repeat(3000);
+    for query in ["token=martin&bad-key=1", &format!("token={long_token}")] {
+        let req = test_get(&format!("/function_zxy_query_test/0/0/0?{query}"));
+        let response = call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{query}");
+    }
 }
 
 #[actix_rt::test]
@@ -1,9 +1,11 @@
 #![cfg(feature = "postgres")]
 
 use ctor::ctor;
+use futures::TryStreamExt as _;
 use indoc::indoc;
 use insta::assert_yaml_snapshot;
-use martin_tile_utils::TileCoord;
+use martin::TileRect;
+use martin_tile_utils::{MvtValue, TileCoord, decode_mvt, get_mvt_stats};
 pub mod utils;
 pub use utils::*;
 
@@ -26,6 +28,9 @@ async fn table_source() {
     MixPoints:
       content_type: application/x-protobuf
       description: a description from comment on table
+    My-Table:
+      content_type: application/x-protobuf
+      description: Hyphen-Schema.My-Table.Geom-Col
     auto_table:
       content_type: application/x-protobuf
       description: autodetect.auto_table.geom
@@ -70,6 +75,9 @@ async fn table_source() {
     points1:
       content_type: application/x-protobuf
       description: public.points1.geom
+    points1_mvw:
+      content_type: application/x-protobuf
+      description: description of a materialized view from SQL comment
     points1_vw:
       content_type: application/x-protobuf
       description: description from SQL comment
@@ -99,14 +107,14 @@ async fn table_source() {
     table: table_source
     srid: 4326
     geometry_column: geom
+    id_column: gid
     bounds:
       - -2
       - -1
       - 142.84131509869133
       - 45
     geometry_type: GEOMETRY
-    properties:
-      gid: int4
+    properties: {}
     ");
 
     let source2 = table(&mock, "table_source_geog");
@@ -115,14 +123,14 @@ async fn table_source() {
     table: table_source_geog
     srid: 4326
     geometry_column: geog
+    id_column: gid
     bounds:
       - -2
       - 0
       - 142.84131509869133
       - 45
     geometry_type: Geometry
-    properties:
-      gid: int4
+    properties: {}
     ");
 }
 
@@ -135,8 +143,7 @@ async fn tables_tilejson() {
     tiles: []
     vector_layers:
       - id: table_source
-        fields:
-          gid: int4
+        fields: {}
     bounds:
       - -2
       - -1
@@ -159,6 +166,25 @@ async fn tables_tile_ok() {
     assert!(!tile.is_empty());
 }
 
+#[actix_rt::test]
+async fn tables_tiles_bulk_ok() {
+    let mock = mock_sources(mock_pgcfg("connection_string: $DATABASE_URL")).await;
+    let src = source(&mock, "table_source");
+    let tiles: Vec<_> = src
+        .get_tiles_bulk(TileRect::new(2, 0, 0, 3, 3))
+        .await
+        .unwrap()
+        .unwrap()
+        .try_collect()
+        .await
+        .unwrap();
+
+    assert!(!tiles.is_empty());
+    for (xyz, data) in tiles {
+        assert_eq!(data, src.get_tile(xyz, None).await.unwrap(), "tile {xyz}");
+    }
+}
+
 #[actix_rt::test]
 async fn tables_srid_ok() {
     let mock = mock_sources(mock_pgcfg(indoc! {"
@@ -180,6 +206,100 @@ async fn tables_srid_ok() {
     assert_eq!(source.srid, 900_913);
 }
 
+#[actix_rt::test]
+async fn tables_materialized_view() {
+    let cfg = mock_pgcfg(indoc! {"
+        connection_string: $DATABASE_URL
+        tables:
+          points_mvw:
+            schema: public
+            table: points1_mvw
+            geometry_column: geom
+            srid: 4326
+            properties:
+              gid: int4
+    "});
+    let mock = mock_sources(cfg).await;
+    assert!(table(&mock, "points_mvw").bounds.is_some());
+
+    let tile = source(&mock, "points_mvw")
+        .get_tile(TileCoord { z: 0, x: 0, y: 0 }, None)
+        .await
+        .unwrap();
+    assert_eq!(get_mvt_stats(&tile).unwrap()[0].features, 30);
+}
+
+#[actix_rt::test]
+async fn tables_geography() {
+    let mock = mock_sources(mock_pgcfg("connection_string: $DATABASE_URL")).await;
+    assert_eq!(table(&mock, "table_source_geog").srid, 4326);
+
+    // Both tables have the same features, except for the curves that geography columns cannot contain
+    let features = async |id: &str| {
+        let tile = source(&mock, id)
+            .get_tile(TileCoord { z: 0, x: 0, y: 0 }, None)
+            .await
+            .unwrap();
+        let mut features: Vec<_> = decode_mvt(&tile).unwrap()[0]
+            .features
+            .iter()
+            .filter(|v| v.id.is_some_and(|id| id <= 14))
+            .map(|v| (v.id, v.geom_type, v.geometry.clone()))
+            .collect();
+        features.sort_by_key(|v| v.0);
+        features
+    };
+    let geography = features("table_source_geog").await;
+    assert!(!geography.is_empty());
+    assert_eq!(geography, features("table_source").await);
+}
+
+#[actix_rt::test]
+async fn tables_quoted_names() {
+    let expected: Vec<_> = [(1, "first"), (2, "second"), (3, "third")]
+        .into_iter()
+        .map(|(gid, name)| {
+            let value = MvtValue::String(name.to_string());
+            (Some(gid), vec![("select".to_string(), value)])
+        })
+        .collect();
+    let features = async |mock: &MockSource, id: &str| {
+        let tile = source(mock, id)
+            .get_tile(TileCoord { z: 0, x: 0, y: 0 }, None)
+            .await
+            .unwrap();
+        let mut features: Vec<_> = decode_mvt(&tile).unwrap()[0]
+            .features
+            .iter()
+            .map(|v| (v.id, v.properties.clone()))
+            .collect();
+        features.sort_by_key(|v| v.0);
+        features
+    };
+
+    let mock = mock_sources(mock_pgcfg("connection_string: $DATABASE_URL")).await;
+    assert_eq!(
+        table(&mock, "My-Table").id_column.as_deref(),
+        Some("Row-Id")
+    );
+    assert_eq!(features(&mock, "My-Table").await, expected);
+
+    let mock = mock_sources(mock_pgcfg(indoc! {"
+        connection_string: $DATABASE_URL
+        tables:
+          configured:
+            schema: hyphen-schema
+            table: my-table
+            geometry_column: geom-col
+            srid: 4326
+            id_column: row-id
+            properties:
+              select: text
+    "}))
+    .await;
+    assert_eq!(features(&mock, "configured").await, expected);
+}
+
 #[actix_rt::test]
 async fn tables_multiple_geom_ok() {
     let mock = mock_sources(mock_pgcfg("connection_string: $DATABASE_URL")).await;
@@ -191,6 +311,188 @@ async fn tables_multiple_geom_ok() {
     assert_eq!(source.geometry_column, "geom2");
 }
 
+#[actix_rt::test]
+async fn tables_filter() {
+    let cfg = mock_pgcfg(indoc! {"
+        connection_string: $DATABASE_URL
+        tables:
+          points_all:
+            schema: public
+            table: points1
+            geometry_column: geom
+            srid: 4326
+          points_low:
+            schema: public
+            table: points1
+            geometry_column: geom
+            srid: 4326
+            filter: gid <= 3
+          points_typo:
+            schema: public
+            table: points1
+            geometry_column: geom
+            srid: 4326
+            filter: gidd <= 3
+    "});
+    let mock = mock_sources(cfg).await;
+    let count_features = async |id: &str| {
+        let tile = source(&mock, id)
+            .get_tile(TileCoord { z: 0, x: 0, y: 0 }, None)
+            .await
+            .unwrap();
+        get_mvt_stats(&tile).unwrap()[0].features
+    };
+    assert_eq!(count_features("points_all").await, 30);
+    assert_eq!(count_features("points_low").await, 3);
+
+    let tilejson = source(&mock, "points_low").get_tilejson().clone();
+    assert_eq!(
+        tilejson.description.as_deref(),
+        Some("public.points1.geom WHERE gid <= 3")
+    );
+
+    // The filter is checked when the source is created
+    assert!(mock.0.tiles.get_source("points_typo").is_err());
+}
+
+#[actix_rt::test]
+async fn tables_feature_ids() {
+    let cfg = mock_pgcfg(indoc! {"
+        connection_string: $DATABASE_URL
+        auto_publish:
+          tables:
+            from_schemas: public
+          functions: false
+        tables:
+          points_low:
+            schema: public
+            table: points1
+            geometry_column: geom
+            srid: 4326
+            id_column: gid
+            filter: gid <= 3
+          points_text_id:
+            schema: public
+            table: points1
+            geometry_column: geom
+            srid: 4326
+            id_column: geom
+    "});
+    let mock = mock_sources(cfg).await;
+    let feature_ids = async |id: &str| {
+        let tile = source(&mock, id)
+            .get_tile(TileCoord { z: 0, x: 0, y: 0 }, None)
+            .await
+            .unwrap();
+        let mut ids: Vec<_> = decode_mvt(&tile).unwrap()[0]
+            .features
+            .iter()
+            .map(|v| v.id)
+            .collect();
+        ids.sort_unstable();
+        ids
+    };
+    assert_eq!(
+        feature_ids("points_low").await,
+        vec![Some(1), Some(2), Some(3)]
+    );
+
+    // The integer primary key of the auto-published tables is used as the feature ID
+    assert_eq!(table(&mock, "points2").id_column.as_deref(), Some("gid"));
+    let ids = feature_ids("points2").await;
+    assert_eq!(ids, (1..=30).map(Some).collect::<Vec<_>>());
+
+    // A non-integer id_column is ignored
+    assert_eq!(table(&mock, "points_text_id").id_column, None);
+    assert!(
+        feature_ids("points_text_id")
+            .await
+            .iter()
+            .all(Option::is_none)
+    );
+}
+
+#[actix_rt::test]
+async fn tables_simplify() {
+    // The curve polygon of the fixture is converted to a polygon with many vertices
+    let cfg = mock_pgcfg(indoc! {"
+        connection_string: $DATABASE_URL
+        tables:
+          detailed:
+            schema: public
+            table: table_source
+            geometry_column: geom
+            srid: 4326
+            filter: ST_GeometryType(geom) = 'ST_CurvePolygon'
+          simplified:
+            schema: public
+            table: table_source
+            geometry_column: geom
+            srid: 4326
+            filter: ST_GeometryType(geom) = 'ST_CurvePolygon'
+            simplify: { 1: 50000, 5: 1000 }
+          simplified_auto:
+            schema: public
+            table: table_source
+            geometry_column: geom
+            srid: 4326
+            filter: ST_GeometryType(geom) = 'ST_CurvePolygon'
+            simplify: auto
+    "});
+    let mock = mock_sources(cfg).await;
+    let get_tile = async |id: &str, z: u8, x: u32, y: u32| {
+        source(&mock, id)
+            .get_tile(TileCoord { z, x, y }, None)
+            .await
+            .unwrap()
+    };
+    let detailed = get_tile("detailed", 1, 1, 0).await;
+    let simplified = get_tile("simplified", 1, 1, 0).await;
+    let simplified_auto = get_tile("simplified_auto", 1, 1, 0).await;
+    assert!(
+        simplified.len() * 2 < detailed.len(),
+        "{} vs {}",
+        simplified.len(),
+        detailed.len()
+    );
+    assert!(simplified_auto.len() < detailed.len());
+    for tile in [&simplified, &simplified_auto] {
+        let stats = get_mvt_stats(tile).unwrap();
+        assert_eq!(stats[0].features, 1);
+        assert!(stats[0].vertices >= 4);
+    }
+
+    // Zooms above the last threshold are not simplified, e.g. the tile north-east of the center of the world
+    let geometry = async |id: &str| {
+        let tile = get_tile(id, 6, 32, 31).await;
+        decode_mvt(&tile).unwrap()[0].features[0].geometry.clone()
+    };
+    assert_eq!(geometry("simplified").await, geometry("detailed").await);
+}
+
+#[actix_rt::test]
+async fn table_source_patterns() {
+    let cfg = mock_pgcfg(indoc! {r#"
+        connection_string: $DATABASE_URL
+        auto_publish:
+          tables:
+            from_schemas: ['auto*', 'mixed*', '"Mixed*"']
+            ignore: ['*_backup', 'bigint_*']
+          functions: false
+    "#});
+    let sources = mock_sources(cfg).await.0;
+    insta::with_settings!({sort_maps => true}, {
+    assert_yaml_snapshot!(sources.tiles.get_catalog(), @r"
+    MixPoints:
+      content_type: application/x-protobuf
+      description: a description from comment on table
+    auto_table:
+      content_type: application/x-protobuf
+      description: autodetect.auto_table.geom
+    ");
+    });
+}
+
 #[actix_rt::test]
 async fn table_source_schemas() {
     let cfg = mock_pgcfg(indoc! {"
@@ -51,13 +51,15 @@ async fn pmt_get_catalog() {
     let response = call_service(&app, req).await;
     let response = assert_response(response).await;
     let body: serde_json::Value = read_body_json(response).await;
-    assert_yaml_snapshot!(body, @r"
+    assert_yaml_snapshot!(body, @r#"
     fonts: {}
     sprites: {}
     tiles:
       stamen_toner__raster_CC-BY-ODbL_z3:
         content_type: image/png
-    ");
+        tilejson_url: "http://localhost:8080/stamen_toner__raster_CC-BY-ODbL_z3"
+        tiles: "http://localhost:8080/stamen_toner__raster_CC-BY-ODbL_z3/{z}/{x}/{y}"
+    "#);
 }
 
 #[actix_rt::test]
@@ -69,13 +71,15 @@ async fn pmt_get_catalog_gzip() {
     let response = assert_response(response).await;
     let body = decode_gzip(&read_body(response).await).unwrap();
     let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
-    assert_yaml_snapshot!(body, @r"
+    assert_yaml_snapshot!(body, @r#"
     fonts: {}
     sprites: {}
     tiles:
       p_png:
         content_type: image/png
-    ");
+        tilejson_url: "http://localhost:8080/p_png"
+        tiles: "http://localhost:8080/p_png/{z}/{x}/{y}"
+    "#);
 }
 
 #[actix_rt::test]
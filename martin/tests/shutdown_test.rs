@@ -0,0 +1,102 @@
+#![cfg(all(unix, feature = "mbtiles"))]
+
+use std::net::TcpListener;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use ctor::ctor;
+use indoc::indoc;
+use martin::srv::{SrvConfig, new_server};
+use martin::{MartinResult, Source, TileData, TileInfoSource, UrlQuery};
+use martin_tile_utils::{Encoding, Format, TileCoord, TileInfo};
+use tilejson::{TileJSON, tilejson};
+
+pub mod utils;
+pub use utils::*;
+
+#[ctor]
+fn init() {
+    let _ = env_logger::builder().is_test(true).try_init();
+}
+
+/// A source that takes a second to generate each tile
+#[derive(Clone, Debug)]
+struct SlowSource {
+    tilejson: TileJSON,
+}
+
+#[async_trait]
+impl Source for SlowSource {
+    fn get_id(&self) -> &'static str {
+        "slow"
+    }
+
+    fn get_tilejson(&self) -> &TileJSON {
+        &self.tilejson
+    }
+
+    fn get_tile_info(&self) -> TileInfo {
+        TileInfo::new(Format::Mvt, Encoding::Uncompressed)
+    }
+
+    fn clone_source(&self) -> TileInfoSource {
+        Box::new(self.clone())
+    }
+
+    async fn get_tile(
+        &self,
+        _xyz: TileCoord,
+        _url_query: Option<&UrlQuery>,
+    ) -> MartinResult<TileData> {
+        actix_rt::time::sleep(Duration::from_secs(1)).await;
+        Ok(b"slow tile".to_vec())
+    }
+}
+
+#[actix_rt::test]
+async fn in_flight_request_completes_on_sigterm() {
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let state = mock_sources(mock_cfg(indoc! {"
+        mbtiles:
+            sources:
+                m_mvt: ../tests/fixtures/mbtiles/world_cities.mbtiles
+    "}))
+    .await
+    .0;
+    state.tiles.insert(Box::new(SlowSource {
+        tilejson: tilejson! { tiles: vec![] },
+    }));
+    let config = SrvConfig {
+        listen_addresses: Some(format!("127.0.0.1:{port}")),
+        worker_processes: Some(1),
+        shutdown_timeout: Some(5),
+        ..Default::default()
+    };
+    let (server, _) = new_server(config, state).unwrap();
+    let server = actix_rt::spawn(server);
+
+    let request = actix_rt::spawn(reqwest::get(format!("http://127.0.0.1:{port}/slow/0/0/0")));
+    actix_rt::time::sleep(Duration::from_millis(300)).await;
+    let status = std::process::Command::new("kill")
+        .args(["-TERM", &std::process::id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let response = request.await.unwrap().unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    assert_eq!(response.headers()[reqwest::header::CONNECTION], "close");
+    assert_eq!(response.bytes().await.unwrap().as_ref(), b"slow tile");
+
+    // The server stops once the request has completed
+    server.await.unwrap().unwrap();
+    assert!(
+        reqwest::get(format!("http://127.0.0.1:{port}/health"))
+            .await
+            .is_err()
+    );
+}
@@ -0,0 +1,72 @@
+#![cfg(feature = "mbtiles")]
+
+use std::net::TcpListener;
+
+use ctor::ctor;
+use indoc::indoc;
+use martin::srv::{SrvConfig, TlsConfig, new_server};
+
+pub mod utils;
+pub use utils::*;
+
+#[ctor]
+fn init() {
+    let _ = env_logger::builder().is_test(true).try_init();
+}
+
+const CERT_FILE: &str = "../tests/fixtures/tls/cert.pem";
+const KEY_FILE: &str = "../tests/fixtures/tls/key.pem";
+
+#[actix_rt::test]
+async fn https_listener() {
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let state = mock_sources(mock_cfg(indoc! {"
+        mbtiles:
+            sources:
+                m_mvt: ../tests/fixtures/mbtiles/world_cities.mbtiles
+    "}))
+    .await
+    .0;
+    let config = SrvConfig {
+        listen_addresses: Some(format!("127.0.0.1:{port}")),
+        worker_processes: Some(1),
+        tls: Some(TlsConfig {
+            cert_file: CERT_FILE.into(),
+            key_file: KEY_FILE.into(),
+        }),
+        ..Default::default()
+    };
+    let (server, _) = new_server(config, state).unwrap();
+    let server = actix_rt::spawn(server);
+
+    // The fixture certificate is self-signed for localhost
+    let cert = reqwest::Certificate::from_pem(&std::fs::read(CERT_FILE).unwrap()).unwrap();
+    let client = reqwest::Client::builder()
+        .add_root_certificate(cert)
+        .build()
+        .unwrap();
+    let response = client
+        .get(format!("https://localhost:{port}/health"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    assert_eq!(response.text().await.unwrap(), "OK");
+
+    let response = client
+        .get(format!("https://localhost:{port}/m_mvt/0/0/0"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    assert_eq!(
+        response.headers()[reqwest::header::CONTENT_TYPE],
+        "application/x-protobuf"
+    );
+
+    server.abort();
+}
@@ -1,10 +1,12 @@
+use std::io::Read as _;
 use std::path::{Path, PathBuf};
 
 use clap::{Parser, Subcommand};
-use log::error;
+use log::{error, warn};
 use mbtiles::{
-    AggHashType, CopyDuplicateMode, CopyType, IntegrityCheckType, MbtResult, MbtTypeCli, Mbtiles,
-    MbtilesCopier, PatchTypeCli, UpdateZoomType, apply_patch,
+    AggHashType, ClusterOrder, CopyDuplicateMode, CopyType, FlipYMode, IntegrityCheckType,
+    MbtResult, MbtTypeCli, Mbtiles, MbtilesCopier, PatchTypeCli, UpdateZoomType, apply_patch,
+    validate_metadata_value,
 };
 use tilejson::Bounds;
 
@@ -13,12 +15,15 @@ use tilejson::Bounds;
     version,
     name = "mbtiles",
     about = "A utility to work with .mbtiles file content",
-    after_help = "Use RUST_LOG environment variable to control logging level, e.g. RUST_LOG=debug or RUST_LOG=mbtiles=debug. See https://docs.rs/env_logger/latest/env_logger/index.html#enabling-logging for more information."
+    after_help = "Use --quiet or --verbose to show fewer or more log messages. For finer control, use RUST_LOG environment variable, e.g. RUST_LOG=debug or RUST_LOG=mbtiles=debug. See https://docs.rs/env_logger/latest/env_logger/index.html#enabling-logging for more information."
 )]
 pub struct Args {
-    /// Display detailed information
-    #[arg(short, long, hide = true)]
+    /// Display detailed information, including debug messages
+    #[arg(short, long, global = true, conflicts_with = "quiet")]
     verbose: bool,
+    /// Only display errors
+    #[arg(short, long, global = true)]
+    quiet: bool,
     #[command(subcommand)]
     command: Commands,
 }
@@ -28,7 +33,13 @@ pub struct Args {
 enum Commands {
     /// Show MBTiles file summary statistics
     #[command(name = "summary", alias = "info")]
-    Summary { file: PathBuf },
+    Summary {
+        file: PathBuf,
+        /// Instead of the summary, estimate how much space tile deduplication would save,
+        /// or how much it already saves if the file is normalized.
+        #[arg(long)]
+        dedup_estimate: bool,
+    },
     /// Prints all values in the metadata table in a free-style, unstable YAML format
     #[command(name = "meta-all")]
     MetaAll {
@@ -52,6 +63,13 @@ enum Commands {
         key: String,
         /// Value to set, or nothing if the key should be deleted.
         value: Option<String>,
+        /// Read the value to set from a file instead, or from stdin if the file is `-`.
+        /// Useful for large values like the `json` key.
+        #[arg(long = "file", value_name = "PATH", conflicts_with = "value")]
+        value_file: Option<PathBuf>,
+        /// Skip validation of well-known keys like bounds, center, minzoom, maxzoom, format, and json.
+        #[arg(long)]
+        force: bool,
     },
     /// Compare two files A and B, and generate a new diff file. If the diff file is applied to A, it will produce B.
     #[command(name = "diff")]
@@ -59,6 +77,18 @@ enum Commands {
     /// Copy tiles from one mbtiles file to another.
     #[command(name = "copy", alias = "cp")]
     Copy(CopyArgs),
+    /// Copy all tiles and metadata to a new file, storing the tiles of each zoom level along a space-filling curve.
+    /// The result is verified to have the same content as the source.
+    #[command(name = "cluster")]
+    Cluster {
+        /// MBTiles file to read from
+        src_file: PathBuf,
+        /// MBTiles file to write to
+        dst_file: PathBuf,
+        /// Order of the tiles within each zoom level
+        #[arg(long, value_enum, default_value_t = ClusterOrder::default())]
+        order: ClusterOrder,
+    },
     /// Apply diff file generated from 'copy' command
     #[command(name = "apply-patch", alias = "apply-diff")]
     ApplyPatch {
@@ -79,6 +109,32 @@ enum Commands {
         #[arg(long, value_enum, default_value_t=UpdateZoomType::default())]
         update_zoom: UpdateZoomType,
     },
+    /// Insert or replace a single tile. Coordinates use the XYZ tile scheme.
+    #[command(name = "tile-set")]
+    TileSet {
+        /// MBTiles file to modify
+        file: PathBuf,
+        /// Zoom level of the tile
+        z: u8,
+        /// Column of the tile
+        x: u32,
+        /// Row of the tile in the XYZ scheme
+        y: u32,
+        /// File with the tile data, or `-` to read it from stdin
+        data: PathBuf,
+    },
+    /// Delete a single tile. Coordinates use the XYZ tile scheme.
+    #[command(name = "tile-delete")]
+    TileDelete {
+        /// MBTiles file to modify
+        file: PathBuf,
+        /// Zoom level of the tile
+        z: u8,
+        /// Column of the tile
+        x: u32,
+        /// Row of the tile in the XYZ scheme
+        y: u32,
+    },
     /// Validate tile data if hash of tile data exists in file
     #[command(name = "validate", alias = "check", alias = "verify")]
     Validate {
@@ -93,6 +149,13 @@ enum Commands {
         /// How should the aggregate tiles hash be checked or updated.
         #[arg(long, value_enum)]
         agg_hash: Option<AggHashType>,
+        /// Verify that every tile blob is intact, e.g. not truncated: compressed MVT tiles must decompress and decode,
+        /// image headers and lengths must be consistent, and tiles must not be empty.
+        #[arg(long)]
+        validate_content: bool,
+        /// Maximum number of invalid tiles to report with `--validate-content`
+        #[arg(long, default_value_t = 20)]
+        max_content_errors: u64,
     },
 }
 
@@ -118,6 +181,9 @@ pub struct CopyArgs {
     /// Specify the type of patch file to generate.
     #[arg(long, requires("diff_with_file"), default_value_t=PatchTypeCli::default())]
     patch_type: PatchTypeCli,
+    /// Print the number of tiles and bytes per zoom level that would be copied, without creating or modifying the destination file.
+    #[arg(long, conflicts_with_all(["diff_with_file", "apply_patch"]))]
+    dry_run: bool,
 }
 
 #[allow(clippy::doc_markdown)]
@@ -168,9 +234,40 @@ pub struct SharedCopyOpts {
     /// Force copy operation, ignoring some warnings that otherwise would prevent the operation. Use with caution.
     #[arg(short, long)]
     force: bool,
+    /// Copy tiles into an existing destination file even if its tiles have a different format than the source tiles, e.g. JPEG and MVT.
+    #[arg(long)]
+    force_format: bool,
     /// Perform agg_hash validation on the original and destination files.
     #[arg(long)]
     validate: bool,
+    /// Do not copy UTFGrid interactivity data (grids and grid_data), even if the source file has it.
+    #[arg(long)]
+    no_grids: bool,
+    /// SQLite page size of a newly created destination file, a power of two between 512 and 65536.
+    /// Defaults to the page size of the source file.
+    /// Larger pages (e.g. 4096 or more) keep large tile blobs in fewer overflow pages, making copying and reading faster,
+    /// while smaller pages waste less space in files with many tiny tiles.
+    #[arg(long)]
+    page_size: Option<u32>,
+    /// Copy tiles using this many parallel jobs, each with its own source and destination connection.
+    /// Only used when creating a new flat or flat-with-hash file, otherwise tiles are copied serially.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u16).range(1..))]
+    jobs: Option<u16>,
+    /// Do not copy empty tiles, i.e. zero-length tiles and gzip-ed tiles that are empty once decompressed (MVT without layers).
+    #[arg(long)]
+    skip_empty: bool,
+    /// Do not copy tiles that are byte-identical to the content of this file, e.g. a blank PNG or a solid ocean JPEG tile.
+    #[arg(long, value_name = "FILE")]
+    skip_identical_to: Option<PathBuf>,
+    /// Write a new destination file without a rollback journal and without waiting for each write to reach the disk,
+    /// which is much faster. If the copy is interrupted, the destination file may be corrupted, but never the source.
+    #[arg(long)]
+    fast_write: bool,
+    /// Convert tile rows between the XYZ and TMS schemes, fixing files written with XYZ rows instead of TMS rows.
+    /// `--flip-y` alone converts all tiles, `--flip-y auto` only if the file seems to use XYZ rows,
+    /// judging by which of the schemes puts more tiles inside of the bounds metadata value.
+    #[arg(long, value_enum, value_name = "MODE", num_args = 0..=1, default_value_t = FlipYMode::default(), default_missing_value = "always")]
+    flip_y: FlipYMode,
 }
 
 impl SharedCopyOpts {
@@ -198,7 +295,17 @@ impl SharedCopyOpts {
             bbox: self.bbox,
             skip_agg_tiles_hash: self.skip_agg_tiles_hash,
             force: self.force,
+            force_format: self.force_format,
             validate: self.validate,
+            no_grids: self.no_grids,
+            page_size: self.page_size,
+            jobs: self.jobs.map(usize::from),
+            skip_empty: self.skip_empty,
+            skip_identical_to: self.skip_identical_to,
+            fast_write: self.fast_write,
+            flip_y: self.flip_y,
+            transform: None,
+            cluster: None,
             // Constants
             dst_type: None, // Taken from dst_type_cli
         }
@@ -207,7 +314,15 @@ impl SharedCopyOpts {
 
 #[tokio::main]
 async fn main() {
-    let env = env_logger::Env::default().default_filter_or("mbtiles=info");
+    let args = Args::parse();
+    let level = if args.quiet {
+        "error"
+    } else if args.verbose {
+        "debug"
+    } else {
+        "info"
+    };
+    let env = env_logger::Env::default().default_filter_or(format!("mbtiles={level}"));
     env_logger::Builder::from_env(env)
         .format_indent(None)
         .format_module_path(false)
@@ -215,14 +330,13 @@ async fn main() {
         .format_timestamp(None)
         .init();
 
-    if let Err(err) = main_int().await {
+    if let Err(err) = main_int(args).await {
         error!("{err}");
         std::process::exit(1);
     }
 }
 
-async fn main_int() -> anyhow::Result<()> {
-    let args = Args::parse();
+async fn main_int(args: Args) -> anyhow::Result<()> {
     match args.command {
         Commands::MetaAll { file } => {
             meta_print_all(file.as_path()).await?;
@@ -230,8 +344,18 @@ async fn main_int() -> anyhow::Result<()> {
         Commands::MetaGetValue { file, key } => {
             meta_get_value(file.as_path(), &key).await?;
         }
-        Commands::MetaSetValue { file, key, value } => {
-            meta_set_value(file.as_path(), &key, value.as_deref()).await?;
+        Commands::MetaSetValue {
+            file,
+            key,
+            value,
+            value_file,
+            force,
+        } => {
+            let value = match value_file {
+                Some(path) => Some(String::from_utf8(read_file_or_stdin(&path)?)?),
+                None => value,
+            };
+            meta_set_value(file.as_path(), &key, value.as_deref(), force).await?;
         }
         Commands::Copy(args) => {
             let copier = args.options.into_copier(
@@ -241,7 +365,17 @@ async fn main_int() -> anyhow::Result<()> {
                 args.apply_patch,
                 args.patch_type,
             );
-            copier.run().await?;
+            if args.dry_run {
+                let src_file = copier.src_file.clone();
+                let summary = copier.dry_run().await?;
+                println!(
+                    "Dry run: tiles that would be copied from {}",
+                    src_file.display()
+                );
+                println!("{summary}");
+            } else {
+                copier.run().await?;
+            }
         }
         Commands::Diff(args) => {
             let copier = args.options.into_copier(
@@ -253,6 +387,23 @@ async fn main_int() -> anyhow::Result<()> {
             );
             copier.run().await?;
         }
+        Commands::Cluster {
+            src_file,
+            dst_file,
+            order,
+        } => {
+            let copier = MbtilesCopier {
+                src_file: src_file.clone(),
+                dst_file: dst_file.clone(),
+                cluster: Some(order),
+                ..Default::default()
+            };
+            copier.run().await?;
+            let mbt = Mbtiles::new(dst_file.as_path())?;
+            let mut conn = mbt.open_readonly().await?;
+            mbt.verify_clustered(&mut conn, &Mbtiles::new(src_file.as_path())?, order)
+                .await?;
+        }
         Commands::ApplyPatch {
             base_file,
             patch_file,
@@ -265,11 +416,35 @@ async fn main_int() -> anyhow::Result<()> {
             let mut conn = mbt.open().await?;
             mbt.update_metadata(&mut conn, update_zoom).await?;
         }
+        Commands::TileSet {
+            file,
+            z,
+            x,
+            y,
+            data,
+        } => {
+            let data = read_file_or_stdin(&data)?;
+            let mbt = Mbtiles::new(file.as_path())?;
+            let mut conn = mbt.open().await?;
+            mbt.insert_tile(&mut conn, z, x, y, &data).await?;
+            mbt.update_agg_tiles_hash(&mut conn).await?;
+        }
+        Commands::TileDelete { file, z, x, y } => {
+            let mbt = Mbtiles::new(file.as_path())?;
+            let mut conn = mbt.open().await?;
+            if mbt.delete_tile(&mut conn, z, x, y).await? {
+                mbt.update_agg_tiles_hash(&mut conn).await?;
+            } else {
+                warn!("Tile {z}/{x}/{y} does not exist in {mbt}");
+            }
+        }
         Commands::Validate {
             file,
             integrity_check,
             update_agg_tiles_hash,
             agg_hash,
+            validate_content,
+            max_content_errors,
         } => {
             if update_agg_tiles_hash && agg_hash.is_some() {
                 anyhow::bail!("Cannot use both --agg-hash and --update-agg-tiles-hash");
@@ -283,12 +458,25 @@ async fn main_int() -> anyhow::Result<()> {
             });
             let mbt = Mbtiles::new(file.as_path())?;
             mbt.open_and_validate(integrity_check, agg_hash).await?;
+            if validate_content {
+                let mut conn = mbt.open_readonly().await?;
+                mbt.check_tiles_content(&mut conn, max_content_errors)
+                    .await?;
+            }
         }
-        Commands::Summary { file } => {
+        Commands::Summary {
+            file,
+            dedup_estimate,
+        } => {
             let mbt = Mbtiles::new(file.as_path())?;
             let mut conn = mbt.open_readonly().await?;
-            println!("MBTiles file summary for {mbt}");
-            println!("{}", mbt.summary(&mut conn).await?);
+            if dedup_estimate {
+                println!("MBTiles deduplication estimate for {mbt}");
+                println!("{}", mbt.dedup_estimate(&mut conn).await?);
+            } else {
+                println!("MBTiles file summary for {mbt}");
+                println!("{}", mbt.summary(&mut conn).await?);
+            }
         }
     }
 
@@ -312,10 +500,24 @@ async fn meta_get_value(file: &Path, key: &str) -> MbtResult<()> {
     Ok(())
 }
 
-async fn meta_set_value(file: &Path, key: &str, value: Option<&str>) -> MbtResult<()> {
+/// Read the content of a file, or of stdin if the file is `-`
+fn read_file_or_stdin(path: &Path) -> std::io::Result<Vec<u8>> {
+    if path.as_os_str() == "-" {
+        let mut buf = Vec::new();
+        std::io::stdin().read_to_end(&mut buf)?;
+        Ok(buf)
+    } else {
+        std::fs::read(path)
+    }
+}
+
+async fn meta_set_value(file: &Path, key: &str, value: Option<&str>, force: bool) -> MbtResult<()> {
     let mbt = Mbtiles::new(file)?;
     let mut conn = mbt.open().await?;
     if let Some(value) = value {
+        if !force {
+            validate_metadata_value(key, value)?;
+        }
         mbt.set_metadata_value(&mut conn, key, value).await
     } else {
         mbt.delete_metadata_value(&mut conn, key).await
@@ -331,9 +533,40 @@ mod tests {
     use mbtiles::CopyDuplicateMode;
 
     use super::*;
-    use crate::Commands::{ApplyPatch, Copy, Diff, MetaGetValue, MetaSetValue, Validate};
+    use crate::Commands::{
+        ApplyPatch, Cluster, Copy, Diff, MetaGetValue, MetaSetValue, TileDelete, TileSet, Validate,
+    };
     use crate::{Args, IntegrityCheckType};
 
+    #[test]
+    fn test_log_level_flags() {
+        let meta_all = || crate::Commands::MetaAll {
+            file: PathBuf::from("src_file"),
+        };
+        assert_eq!(
+            Args::parse_from(["mbtiles", "-q", "meta-all", "src_file"]),
+            Args {
+                verbose: false,
+                quiet: true,
+                command: meta_all(),
+            }
+        );
+        assert_eq!(
+            Args::parse_from(["mbtiles", "meta-all", "src_file", "--verbose"]),
+            Args {
+                verbose: true,
+                quiet: false,
+                command: meta_all(),
+            }
+        );
+        assert_eq!(
+            Args::try_parse_from(["mbtiles", "-q", "-v", "meta-all", "src_file"])
+                .unwrap_err()
+                .kind(),
+            ErrorKind::ArgumentConflict
+        );
+    }
+
     #[test]
     fn test_copy_no_arguments() {
         assert_eq!(
@@ -350,6 +583,7 @@ mod tests {
             Args::parse_from(["mbtiles", "copy", "src_file", "dst_file"]),
             Args {
                 verbose: false,
+                quiet: false,
                 command: Copy(CopyArgs {
                     src_file: PathBuf::from("src_file"),
                     dst_file: PathBuf::from("dst_file"),
@@ -375,6 +609,7 @@ mod tests {
             args,
             Args {
                 verbose: false,
+                quiet: false,
                 command: Copy(CopyArgs {
                     src_file: PathBuf::from("src_file"),
                     dst_file: PathBuf::from("dst_file"),
@@ -440,6 +675,7 @@ mod tests {
             ]),
             Args {
                 verbose: false,
+                quiet: false,
                 command: Copy(CopyArgs {
                     src_file: PathBuf::from("src_file"),
                     dst_file: PathBuf::from("dst_file"),
@@ -466,6 +702,7 @@ mod tests {
             ]),
             Args {
                 verbose: false,
+                quiet: false,
                 command: Copy(CopyArgs {
                     src_file: PathBuf::from("src_file"),
                     dst_file: PathBuf::from("dst_file"),
@@ -489,6 +726,7 @@ mod tests {
             ]),
             Args {
                 verbose: false,
+                quiet: false,
                 command: Copy(CopyArgs {
                     src_file: PathBuf::from("src_file"),
                     dst_file: PathBuf::from("dst_file"),
@@ -510,6 +748,7 @@ mod tests {
             ]),
             Args {
                 verbose: false,
+                quiet: false,
                 command: Copy(CopyArgs {
                     src_file: PathBuf::from("src_file"),
                     dst_file: PathBuf::from("dst_file"),
@@ -523,6 +762,37 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_copy_dry_run() {
+        assert_eq!(
+            Args::parse_from(["mbtiles", "copy", "src_file", "dst_file", "--dry-run"]),
+            Args {
+                verbose: false,
+                quiet: false,
+                command: Copy(CopyArgs {
+                    src_file: PathBuf::from("src_file"),
+                    dst_file: PathBuf::from("dst_file"),
+                    dry_run: true,
+                    ..Default::default()
+                })
+            }
+        );
+        assert_eq!(
+            Args::try_parse_from([
+                "mbtiles",
+                "copy",
+                "src_file",
+                "dst_file",
+                "--dry-run",
+                "--apply-patch",
+                "patch_file",
+            ])
+            .unwrap_err()
+            .kind(),
+            ErrorKind::ArgumentConflict
+        );
+    }
+
     #[test]
     fn test_diff() {
         assert_eq!(
@@ -537,6 +807,7 @@ mod tests {
             ]),
             Args {
                 verbose: false,
+                quiet: false,
                 command: Diff(DiffArgs {
                     file1: PathBuf::from("file1.mbtiles"),
                     file2: PathBuf::from("file2.mbtiles"),
@@ -567,6 +838,7 @@ mod tests {
             Args::parse_from(["mbtiles", "meta-get", "src_file", "key"]),
             Args {
                 verbose: false,
+                quiet: false,
                 command: MetaGetValue {
                     file: PathBuf::from("src_file"),
                     key: "key".to_string(),
@@ -591,10 +863,13 @@ mod tests {
             Args::parse_from(["mbtiles", "meta-set", "src_file", "key"]),
             Args {
                 verbose: false,
+                quiet: false,
                 command: MetaSetValue {
                     file: PathBuf::from("src_file"),
                     key: "key".to_string(),
-                    value: None
+                    value: None,
+                    value_file: None,
+                    force: false,
                 }
             }
         );
@@ -606,13 +881,42 @@ mod tests {
             Args::parse_from(["mbtiles", "meta-set", "src_file", "key", "value"]),
             Args {
                 verbose: false,
+                quiet: false,
                 command: MetaSetValue {
                     file: PathBuf::from("src_file"),
                     key: "key".to_string(),
-                    value: Some("value".to_string())
+                    value: Some("value".to_string()),
+                    value_file: None,
+                    force: false,
+                }
+            }
+        );
+    }
+
+    #[test]
+    fn test_meta_set_from_file() {
+        assert_eq!(
+            Args::parse_from(["mbtiles", "meta-set", "src_file", "json", "--file", "-"]),
+            Args {
+                verbose: false,
+                quiet: false,
+                command: MetaSetValue {
+                    file: PathBuf::from("src_file"),
+                    key: "json".to_string(),
+                    value: None,
+                    value_file: Some(PathBuf::from("-")),
+                    force: false,
                 }
             }
         );
+        assert_eq!(
+            Args::try_parse_from([
+                "mbtiles", "meta-set", "src_file", "json", "{}", "--file", "a.json"
+            ])
+            .unwrap_err()
+            .kind(),
+            ErrorKind::ArgumentConflict
+        );
     }
 
     #[test]
@@ -621,6 +925,7 @@ mod tests {
             Args::parse_from(["mbtiles", "apply-diff", "src_file", "diff_file"]),
             Args {
                 verbose: false,
+                quiet: false,
                 command: ApplyPatch {
                     base_file: PathBuf::from("src_file"),
                     patch_file: PathBuf::from("diff_file"),
@@ -636,11 +941,75 @@ mod tests {
             Args::parse_from(["mbtiles", "validate", "src_file", "--agg-hash", "off"]),
             Args {
                 verbose: false,
+                quiet: false,
                 command: Validate {
                     file: PathBuf::from("src_file"),
                     integrity_check: IntegrityCheckType::Quick,
                     update_agg_tiles_hash: false,
                     agg_hash: Some(AggHashType::Off),
+                    validate_content: false,
+                    max_content_errors: 20,
+                }
+            }
+        );
+    }
+
+    #[test]
+    fn test_tile_set_and_delete() {
+        assert_eq!(
+            Args::parse_from(["mbtiles", "tile-set", "src_file", "3", "1", "2", "-"]),
+            Args {
+                verbose: false,
+                quiet: false,
+                command: TileSet {
+                    file: PathBuf::from("src_file"),
+                    z: 3,
+                    x: 1,
+                    y: 2,
+                    data: PathBuf::from("-"),
+                }
+            }
+        );
+        assert_eq!(
+            Args::parse_from(["mbtiles", "tile-delete", "src_file", "3", "1", "2"]),
+            Args {
+                verbose: false,
+                quiet: false,
+                command: TileDelete {
+                    file: PathBuf::from("src_file"),
+                    z: 3,
+                    x: 1,
+                    y: 2,
+                }
+            }
+        );
+    }
+
+    #[test]
+    fn test_cluster() {
+        assert_eq!(
+            Args::parse_from(["mbtiles", "cluster", "src_file", "dst_file"]),
+            Args {
+                verbose: false,
+                quiet: false,
+                command: Cluster {
+                    src_file: PathBuf::from("src_file"),
+                    dst_file: PathBuf::from("dst_file"),
+                    order: ClusterOrder::Hilbert,
+                }
+            }
+        );
+        assert_eq!(
+            Args::parse_from([
+                "mbtiles", "cluster", "src_file", "dst_file", "--order", "z-order"
+            ]),
+            Args {
+                verbose: false,
+                quiet: false,
+                command: Cluster {
+                    src_file: PathBuf::from("src_file"),
+                    dst_file: PathBuf::from("dst_file"),
+                    order: ClusterOrder::ZOrder,
                 }
             }
         );
@@ -0,0 +1,220 @@
+use enum_display::EnumDisplay;
+use log::{debug, info};
+use martin_tile_utils::MAX_ZOOM;
+use serde::{Deserialize, Serialize};
+use sqlite_hashes::rusqlite::functions::FunctionFlags;
+use sqlite_hashes::rusqlite::{Connection, Error as RusqliteError};
+use sqlx::{Connection as _, Row as _, SqliteConnection, query};
+
+use crate::MbtType::{Flat, FlatWithHash, Normalized};
+use crate::errors::{MbtError, MbtResult};
+use crate::queries::detach_db;
+use crate::{AGG_TILES_HASH, Mbtiles, calc_agg_tiles_hash};
+
+/// Order of the tiles within each zoom level of a clustered file, see [`crate::MbtilesCopier::cluster`]
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, EnumDisplay)]
+#[enum_display(case = "Kebab")]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum ClusterOrder {
+    /// Order tiles along the Hilbert curve, same as `PMTiles`
+    #[default]
+    Hilbert,
+    /// Order tiles along the Z-order (Morton) curve
+    ZOrder,
+}
+
+impl ClusterOrder {
+    /// Position of the tile `z/x/y` (in XYZ scheme) along the curve of its zoom level
+    #[must_use]
+    pub fn index(self, zoom: u8, x: u32, y: u32) -> u64 {
+        let (x, y) = (u64::from(x), u64::from(y));
+        match self {
+            Self::Hilbert => {
+                let n = 1_u64 << zoom;
+                let (mut x, mut y) = (x, y);
+                let mut index = 0;
+                let mut s = n / 2;
+                while s > 0 {
+                    let rx = u64::from((x & s) > 0);
+                    let ry = u64::from((y & s) > 0);
+                    index += s * s * ((3 * rx) ^ ry);
+                    if ry == 0 {
+                        if rx == 1 {
+                            x = n - 1 - x;
+                            y = n - 1 - y;
+                        }
+                        std::mem::swap(&mut x, &mut y);
+                    }
+                    s /= 2;
+                }
+                index
+            }
+            Self::ZOrder => (0..zoom).fold(0, |index, bit| {
+                index | (((x >> bit) & 1) << (2 * bit)) | (((y >> bit) & 1) << (2 * bit + 1))
+            }),
+        }
+    }
+
+    /// Same as [`ClusterOrder::index`], but for a `tile_row` in TMS scheme as stored in `MBTiles`.
+    /// Tiles with invalid coordinates are placed after all valid ones.
+    #[must_use]
+    pub fn tms_index(self, zoom: u8, column: u32, row: u32) -> u64 {
+        if zoom > MAX_ZOOM {
+            return u64::MAX;
+        }
+        let size = 1_u32 << zoom;
+        match size.checked_sub(1).and_then(|max| max.checked_sub(row)) {
+            Some(y) if column < size => self.index(zoom, column, y),
+            _ => u64::MAX,
+        }
+    }
+
+    /// Name of the SQL function computing [`ClusterOrder::tms_index`] of `(zoom_level, tile_column, tile_row)`
+    pub(crate) fn sql_function(self) -> &'static str {
+        match self {
+            Self::Hilbert => "hilbert_tile_index",
+            Self::ZOrder => "zorder_tile_index",
+        }
+    }
+
+    /// Register the SQL functions of all cluster orders
+    pub(crate) fn register_functions(conn: &Connection) -> Result<(), RusqliteError> {
+        for order in [Self::Hilbert, Self::ZOrder] {
+            conn.create_scalar_function(
+                order.sql_function(),
+                3,
+                FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+                move |ctx| {
+                    let (z, x, y): (i64, i64, i64) = (ctx.get(0)?, ctx.get(1)?, ctx.get(2)?);
+                    let index = match (u8::try_from(z), u32::try_from(x), u32::try_from(y)) {
+                        (Ok(z), Ok(x), Ok(y)) => order.tms_index(z, x, y),
+                        _ => u64::MAX,
+                    };
+                    Ok(i64::try_from(index).unwrap_or(i64::MAX))
+                },
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Mbtiles {
+    /// Verify that this file, created by copying `src` with [`crate::MbtilesCopier::cluster`], has the same tiles
+    /// and metadata as `src`, and that the tiles of its highest zoom level are stored in the given order.
+    pub async fn verify_clustered(
+        &self,
+        conn: &mut SqliteConnection,
+        src: &Mbtiles,
+        order: ClusterOrder,
+    ) -> MbtResult<()> {
+        let mismatch = |reason: String| MbtError::ClusterMismatch(self.to_string(), reason);
+        let count_sql = "SELECT COUNT(*) FROM tiles";
+
+        let mut src_conn = src.open_readonly().await?;
+        let src_count: i64 = query(count_sql).fetch_one(&mut src_conn).await?.get(0);
+        let src_hash = calc_agg_tiles_hash(&mut src_conn).await?;
+        src_conn.close().await?;
+
+        let count: i64 = query(count_sql).fetch_one(&mut *conn).await?.get(0);
+        if count != src_count {
+            return Err(mismatch(format!(
+                "it has {count} tiles, but {src} has {src_count}"
+            )));
+        }
+        let hash = calc_agg_tiles_hash(&mut *conn).await?;
+        if hash != src_hash {
+            return Err(mismatch(format!(
+                "aggregate tiles hash {hash} does not match {src_hash} of {src}"
+            )));
+        }
+
+        src.attach_to(&mut *conn, "clusterSrcDb").await?;
+        let different: i64 = query(&format!(
+            "
+    SELECT COUNT(*) FROM (
+        SELECT name, value FROM (SELECT name, value FROM metadata
+                                 EXCEPT SELECT name, value FROM clusterSrcDb.metadata)
+        UNION ALL
+        SELECT name, value FROM (SELECT name, value FROM clusterSrcDb.metadata
+                                 EXCEPT SELECT name, value FROM metadata)
+    ) WHERE name != '{AGG_TILES_HASH}'"
+        ))
+        .fetch_one(&mut *conn)
+        .await?
+        .get(0);
+        detach_db(&mut *conn, "clusterSrcDb").await?;
+        if different > 0 {
+            return Err(mismatch(format!(
+                "{different} metadata values differ from {src}"
+            )));
+        }
+
+        let table = match self.detect_type(&mut *conn).await? {
+            Flat => "tiles",
+            FlatWithHash => "tiles_with_hash",
+            Normalized { .. } => "map",
+        };
+        let rows = query(&format!(
+            "
+    SELECT zoom_level, tile_column, tile_row
+    FROM {table}
+    WHERE zoom_level = (SELECT MAX(zoom_level) FROM {table})
+    ORDER BY rowid"
+        ))
+        .fetch_all(&mut *conn)
+        .await?;
+        let mut last_index = 0;
+        for row in &rows {
+            let index = order.tms_index(row.get(0), row.get(1), row.get(2));
+            if index < last_index {
+                return Err(mismatch(format!(
+                    "tile {}/{}/{} (TMS) is not stored in {order} order",
+                    row.get::<u8, _>(0),
+                    row.get::<u32, _>(1),
+                    row.get::<u32, _>(2),
+                )));
+            }
+            last_index = index;
+        }
+        debug!(
+            "Verified {order} order of {} tiles of the highest zoom level of {self}",
+            rows.len()
+        );
+
+        info!("Verified that {self} has the same {count} tiles and metadata as {src}");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn cluster_index() {
+        // Hilbert curve of zoom 1 starts at the top left, goes down, right, and up
+        let hilbert: Vec<_> = [(0, 0), (0, 1), (1, 1), (1, 0)]
+            .into_iter()
+            .map(|(x, y)| ClusterOrder::Hilbert.index(1, x, y))
+            .collect();
+        assert_eq!(hilbert, [0, 1, 2, 3]);
+        let zorder: Vec<_> = [(0, 0), (1, 0), (0, 1), (1, 1)]
+            .into_iter()
+            .map(|(x, y)| ClusterOrder::ZOrder.index(1, x, y))
+            .collect();
+        assert_eq!(zorder, [0, 1, 2, 3]);
+
+        for order in [ClusterOrder::Hilbert, ClusterOrder::ZOrder] {
+            let indexes: HashSet<_> = (0..8)
+                .flat_map(|x| (0..8).map(move |y| order.index(3, x, y)))
+                .collect();
+            assert_eq!(indexes, (0..64).collect(), "{order}");
+            assert_eq!(order.tms_index(3, 1, 7), order.index(3, 1, 0));
+            assert_eq!(order.tms_index(3, 1, 8), u64::MAX);
+            assert_eq!(order.tms_index(3, 8, 1), u64::MAX);
+            assert_eq!(order.tms_index(31, 0, 0), u64::MAX);
+        }
+    }
+}
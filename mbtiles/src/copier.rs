@@ -1,12 +1,19 @@
-use std::fmt::Write as _;
+use std::fmt::{Display, Formatter, Write as _};
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use enum_display::EnumDisplay;
 use itertools::Itertools as _;
-use log::{debug, info, trace, warn};
+use log::{debug, info, warn};
 use martin_tile_utils::{MAX_ZOOM, bbox_to_xyz};
 use serde::{Deserialize, Serialize};
-use sqlite_hashes::rusqlite::Connection;
+use size_format::SizeFormatterBinary;
+use sqlite_compressions::register_gzip_functions;
+use sqlite_hashes::register_md5_functions;
+use sqlite_hashes::rusqlite::{
+    Connection, OpenFlags, Rows, Transaction, TransactionBehavior, params,
+};
 use sqlx::{Connection as _, Executor as _, Row, SqliteConnection, query};
 use tilejson::Bounds;
 
@@ -19,12 +26,13 @@ use crate::bindiff::{BinDiffDiffer, BinDiffPatcher, BinDiffer as _, PatchType};
 use crate::errors::MbtResult;
 use crate::mbtiles::PatchFileInfo;
 use crate::queries::{
-    create_tiles_with_hash_view, detach_db, init_mbtiles_schema, is_empty_database,
+    DEFAULT_PAGE_SIZE, create_tiles_with_hash_view, detach_db, init_mbtiles_schema_with_page_size,
+    is_empty_database, validate_page_size,
 };
 use crate::{
-    AGG_TILES_HASH, AGG_TILES_HASH_AFTER_APPLY, AGG_TILES_HASH_BEFORE_APPLY, AggHashType, CopyType,
-    MbtError, MbtType, MbtTypeCli, Mbtiles, action_with_rusqlite, get_bsdiff_tbl_name,
-    invert_y_value, reset_db_settings,
+    AGG_TILES_HASH, AGG_TILES_HASH_AFTER_APPLY, AGG_TILES_HASH_BEFORE_APPLY, AggHashType,
+    ClusterOrder, CopyType, MbtError, MbtType, MbtTypeCli, Mbtiles, TileFilter,
+    action_with_rusqlite, get_bsdiff_tbl_name, invert_y_value, reset_db_settings,
 };
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, EnumDisplay)]
@@ -36,6 +44,20 @@ pub enum CopyDuplicateMode {
     Abort,
 }
 
+/// Whether to convert the `tile_row` values of copied tiles between the XYZ and TMS tile schemes
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, EnumDisplay)]
+#[enum_display(case = "Kebab")]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum FlipYMode {
+    /// Copy tile rows as they are
+    #[default]
+    Never,
+    /// Convert all tile rows, fixing files written with XYZ rows instead of TMS rows
+    Always,
+    /// Convert tile rows only if the source seems to use XYZ rows, judging by its `bounds` metadata
+    Auto,
+}
+
 impl CopyDuplicateMode {
     #[must_use]
     pub fn to_sql(self) -> &'static str {
@@ -77,8 +99,156 @@ pub struct MbtilesCopier {
     pub skip_agg_tiles_hash: bool,
     /// Ignore some warnings and continue with the copying operation
     pub force: bool,
+    /// Copy tiles into an existing destination file even if its tiles have a different format than the source tiles
+    pub force_format: bool,
     /// Perform `agg_hash` validation on the original and destination files.
     pub validate: bool,
+    /// Do not copy `UTFGrid` interactivity data (`grids` and `grid_data`) even if the source has it
+    pub no_grids: bool,
+    /// `SQLite` page size of a newly created destination file. If not set, the page size of the source file is used.
+    pub page_size: Option<u32>,
+    /// Number of parallel jobs copying tiles into a new flat or flat-with-hash file, each with its own connections.
+    /// Other copy operations, including to a normalized file, always run serially.
+    pub jobs: Option<usize>,
+    /// Do not copy empty tiles: zero-length tiles, and gzip-ed tiles that are empty once decompressed, e.g. MVT without layers
+    pub skip_empty: bool,
+    /// Do not copy tiles that are byte-identical to the content of this file, e.g. a blank PNG or a solid ocean JPEG tile
+    pub skip_identical_to: Option<PathBuf>,
+    /// Speed up writing to a new destination file by disabling its rollback journal and `fsync` calls until the copy completes.
+    /// If the copy is interrupted, e.g. by a crash or a power loss, the destination file may be corrupted, but never the source.
+    pub fast_write: bool,
+    /// Function applied to each copied tile, see [`MbtilesCopier::with_transform`]
+    pub transform: Option<TileTransform>,
+    /// Convert the `tile_row` values between the XYZ and TMS schemes, for files written with the wrong scheme
+    pub flip_y: FlipYMode,
+    /// Write the tiles of each zoom level in the order of a space-filling curve,
+    /// so that nearby tiles are stored close to each other, improving read locality.
+    /// Tiles are copied serially, sorting one zoom level at a time.
+    pub cluster: Option<ClusterOrder>,
+}
+
+/// A function applied to each copied tile, given its `z`, `x`, `y` (in TMS scheme) and data.
+/// Returns the new tile data, or `None` to drop the tile.
+#[derive(Clone)]
+pub struct TileTransform(Arc<dyn Fn(u8, u32, u32, Vec<u8>) -> Option<Vec<u8>> + Send + Sync>);
+
+impl TileTransform {
+    pub fn new(
+        transform: impl Fn(u8, u32, u32, Vec<u8>) -> Option<Vec<u8>> + Send + Sync + 'static,
+    ) -> Self {
+        Self(Arc::new(transform))
+    }
+
+    #[must_use]
+    pub fn apply(&self, z: u8, x: u32, y: u32, data: Vec<u8>) -> Option<Vec<u8>> {
+        (self.0)(z, x, y, data)
+    }
+}
+
+impl std::fmt::Debug for TileTransform {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("TileTransform")
+    }
+}
+
+impl PartialEq for TileTransform {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// Number of tiles and their total size that would be copied for a single zoom level
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct DryRunZoomInfo {
+    pub zoom: u8,
+    pub tile_count: u64,
+    pub total_size: u64,
+}
+
+/// Result of [`MbtilesCopier::dry_run`], describing what would be copied
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct DryRunSummary {
+    pub zoom_info: Vec<DryRunZoomInfo>,
+}
+
+impl DryRunSummary {
+    #[must_use]
+    pub fn tile_count(&self) -> u64 {
+        self.zoom_info.iter().map(|z| z.tile_count).sum()
+    }
+
+    #[must_use]
+    pub fn total_size(&self) -> u64 {
+        self.zoom_info.iter().map(|z| z.total_size).sum()
+    }
+}
+
+impl Display for DryRunSummary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, " {:^4} | {:^9} | {:^9}", "Zoom", "Count", "Size")?;
+        for z in &self.zoom_info {
+            let size = SizeFormatterBinary::new(z.total_size);
+            writeln!(
+                f,
+                " {:>4} | {:>9} | {:>9}",
+                z.zoom,
+                z.tile_count,
+                format!("{size:.1}B"),
+            )?;
+        }
+        let size = SizeFormatterBinary::new(self.total_size());
+        writeln!(
+            f,
+            " {:>4} | {:>9} | {:>9}",
+            "all",
+            self.tile_count(),
+            format!("{size:.1}B"),
+        )
+    }
+}
+
+/// Number of tiles written in a single transaction when copying tiles row by row
+const COPY_BATCH_SIZE: usize = 1000;
+/// Cache size of the destination connection in fast write mode, in KiB
+const FAST_WRITE_CACHE_SIZE_KIB: u32 = 256 * 1024;
+/// How long a parallel copy job may wait for the other jobs to finish writing
+const PARALLEL_BUSY_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A range of tile columns of a single zoom level, copied by a single parallel job
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct TilePartition {
+    zoom: u8,
+    min_column: u32,
+    max_column: u32,
+}
+
+/// Split the tiles of the given zoom levels into up to `jobs` disjoint partitions.
+/// Zoom levels with at least `jobs` columns are split into equal column ranges,
+/// smaller zoom levels are assigned to a single job as a whole.
+fn partition_tiles(zooms: &[u8], jobs: usize) -> Vec<Vec<TilePartition>> {
+    let mut partitions = vec![Vec::new(); jobs];
+    for (idx, &zoom) in zooms.iter().enumerate() {
+        let width = 1_u64 << zoom;
+        if width < jobs as u64 {
+            partitions[idx % jobs].push(TilePartition {
+                zoom,
+                min_column: 0,
+                max_column: u32::try_from(width - 1).unwrap(),
+            });
+        } else {
+            for (job, partition) in partitions.iter_mut().enumerate() {
+                let min = width * job as u64 / jobs as u64;
+                let max = width * (job as u64 + 1) / jobs as u64 - 1;
+                partition.push(TilePartition {
+                    zoom,
+                    min_column: u32::try_from(min).unwrap(),
+                    max_column: u32::try_from(max).unwrap(),
+                });
+            }
+        }
+    }
+    partitions.retain(|p| !p.is_empty());
+    partitions
 }
 
 #[derive(Clone, Debug)]
@@ -86,6 +256,8 @@ struct MbtileCopierInt {
     src_mbt: Mbtiles,
     dst_mbt: Mbtiles,
     options: MbtilesCopier,
+    /// Content of the [`MbtilesCopier::skip_identical_to`] file
+    skip_tile_data: Option<Vec<u8>>,
 }
 
 impl MbtilesCopier {
@@ -93,6 +265,28 @@ impl MbtilesCopier {
         MbtileCopierInt::new(self)?.run().await
     }
 
+    /// Compute the number of tiles and bytes per zoom level that [`MbtilesCopier::run`] would copy,
+    /// without creating or modifying the destination file.
+    pub async fn dry_run(self) -> MbtResult<DryRunSummary> {
+        MbtileCopierInt::new(self)?.dry_run().await
+    }
+
+    /// Apply `transform` to each copied tile, e.g. to re-encode or filter tiles.
+    /// Tiles for which it returns `None` are not copied.
+    ///
+    /// With a transform, tiles are read and written row by row instead of with a single SQL statement,
+    /// which is considerably slower. Tile hashes are computed from the transformed data.
+    /// The transform does not change the metadata: if it changes the tile encoding,
+    /// the caller is responsible for updating the `format` metadata value of the destination.
+    #[must_use]
+    pub fn with_transform(
+        mut self,
+        transform: impl Fn(u8, u32, u32, Vec<u8>) -> Option<Vec<u8>> + Send + Sync + 'static,
+    ) -> Self {
+        self.transform = Some(TileTransform::new(transform));
+        self
+    }
+
     pub(crate) fn dst_type(&self) -> Option<MbtType> {
         self.dst_type.or_else(|| {
             self.dst_type_cli.map(|t| match t {
@@ -123,16 +317,61 @@ impl MbtileCopierInt {
                 return Err(MbtError::SameDiffAndSourceOrDestination(options.src_file));
             }
         }
+        if let Some(page_size) = options.page_size {
+            validate_page_size(page_size)?;
+        }
+        if (options.skip_empty || options.skip_identical_to.is_some())
+            && (options.diff_with_file.is_some() || options.apply_patch.is_some())
+        {
+            return Err(MbtError::UnsupportedCopyOperation {
+                reason: "skipping empty or identical tiles is not supported when diffing or applying a patch".to_string(),
+            });
+        }
+        if options.transform.is_some()
+            && (options.diff_with_file.is_some() || options.apply_patch.is_some())
+        {
+            return Err(MbtError::UnsupportedCopyOperation {
+                reason: "transforming tiles is not supported when diffing or applying a patch"
+                    .to_string(),
+            });
+        }
+        if options.flip_y != FlipYMode::Never {
+            if options.diff_with_file.is_some() || options.apply_patch.is_some() {
+                return Err(MbtError::UnsupportedCopyOperation {
+                    reason: "flipping tile rows is not supported when diffing or applying a patch"
+                        .to_string(),
+                });
+            }
+            if options.on_duplicate == Some(CopyDuplicateMode::Abort) {
+                return Err(MbtError::UnsupportedCopyOperation {
+                    reason: "flipping tile rows is not supported with --on-duplicate abort"
+                        .to_string(),
+                });
+            }
+        }
+        let skip_tile_data = options
+            .skip_identical_to
+            .as_ref()
+            .map(std::fs::read)
+            .transpose()?;
 
         Ok(MbtileCopierInt {
             src_mbt: Mbtiles::new(&options.src_file)?,
             dst_mbt: Mbtiles::new(&options.dst_file)?,
             options,
+            skip_tile_data,
         })
     }
 
     pub async fn run(self) -> MbtResult<SqliteConnection> {
-        if let Some((diff_file, patch_type)) = &self.options.diff_with_file {
+        let start = Instant::now();
+        let dst_mbt = self.dst_mbt.clone();
+        let fast_write = if self.options.fast_write {
+            " in fast write mode"
+        } else {
+            ""
+        };
+        let conn = if let Some((diff_file, patch_type)) = &self.options.diff_with_file {
             let mbt = Mbtiles::new(diff_file)?;
             let patch_type = *patch_type;
             self.run_with_diff(mbt, patch_type).await
@@ -141,12 +380,71 @@ impl MbtileCopierInt {
             self.run_with_patch(mbt).await
         } else {
             self.run_simple().await
+        }?;
+        info!(
+            "Finished writing {dst_mbt}{fast_write} in {:.2?}",
+            start.elapsed()
+        );
+        Ok(conn)
+    }
+
+    async fn dry_run(self) -> MbtResult<DryRunSummary> {
+        if self.options.diff_with_file.is_some() || self.options.apply_patch.is_some() {
+            return Err(MbtError::UnsupportedCopyOperation {
+                reason: "dry run is not supported when diffing or applying a patch".to_string(),
+            });
+        }
+
+        let mut conn = self.src_mbt.open_readonly().await?;
+        let src_type = self.src_mbt.detect_type(&mut conn).await?;
+        let flip_y = self.is_flip_y(&mut conn).await?;
+        conn.close().await?;
+
+        if !self.options.copy.copy_tiles() {
+            info!("Dry run: no tiles would be copied from {}", self.src_mbt);
+            return Ok(DryRunSummary::default());
+        }
+
+        // Use a throw-away in-memory database so that the destination file is never created
+        let mut conn = Mbtiles::new(":memory:")?.open().await?;
+        self.src_mbt.attach_to(&mut conn, "sourceDb").await?;
+
+        // Tile hashes are not needed for counting, so select as if copying into a flat file.
+        // The filtering is identical to the one used by the real copy.
+        let select_from = get_select_from(src_type, Flat, flip_y);
+        let where_clause = self.get_tiles_where_clause();
+        let sql = format!(
+            "
+    SELECT zoom_level, COUNT(*), SUM(LENGTH(tile_data))
+    FROM ({select_from} {where_clause})
+    GROUP BY zoom_level
+    ORDER BY zoom_level"
+        );
+        debug!("Dry run of copying {src_type} with {sql}");
+
+        let mut zoom_info = Vec::new();
+        for row in query(&sql).fetch_all(&mut conn).await? {
+            let zoom: i64 = row.get(0);
+            let count: i64 = row.get(1);
+            let size: Option<i64> = row.get(2);
+            zoom_info.push(DryRunZoomInfo {
+                zoom: u8::try_from(zoom)
+                    .map_err(|_| MbtError::InvalidZoomValue("zoom_level", zoom.to_string()))?,
+                tile_count: u64::try_from(count).unwrap_or_default(),
+                total_size: size.and_then(|v| u64::try_from(v).ok()).unwrap_or_default(),
+            });
         }
+
+        detach_db(&mut conn, "sourceDb").await?;
+        conn.close().await?;
+
+        Ok(DryRunSummary { zoom_info })
     }
 
     async fn run_simple(self) -> MbtResult<SqliteConnection> {
         let mut conn = self.src_mbt.open_readonly().await?;
         let src_type = self.src_mbt.detect_type(&mut conn).await?;
+        let flip_y = self.is_flip_y(&mut conn).await?;
         conn.close().await?;
 
         conn = self.dst_mbt.open_or_new().await?;
@@ -160,13 +458,29 @@ impl MbtileCopierInt {
             return Err(MbtError::DestinationFileExists(self.options.dst_file));
         };
 
+        if !is_empty_db && self.options.copy.copy_tiles() {
+            self.validate_tile_formats(&mut conn).await?;
+        }
+
         self.src_mbt.attach_to(&mut conn, "sourceDb").await?;
+        if flip_y
+            && self.options.copy.copy_tiles()
+            && !self.options.no_grids
+            && src_has_grids(&mut conn).await?
+        {
+            return Err(MbtError::UnsupportedCopyOperation {
+                reason:
+                    "flipping tile rows of UTFGrid data is not supported, use --no-grids to skip it"
+                        .to_string(),
+            });
+        }
 
         let dst_type = if is_empty_db {
             self.options.dst_type().unwrap_or(src_type)
         } else {
             self.validate_dst_type(self.dst_mbt.detect_type(&mut conn).await?)?
         };
+        let fast_write = self.enable_fast_write(&mut conn, is_empty_db).await?;
 
         info!(
             "Copying {src_mbt} ({src_type}) {what}to a {is_new} file {dst_mbt} ({dst_type})",
@@ -176,23 +490,45 @@ impl MbtileCopierInt {
             dst_mbt = self.dst_mbt,
         );
 
-        if is_empty_db {
-            self.init_schema(&mut conn, src_type, dst_type).await?;
+        let deferred_indexes = if is_empty_db {
+            self.init_schema(&mut conn, src_type, dst_type).await?
+        } else {
+            Vec::new()
+        };
+
+        let select_from = get_select_from(src_type, dst_type, flip_y);
+        if self.options.copy.copy_tiles() {
+            self.report_skipped_tiles(&mut conn, &select_from).await?;
+        }
+        if let Some(jobs) = self.parallel_jobs(is_empty_db, dst_type) {
+            self.copy_tiles_parallel(&mut conn, jobs, dst_type, on_duplicate, &select_from)
+                .await?;
+            if self.options.copy.copy_metadata() {
+                action_with_rusqlite(&mut conn, |c| self.copy_metadata(c, on_duplicate)).await?;
+            }
+        } else {
+            self.copy_with_rusqlite(&mut conn, on_duplicate, dst_type, &select_from)
+                .await?;
         }
+        Self::create_deferred_indexes(&mut conn, deferred_indexes).await?;
 
-        self.copy_with_rusqlite(
-            &mut conn,
-            on_duplicate,
-            dst_type,
-            get_select_from(src_type, dst_type),
-        )
-        .await?;
+        if self.options.copy.copy_tiles() {
+            if self.options.no_grids {
+                debug!("Skipping copying grids");
+            } else if src_has_grids(&mut conn).await? {
+                action_with_rusqlite(&mut conn, |c| self.copy_grids(c, dst_type, on_duplicate))
+                    .await?;
+            }
+        }
 
         if self.options.copy.copy_tiles() && !self.options.skip_agg_tiles_hash {
             self.dst_mbt.update_agg_tiles_hash(&mut conn).await?;
         }
 
         detach_db(&mut conn, "sourceDb").await?;
+        if fast_write {
+            Self::disable_fast_write(&mut conn).await?;
+        }
 
         Ok(conn)
     }
@@ -214,6 +550,7 @@ impl MbtileCopierInt {
         if !is_empty_database(&mut conn).await? {
             return Err(MbtError::NonEmptyTargetFile(self.options.dst_file));
         }
+        let fast_write = self.enable_fast_write(&mut conn, true).await?;
 
         self.src_mbt.attach_to(&mut conn, "sourceDb").await?;
         dif_mbt.attach_to(&mut conn, "diffDb").await?;
@@ -227,14 +564,15 @@ impl MbtileCopierInt {
             "Comparing {src_mbt} ({src_type}) and {dif_path} ({dif_type}) {what}into a new file {dst_path} ({dst_type}){patch}",
             src_mbt = self.src_mbt,
             src_type = src_info.mbt_type,
-            dif_path = dif_mbt.filepath(),
+            dif_path = dif_mbt,
             dif_type = dif_info.mbt_type,
             what = self.copy_text(),
-            dst_path = self.dst_mbt.filepath(),
+            dst_path = self.dst_mbt,
             patch = patch_type_str(patch_type),
         );
 
-        self.init_schema(&mut conn, src_info.mbt_type, dst_type)
+        let deferred_indexes = self
+            .init_schema(&mut conn, src_info.mbt_type, dst_type)
             .await?;
         self.copy_with_rusqlite(
             &mut conn,
@@ -243,6 +581,7 @@ impl MbtileCopierInt {
             &get_select_from_with_diff(dif_info.mbt_type, dst_type, patch_type),
         )
         .await?;
+        Self::create_deferred_indexes(&mut conn, deferred_indexes).await?;
 
         // Bindiff copying uses separate threads to read and write data, so we need
         // to open a separate connection to source+diff files to avoid locking issues
@@ -270,6 +609,9 @@ impl MbtileCopierInt {
         if self.options.copy.copy_tiles() && !self.options.skip_agg_tiles_hash {
             self.dst_mbt.update_agg_tiles_hash(&mut conn).await?;
         }
+        if fast_write {
+            Self::disable_fast_write(&mut conn).await?;
+        }
 
         self.validate(&self.dst_mbt, &mut conn).await?;
 
@@ -294,21 +636,22 @@ impl MbtileCopierInt {
         if !is_empty_database(&mut conn).await? {
             return Err(MbtError::NonEmptyTargetFile(self.options.dst_file));
         }
+        let fast_write = self.enable_fast_write(&mut conn, true).await?;
 
         self.src_mbt.attach_to(&mut conn, "sourceDb").await?;
         dif_mbt.attach_to(&mut conn, "diffDb").await?;
 
         info!(
             "Applying patch from {dif_path} ({dif_type}) to {src_mbt} ({src_type}) {what}into a new file {dst_path} ({dst_type}){patch}",
-            dif_path = dif_mbt.filepath(),
+            dif_path = dif_mbt,
             dif_type = dif_info.mbt_type,
             src_mbt = self.src_mbt,
             what = self.copy_text(),
-            dst_path = self.dst_mbt.filepath(),
+            dst_path = self.dst_mbt,
             patch = patch_type_str(dif_info.patch_type),
         );
 
-        self.init_schema(&mut conn, src_type, dst_type).await?;
+        let deferred_indexes = self.init_schema(&mut conn, src_type, dst_type).await?;
         self.copy_with_rusqlite(
             &mut conn,
             CopyDuplicateMode::Override,
@@ -316,6 +659,7 @@ impl MbtileCopierInt {
             &get_select_from_apply_patch(src_type, &dif_info, dst_type),
         )
         .await?;
+        Self::create_deferred_indexes(&mut conn, deferred_indexes).await?;
 
         detach_db(&mut conn, "diffDb").await?;
         detach_db(&mut conn, "sourceDb").await?;
@@ -338,9 +682,9 @@ impl MbtileCopierInt {
                 match (dif_info.agg_tiles_hash_after_apply, new_hash) {
                     (Some(expected), Some(actual)) if expected != actual => {
                         let err = MbtError::AggHashMismatchAfterApply(
-                            dif_mbt.filepath().to_string(),
+                            dif_mbt.to_string(),
                             expected,
-                            self.dst_mbt.filepath().to_string(),
+                            self.dst_mbt.to_string(),
                             actual,
                         );
                         if !self.options.force {
@@ -353,6 +697,10 @@ impl MbtileCopierInt {
             }
         }
 
+        if fast_write {
+            Self::disable_fast_write(&mut conn).await?;
+        }
+
         let hash_type =
             if matches!(dif_info.patch_type, Some(BinDiffGz)) || self.options.skip_agg_tiles_hash {
                 AggHashType::Off
@@ -384,6 +732,50 @@ impl MbtileCopierInt {
         Ok(src_info)
     }
 
+    /// Decide if the tile rows must be flipped, guessing it from the source file for [`FlipYMode::Auto`]
+    /// Make sure the source tiles can be added to the existing tiles of the destination file.
+    /// Tiles that only differ by their encoding, e.g. gzip vs zlib compressed MVT, are copied with a warning.
+    async fn validate_tile_formats(&self, dst_conn: &mut SqliteConnection) -> MbtResult<()> {
+        let dst_info = match self.dst_mbt.get_metadata(&mut *dst_conn).await {
+            Ok(metadata) => metadata.tile_info,
+            Err(MbtError::NoTilesFound) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let mut conn = self.src_mbt.open_readonly().await?;
+        let src_info = self.src_mbt.get_metadata(&mut conn).await?.tile_info;
+        conn.close().await?;
+
+        if src_info.format != dst_info.format {
+            if !self.options.force_format {
+                return Err(MbtError::MismatchedTileFormats(src_info, dst_info));
+            }
+            warn!(
+                "Copying {src_info} tiles of {} into {} with {dst_info} tiles because of --force-format",
+                self.src_mbt, self.dst_mbt
+            );
+        } else if src_info.encoding != dst_info.encoding {
+            warn!(
+                "Tiles of {} are {src_info}, but {} has {dst_info} tiles. The destination file will contain tiles with different encodings",
+                self.src_mbt, self.dst_mbt
+            );
+        }
+        Ok(())
+    }
+
+    async fn is_flip_y(&self, src_conn: &mut SqliteConnection) -> MbtResult<bool> {
+        Ok(match self.options.flip_y {
+            FlipYMode::Never => false,
+            FlipYMode::Always => true,
+            FlipYMode::Auto => {
+                let flip_y = has_xyz_tile_rows(&self.src_mbt, src_conn).await?;
+                if flip_y {
+                    info!("Tile rows of {} will be flipped", self.src_mbt);
+                }
+                flip_y
+            }
+        })
+    }
+
     fn copy_text(&self) -> &str {
         match self.options.copy {
             CopyType::All => "",
@@ -401,7 +793,14 @@ impl MbtileCopierInt {
     ) -> Result<(), MbtError> {
         if self.options.copy.copy_tiles() {
             action_with_rusqlite(conn, |c| {
-                self.copy_tiles(c, dst_type, on_duplicate, select_from)
+                if self.options.cluster.is_some() {
+                    ClusterOrder::register_functions(c)?;
+                }
+                if self.options.transform.is_some() {
+                    self.copy_tiles_transformed(c, dst_type, on_duplicate, select_from)
+                } else {
+                    self.copy_tiles(c, dst_type, on_duplicate, select_from)
+                }
             })
             .await?;
         } else {
@@ -416,6 +815,285 @@ impl MbtileCopierInt {
         }
     }
 
+    /// Apply the fast write pragmas to a new destination, returning `true` if they were applied.
+    /// The destination is disposable until the copy completes, so there is no need for a rollback journal or `fsync` calls.
+    async fn enable_fast_write(
+        &self,
+        conn: &mut SqliteConnection,
+        is_empty_db: bool,
+    ) -> MbtResult<bool> {
+        if !self.options.fast_write {
+            return Ok(false);
+        }
+        if !is_empty_db {
+            info!("Not using fast write mode because the destination file is not empty");
+            return Ok(false);
+        }
+        debug!(
+            "Disabling journal and synchronous writes of {}",
+            self.dst_mbt
+        );
+        query("PRAGMA main.journal_mode = OFF")
+            .execute(&mut *conn)
+            .await?;
+        query("PRAGMA main.synchronous = OFF")
+            .execute(&mut *conn)
+            .await?;
+        query(&format!(
+            "PRAGMA main.cache_size = -{FAST_WRITE_CACHE_SIZE_KIB}"
+        ))
+        .execute(&mut *conn)
+        .await?;
+        Ok(true)
+    }
+
+    /// Restore safe defaults once the copy completes, so that the destination behaves like any other file
+    async fn disable_fast_write(conn: &mut SqliteConnection) -> MbtResult<()> {
+        query("PRAGMA main.journal_mode = DELETE")
+            .execute(&mut *conn)
+            .await?;
+        query("PRAGMA main.synchronous = NORMAL")
+            .execute(&mut *conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Number of jobs to copy tiles with in parallel, or `None` if tiles must be copied serially
+    fn parallel_jobs(&self, is_empty_db: bool, dst_type: MbtType) -> Option<usize> {
+        let jobs = self.options.jobs.filter(|&v| v > 1)?;
+        let reason = if !self.options.copy.copy_tiles() {
+            return None;
+        } else if self.options.cluster.is_some() {
+            "tiles must be written in the cluster order"
+        } else if dst_type.is_normalized() {
+            "deduplicated destinations require coordinated writes"
+        } else if !is_empty_db {
+            "the destination file is not empty"
+        } else if self.dst_mbt.is_in_memory() {
+            "the destination is an in-memory database"
+        } else {
+            return Some(jobs);
+        };
+        info!("Copying tiles serially because {reason}");
+        None
+    }
+
+    /// Copy tiles with several jobs in parallel, each handling a disjoint set of tiles.
+    /// Each job reads from its own source connection, and writes to the destination in small
+    /// transactions, allowing the other jobs to read and hash tiles while one of them is writing.
+    async fn copy_tiles_parallel(
+        &self,
+        conn: &mut SqliteConnection,
+        jobs: usize,
+        dst_type: MbtType,
+        on_duplicate: CopyDuplicateMode,
+        select_from: &str,
+    ) -> MbtResult<()> {
+        let where_clause = self.get_where_clause("");
+        let zooms: Vec<u8> = query(&format!(
+            "SELECT DISTINCT zoom_level FROM ({select_from} {where_clause}) ORDER BY zoom_level"
+        ))
+        .fetch_all(&mut *conn)
+        .await?
+        .into_iter()
+        .map(|row| row.get(0))
+        .collect();
+        let partitions = partition_tiles(&zooms, jobs);
+        info!("Copying tiles with {} parallel jobs", partitions.len());
+
+        let journal_mode: String = query("PRAGMA main.journal_mode")
+            .fetch_one(&mut *conn)
+            .await?
+            .get(0);
+        query("PRAGMA main.journal_mode = WAL")
+            .execute(&mut *conn)
+            .await?;
+
+        // The jobs block their threads, so they are joined outside of the async runtime
+        let copier = self.clone();
+        let select_from = select_from.to_string();
+        let result = tokio::task::spawn_blocking(move || {
+            std::thread::scope(|s| {
+                let handles: Vec<_> = partitions
+                    .iter()
+                    .map(|partition| {
+                        s.spawn(|| {
+                            copier.copy_tiles_job(partition, dst_type, on_duplicate, &select_from)
+                        })
+                    })
+                    .collect();
+                handles
+                    .into_iter()
+                    .try_for_each(|h| h.join().expect("parallel copy job panicked"))
+            })
+        })
+        .await
+        .expect("parallel copy jobs panicked");
+
+        // Restored even if a job failed, so that the file is not left in the WAL mode
+        query(&format!("PRAGMA main.journal_mode = {journal_mode}"))
+            .execute(&mut *conn)
+            .await?;
+        result
+    }
+
+    /// Copy the tiles of a single partition, see [`partition_tiles`]
+    fn copy_tiles_job(
+        &self,
+        partition: &[TilePartition],
+        dst_type: MbtType,
+        on_duplicate: CopyDuplicateMode,
+        select_from: &str,
+    ) -> MbtResult<()> {
+        if dst_type.is_normalized() {
+            return Err(MbtError::UnsupportedCopyOperation {
+                reason: "parallel copy to a normalized file".to_string(),
+            });
+        }
+
+        let src_conn = Connection::open_with_flags(
+            ":memory:",
+            OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_URI,
+        )?;
+        register_md5_functions(&src_conn)?;
+        register_gzip_functions(&src_conn)?;
+        src_conn.execute(
+            "ATTACH DATABASE ? AS sourceDb",
+            [self.src_mbt.sqlite_filename()],
+        )?;
+
+        let dst_conn = Connection::open_with_flags(
+            self.dst_mbt.sqlite_filename(),
+            OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_URI,
+        )?;
+        dst_conn.busy_timeout(PARALLEL_BUSY_TIMEOUT)?;
+        if self.options.fast_write {
+            dst_conn.pragma_update(None, "synchronous", "OFF")?;
+        }
+
+        let cond = partition
+            .iter()
+            .map(|p| {
+                format!(
+                    "(zoom_level = {} AND tile_column BETWEEN {} AND {})",
+                    p.zoom, p.min_column, p.max_column
+                )
+            })
+            .join(" OR ");
+        let where_clause = self.get_tiles_where_clause();
+        let sql = format!("{select_from} {where_clause} AND ({cond})");
+        debug!("Parallel copy job reading tiles with {sql}");
+        let mut select = src_conn.prepare(&sql)?;
+        let mut rows = select.query([])?;
+
+        self.insert_tiles(
+            &mut rows,
+            &dst_conn,
+            dst_type,
+            on_duplicate,
+            TransactionBehavior::Immediate,
+        )
+    }
+
+    /// Copy tiles row by row instead of with a single SQL statement, applying the tile transform
+    fn copy_tiles_transformed(
+        &self,
+        rusqlite_conn: &Connection,
+        dst_type: MbtType,
+        on_duplicate: CopyDuplicateMode,
+        select_from: &str,
+    ) -> MbtResult<()> {
+        let where_clause = self.get_tiles_where_clause();
+        let order_by = self.get_cluster_order_by();
+        let sql = format!("{select_from} {where_clause}{order_by}");
+        debug!("Copying transformed tiles to {dst_type}, reading them with {sql}");
+        let mut select = rusqlite_conn.prepare(&sql)?;
+        let mut rows = select.query([])?;
+        self.insert_tiles(
+            &mut rows,
+            rusqlite_conn,
+            dst_type,
+            on_duplicate,
+            TransactionBehavior::Deferred,
+        )
+    }
+
+    /// Insert tiles returned by `rows` into the destination in batches of [`COPY_BATCH_SIZE`] per transaction,
+    /// applying the tile transform if one is set. Each row must contain `zoom_level, tile_column, tile_row, tile_data`,
+    /// followed by `tile_hash` unless the destination is flat.
+    fn insert_tiles(
+        &self,
+        rows: &mut Rows<'_>,
+        dst_conn: &Connection,
+        dst_type: MbtType,
+        on_duplicate: CopyDuplicateMode,
+        behavior: TransactionBehavior,
+    ) -> MbtResult<()> {
+        let on_dupl = on_duplicate.to_sql();
+        // Parameters are always bound as (z, x, y, data, hash), statements may use only some of them
+        let (insert, insert_image) = match dst_type {
+            Flat => (
+                format!(
+                    "INSERT {on_dupl} INTO tiles (zoom_level, tile_column, tile_row, tile_data) VALUES (?1, ?2, ?3, ?4)"
+                ),
+                None,
+            ),
+            FlatWithHash => (
+                format!(
+                    "INSERT {on_dupl} INTO tiles_with_hash (zoom_level, tile_column, tile_row, tile_data, tile_hash) VALUES (?1, ?2, ?3, ?4, ?5)"
+                ),
+                None,
+            ),
+            Normalized { .. } => (
+                format!(
+                    "INSERT {on_dupl} INTO map (zoom_level, tile_column, tile_row, tile_id) VALUES (?1, ?2, ?3, ?5)"
+                ),
+                Some("INSERT OR IGNORE INTO images (tile_id, tile_data) VALUES (?5, ?4)"),
+            ),
+        };
+        let with_hash = dst_type != Flat;
+
+        let mut batch: Vec<(u8, u32, u32, Option<Vec<u8>>, Option<String>)> =
+            Vec::with_capacity(COPY_BATCH_SIZE);
+        loop {
+            let row = rows.next()?;
+            if let Some(row) = row {
+                let (z, x, y): (u8, u32, u32) = (row.get(0)?, row.get(1)?, row.get(2)?);
+                let data: Option<Vec<u8>> = row.get(3)?;
+                if let Some(transform) = &self.options.transform {
+                    if let Some(data) = transform.apply(z, x, y, data.unwrap_or_default()) {
+                        let hash = with_hash.then(|| format!("{:X}", md5::compute(&data)));
+                        batch.push((z, x, y, Some(data), hash));
+                    }
+                } else {
+                    let hash = if with_hash { row.get(4)? } else { None };
+                    batch.push((z, x, y, data, hash));
+                }
+            }
+            if batch.len() >= COPY_BATCH_SIZE || (row.is_none() && !batch.is_empty()) {
+                let tx = Transaction::new_unchecked(dst_conn, behavior)?;
+                {
+                    let mut stmt = tx.prepare_cached(&insert)?;
+                    let mut image_stmt =
+                        insert_image.map(|sql| tx.prepare_cached(sql)).transpose()?;
+                    for (z, x, y, data, hash) in batch.drain(..) {
+                        let values = params![z, x, y, data, hash];
+                        if let Some(image_stmt) = &mut image_stmt {
+                            image_stmt.execute(values)?;
+                        }
+                        stmt.execute(&values[..stmt.parameter_count()])?;
+                    }
+                }
+                tx.commit()?;
+            }
+            if row.is_none() {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
     fn copy_metadata(
         &self,
         rusqlite_conn: &Connection,
@@ -475,10 +1153,51 @@ impl MbtileCopierInt {
         dst_type: MbtType,
         on_duplicate: CopyDuplicateMode,
         select_from: &str,
+    ) -> Result<(), MbtError> {
+        let where_clause = self.get_tiles_where_clause();
+        if self.options.cluster.is_none() {
+            return self.copy_tiles_chunk(
+                rusqlite_conn,
+                dst_type,
+                on_duplicate,
+                select_from,
+                &where_clause,
+            );
+        }
+
+        // Sort and write one zoom level at a time to keep each sort small
+        let sql = format!(
+            "SELECT DISTINCT zoom_level FROM ({select_from} {where_clause}) ORDER BY zoom_level"
+        );
+        let zooms = rusqlite_conn
+            .prepare(&sql)?
+            .query_map([], |row| row.get::<_, i64>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        for zoom in zooms {
+            debug!("Copying zoom level {zoom} in cluster order");
+            self.copy_tiles_chunk(
+                rusqlite_conn,
+                dst_type,
+                on_duplicate,
+                select_from,
+                &format!("{where_clause} AND zoom_level = {zoom}"),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Copy the tiles matching the `where_clause`, in the cluster order if it is set
+    fn copy_tiles_chunk(
+        &self,
+        rusqlite_conn: &Connection,
+        dst_type: MbtType,
+        on_duplicate: CopyDuplicateMode,
+        select_from: &str,
+        where_clause: &str,
     ) -> Result<(), MbtError> {
         let on_dupl = on_duplicate.to_sql();
-        let where_clause = self.get_where_clause("");
         let sql_cond = Self::get_on_duplicate_sql_cond(on_duplicate, dst_type);
+        let order_by = self.get_cluster_order_by();
 
         let sql = match dst_type {
             Flat => {
@@ -486,7 +1205,7 @@ impl MbtileCopierInt {
                     "
     INSERT {on_dupl} INTO tiles
            (zoom_level, tile_column, tile_row, tile_data)
-    {select_from} {where_clause} {sql_cond}"
+    {select_from} {where_clause} {sql_cond}{order_by}"
                 )
             }
             FlatWithHash => {
@@ -494,7 +1213,7 @@ impl MbtileCopierInt {
                     "
     INSERT {on_dupl} INTO tiles_with_hash
            (zoom_level, tile_column, tile_row, tile_data, tile_hash)
-    {select_from} {where_clause} {sql_cond}"
+    {select_from} {where_clause} {sql_cond}{order_by}"
                 )
             }
             Normalized { .. } => {
@@ -503,7 +1222,7 @@ impl MbtileCopierInt {
     INSERT OR IGNORE INTO images
            (tile_id, tile_data)
     SELECT tile_hash as tile_id, tile_data
-    FROM ({select_from} {where_clause})"
+    FROM ({select_from} {where_clause}){order_by}"
                 );
                 debug!("Copying to {dst_type} with {sql}");
                 rusqlite_conn.execute(&sql, [])?;
@@ -513,7 +1232,7 @@ impl MbtileCopierInt {
     INSERT {on_dupl} INTO map
            (zoom_level, tile_column, tile_row, tile_id)
     SELECT zoom_level, tile_column, tile_row, tile_hash as tile_id
-    FROM ({select_from} {where_clause} {sql_cond})"
+    FROM ({select_from} {where_clause} {sql_cond}){order_by}"
                 )
             }
         };
@@ -524,42 +1243,201 @@ impl MbtileCopierInt {
         Ok(())
     }
 
-    /// Check if the detected destination file type matches the one given by the options
-    fn validate_dst_type(&self, dst_type: MbtType) -> MbtResult<MbtType> {
-        if let Some(cli) = self.options.dst_type() {
-            match (cli, dst_type) {
-                (Flat, Flat)
-                | (FlatWithHash, FlatWithHash)
-                | (Normalized { .. }, Normalized { .. }) => {}
-                (cli, dst) => {
-                    return Err(MbtError::MismatchedTargetType(
-                        self.options.dst_file.clone(),
-                        dst,
-                        cli,
-                    ));
-                }
-            }
-        }
-        Ok(dst_type)
-    }
-
-    async fn init_schema(
+    /// Copy `UTFGrid` interactivity data, applying the same zoom and bbox filters as for tiles.
+    /// Flat destinations store it in the `grids` and `grid_data` tables as defined by the `MBTiles` spec.
+    /// Normalized destinations use the deduplicated `grid_utfgrid`, `keymap`, and `grid_key` tables
+    /// linked from `map.grid_id`, with `grids` and `grid_data` views on top of them.
+    fn copy_grids(
         &self,
-        conn: &mut SqliteConnection,
-        src: MbtType,
-        dst: MbtType,
-    ) -> MbtResult<()> {
-        if src == dst {
-            reset_db_settings(conn).await?;
-            debug!("Copying DB schema verbatim");
-            // DB objects must be created in a specific order: tables, views, triggers, indexes.
-            let sql_objects = conn
-                .fetch_all(
-                    "SELECT sql, tbl_name, type
-                     FROM sourceDb.sqlite_schema
-                     WHERE tbl_name IN ('metadata', 'tiles', 'map', 'images', 'tiles_with_hash')
-                       AND type     IN ('table', 'view', 'trigger', 'index')
-                     ORDER BY CASE
+        rusqlite_conn: &Connection,
+        dst_type: MbtType,
+        on_duplicate: CopyDuplicateMode,
+    ) -> Result<(), MbtError> {
+        let on_dupl = on_duplicate.to_sql();
+        let where_clause = self.get_where_clause("");
+
+        match dst_type {
+            Flat | FlatWithHash => {
+                debug!("Creating if needed grids and grid_data tables");
+                rusqlite_conn.execute_batch(
+                    "
+    CREATE TABLE IF NOT EXISTS grids (
+         zoom_level integer NOT NULL,
+         tile_column integer NOT NULL,
+         tile_row integer NOT NULL,
+         grid blob,
+         PRIMARY KEY(zoom_level, tile_column, tile_row));
+    CREATE TABLE IF NOT EXISTS grid_data (
+         zoom_level integer NOT NULL,
+         tile_column integer NOT NULL,
+         tile_row integer NOT NULL,
+         key_name text NOT NULL,
+         key_json text,
+         PRIMARY KEY(zoom_level, tile_column, tile_row, key_name));",
+                )?;
+
+                let sql = format!(
+                    "
+    INSERT {on_dupl} INTO grids
+           (zoom_level, tile_column, tile_row, grid)
+    SELECT zoom_level, tile_column, tile_row, grid
+    FROM sourceDb.grids
+    WHERE TRUE {where_clause}"
+                );
+                debug!("Copying grids with {sql}");
+                rusqlite_conn.execute(&sql, [])?;
+
+                let sql = format!(
+                    "
+    INSERT {on_dupl} INTO grid_data
+           (zoom_level, tile_column, tile_row, key_name, key_json)
+    SELECT zoom_level, tile_column, tile_row, key_name, key_json
+    FROM sourceDb.grid_data
+    WHERE TRUE {where_clause}"
+                );
+                debug!("Copying grid data with {sql}");
+                rusqlite_conn.execute(&sql, [])?;
+            }
+            Normalized { .. } => {
+                debug!("Creating if needed normalized grid tables and views");
+                rusqlite_conn.execute_batch(
+                    "
+    CREATE TABLE IF NOT EXISTS grid_utfgrid (
+         grid_id text NOT NULL PRIMARY KEY,
+         grid_utfgrid blob);
+    CREATE TABLE IF NOT EXISTS keymap (
+         key_name text NOT NULL PRIMARY KEY,
+         key_json text);
+    CREATE TABLE IF NOT EXISTS grid_key (
+         grid_id text NOT NULL,
+         key_name text NOT NULL,
+         PRIMARY KEY(grid_id, key_name));",
+                )?;
+
+                let has_grid_id: bool = rusqlite_conn.query_row(
+                    "SELECT COUNT(*) > 0 FROM pragma_table_info('map') WHERE name = 'grid_id'",
+                    [],
+                    |row| row.get(0),
+                )?;
+                if !has_grid_id {
+                    rusqlite_conn.execute("ALTER TABLE map ADD COLUMN grid_id text", [])?;
+                }
+
+                rusqlite_conn.execute_batch(
+                    "
+    CREATE VIEW IF NOT EXISTS grids AS
+         SELECT map.zoom_level AS zoom_level,
+                map.tile_column AS tile_column,
+                map.tile_row AS tile_row,
+                grid_utfgrid.grid_utfgrid AS grid
+         FROM map
+         JOIN grid_utfgrid ON grid_utfgrid.grid_id = map.grid_id;
+    CREATE VIEW IF NOT EXISTS grid_data AS
+         SELECT map.zoom_level AS zoom_level,
+                map.tile_column AS tile_column,
+                map.tile_row AS tile_row,
+                keymap.key_name AS key_name,
+                keymap.key_json AS key_json
+         FROM map
+         JOIN grid_key ON map.grid_id = grid_key.grid_id
+         JOIN keymap ON grid_key.key_name = keymap.key_name;",
+                )?;
+
+                let g_where_clause = self.get_where_clause("g.");
+                let sql = format!(
+                    "
+    INSERT OR IGNORE INTO grid_utfgrid
+           (grid_id, grid_utfgrid)
+    SELECT md5_hex(grid), grid
+    FROM sourceDb.grids
+    WHERE TRUE {where_clause};
+
+    INSERT OR IGNORE INTO keymap
+           (key_name, key_json)
+    SELECT key_name, key_json
+    FROM sourceDb.grid_data
+    WHERE TRUE {where_clause};
+
+    INSERT OR IGNORE INTO grid_key
+           (grid_id, key_name)
+    SELECT DISTINCT md5_hex(g.grid), d.key_name
+    FROM sourceDb.grids AS g
+    JOIN sourceDb.grid_data AS d
+      ON g.zoom_level = d.zoom_level
+        AND g.tile_column = d.tile_column
+        AND g.tile_row = d.tile_row
+    WHERE TRUE {g_where_clause};
+
+    UPDATE map
+    SET grid_id = g.grid_id
+    FROM (SELECT zoom_level, tile_column, tile_row, md5_hex(grid) AS grid_id
+          FROM sourceDb.grids
+          WHERE TRUE {where_clause}) AS g
+    WHERE map.zoom_level = g.zoom_level
+      AND map.tile_column = g.tile_column
+      AND map.tile_row = g.tile_row;"
+                );
+                debug!("Copying grids to {dst_type} with {sql}");
+                rusqlite_conn.execute_batch(&sql)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check if the detected destination file type matches the one given by the options
+    fn validate_dst_type(&self, dst_type: MbtType) -> MbtResult<MbtType> {
+        if let Some(cli) = self.options.dst_type() {
+            match (cli, dst_type) {
+                (Flat, Flat)
+                | (FlatWithHash, FlatWithHash)
+                | (Normalized { .. }, Normalized { .. }) => {}
+                (cli, dst) => {
+                    return Err(MbtError::MismatchedTargetType(
+                        self.options.dst_file.clone(),
+                        dst,
+                        cli,
+                    ));
+                }
+            }
+        }
+        Ok(dst_type)
+    }
+
+    /// Create the destination schema, and return the index creation statements
+    /// that should be executed once the tiles have been copied.
+    async fn init_schema(
+        &self,
+        conn: &mut SqliteConnection,
+        src: MbtType,
+        dst: MbtType,
+    ) -> MbtResult<Vec<String>> {
+        let page_size = if let Some(page_size) = self.options.page_size {
+            page_size
+        } else {
+            let page_size: i64 = query("PRAGMA sourceDb.page_size")
+                .fetch_one(&mut *conn)
+                .await?
+                .get(0);
+            u32::try_from(page_size).unwrap_or(DEFAULT_PAGE_SIZE)
+        };
+
+        let mut deferred_indexes = Vec::new();
+        if src == dst {
+            reset_db_settings(conn, page_size).await?;
+            debug!("Copying DB schema verbatim");
+            // DB objects must be created in a specific order: tables, views, triggers, indexes.
+            // Match by name too, because views and triggers may be registered under a different tbl_name.
+            // Auto-indexes have no SQL, and are re-created together with their tables.
+            let sql_objects = conn
+                .fetch_all(
+                    "SELECT sql, name, type
+                     FROM sourceDb.sqlite_schema
+                     WHERE (tbl_name IN ('metadata', 'tiles', 'map', 'images', 'tiles_with_hash')
+                            OR name IN ('metadata', 'tiles', 'map', 'images', 'tiles_with_hash'))
+                       AND type IN ('table', 'view', 'trigger', 'index')
+                       AND sql NOT NULL
+                     ORDER BY CASE
                          WHEN type = 'table' THEN 1
                          WHEN type = 'view' THEN 2
                          WHEN type = 'trigger' THEN 3
@@ -569,21 +1447,39 @@ impl MbtileCopierInt {
                 .await?;
 
             for row in sql_objects {
-                debug!(
-                    "Creating {typ} {tbl_name}...",
-                    typ = row.get::<&str, _>(2),
-                    tbl_name = row.get::<&str, _>(1),
-                );
-                query(row.get(0)).execute(&mut *conn).await?;
+                let sql: String = row.get(0);
+                let name: &str = row.get(1);
+                let typ: &str = row.get(2);
+                // Unique indexes are needed while copying to resolve duplicates,
+                // all other indexes are faster to create once the data is in place.
+                if typ == "index" && !is_unique_index_sql(&sql) {
+                    debug!("Deferring creation of index {name} until the tiles are copied");
+                    deferred_indexes.push(sql);
+                } else {
+                    debug!("Creating {typ} {name}...");
+                    query(&sql).execute(&mut *conn).await?;
+                }
             }
             if dst.is_normalized() {
                 // Some normalized mbtiles files might not have this view, so even if src == dst, it might not exist
                 create_tiles_with_hash_view(&mut *conn).await?;
             }
         } else {
-            init_mbtiles_schema(&mut *conn, dst).await?;
+            init_mbtiles_schema_with_page_size(&mut *conn, dst, page_size).await?;
         };
 
+        Ok(deferred_indexes)
+    }
+
+    /// Create the indexes returned by [`MbtileCopierInt::init_schema`]
+    async fn create_deferred_indexes(
+        conn: &mut SqliteConnection,
+        indexes: Vec<String>,
+    ) -> MbtResult<()> {
+        for sql in indexes {
+            debug!("Creating deferred index with {sql}");
+            query(&sql).execute(&mut *conn).await?;
+        }
         Ok(())
     }
 
@@ -613,53 +1509,113 @@ impl MbtileCopierInt {
         }
     }
 
-    /// Format SQL WHERE clause and return it along with the query arguments.
-    /// Note that there is no risk of SQL injection here, as the arguments are integers.
+    /// Format SQL conditions for the zoom and bounding box options
     fn get_where_clause(&self, prefix: &str) -> String {
-        let mut sql = if !&self.options.zoom_levels.is_empty() {
-            let zooms = self.options.zoom_levels.iter().join(",");
-            format!(" AND {prefix}zoom_level IN ({zooms})")
-        } else if let Some(min_zoom) = self.options.min_zoom {
-            if let Some(max_zoom) = self.options.max_zoom {
-                format!(" AND {prefix}zoom_level BETWEEN {min_zoom} AND {max_zoom}")
-            } else {
-                format!(" AND {prefix}zoom_level >= {min_zoom}")
-            }
-        } else if let Some(max_zoom) = self.options.max_zoom {
-            format!(" AND {prefix}zoom_level <= {max_zoom}")
-        } else {
-            String::new()
-        };
+        TileFilter {
+            min_zoom: self.options.min_zoom,
+            max_zoom: self.options.max_zoom,
+            zoom_levels: self.options.zoom_levels.clone(),
+            bbox: self.options.bbox.clone(),
+        }
+        .sql_conditions(prefix)
+    }
 
-        if !self.options.bbox.is_empty() {
-            sql.push_str(" AND (\n");
-            for (idx, bbox) in self.options.bbox.iter().enumerate() {
-                // Use maximum zoom value for easy filtering,
-                // converting it on the fly to the actual zoom level
-                let (min_x, min_y, max_x, max_y) =
-                    bbox_to_xyz(bbox.left, bbox.bottom, bbox.right, bbox.top, MAX_ZOOM);
-                trace!(
-                    "Bounding box {bbox} converted to {min_x},{min_y},{max_x},{max_y} at zoom {MAX_ZOOM}"
-                );
-                let (min_y, max_y) = (
-                    invert_y_value(MAX_ZOOM, max_y),
-                    invert_y_value(MAX_ZOOM, min_y),
-                );
+    /// `ORDER BY` clause sorting the tiles of each zoom level in the cluster order, if it is set
+    fn get_cluster_order_by(&self) -> String {
+        self.options.cluster.map_or_else(String::new, |order| {
+            format!(
+                " ORDER BY zoom_level, {}(zoom_level, tile_column, tile_row)",
+                order.sql_function()
+            )
+        })
+    }
 
-                if idx > 0 {
-                    sql.push_str(" OR\n");
-                }
-                writeln!(
-                    sql,
-                    "(({prefix}tile_column * (1 << ({MAX_ZOOM} - {prefix}zoom_level))) BETWEEN {min_x} AND {max_x} \
-                     AND ({prefix}tile_row * (1 << ({MAX_ZOOM} - {prefix}zoom_level))) BETWEEN {min_y} AND {max_y})",
-                ).unwrap();
+    /// Condition matching the tiles that must not be copied, if any.
+    /// Tiles with up to 64 bytes starting with the gzip magic number are decompressed to check if they are empty.
+    fn get_skip_condition(&self) -> Option<String> {
+        let mut conditions = Vec::new();
+        if self.options.skip_empty {
+            conditions.push(
+                "tile_data IS NULL OR length(tile_data) = 0 \
+                 OR (length(tile_data) <= 64 AND substr(tile_data, 1, 2) = x'1f8b' \
+                     AND length(gzip_decode(tile_data)) = 0)"
+                    .to_string(),
+            );
+        }
+        if let Some(data) = &self.skip_tile_data {
+            let mut cond = "tile_data IS x'".to_string();
+            for b in data {
+                write!(cond, "{b:02x}").unwrap();
             }
-            sql.push(')');
+            cond.push('\'');
+            conditions.push(cond);
+        }
+        if conditions.is_empty() {
+            None
+        } else {
+            Some(conditions.iter().map(|c| format!("({c})")).join(" OR "))
         }
+    }
 
+    /// Same as [`Self::get_where_clause`], but also excluding the skipped tiles.
+    /// Must only be used with queries selecting `tile_data`.
+    fn get_tiles_where_clause(&self) -> String {
+        let mut sql = self.get_where_clause("");
+        if let Some(cond) = self.get_skip_condition() {
+            write!(sql, " AND NOT ({cond})").unwrap();
+        }
         sql
     }
+
+    /// Log how many tiles of each zoom level will not be copied because of the skip options
+    async fn report_skipped_tiles(
+        &self,
+        conn: &mut SqliteConnection,
+        select_from: &str,
+    ) -> MbtResult<()> {
+        let Some(cond) = self.get_skip_condition() else {
+            return Ok(());
+        };
+        let where_clause = self.get_where_clause("");
+        let sql = format!(
+            "
+    SELECT zoom_level, COUNT(*)
+    FROM ({select_from} {where_clause} AND ({cond}))
+    GROUP BY zoom_level
+    ORDER BY zoom_level"
+        );
+        debug!("Counting skipped tiles with {sql}");
+
+        let mut total = 0;
+        for row in query(&sql).fetch_all(&mut *conn).await? {
+            let zoom: i64 = row.get(0);
+            let count: i64 = row.get(1);
+            info!("Skipping {count} empty or identical tiles at zoom {zoom}");
+            total += count;
+        }
+        info!("Skipping {total} empty or identical tiles in total");
+        Ok(())
+    }
+}
+
+/// Check if an index was created with `CREATE UNIQUE INDEX ...`
+fn is_unique_index_sql(sql: &str) -> bool {
+    let mut words = sql.split_whitespace();
+    matches!(
+        (words.next(), words.next()),
+        (Some(create), Some(unique))
+            if create.eq_ignore_ascii_case("CREATE") && unique.eq_ignore_ascii_case("UNIQUE")
+    )
+}
+
+/// Check if the attached `sourceDb` has both `grids` and `grid_data` tables or views
+async fn src_has_grids(conn: &mut SqliteConnection) -> MbtResult<bool> {
+    let sql = "
+    SELECT COUNT(*)
+    FROM sourceDb.sqlite_schema
+    WHERE name IN ('grids', 'grid_data')
+      AND type IN ('table', 'view')";
+    Ok(query(sql).fetch_one(&mut *conn).await?.get::<i64, _>(0) == 2)
 }
 
 fn get_select_from_apply_patch(
@@ -795,7 +1751,26 @@ fn get_select_from_with_diff(
     )
 }
 
-fn get_select_from(src_type: MbtType, dst_type: MbtType) -> &'static str {
+/// Query selecting the source tiles with the columns needed for the destination type.
+/// The query ends with a `WHERE` clause, so that more conditions can be appended with `AND`.
+/// If `flip_y` is set, the `tile_row` values are converted between the XYZ and TMS schemes,
+/// and the appended conditions apply to the converted values.
+fn get_select_from(src_type: MbtType, dst_type: MbtType, flip_y: bool) -> String {
+    let select = get_select_tiles(src_type, dst_type);
+    if flip_y {
+        let tile_hash = if dst_type == Flat { "" } else { ", tile_hash" };
+        format!(
+            "
+        SELECT zoom_level, tile_column, (1 << zoom_level) - 1 - tile_row AS tile_row, tile_data{tile_hash}
+        FROM ({select})
+        WHERE TRUE"
+        )
+    } else {
+        select.to_string()
+    }
+}
+
+fn get_select_tiles(src_type: MbtType, dst_type: MbtType) -> &'static str {
     if dst_type == Flat {
         "SELECT zoom_level, tile_column, tile_row, tile_data FROM sourceDb.tiles WHERE TRUE"
     } else {
@@ -823,6 +1798,61 @@ fn get_select_from(src_type: MbtType, dst_type: MbtType) -> &'static str {
     }
 }
 
+/// Guess if the tile rows of a file use the XYZ scheme instead of TMS, by counting how many tiles
+/// of the highest zoom level are inside of the `bounds` metadata value with either scheme.
+/// Returns `false` if there are no valid bounds, or if both schemes match the same number of tiles,
+/// e.g. if the bounds are symmetric around the equator.
+async fn has_xyz_tile_rows(mbt: &Mbtiles, conn: &mut SqliteConnection) -> MbtResult<bool> {
+    let Some(value) = mbt.get_metadata_value(&mut *conn, "bounds").await? else {
+        info!("Unable to detect the tile scheme of {mbt} because it has no bounds metadata");
+        return Ok(false);
+    };
+    let bounds = match value.parse::<Bounds>() {
+        Ok(bounds) => bounds,
+        Err(e) => {
+            warn!(
+                "Unable to detect the tile scheme of {mbt} because of invalid bounds '{value}': {e}"
+            );
+            return Ok(false);
+        }
+    };
+    let max_zoom: Option<i64> = query("SELECT MAX(zoom_level) FROM tiles")
+        .fetch_one(&mut *conn)
+        .await?
+        .get(0);
+    let Some(zoom) = max_zoom else {
+        return Ok(false);
+    };
+    let zoom = u8::try_from(zoom)
+        .ok()
+        .filter(|&z| z <= MAX_ZOOM)
+        .ok_or_else(|| MbtError::InvalidZoomValue("zoom_level", zoom.to_string()))?;
+
+    let (min_x, min_y, max_x, max_y) =
+        bbox_to_xyz(bounds.left, bounds.bottom, bounds.right, bounds.top, zoom);
+    let row = query(
+        "
+    SELECT COALESCE(SUM(tile_row BETWEEN ?4 AND ?5), 0),
+           COALESCE(SUM(tile_row BETWEEN ?6 AND ?7), 0)
+    FROM tiles
+    WHERE zoom_level = ?1 AND tile_column BETWEEN ?2 AND ?3",
+    )
+    .bind(zoom)
+    .bind(min_x)
+    .bind(max_x)
+    .bind(invert_y_value(zoom, max_y))
+    .bind(invert_y_value(zoom, min_y))
+    .bind(min_y)
+    .bind(max_y)
+    .fetch_one(&mut *conn)
+    .await?;
+    let (as_tms, as_xyz): (i64, i64) = (row.get(0), row.get(1));
+    debug!(
+        "At zoom {zoom}, {as_tms} tiles of {mbt} are within bounds {bounds} as TMS rows, and {as_xyz} as XYZ rows"
+    );
+    Ok(as_xyz > as_tms)
+}
+
 fn patch_type_str(patch_type: Option<PatchType>) -> &'static str {
     if let Some(v) = patch_type {
         match v {
@@ -836,6 +1866,11 @@ fn patch_type_str(patch_type: Option<PatchType>) -> &'static str {
 
 #[cfg(test)]
 mod tests {
+    use std::io::Write as _;
+
+    use flate2::Compression;
+    use flate2::write::ZlibEncoder;
+    use martin_tile_utils::{Format, decode_gzip};
     use sqlx::{Decode, Sqlite, SqliteConnection, Type};
 
     use super::*;
@@ -1005,6 +2040,564 @@ mod tests {
         verify_copy_with_zoom_filter(opt, 2).await
     }
 
+    #[actix_rt::test]
+    async fn dry_run_with_min_max_zoom() -> MbtResult<()> {
+        let dst = std::env::temp_dir().join("dry_run_with_min_max_zoom.mbtiles");
+        let opt = MbtilesCopier {
+            src_file: PathBuf::from("../tests/fixtures/mbtiles/world_cities.mbtiles"),
+            dst_file: dst.clone(),
+            min_zoom: Some(2),
+            max_zoom: Some(4),
+            ..Default::default()
+        };
+        let summary = opt.dry_run().await?;
+
+        assert_eq!(
+            summary
+                .zoom_info
+                .iter()
+                .map(|z| (z.zoom, z.tile_count))
+                .collect::<Vec<_>>(),
+            vec![(2, 7), (3, 17), (4, 38)]
+        );
+        assert_eq!(summary.tile_count(), 62);
+        assert!(!dst.exists());
+
+        Ok(())
+    }
+
+    #[actix_rt::test]
+    async fn copy_page_size() -> MbtResult<()> {
+        for (page_size, expected) in [(None, 4096), (Some(8192), 8192), (Some(512), 512)] {
+            let opt = MbtilesCopier {
+                src_file: PathBuf::from("../tests/fixtures/mbtiles/world_cities.mbtiles"),
+                dst_file: PathBuf::from(format!(
+                    "file:copy_page_size_{expected}_mem_db?mode=memory&cache=shared"
+                )),
+                page_size,
+                ..Default::default()
+            };
+            let mut dst_conn = opt.run().await?;
+            assert_eq!(
+                get_one::<i64>(&mut dst_conn, "PRAGMA page_size").await,
+                expected
+            );
+        }
+
+        for page_size in [0, 256, 1000, 131_072] {
+            let opt = MbtilesCopier {
+                src_file: PathBuf::from("../tests/fixtures/mbtiles/world_cities.mbtiles"),
+                dst_file: PathBuf::from("file:copy_bad_page_size_mem_db?mode=memory&cache=shared"),
+                page_size: Some(page_size),
+                ..Default::default()
+            };
+            assert!(matches!(
+                opt.run().await.unwrap_err(),
+                MbtError::InvalidPageSize(v) if v == page_size
+            ));
+        }
+
+        Ok(())
+    }
+
+    #[actix_rt::test]
+    async fn copy_creates_missing_destination() -> MbtResult<()> {
+        let dir = std::env::temp_dir().join("mbtiles-copy-new-dst");
+        std::fs::create_dir_all(&dir)?;
+        let dst = dir.join("world_cities_copy.mbtiles");
+        let _ = std::fs::remove_file(&dst);
+
+        let opt = MbtilesCopier {
+            src_file: PathBuf::from("../tests/fixtures/mbtiles/world_cities.mbtiles"),
+            dst_file: dst.clone(),
+            ..Default::default()
+        };
+        let mut dst_conn = opt.run().await?;
+        assert_eq!(
+            get_one::<i64>(&mut dst_conn, "SELECT COUNT(*) FROM tiles").await,
+            196
+        );
+        drop(dst_conn);
+        assert!(dst.exists());
+
+        let missing_src = MbtilesCopier {
+            src_file: dir.join("missing.mbtiles"),
+            dst_file: dir.join("missing_copy.mbtiles"),
+            ..Default::default()
+        };
+        assert!(matches!(
+            missing_src.run().await.unwrap_err(),
+            MbtError::FileNotFound(_)
+        ));
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    async fn get_indexes(conn: &mut SqliteConnection, table: &str) -> Vec<(String, bool)> {
+        query(r#"SELECT name, "unique" FROM pragma_index_list(?) ORDER BY name"#)
+            .bind(table)
+            .fetch_all(conn)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|row| (row.get(0), row.get(1)))
+            .collect()
+    }
+
+    #[actix_rt::test]
+    async fn copy_recreates_indexes() -> MbtResult<()> {
+        let opt = MbtilesCopier {
+            src_file: PathBuf::from("../tests/fixtures/mbtiles/geography-class-jpg.mbtiles"),
+            dst_file: PathBuf::from("file:copy_recreates_indexes_mem_db?mode=memory&cache=shared"),
+            ..Default::default()
+        };
+        let mut dst_conn = opt.run().await?;
+        assert_eq!(
+            get_indexes(&mut dst_conn, "map").await,
+            vec![("map_index".to_string(), true)]
+        );
+        assert_eq!(
+            get_indexes(&mut dst_conn, "images").await,
+            vec![("images_id".to_string(), true)]
+        );
+        assert_eq!(
+            get_one::<String>(
+                &mut dst_conn,
+                "SELECT type FROM sqlite_schema WHERE name = 'tiles'"
+            )
+            .await,
+            "view"
+        );
+
+        // Non-unique indexes are created after the tiles are copied
+        let dir = std::env::temp_dir().join("mbtiles-copy-indexes");
+        std::fs::create_dir_all(&dir)?;
+        let src = dir.join("world_cities_indexed.mbtiles");
+        let _ = std::fs::remove_file(&src);
+        let mut src_conn = MbtilesCopier {
+            src_file: PathBuf::from("../tests/fixtures/mbtiles/world_cities.mbtiles"),
+            dst_file: src.clone(),
+            ..Default::default()
+        }
+        .run()
+        .await?;
+        src_conn
+            .execute("CREATE INDEX tiles_zoom ON tiles (zoom_level)")
+            .await?;
+        src_conn.close().await?;
+
+        let opt = MbtilesCopier {
+            src_file: src,
+            dst_file: PathBuf::from(
+                "file:copy_recreates_non_unique_indexes_mem_db?mode=memory&cache=shared",
+            ),
+            ..Default::default()
+        };
+        let mut dst_conn = opt.run().await?;
+        assert_eq!(
+            get_indexes(&mut dst_conn, "tiles").await,
+            vec![
+                ("tile_index".to_string(), true),
+                ("tiles_zoom".to_string(), false)
+            ]
+        );
+        assert_eq!(
+            get_one::<i64>(&mut dst_conn, "SELECT COUNT(*) FROM tiles").await,
+            196
+        );
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn unique_index_sql() {
+        assert!(is_unique_index_sql(
+            "CREATE UNIQUE INDEX map_index ON map (zoom_level, tile_column, tile_row)"
+        ));
+        assert!(is_unique_index_sql(
+            "create  unique index name on metadata (name)"
+        ));
+        assert!(!is_unique_index_sql(
+            "CREATE INDEX tiles_zoom ON tiles (zoom_level)"
+        ));
+    }
+
+    #[test]
+    fn test_partition_tiles() {
+        let part = |zoom, min_column, max_column| TilePartition {
+            zoom,
+            min_column,
+            max_column,
+        };
+        assert_eq!(partition_tiles(&[], 4), Vec::<Vec<TilePartition>>::new());
+        assert_eq!(partition_tiles(&[0], 4), vec![vec![part(0, 0, 0)]]);
+        assert_eq!(
+            partition_tiles(&[0, 1, 3], 3),
+            vec![
+                vec![part(0, 0, 0), part(3, 0, 1)],
+                vec![part(1, 0, 1), part(3, 2, 4)],
+                vec![part(3, 5, 7)],
+            ]
+        );
+    }
+
+    #[actix_rt::test]
+    async fn copy_parallel() -> MbtResult<()> {
+        let dir = std::env::temp_dir().join("mbtiles-copy-parallel");
+        std::fs::create_dir_all(&dir)?;
+        let src = PathBuf::from("../tests/fixtures/mbtiles/world_cities.mbtiles");
+
+        for dst_type_cli in [MbtTypeCli::Flat, MbtTypeCli::FlatWithHash] {
+            let serial = MbtilesCopier {
+                src_file: src.clone(),
+                dst_file: PathBuf::from(format!(
+                    "file:copy_parallel_{dst_type_cli}_mem_db?mode=memory&cache=shared"
+                )),
+                dst_type_cli: Some(dst_type_cli),
+                ..Default::default()
+            };
+            let mut serial_conn = serial.run().await?;
+
+            let dst = dir.join(format!("{dst_type_cli}.mbtiles"));
+            let _ = std::fs::remove_file(&dst);
+            let parallel = MbtilesCopier {
+                src_file: src.clone(),
+                dst_file: dst,
+                dst_type_cli: Some(dst_type_cli),
+                jobs: Some(3),
+                ..Default::default()
+            };
+            let mut parallel_conn = parallel.run().await?;
+
+            let hash_sql = "SELECT value FROM metadata WHERE name = 'agg_tiles_hash'";
+            assert_eq!(
+                get_one::<String>(&mut serial_conn, hash_sql).await,
+                get_one::<String>(&mut parallel_conn, hash_sql).await
+            );
+            assert_eq!(
+                get_one::<i64>(&mut parallel_conn, "SELECT COUNT(*) FROM tiles").await,
+                196
+            );
+            assert_eq!(
+                get_one::<String>(&mut parallel_conn, "PRAGMA journal_mode").await,
+                "delete"
+            );
+        }
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[actix_rt::test]
+    async fn copy_fast_write() -> MbtResult<()> {
+        let dir = std::env::temp_dir().join("mbtiles-copy-fast-write");
+        std::fs::create_dir_all(&dir)?;
+        let dst = dir.join("dst.mbtiles");
+        let _ = std::fs::remove_file(&dst);
+        let opt = MbtilesCopier {
+            src_file: PathBuf::from("../tests/fixtures/mbtiles/world_cities.mbtiles"),
+            dst_file: dst,
+            fast_write: true,
+            ..Default::default()
+        };
+        let mut dst_conn = opt.run().await?;
+
+        assert_eq!(
+            get_one::<i64>(&mut dst_conn, "SELECT COUNT(*) FROM tiles").await,
+            196
+        );
+        assert_eq!(
+            get_one::<String>(&mut dst_conn, "PRAGMA journal_mode").await,
+            "delete"
+        );
+        // NORMAL
+        assert_eq!(get_one::<i64>(&mut dst_conn, "PRAGMA synchronous").await, 1);
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[actix_rt::test]
+    async fn copy_skip_empty_tiles() -> MbtResult<()> {
+        let dir = std::env::temp_dir().join("mbtiles-copy-skip-empty");
+        std::fs::create_dir_all(&dir)?;
+        let src = dir.join("src.mbtiles");
+        let blank = dir.join("blank.bin");
+        let _ = std::fs::remove_file(&src);
+        std::fs::write(&blank, b"blank")?;
+
+        let mut conn = Mbtiles::new(&src)?.open_or_new().await?;
+        crate::init_mbtiles_schema(&mut conn, Flat).await?;
+        let tiles = [
+            (0, 0, 0, Vec::new()),
+            (1, 0, 0, martin_tile_utils::encode_gzip(&[])?),
+            (1, 0, 1, b"blank".to_vec()),
+            (1, 1, 0, b"data".to_vec()),
+            (1, 1, 1, martin_tile_utils::encode_gzip(b"data")?),
+        ];
+        for (z, x, y, data) in tiles {
+            query("INSERT INTO tiles VALUES (?, ?, ?, ?)")
+                .bind(z)
+                .bind(x)
+                .bind(y)
+                .bind(data)
+                .execute(&mut conn)
+                .await?;
+        }
+        conn.close().await?;
+
+        let copy = |db: &str, skip_identical_to: Option<PathBuf>| MbtilesCopier {
+            src_file: src.clone(),
+            dst_file: PathBuf::from(format!("file:{db}_mem_db?mode=memory&cache=shared")),
+            skip_empty: true,
+            skip_identical_to,
+            ..Default::default()
+        };
+        let count_sql = "SELECT COUNT(*) FROM tiles";
+
+        let mut dst_conn = copy("copy_skip_empty", None).run().await?;
+        assert_eq!(get_one::<i64>(&mut dst_conn, count_sql).await, 3);
+
+        let mut dst_conn = copy("copy_skip_identical", Some(blank)).run().await?;
+        assert_eq!(get_one::<i64>(&mut dst_conn, count_sql).await, 2);
+        assert_eq!(
+            get_one::<i64>(
+                &mut dst_conn,
+                "SELECT COUNT(*) FROM tiles WHERE tile_column = 1"
+            )
+            .await,
+            2
+        );
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[actix_rt::test]
+    async fn copy_with_transform() -> MbtResult<()> {
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = calls.clone();
+        let opt = MbtilesCopier {
+            src_file: PathBuf::from("../tests/fixtures/mbtiles/world_cities.mbtiles"),
+            dst_file: PathBuf::from("file:copy_with_transform_mem_db?mode=memory&cache=shared"),
+            dst_type_cli: FLAT_WITH_HASH,
+            ..Default::default()
+        }
+        .with_transform(move |z, _x, _y, data| {
+            counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            (z % 2 == 0).then_some(data)
+        });
+        let mut dst_conn = opt.run().await?;
+
+        assert_eq!(calls.load(std::sync::atomic::Ordering::Relaxed), 196);
+        assert!(get_one::<i64>(&mut dst_conn, "SELECT COUNT(*) FROM tiles").await > 0);
+        assert_eq!(
+            get_one::<i64>(
+                &mut dst_conn,
+                "SELECT COUNT(*) FROM tiles WHERE zoom_level % 2 = 1"
+            )
+            .await,
+            0
+        );
+        assert_eq!(
+            get_one::<i64>(
+                &mut dst_conn,
+                "SELECT COUNT(*) FROM tiles_with_hash WHERE tile_hash != md5_hex(tile_data)"
+            )
+            .await,
+            0
+        );
+        Ok(())
+    }
+
+    #[actix_rt::test]
+    async fn copy_flip_y() -> MbtResult<()> {
+        let src = PathBuf::from("../tests/fixtures/mbtiles/world_cities.mbtiles");
+        let flipped_sql = "
+            SELECT COUNT(*)
+            FROM testSrcDb.tiles AS s JOIN tiles AS d
+              ON s.zoom_level = d.zoom_level AND s.tile_column = d.tile_column
+                 AND d.tile_row = (1 << s.zoom_level) - 1 - s.tile_row
+                 AND s.tile_data = d.tile_data";
+        for (db, dst_type_cli) in [("flat", FLAT), ("norm", NORM_CLI)] {
+            let opt = MbtilesCopier {
+                src_file: src.clone(),
+                dst_file: PathBuf::from(format!(
+                    "file:copy_flip_y_{db}_mem_db?mode=memory&cache=shared"
+                )),
+                dst_type_cli,
+                flip_y: FlipYMode::Always,
+                ..Default::default()
+            };
+            let mut dst_conn = opt.run().await?;
+            Mbtiles::new(&src)?
+                .attach_to(&mut dst_conn, "testSrcDb")
+                .await?;
+            assert_eq!(get_one::<i64>(&mut dst_conn, flipped_sql).await, 196);
+        }
+
+        // The bounds of world_cities cover the whole world, so both schemes match equally
+        let opt = MbtilesCopier {
+            src_file: src.clone(),
+            dst_file: PathBuf::from("file:copy_flip_y_auto_mem_db?mode=memory&cache=shared"),
+            flip_y: FlipYMode::Auto,
+            ..Default::default()
+        };
+        let mut dst_conn = opt.run().await?;
+        Mbtiles::new(&src)?
+            .attach_to(&mut dst_conn, "testSrcDb")
+            .await?;
+        assert!(
+            dst_conn
+                .fetch_optional("SELECT * FROM testSrcDb.tiles EXCEPT SELECT * FROM tiles")
+                .await?
+                .is_none()
+        );
+
+        let opt = MbtilesCopier {
+            src_file: src,
+            dst_file: PathBuf::from("file:copy_flip_y_abort_mem_db?mode=memory&cache=shared"),
+            on_duplicate: Some(CopyDuplicateMode::Abort),
+            flip_y: FlipYMode::Always,
+            ..Default::default()
+        };
+        assert!(matches!(
+            opt.run().await,
+            Err(MbtError::UnsupportedCopyOperation { .. })
+        ));
+        Ok(())
+    }
+
+    #[actix_rt::test]
+    async fn copy_flip_y_auto() -> MbtResult<()> {
+        let dir = std::env::temp_dir().join("mbtiles-copy-flip-y-auto");
+        std::fs::create_dir_all(&dir)?;
+        let src = dir.join("src.mbtiles");
+        let _ = std::fs::remove_file(&src);
+
+        // Tiles of the northern hemisphere, written with XYZ rows
+        let mbt = Mbtiles::new(&src)?;
+        let mut conn = mbt.open_or_new().await?;
+        crate::init_mbtiles_schema(&mut conn, Flat).await?;
+        mbt.set_metadata_value(&mut conn, "bounds", "0,40,10,50")
+            .await?;
+        for (z, x, y) in [(0, 0, 0), (4, 8, 5), (4, 8, 6)] {
+            query("INSERT INTO tiles VALUES (?, ?, ?, x'00')")
+                .bind(z)
+                .bind(x)
+                .bind(y)
+                .execute(&mut conn)
+                .await?;
+        }
+        assert!(has_xyz_tile_rows(&mbt, &mut conn).await?);
+        conn.close().await?;
+
+        let opt = MbtilesCopier {
+            src_file: src,
+            dst_file: PathBuf::from("file:copy_flip_y_detect_mem_db?mode=memory&cache=shared"),
+            flip_y: FlipYMode::Auto,
+            ..Default::default()
+        };
+        let mut dst_conn = opt.run().await?;
+        assert_eq!(
+            get_one::<String>(
+                &mut dst_conn,
+                "SELECT group_concat(tile_row) FROM (SELECT tile_row FROM tiles WHERE zoom_level = 4 ORDER BY tile_row)"
+            )
+            .await,
+            "9,10"
+        );
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[actix_rt::test]
+    async fn copy_cluster() -> MbtResult<()> {
+        let src = PathBuf::from("../tests/fixtures/mbtiles/world_cities.mbtiles");
+        for (order, dst_type) in [
+            (ClusterOrder::Hilbert, FLAT),
+            (ClusterOrder::ZOrder, NORM_CLI),
+        ] {
+            let dst = PathBuf::from(format!(
+                "file:copy_cluster_{order}_mem_db?mode=memory&cache=shared"
+            ));
+            let opt = MbtilesCopier {
+                src_file: src.clone(),
+                dst_file: dst.clone(),
+                dst_type_cli: dst_type,
+                cluster: Some(order),
+                ..Default::default()
+            };
+            let mut dst_conn = opt.run().await?;
+            Mbtiles::new(&dst)?
+                .verify_clustered(&mut dst_conn, &Mbtiles::new(&src)?, order)
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn verify_copy_grids(dst_type_cli: Option<MbtTypeCli>, dst: &str) -> MbtResult<()> {
+        let src = PathBuf::from("../tests/fixtures/mbtiles/geography-class-jpg.mbtiles");
+        let opt = MbtilesCopier {
+            src_file: src.clone(),
+            dst_file: PathBuf::from(dst),
+            dst_type_cli,
+            ..Default::default()
+        };
+        let mut dst_conn = opt.run().await?;
+        Mbtiles::new(src)?
+            .attach_to(&mut dst_conn, "testSrcDb")
+            .await?;
+
+        for tbl in ["grids", "grid_data"] {
+            assert!(
+                get_one::<i64>(&mut dst_conn, &format!("SELECT COUNT(*) FROM {tbl}")).await > 0
+            );
+            let sql = format!(
+                "SELECT * FROM testSrcDb.{tbl} EXCEPT SELECT * FROM {tbl}
+                 UNION
+                 SELECT * FROM {tbl} EXCEPT SELECT * FROM testSrcDb.{tbl}"
+            );
+            assert!(dst_conn.fetch_optional(sql.as_str()).await?.is_none());
+        }
+
+        Ok(())
+    }
+
+    #[actix_rt::test]
+    async fn copy_grids_to_normalized() -> MbtResult<()> {
+        let dst = "file:copy_grids_to_normalized_mem_db?mode=memory&cache=shared";
+        verify_copy_grids(None, dst).await
+    }
+
+    #[actix_rt::test]
+    async fn copy_grids_to_flat() -> MbtResult<()> {
+        let dst = "file:copy_grids_to_flat_mem_db?mode=memory&cache=shared";
+        verify_copy_grids(FLAT, dst).await
+    }
+
+    #[actix_rt::test]
+    async fn copy_no_grids() -> MbtResult<()> {
+        let opt = MbtilesCopier {
+            src_file: PathBuf::from("../tests/fixtures/mbtiles/geography-class-jpg.mbtiles"),
+            dst_file: PathBuf::from("file:copy_no_grids_mem_db?mode=memory&cache=shared"),
+            dst_type_cli: FLAT,
+            no_grids: true,
+            ..Default::default()
+        };
+        let mut dst_conn = opt.run().await?;
+        assert!(
+            dst_conn
+                .fetch_optional("SELECT 1 FROM sqlite_schema WHERE name IN ('grids', 'grid_data')")
+                .await?
+                .is_none()
+        );
+        Ok(())
+    }
+
     #[actix_rt::test]
     async fn copy_with_diff_with_file() -> MbtResult<()> {
         let src = PathBuf::from("../tests/fixtures/mbtiles/geography-class-jpg.mbtiles");
@@ -1082,6 +2675,82 @@ mod tests {
         ));
     }
 
+    #[actix_rt::test]
+    async fn copy_to_existing_mismatched_format() -> MbtResult<()> {
+        let dst = PathBuf::from(
+            "file:copy_to_existing_mismatched_format_mem_db?mode=memory&cache=shared",
+        );
+        let _dst_conn = MbtilesCopier {
+            src_file: PathBuf::from("../tests/fixtures/mbtiles/world_cities.mbtiles"),
+            dst_file: dst.clone(),
+            ..Default::default()
+        }
+        .run()
+        .await?;
+
+        let copy_jpeg = |force_format| MbtilesCopier {
+            src_file: PathBuf::from("../tests/fixtures/mbtiles/geography-class-jpg.mbtiles"),
+            dst_file: dst.clone(),
+            on_duplicate: Some(CopyDuplicateMode::Override),
+            force_format,
+            ..Default::default()
+        };
+        assert!(matches!(
+            copy_jpeg(false).run().await.unwrap_err(),
+            MbtError::MismatchedTileFormats(src, dst)
+                if src.format == Format::Jpeg && dst.format == Format::Mvt
+        ));
+        let mut dst_conn = copy_jpeg(true).run().await?;
+        assert!(
+            get_one::<i64>(
+                &mut dst_conn,
+                "SELECT COUNT(*) FROM tiles WHERE hex(substr(tile_data, 1, 3)) = 'FFD8FF'"
+            )
+            .await
+                > 0
+        );
+        Ok(())
+    }
+
+    #[actix_rt::test]
+    async fn copy_to_existing_different_encoding() -> MbtResult<()> {
+        let src = PathBuf::from("../tests/fixtures/mbtiles/world_cities.mbtiles");
+        let dst = PathBuf::from(
+            "file:copy_to_existing_different_encoding_mem_db?mode=memory&cache=shared",
+        );
+        let _dst_conn = MbtilesCopier {
+            src_file: src.clone(),
+            dst_file: dst.clone(),
+            ..Default::default()
+        }
+        .with_transform(|_z, _x, _y, data| {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(&decode_gzip(&data).unwrap()).unwrap();
+            Some(encoder.finish().unwrap())
+        })
+        .run()
+        .await?;
+
+        // Only the encoding differs, so the gzip-ed tiles are copied with a warning
+        let mut dst_conn = MbtilesCopier {
+            src_file: src,
+            dst_file: dst,
+            on_duplicate: Some(CopyDuplicateMode::Override),
+            ..Default::default()
+        }
+        .run()
+        .await?;
+        assert_eq!(
+            get_one::<i64>(
+                &mut dst_conn,
+                "SELECT COUNT(*) FROM tiles WHERE hex(substr(tile_data, 1, 2)) = '1F8B'"
+            )
+            .await,
+            196
+        );
+        Ok(())
+    }
+
     #[actix_rt::test]
     async fn copy_to_existing_override_mode() -> MbtResult<()> {
         let src_file = PathBuf::from("../tests/fixtures/mbtiles/world_cities_modified.mbtiles");
@@ -22,15 +22,25 @@ pub enum MbtError {
     #[error(transparent)]
     JsonSerdeError(#[from] serde_json::Error),
 
-    #[error("MBTile filepath contains unsupported characters: {0}")]
-    UnsupportedCharsInFilepath(PathBuf),
-
     #[error("Inconsistent tile formats detected: {0} vs {1}")]
     InconsistentMetadata(TileInfo, TileInfo),
 
+    #[error(
+        "Source tiles are {0}, but the destination file already has {1} tiles. Use --force-format to copy them anyway"
+    )]
+    MismatchedTileFormats(TileInfo, TileInfo),
+
     #[error("Invalid data format for MBTile file {0}")]
     InvalidDataFormat(String),
 
+    #[error(
+        "MBTiles file {0} has a tiles view without the underlying tables it is based on, so its tiles cannot be modified"
+    )]
+    ReadOnlyTilesView(String),
+
+    #[error("Clustered MBTiles file {0} does not match its source: {1}")]
+    ClusterMismatch(String, String),
+
     #[error("Integrity check failed for MBTile file {0} for the following reasons:\n    {1:?}")]
     FailedIntegrityCheck(String, Vec<String>),
 
@@ -44,6 +54,9 @@ pub enum MbtError {
     )]
     InvalidTileIndex(String, String, String, String),
 
+    #[error("{1} tiles have truncated or corrupted data in MBTile file {0}")]
+    InvalidTileContent(String, u64),
+
     #[error(
         "Computed aggregate tiles hash {0} does not match tile data in metadata {1} for MBTile file {2}"
     )]
@@ -54,6 +67,9 @@ pub enum MbtError {
     )]
     AggHashValueNotFound(String),
 
+    #[error("MBTiles file {0} does not exist")]
+    FileNotFound(PathBuf),
+
     #[error(r#"Filename "{0}" passed to SQLite must be valid UTF-8"#)]
     InvalidFilenameType(PathBuf),
 
@@ -88,6 +104,17 @@ pub enum MbtError {
     #[error("Invalid zoom value {0}={1}, expecting an integer between 0..{MAX_ZOOM}")]
     InvalidZoomValue(&'static str, String),
 
+    #[error(
+        "Tile coordinate {0}/{1}/{2} is out of range, expecting zoom 0..={MAX_ZOOM} and x and y below 2^zoom"
+    )]
+    InvalidTileCoordinate(u8, u32, u32),
+
+    #[error("Invalid metadata value {0}='{1}': {2}. Use `--force` to set it anyway.")]
+    InvalidMetadataValue(String, String, String),
+
+    #[error("Invalid page size {0}, expecting a power of two between 512 and 65536")]
+    InvalidPageSize(u32),
+
     #[error(
         "A file {0} does not have an {AGG_TILES_HASH} metadata entry, probably because it was not created by this tool. Use `--force` to ignore this warning, or run this to update hash value: `mbtiles validate --agg-hash update {0}`"
     )]
@@ -0,0 +1,75 @@
+use std::fmt::Write as _;
+
+use itertools::Itertools as _;
+use log::trace;
+use martin_tile_utils::{MAX_ZOOM, bbox_to_xyz};
+use tilejson::Bounds;
+
+use crate::invert_y_value;
+
+/// Zoom levels and bounding boxes selecting a subset of tiles,
+/// used by [`crate::MbtilesCopier`] and [`crate::Mbtiles::count_tiles`].
+/// An empty filter selects all tiles.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TileFilter {
+    /// Minimum zoom level, ignored if `zoom_levels` is set
+    pub min_zoom: Option<u8>,
+    /// Maximum zoom level, ignored if `zoom_levels` is set
+    pub max_zoom: Option<u8>,
+    /// List of zoom levels
+    pub zoom_levels: Vec<u8>,
+    /// Bounding boxes, a tile must intersect with at least one of them
+    pub bbox: Vec<Bounds>,
+}
+
+impl TileFilter {
+    /// Format SQL conditions matching the filter, each starting with ` AND`, to be added after a `WHERE` clause.
+    /// The `prefix` is prepended to all column names, e.g. a table name with a dot.
+    /// Note that there is no risk of SQL injection here, as the arguments are numbers.
+    #[must_use]
+    pub fn sql_conditions(&self, prefix: &str) -> String {
+        let mut sql = if !self.zoom_levels.is_empty() {
+            let zooms = self.zoom_levels.iter().join(",");
+            format!(" AND {prefix}zoom_level IN ({zooms})")
+        } else if let Some(min_zoom) = self.min_zoom {
+            if let Some(max_zoom) = self.max_zoom {
+                format!(" AND {prefix}zoom_level BETWEEN {min_zoom} AND {max_zoom}")
+            } else {
+                format!(" AND {prefix}zoom_level >= {min_zoom}")
+            }
+        } else if let Some(max_zoom) = self.max_zoom {
+            format!(" AND {prefix}zoom_level <= {max_zoom}")
+        } else {
+            String::new()
+        };
+
+        if !self.bbox.is_empty() {
+            sql.push_str(" AND (\n");
+            for (idx, bbox) in self.bbox.iter().enumerate() {
+                // Use maximum zoom value for easy filtering,
+                // converting it on the fly to the actual zoom level
+                let (min_x, min_y, max_x, max_y) =
+                    bbox_to_xyz(bbox.left, bbox.bottom, bbox.right, bbox.top, MAX_ZOOM);
+                trace!(
+                    "Bounding box {bbox} converted to {min_x},{min_y},{max_x},{max_y} at zoom {MAX_ZOOM}"
+                );
+                let (min_y, max_y) = (
+                    invert_y_value(MAX_ZOOM, max_y),
+                    invert_y_value(MAX_ZOOM, min_y),
+                );
+
+                if idx > 0 {
+                    sql.push_str(" OR\n");
+                }
+                writeln!(
+                    sql,
+                    "(({prefix}tile_column * (1 << ({MAX_ZOOM} - {prefix}zoom_level))) BETWEEN {min_x} AND {max_x} \
+                     AND ({prefix}tile_row * (1 << ({MAX_ZOOM} - {prefix}zoom_level))) BETWEEN {min_y} AND {max_y})",
+                ).unwrap();
+            }
+            sql.push(')');
+        }
+
+        sql
+    }
+}
@@ -4,17 +4,25 @@
 pub use bindiff::{PatchType, PatchTypeCli};
 pub use sqlx;
 
+mod cluster;
+pub use cluster::ClusterOrder;
+
 mod copier;
-pub use copier::{CopyDuplicateMode, MbtilesCopier};
+pub use copier::{
+    CopyDuplicateMode, DryRunSummary, DryRunZoomInfo, FlipYMode, MbtilesCopier, TileTransform,
+};
 
 mod errors;
 pub use errors::{MbtError, MbtResult};
 
+mod filter;
+pub use filter::TileFilter;
+
 mod mbtiles;
 pub use mbtiles::{CopyType, MbtTypeCli, Mbtiles};
 
 mod metadata;
-pub use metadata::Metadata;
+pub use metadata::{Metadata, validate_metadata_value};
 
 mod patcher;
 pub use patcher::apply_patch;
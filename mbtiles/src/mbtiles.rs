@@ -1,18 +1,22 @@
 use std::ffi::OsStr;
 use std::fmt::{Display, Formatter};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use enum_display::EnumDisplay;
 use log::debug;
+use martin_tile_utils::MAX_ZOOM;
 use serde::{Deserialize, Serialize};
 use sqlite_compressions::{register_bsdiffraw_functions, register_gzip_functions};
 use sqlite_hashes::register_md5_functions;
 use sqlx::sqlite::SqliteConnectOptions;
-use sqlx::{Connection as _, Executor, SqliteConnection, SqliteExecutor, Statement, query};
+use sqlx::{
+    Connection as _, Executor, Row as _, SqliteConnection, SqliteExecutor, Statement, query,
+    query_scalar,
+};
 
 use crate::bindiff::PatchType;
 use crate::errors::{MbtError, MbtResult};
-use crate::{CopyDuplicateMode, MbtType, invert_y_value};
+use crate::{CopyDuplicateMode, MbtType, TileFilter, invert_y_value};
 
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Serialize, Deserialize, EnumDisplay)]
 #[enum_display(case = "Kebab")]
@@ -54,13 +58,14 @@ pub struct PatchFileInfo {
 
 #[derive(Clone, Debug)]
 pub struct Mbtiles {
-    filepath: String,
+    filepath: PathBuf,
     filename: String,
+    sqlite_filename: String,
 }
 
 impl Display for Mbtiles {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.filepath)
+        write!(f, "{}", self.filepath.display())
     }
 }
 
@@ -68,28 +73,30 @@ impl Mbtiles {
     pub fn new<P: AsRef<Path>>(filepath: P) -> MbtResult<Self> {
         let path = filepath.as_ref();
         Ok(Self {
-            filepath: path
-                .to_str()
-                .ok_or_else(|| MbtError::UnsupportedCharsInFilepath(path.to_path_buf()))?
-                .to_string(),
+            filepath: path.to_path_buf(),
             filename: path
                 .file_stem()
                 .unwrap_or_else(|| OsStr::new("unknown"))
                 .to_string_lossy()
                 .to_string(),
+            sqlite_filename: to_sqlite_filename(path)?,
         })
     }
 
+    /// Open an existing file for reading and writing. Use [`Mbtiles::open_or_new`] to create a new file.
     pub async fn open(&self) -> MbtResult<SqliteConnection> {
         debug!("Opening w/ defaults {self}");
-        let opt = SqliteConnectOptions::new().filename(self.filepath());
-        Self::open_int(&opt).await
+        let opt = SqliteConnectOptions::new()
+            .filename(self.sqlite_filename())
+            .create_if_missing(false);
+        self.open_existing(&opt).await
     }
 
+    /// Open a file for reading and writing, creating it if it does not exist.
     pub async fn open_or_new(&self) -> MbtResult<SqliteConnection> {
         debug!("Opening or creating {self}");
         let opt = SqliteConnectOptions::new()
-            .filename(self.filepath())
+            .filename(self.sqlite_filename())
             .create_if_missing(true);
         Self::open_int(&opt).await
     }
@@ -97,19 +104,36 @@ impl Mbtiles {
     pub async fn open_readonly(&self) -> MbtResult<SqliteConnection> {
         debug!("Opening as readonly {self}");
         let opt = SqliteConnectOptions::new()
-            .filename(self.filepath())
-            .read_only(true);
-        Self::open_int(&opt).await
+            .filename(self.sqlite_filename())
+            .read_only(true)
+            .create_if_missing(false);
+        self.open_existing(&opt).await
     }
 
-    async fn open_int(opt: &SqliteConnectOptions) -> Result<SqliteConnection, MbtError> {
+    async fn open_int(opt: &SqliteConnectOptions) -> MbtResult<SqliteConnection> {
         let mut conn = SqliteConnection::connect_with(opt).await?;
         attach_sqlite_fn(&mut conn).await?;
         Ok(conn)
     }
 
+    async fn open_existing(&self, opt: &SqliteConnectOptions) -> MbtResult<SqliteConnection> {
+        Self::open_int(opt)
+            .await
+            .map_err(|e| self.map_open_error(e))
+    }
+
+    /// Report a missing file as [`MbtError::FileNotFound`] instead of a generic `SQLite` error.
+    /// Must only be used when opening without `create_if_missing`.
+    pub(crate) fn map_open_error<E: Into<MbtError>>(&self, err: E) -> MbtError {
+        if !self.filepath.exists() && !self.is_in_memory() {
+            MbtError::FileNotFound(self.filepath.clone())
+        } else {
+            err.into()
+        }
+    }
+
     #[must_use]
-    pub fn filepath(&self) -> &str {
+    pub fn filepath(&self) -> &Path {
         &self.filepath
     }
 
@@ -118,6 +142,21 @@ impl Mbtiles {
         &self.filename
     }
 
+    /// The filename as it is passed to `SQLite`. This is the same as [`Mbtiles::filepath`]
+    /// unless the path is not valid UTF-8, in which case it is a `file:` URI with percent-encoded bytes.
+    #[must_use]
+    pub fn sqlite_filename(&self) -> &str {
+        &self.sqlite_filename
+    }
+
+    /// Check if this refers to an in-memory database rather than a file
+    pub(crate) fn is_in_memory(&self) -> bool {
+        let name = self.sqlite_filename.as_str();
+        name == ":memory:"
+            || name.starts_with("file::memory:")
+            || (name.starts_with("file:") && name.contains("mode=memory"))
+    }
+
     /// Attach this `MBTiles` file to the given `SQLite` connection as a given name
     pub async fn attach_to<T>(&self, conn: &mut T, name: &str) -> MbtResult<()>
     where
@@ -125,12 +164,14 @@ impl Mbtiles {
     {
         debug!("Attaching {self} as {name}");
         query(&format!("ATTACH DATABASE ? AS {name}"))
-            .bind(self.filepath())
+            .bind(self.sqlite_filename())
             .execute(conn)
             .await?;
         Ok(())
     }
 
+    /// Get the data of the tile at `z/x/y` in the XYZ scheme, or `None` if there is no such tile.
+    /// Returns [`MbtError::InvalidTileCoordinate`] if `x` or `y` are not valid for the zoom level.
     pub async fn get_tile<T>(
         &self,
         conn: &mut T,
@@ -141,6 +182,9 @@ impl Mbtiles {
     where
         for<'e> &'e mut T: SqliteExecutor<'e>,
     {
+        if !is_valid_tile_coord(z, x, y) {
+            return Err(MbtError::InvalidTileCoordinate(z, x, y));
+        }
         let y = invert_y_value(z, y);
         let query = query! {"SELECT tile_data from tiles where zoom_level = ? AND tile_column = ? AND tile_row = ?", z, x, y};
         let row = query.fetch_optional(conn).await?;
@@ -152,6 +196,56 @@ impl Mbtiles {
         Ok(None)
     }
 
+    /// Check if a tile exists at `z/x/y` in the XYZ scheme, without reading its data.
+    /// Unlike [`Mbtiles::get_tile`], this avoids joining the `images` table of normalized files.
+    pub async fn has_tile<T>(&self, conn: &mut T, z: u8, x: u32, y: u32) -> MbtResult<bool>
+    where
+        for<'e> &'e mut T: SqliteExecutor<'e>,
+    {
+        if !is_valid_tile_coord(z, x, y) {
+            return Err(MbtError::InvalidTileCoordinate(z, x, y));
+        }
+        let table = self.get_tiles_table(&mut *conn).await;
+        let exists: bool = query_scalar(&format!(
+            "SELECT EXISTS (SELECT 1 FROM {table} WHERE zoom_level = ? AND tile_column = ? AND tile_row = ?)"
+        ))
+        .bind(z)
+        .bind(x)
+        .bind(invert_y_value(z, y))
+        .fetch_one(&mut *conn)
+        .await?;
+        Ok(exists)
+    }
+
+    /// Count the tiles matching the filter, without reading their data
+    pub async fn count_tiles<T>(&self, conn: &mut T, filter: &TileFilter) -> MbtResult<u64>
+    where
+        for<'e> &'e mut T: SqliteExecutor<'e>,
+    {
+        let table = self.get_tiles_table(&mut *conn).await;
+        let conditions = filter.sql_conditions("");
+        let count: i64 = query(&format!(
+            "SELECT COUNT(*) FROM {table} WHERE TRUE{conditions}"
+        ))
+        .fetch_one(&mut *conn)
+        .await?
+        .get(0);
+        Ok(u64::try_from(count).unwrap_or_default())
+    }
+
+    /// Name of the table storing the tile coordinates for the detected layout.
+    /// Files with an unrecognized layout are queried through the `tiles` view or table.
+    async fn get_tiles_table<T>(&self, conn: &mut T) -> &'static str
+    where
+        for<'e> &'e mut T: SqliteExecutor<'e>,
+    {
+        match self.detect_type(&mut *conn).await {
+            Ok(MbtType::Flat) | Err(_) => "tiles",
+            Ok(MbtType::FlatWithHash) => "tiles_with_hash",
+            Ok(MbtType::Normalized { .. }) => "map",
+        }
+    }
+
     pub async fn insert_tiles(
         &self,
         conn: &mut SqliteConnection,
@@ -186,6 +280,86 @@ impl Mbtiles {
         Ok(())
     }
 
+    /// Insert or replace a single tile at `z/x/y` in the XYZ scheme, regardless of the file layout.
+    /// In a normalized file, the tile data is stored in `images` under its hash,
+    /// and the data of the replaced tile is deleted unless it is used by other tiles.
+    /// The `agg_tiles_hash` metadata value is not updated, see [`Mbtiles::update_agg_tiles_hash`].
+    pub async fn insert_tile(
+        &self,
+        conn: &mut SqliteConnection,
+        z: u8,
+        x: u32,
+        y: u32,
+        tile_data: &[u8],
+    ) -> MbtResult<()> {
+        if !is_valid_tile_coord(z, x, y) {
+            return Err(MbtError::InvalidTileCoordinate(z, x, y));
+        }
+        let mbt_type = self.detect_writable_type(&mut *conn).await?;
+        let mut tx = conn.begin().await?;
+        let old_tile_id = get_normalized_tile_id(&mut tx, mbt_type, z, x, y).await?;
+        self.insert_tiles(
+            &mut tx,
+            mbt_type,
+            CopyDuplicateMode::Override,
+            &[(z, x, y, tile_data.to_vec())],
+        )
+        .await?;
+        if let Some(tile_id) = old_tile_id {
+            delete_orphaned_image(&mut tx, &tile_id).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Delete a single tile at `z/x/y` in the XYZ scheme, returning `false` if there was no such tile.
+    /// In a normalized file, the tile data is deleted from `images` unless it is used by other tiles.
+    /// The `agg_tiles_hash` metadata value is not updated, see [`Mbtiles::update_agg_tiles_hash`].
+    pub async fn delete_tile(
+        &self,
+        conn: &mut SqliteConnection,
+        z: u8,
+        x: u32,
+        y: u32,
+    ) -> MbtResult<bool> {
+        if !is_valid_tile_coord(z, x, y) {
+            return Err(MbtError::InvalidTileCoordinate(z, x, y));
+        }
+        let mbt_type = self.detect_writable_type(&mut *conn).await?;
+        let table = match mbt_type {
+            MbtType::Flat => "tiles",
+            MbtType::FlatWithHash => "tiles_with_hash",
+            MbtType::Normalized { .. } => "map",
+        };
+        let mut tx = conn.begin().await?;
+        let old_tile_id = get_normalized_tile_id(&mut tx, mbt_type, z, x, y).await?;
+        let deleted = query(&format!(
+            "DELETE FROM {table} WHERE zoom_level = ? AND tile_column = ? AND tile_row = ?"
+        ))
+        .bind(z)
+        .bind(x)
+        .bind(invert_y_value(z, y))
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+        if let Some(tile_id) = old_tile_id {
+            delete_orphaned_image(&mut tx, &tile_id).await?;
+        }
+        tx.commit().await?;
+        Ok(deleted > 0)
+    }
+
+    /// Same as [`Mbtiles::detect_type`], but reports a `tiles` view without
+    /// the tables it is based on as [`MbtError::ReadOnlyTilesView`].
+    async fn detect_writable_type(&self, conn: &mut SqliteConnection) -> MbtResult<MbtType> {
+        match self.detect_type(&mut *conn).await {
+            Err(MbtError::InvalidDataFormat(_)) if is_tiles_view(&mut *conn).await? => {
+                Err(MbtError::ReadOnlyTilesView(self.to_string()))
+            }
+            res => res,
+        }
+    }
+
     fn get_insert_sql(
         src_type: MbtType,
         on_duplicate: CopyDuplicateMode,
@@ -224,6 +398,38 @@ impl Mbtiles {
     }
 }
 
+/// Convert a path into a string that `SQLite` can open.
+/// `SQLite` filenames are raw bytes, but `sqlx` only accepts UTF-8 strings, so any path that is not valid UTF-8
+/// is converted into a `file:` URI with percent-encoded bytes, which `SQLite` decodes back into the original filename.
+#[cfg(unix)]
+#[allow(clippy::unnecessary_wraps)]
+fn to_sqlite_filename(path: &Path) -> MbtResult<String> {
+    use std::fmt::Write as _;
+    use std::os::unix::ffi::OsStrExt as _;
+
+    if let Some(path) = path.to_str() {
+        return Ok(path.to_string());
+    }
+
+    let mut uri = String::from("file:");
+    for &b in path.as_os_str().as_bytes() {
+        if b.is_ascii_alphanumeric() || b"/-_.~".contains(&b) {
+            uri.push(char::from(b));
+        } else {
+            write!(uri, "%{b:02X}").unwrap();
+        }
+    }
+    Ok(uri)
+}
+
+/// Convert a path into a string that `SQLite` can open. Only valid UTF-8 paths are supported on this platform.
+#[cfg(not(unix))]
+fn to_sqlite_filename(path: &Path) -> MbtResult<String> {
+    path.to_str()
+        .map(ToString::to_string)
+        .ok_or_else(|| MbtError::InvalidFilenameType(path.to_path_buf()))
+}
+
 pub async fn attach_sqlite_fn(conn: &mut SqliteConnection) -> MbtResult<()> {
     let mut handle_lock = conn.lock_handle().await?;
     let handle = handle_lock.as_raw_handle().as_ptr();
@@ -236,6 +442,55 @@ pub async fn attach_sqlite_fn(conn: &mut SqliteConnection) -> MbtResult<()> {
     Ok(())
 }
 
+/// Get the `tile_id` of a tile in a normalized file, or `None` for other layouts and missing tiles
+async fn get_normalized_tile_id(
+    conn: &mut SqliteConnection,
+    mbt_type: MbtType,
+    z: u8,
+    x: u32,
+    y: u32,
+) -> MbtResult<Option<String>> {
+    if !matches!(mbt_type, MbtType::Normalized { .. }) {
+        return Ok(None);
+    }
+    let row =
+        query("SELECT tile_id FROM map WHERE zoom_level = ? AND tile_column = ? AND tile_row = ?")
+            .bind(z)
+            .bind(x)
+            .bind(invert_y_value(z, y))
+            .fetch_optional(&mut *conn)
+            .await?;
+    Ok(row.and_then(|r| r.get(0)))
+}
+
+/// Delete the tile data from the `images` table of a normalized file, unless some tile still uses it
+async fn delete_orphaned_image(conn: &mut SqliteConnection, tile_id: &str) -> MbtResult<()> {
+    query(
+        "DELETE FROM images
+         WHERE tile_id = ?1
+           AND NOT EXISTS (SELECT 1 FROM map WHERE map.tile_id = ?1)",
+    )
+    .bind(tile_id)
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Check if `tiles` is a view rather than a table
+async fn is_tiles_view(conn: &mut SqliteConnection) -> MbtResult<bool> {
+    let row = query("SELECT COUNT(*) FROM sqlite_master WHERE name = 'tiles' AND type = 'view'")
+        .fetch_one(&mut *conn)
+        .await?;
+    Ok(row.get::<i64, _>(0) > 0)
+}
+
+/// Check that the zoom is valid, and that `x` and `y` are below `2^z`.
+/// Uses checked shifts, so any zoom value is safe to pass.
+fn is_valid_tile_coord(z: u8, x: u32, y: u32) -> bool {
+    let z_shift = u32::from(z);
+    z <= MAX_ZOOM && x.checked_shr(z_shift) == Some(0) && y.checked_shr(z_shift) == Some(0)
+}
+
 #[cfg(test)]
 pub(crate) mod tests {
     use super::*;
@@ -244,4 +499,178 @@ pub(crate) mod tests {
         let mbt = Mbtiles::new(filepath)?;
         mbt.open().await.map(|conn| (conn, mbt))
     }
+
+    async fn copy_fixture_and_open(filepath: PathBuf) -> MbtResult<()> {
+        std::fs::create_dir_all(filepath.parent().unwrap())?;
+        std::fs::copy("../tests/fixtures/mbtiles/world_cities.mbtiles", &filepath)?;
+
+        let mbt = Mbtiles::new(&filepath)?;
+        assert_eq!(mbt.filepath(), filepath.as_path());
+        let mut conn = mbt.open_readonly().await?;
+        let metadata = mbt.get_metadata(&mut conn).await?;
+        assert_eq!(
+            metadata.tilejson.name.as_deref(),
+            Some("Major cities from Natural Earth data")
+        );
+        assert!(mbt.get_tile(&mut conn, 0, 0, 0).await?.is_some());
+
+        // Attaching the file to another connection must use the same filename
+        let (mut mem_conn, _) = open(":memory:").await?;
+        mbt.attach_to(&mut mem_conn, "otherDb").await?;
+
+        std::fs::remove_dir_all(filepath.parent().unwrap())?;
+        Ok(())
+    }
+
+    #[actix_rt::test]
+    async fn get_tile_out_of_range() -> MbtResult<()> {
+        let (mut conn, mbt) = open("../tests/fixtures/mbtiles/world_cities.mbtiles").await?;
+        assert!(mbt.get_tile(&mut conn, 1, 1, 1).await?.is_some());
+        for (z, x, y) in [
+            (1, 0, 2),
+            (1, 2, 0),
+            (0, 0, 1),
+            (31, 0, 0),
+            (64, 0, 0),
+            (255, 0, 0),
+        ] {
+            assert!(
+                matches!(
+                    mbt.get_tile(&mut conn, z, x, y).await,
+                    Err(MbtError::InvalidTileCoordinate(..))
+                ),
+                "{z}/{x}/{y}"
+            );
+        }
+        Ok(())
+    }
+
+    #[actix_rt::test]
+    async fn has_and_count_tiles() -> MbtResult<()> {
+        let (mut conn, mbt) = open("../tests/fixtures/mbtiles/world_cities.mbtiles").await?;
+        assert!(mbt.has_tile(&mut conn, 0, 0, 0).await?);
+        assert!(!mbt.has_tile(&mut conn, 7, 0, 0).await?);
+        assert!(matches!(
+            mbt.has_tile(&mut conn, 1, 2, 0).await,
+            Err(MbtError::InvalidTileCoordinate(..))
+        ));
+        assert_eq!(
+            mbt.count_tiles(&mut conn, &TileFilter::default()).await?,
+            196
+        );
+        let filter = TileFilter {
+            max_zoom: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(mbt.count_tiles(&mut conn, &filter).await?, 5);
+
+        let (mut conn, mbt) = open(":memory:").await?;
+        crate::init_mbtiles_schema(&mut conn, MbtType::Normalized { hash_view: false }).await?;
+        mbt.insert_tile(&mut conn, 2, 1, 0, b"data").await?;
+        assert!(mbt.has_tile(&mut conn, 2, 1, 0).await?);
+        assert!(!mbt.has_tile(&mut conn, 2, 1, 3).await?);
+        let filter = TileFilter {
+            zoom_levels: vec![2],
+            ..Default::default()
+        };
+        assert_eq!(mbt.count_tiles(&mut conn, &filter).await?, 1);
+        Ok(())
+    }
+
+    #[actix_rt::test]
+    async fn insert_and_delete_tile() -> MbtResult<()> {
+        for mbt_type in [
+            MbtType::Flat,
+            MbtType::FlatWithHash,
+            MbtType::Normalized { hash_view: false },
+        ] {
+            let (mut conn, mbt) = open(":memory:").await?;
+            crate::init_mbtiles_schema(&mut conn, mbt_type).await?;
+            mbt.insert_tile(&mut conn, 1, 0, 0, b"one").await?;
+            mbt.insert_tile(&mut conn, 1, 1, 0, b"one").await?;
+            mbt.insert_tile(&mut conn, 1, 0, 0, b"two").await?;
+            assert_eq!(
+                mbt.get_tile(&mut conn, 1, 0, 0).await?,
+                Some(b"two".to_vec())
+            );
+            assert_eq!(
+                mbt.get_tile(&mut conn, 1, 1, 0).await?,
+                Some(b"one".to_vec())
+            );
+            let tile_row: i64 =
+                query("SELECT tile_row FROM tiles WHERE zoom_level = 1 AND tile_column = 0")
+                    .fetch_one(&mut conn)
+                    .await?
+                    .get(0);
+            assert_eq!(tile_row, 1, "{mbt_type}");
+
+            assert!(mbt.delete_tile(&mut conn, 1, 1, 0).await?);
+            assert!(!mbt.delete_tile(&mut conn, 1, 1, 0).await?);
+            assert_eq!(mbt.get_tile(&mut conn, 1, 1, 0).await?, None);
+            assert!(matches!(
+                mbt.delete_tile(&mut conn, 1, 2, 0).await,
+                Err(MbtError::InvalidTileCoordinate(..))
+            ));
+
+            if let MbtType::Normalized { .. } = mbt_type {
+                let images: i64 = query("SELECT COUNT(*) FROM images")
+                    .fetch_one(&mut conn)
+                    .await?
+                    .get(0);
+                assert_eq!(
+                    images, 1,
+                    "the replaced and deleted tile data must be removed"
+                );
+            }
+        }
+
+        let (mut conn, mbt) = open(":memory:").await?;
+        conn.execute(
+            "CREATE VIEW tiles AS
+             SELECT 0 AS zoom_level, 0 AS tile_column, 0 AS tile_row, x'00' AS tile_data",
+        )
+        .await?;
+        assert!(matches!(
+            mbt.insert_tile(&mut conn, 0, 0, 0, b"data").await,
+            Err(MbtError::ReadOnlyTilesView(_))
+        ));
+        assert!(matches!(
+            mbt.delete_tile(&mut conn, 0, 0, 0).await,
+            Err(MbtError::ReadOnlyTilesView(_))
+        ));
+        Ok(())
+    }
+
+    #[actix_rt::test]
+    async fn open_path_with_special_chars() -> MbtResult<()> {
+        let dir = std::env::temp_dir().join("mbtiles odd #dir %20");
+        copy_fixture_and_open(dir.join("world cities #1 (copy).mbtiles")).await
+    }
+
+    #[cfg(unix)]
+    #[actix_rt::test]
+    async fn open_non_utf8_path() -> MbtResult<()> {
+        use std::ffi::OsString;
+        use std::os::unix::ffi::OsStringExt as _;
+
+        let dir = std::env::temp_dir().join(OsString::from_vec(b"mbtiles-\xff\xfe-dir".to_vec()));
+        let file = dir.join(OsString::from_vec(b"cities-\xe9.mbtiles".to_vec()));
+        let sqlite_filename = to_sqlite_filename(&file)?;
+        assert!(sqlite_filename.starts_with("file:"));
+        assert!(sqlite_filename.ends_with("/mbtiles-%FF%FE-dir/cities-%E9.mbtiles"));
+        copy_fixture_and_open(file).await
+    }
+
+    #[actix_rt::test]
+    async fn open_missing_file() {
+        let path = std::env::temp_dir().join("mbtiles-missing-file.mbtiles");
+        let mbt = Mbtiles::new(&path).unwrap();
+        assert!(matches!(mbt.open().await, Err(MbtError::FileNotFound(p)) if p == path));
+        assert!(matches!(mbt.open_readonly().await, Err(MbtError::FileNotFound(p)) if p == path));
+        assert!(matches!(
+            crate::MbtilesPool::new(&path).await,
+            Err(MbtError::FileNotFound(p)) if p == path
+        ));
+        assert!(!path.exists());
+    }
 }
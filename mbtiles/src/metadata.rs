@@ -3,14 +3,14 @@ use std::str::FromStr;
 
 use futures::TryStreamExt;
 use log::{info, warn};
-use martin_tile_utils::TileInfo;
+use martin_tile_utils::{Format, TileInfo};
 use serde::ser::SerializeStruct;
 use serde::{Serialize, Serializer};
 use serde_json::{Value as JSONValue, Value, json};
 use sqlx::{SqliteExecutor, query};
 use tilejson::{Bounds, Center, TileJSON, tilejson};
 
-use crate::MbtError::InvalidZoomValue;
+use crate::MbtError::{InvalidMetadataValue, InvalidZoomValue};
 use crate::Mbtiles;
 use crate::errors::MbtResult;
 
@@ -26,6 +26,87 @@ pub struct Metadata {
     pub agg_tiles_hash: Option<String>,
 }
 
+/// Validate a metadata value for keys that have special meaning in [`Mbtiles::get_metadata`],
+/// using the same parsing logic. Unknown keys are always accepted.
+pub fn validate_metadata_value(key: &str, value: &str) -> MbtResult<()> {
+    let err = match key {
+        "bounds" => Bounds::from_str(value).err().map(|e| e.to_string()),
+        "center" => Center::from_str(value).err().map(|e| e.to_string()),
+        "minzoom" | "maxzoom" => value.parse::<u8>().err().map(|e| e.to_string()),
+        "format" => Format::parse(value)
+            .is_none()
+            .then(|| "unrecognized tile format".to_string()),
+        "json" => serde_json::from_str::<JSONValue>(value)
+            .err()
+            .map(|e| e.to_string()),
+        _ => None,
+    };
+    match err {
+        Some(reason) => Err(InvalidMetadataValue(
+            key.to_string(),
+            value.to_string(),
+            reason,
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Fix common mistakes in `bounds`: semicolon separators, and min and max latitudes in the wrong order.
+/// A `left` greater than `right` is kept, as it describes bounds crossing the antimeridian.
+/// Returns `None` if the value is already valid or cannot be fixed.
+fn fix_bounds(value: &str) -> Option<Bounds> {
+    let mut bounds = Bounds::from_str(&value.replace(';', ",")).ok()?;
+    let mut fixed = value.contains(';');
+    if bounds.bottom > bounds.top {
+        std::mem::swap(&mut bounds.bottom, &mut bounds.top);
+        fixed = true;
+    }
+    fixed.then_some(bounds)
+}
+
+/// Fix common mistakes in `center`: semicolon separators, a zoom written as a float,
+/// and a missing zoom, which is replaced with `default_zoom`.
+/// Returns `None` if the value is already valid or cannot be fixed.
+fn fix_center(value: &str, default_zoom: u8) -> Option<Center> {
+    let mut parts: Vec<String> = value
+        .split([',', ';'])
+        .map(|v| v.trim().to_string())
+        .collect();
+    let mut fixed = value.contains(';');
+    match parts.len() {
+        2 => {
+            parts.push(default_zoom.to_string());
+            fixed = true;
+        }
+        3 => {
+            if let Some(zoom) = fix_zoom(&parts[2]) {
+                parts[2] = zoom.to_string();
+                fixed = true;
+            }
+        }
+        _ => return None,
+    }
+    if fixed {
+        Center::from_str(&parts.join(",")).ok()
+    } else {
+        None
+    }
+}
+
+/// Fix a zoom level written as a float with no fractional part, e.g. `5.0`.
+/// Returns `None` if the value is already valid or cannot be fixed.
+fn fix_zoom(value: &str) -> Option<u8> {
+    if value.parse::<u8>().is_ok() {
+        return None;
+    }
+    let (int, frac) = value.trim().split_once('.')?;
+    if frac.chars().all(|c| c == '0') {
+        int.parse().ok()
+    } else {
+        None
+    }
+}
+
 #[allow(clippy::trivially_copy_pass_by_ref)]
 fn serialize_ti<S: Serializer>(ti: &TileInfo, serializer: S) -> Result<S::Ok, S::Error> {
     let mut s = serializer.serialize_struct("TileInfo", 2)?;
@@ -49,6 +130,25 @@ impl Mbtiles {
         }
     }
 
+    /// Parse a metadata value, unless `strict` is set, first trying to `fix` common mistakes in it
+    fn parse_val<V, F>(&self, name: &str, value: &str, strict: bool, fix: F) -> Option<V>
+    where
+        V: FromStr + Display,
+        V::Err: Display,
+        F: FnOnce(&str) -> Option<V>,
+    {
+        if !strict {
+            if let Some(fixed) = fix(value) {
+                let file = &self.filename();
+                warn!(
+                    "Corrected invalid metadata value {name}='{value}' in {file} to '{fixed}'. Use strict metadata mode to ignore such values instead."
+                );
+                return Some(fixed);
+            }
+        }
+        self.to_val(V::from_str(value), name)
+    }
+
     /// Get a single metadata value from the metadata table
     pub async fn get_metadata_value<T>(&self, conn: &mut T, key: &str) -> MbtResult<Option<String>>
     where
@@ -104,7 +204,18 @@ impl Mbtiles {
         Ok(())
     }
 
+    /// Get all metadata values, correcting common mistakes like swapped bounds
+    /// or zoom levels written as floats, and logging a warning for each correction.
     pub async fn get_metadata<T>(&self, conn: &mut T) -> MbtResult<Metadata>
+    where
+        for<'e> &'e mut T: SqliteExecutor<'e>,
+    {
+        self.get_metadata_ext(conn, false).await
+    }
+
+    /// Same as [`Mbtiles::get_metadata`], but if `strict` is set,
+    /// invalid metadata values are ignored instead of corrected.
+    pub async fn get_metadata_ext<T>(&self, conn: &mut T, strict: bool) -> MbtResult<Metadata>
     where
         for<'e> &'e mut T: SqliteExecutor<'e>,
     {
@@ -115,6 +226,7 @@ impl Mbtiles {
         let mut layer_type: Option<String> = None;
         let mut json: Option<JSONValue> = None;
         let mut agg_tiles_hash: Option<String> = None;
+        let mut center: Option<String> = None;
 
         while let Some(row) = rows.try_next().await? {
             if let (Some(name), Some(value)) = (row.name, row.value) {
@@ -122,10 +234,11 @@ impl Mbtiles {
                     // This list should loosely match the `insert_metadata` function below
                     "name" => tj.name = Some(value),
                     "version" => tj.version = Some(value),
-                    "bounds" => tj.bounds = self.to_val(Bounds::from_str(value.as_str()), &name),
-                    "center" => tj.center = self.to_val(Center::from_str(value.as_str()), &name),
-                    "minzoom" => tj.minzoom = self.to_val(value.parse(), &name),
-                    "maxzoom" => tj.maxzoom = self.to_val(value.parse(), &name),
+                    "bounds" => tj.bounds = self.parse_val(&name, &value, strict, fix_bounds),
+                    // Parsed after the loop, because a missing center zoom defaults to minzoom
+                    "center" => center = Some(value),
+                    "minzoom" => tj.minzoom = self.parse_val(&name, &value, strict, fix_zoom),
+                    "maxzoom" => tj.maxzoom = self.parse_val(&name, &value, strict, fix_zoom),
                     "description" => tj.description = Some(value),
                     "attribution" => tj.attribution = Some(value),
                     "type" => layer_type = Some(value),
@@ -153,6 +266,11 @@ impl Mbtiles {
             }
         }
 
+        if let Some(value) = center {
+            let default_zoom = tj.minzoom.unwrap_or_default();
+            tj.center = self.parse_val("center", &value, strict, |v| fix_center(v, default_zoom));
+        }
+
         if let Some(JSONValue::Object(obj)) = &mut json {
             if let Some(value) = obj.remove("vector_layers") {
                 if let Ok(v) = serde_json::from_value(value) {
@@ -183,6 +301,73 @@ impl Mbtiles {
     }
 
     pub async fn insert_metadata<T>(&self, conn: &mut T, tile_json: &TileJSON) -> MbtResult<()>
+    where
+        for<'e> &'e mut T: SqliteExecutor<'e>,
+    {
+        self.insert_tilejson_values(&mut *conn, tile_json).await?;
+        if let Some(vector_layers) = &tile_json.vector_layers {
+            self.set_metadata_value(
+                conn,
+                "json",
+                &serde_json::to_string(&json!({ "vector_layers": vector_layers }))?,
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Write [`Metadata`] into the metadata table. This is the inverse of [`Mbtiles::get_metadata`]:
+    /// all values are stored using the same keys and string formats that `get_metadata` parses,
+    /// `vector_layers` are merged into the `json` value, and unrecognized keys are preserved.
+    /// The `agg_tiles_hash` value is not written because it must be computed from the tiles,
+    /// see [`Mbtiles::update_agg_tiles_hash`].
+    pub async fn apply_metadata<T>(&self, conn: &mut T, metadata: &Metadata) -> MbtResult<()>
+    where
+        for<'e> &'e mut T: SqliteExecutor<'e>,
+    {
+        let tj = &metadata.tilejson;
+        self.insert_tilejson_values(&mut *conn, tj).await?;
+
+        if !tj.other.contains_key("format") {
+            let format = metadata.tile_info.format.metadata_format_value();
+            self.set_metadata_value(&mut *conn, "format", format)
+                .await?;
+        }
+        if let Some(layer_type) = &metadata.layer_type {
+            self.set_metadata_value(&mut *conn, "type", layer_type)
+                .await?;
+        }
+
+        let mut json = match &metadata.json {
+            Some(JSONValue::Object(obj)) => obj.clone(),
+            Some(other) => {
+                warn!(
+                    "Metadata json value is not an object, storing it as is in {}",
+                    self.filename()
+                );
+                return self
+                    .set_metadata_value(conn, "json", &serde_json::to_string(other)?)
+                    .await;
+            }
+            None => serde_json::Map::new(),
+        };
+        if let Some(vector_layers) = &tj.vector_layers {
+            json.insert(
+                "vector_layers".to_string(),
+                serde_json::to_value(vector_layers)?,
+            );
+        }
+        if !json.is_empty() {
+            self.set_metadata_value(conn, "json", &serde_json::to_string(&json)?)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Store all [`TileJSON`] values except `vector_layers`, which must be stored inside the `json` value
+    async fn insert_tilejson_values<T>(&self, conn: &mut T, tile_json: &TileJSON) -> MbtResult<()>
     where
         for<'e> &'e mut T: SqliteExecutor<'e>,
     {
@@ -218,14 +403,6 @@ impl Mbtiles {
         if let Some(maxzoom) = &tile_json.maxzoom {
             self.set_metadata_value(conn, "maxzoom", maxzoom).await?;
         }
-        if let Some(vector_layers) = &tile_json.vector_layers {
-            self.set_metadata_value(
-                conn,
-                "json",
-                &serde_json::to_string(&json!({ "vector_layers": vector_layers }))?,
-            )
-            .await?;
-        }
 
         Ok(())
     }
@@ -234,19 +411,21 @@ impl Mbtiles {
 #[cfg(test)]
 mod tests {
     use std::collections::BTreeMap;
+    use std::path::Path;
 
-    use martin_tile_utils::{Encoding, Format};
+    use martin_tile_utils::Encoding;
     use sqlx::Executor as _;
     use tilejson::VectorLayer;
 
     use super::*;
     use crate::mbtiles::tests::open;
+    use crate::{CopyType, MbtilesCopier};
 
     #[actix_rt::test]
     async fn mbtiles_meta() -> MbtResult<()> {
         let filepath = "../tests/fixtures/mbtiles/geography-class-jpg.mbtiles";
         let mbt = Mbtiles::new(filepath)?;
-        assert_eq!(mbt.filepath(), filepath);
+        assert_eq!(mbt.filepath(), Path::new(filepath));
         assert_eq!(mbt.filename(), "geography-class-jpg");
         Ok(())
     }
@@ -324,6 +503,31 @@ mod tests {
         Ok(())
     }
 
+    #[actix_rt::test]
+    async fn metadata_round_trip() -> MbtResult<()> {
+        let src = "../tests/fixtures/mbtiles/world_cities.mbtiles";
+        let (mut conn, mbt) = open(src).await?;
+        let metadata = mbt.get_metadata(&mut conn).await?;
+
+        let dst = "file:metadata_round_trip_mem_db?mode=memory&cache=shared";
+        let mut dst_conn = MbtilesCopier {
+            src_file: src.into(),
+            dst_file: dst.into(),
+            copy: CopyType::Tiles,
+            ..Default::default()
+        }
+        .run()
+        .await?;
+        let dst_mbt = Mbtiles::new(dst)?;
+        dst_mbt.apply_metadata(&mut dst_conn, &metadata).await?;
+
+        let mut actual = dst_mbt.get_metadata(&mut dst_conn).await?;
+        actual.id.clone_from(&metadata.id);
+        assert_eq!(actual, metadata);
+
+        Ok(())
+    }
+
     #[actix_rt::test]
     async fn metadata_set_key() -> MbtResult<()> {
         let (mut conn, mbt) = open("file:metadata_set_key_mem_db?mode=memory&cache=shared").await?;
@@ -354,4 +558,137 @@ mod tests {
 
         Ok(())
     }
+
+    #[actix_rt::test]
+    async fn metadata_set_large_json() -> MbtResult<()> {
+        let (mut conn, mbt) =
+            open("file:metadata_set_large_json_mem_db?mode=memory&cache=shared").await?;
+        crate::init_mbtiles_schema(&mut conn, crate::MbtType::Flat).await?;
+
+        let layers: Vec<_> = (0..200)
+            .map(|i| json!({"id": format!("layer{i}"), "fields": {"name": "String", "rank": "Number"}}))
+            .collect();
+        let value = json!({"vector_layers": layers, "tilestats": {"layerCount": 200}});
+        let value = serde_json::to_string(&value)?;
+        assert!(value.len() > 10_000);
+        validate_metadata_value("json", &value)?;
+        mbt.set_metadata_value(&mut conn, "json", &value).await?;
+
+        let metadata = mbt.get_metadata(&mut conn).await?;
+        let vector_layers = metadata.tilejson.vector_layers.unwrap();
+        assert_eq!(vector_layers.len(), 200);
+        assert_eq!(vector_layers[199].id, "layer199");
+        assert_eq!(
+            metadata.json,
+            Some(json!({"tilestats": {"layerCount": 200}}))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn validate_metadata_values() {
+        let valid = [
+            ("bounds", "-123.123590,-37.818085,174.763027,59.352706"),
+            ("center", "-75.9375,38.788894,6"),
+            ("minzoom", "0"),
+            ("maxzoom", "14"),
+            ("format", "pbf"),
+            ("json", r#"{"vector_layers":[]}"#),
+            ("name", "anything goes"),
+            ("unknown", "not,a,bound"),
+        ];
+        for (key, value) in valid {
+            assert!(
+                validate_metadata_value(key, value).is_ok(),
+                "{key}={value} should be accepted"
+            );
+        }
+
+        let invalid = [
+            ("bounds", "1,2,3"),
+            ("center", "east"),
+            ("minzoom", "-1"),
+            ("maxzoom", "high"),
+            ("format", "tiff"),
+            ("json", "{not json"),
+        ];
+        for (key, value) in invalid {
+            assert!(
+                matches!(
+                    validate_metadata_value(key, value),
+                    Err(InvalidMetadataValue(k, v, _)) if k == key && v == value
+                ),
+                "{key}={value} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn fix_metadata_values() {
+        let expected = Some(Bounds::new(-10.0, -20.0, 30.0, 40.0));
+        assert_eq!(fix_bounds("-10,-20,30,40"), None);
+        assert_eq!(fix_bounds("-10,40,30,-20"), expected);
+        assert_eq!(fix_bounds("-10;40;30;-20"), expected);
+        assert_eq!(fix_bounds("1,2,3"), None);
+        // Bounds crossing the antimeridian are valid
+        assert_eq!(fix_bounds("170,-10,-170,10"), None);
+        assert_eq!(
+            fix_bounds("170,10,-170,-10"),
+            Some(Bounds::new(170.0, -10.0, -170.0, 10.0))
+        );
+
+        let expected = Some(Center {
+            longitude: -75.9375,
+            latitude: 38.788894,
+            zoom: 6,
+        });
+        assert_eq!(fix_center("-75.9375,38.788894,6", 3), None);
+        assert_eq!(fix_center("-75.9375,38.788894", 6), expected);
+        assert_eq!(fix_center("-75.9375;38.788894;6", 3), expected);
+        assert_eq!(fix_center("-75.9375,38.788894,6.0", 3), expected);
+        assert_eq!(fix_center("east", 3), None);
+
+        assert_eq!(fix_zoom("6"), None);
+        assert_eq!(fix_zoom("6.0"), Some(6));
+        assert_eq!(fix_zoom("14.00"), Some(14));
+        assert_eq!(fix_zoom("6.5"), None);
+        assert_eq!(fix_zoom("high"), None);
+    }
+
+    #[actix_rt::test]
+    async fn metadata_corrections() -> MbtResult<()> {
+        let (mut conn, mbt) =
+            open("file:metadata_corrections_mem_db?mode=memory&cache=shared").await?;
+        crate::init_mbtiles_schema(&mut conn, crate::MbtType::Flat).await?;
+        for (key, value) in [
+            ("bounds", "30;40;-10;-20"),
+            ("center", "10,20"),
+            ("minzoom", "2.0"),
+            ("maxzoom", "6"),
+            ("format", "png"),
+        ] {
+            mbt.set_metadata_value(&mut conn, key, value).await?;
+        }
+
+        let tj = mbt.get_metadata(&mut conn).await?.tilejson;
+        assert_eq!(tj.bounds, Some(Bounds::new(-10.0, -20.0, 30.0, 40.0)));
+        assert_eq!(
+            tj.center,
+            Some(Center {
+                longitude: 10.0,
+                latitude: 20.0,
+                zoom: 2,
+            })
+        );
+        assert_eq!(tj.minzoom, Some(2));
+        assert_eq!(tj.maxzoom, Some(6));
+
+        let tj = mbt.get_metadata_ext(&mut conn, true).await?.tilejson;
+        assert_eq!(tj.bounds, None);
+        assert_eq!(tj.center, None);
+        assert_eq!(tj.minzoom, None);
+        assert_eq!(tj.maxzoom, Some(6));
+
+        Ok(())
+    }
 }
@@ -31,9 +31,9 @@ pub async fn apply_patch(base_file: PathBuf, patch_file: PathBuf, force: bool) -
     match (force, base_hash, patch_info.agg_tiles_hash_before_apply) {
         (false, Some(base_hash), Some(expected_hash)) if base_hash != expected_hash => {
             return Err(MbtError::AggHashMismatchWithDiff(
-                patch_mbt.filepath().to_string(),
+                patch_mbt.to_string(),
                 expected_hash,
-                base_mbt.filepath().to_string(),
+                base_mbt.to_string(),
                 base_hash,
             ));
         }
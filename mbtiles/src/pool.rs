@@ -1,9 +1,10 @@
 use std::path::Path;
 
-use sqlx::{Pool, Sqlite, SqlitePool};
+use sqlx::sqlite::SqliteConnectOptions;
+use sqlx::{Pool, Sqlite, SqlitePool, query};
 
 use crate::errors::MbtResult;
-use crate::{Mbtiles, Metadata};
+use crate::{Mbtiles, Metadata, TileFilter, compute_tile_count_per_zoom};
 
 #[derive(Clone, Debug)]
 pub struct MbtilesPool {
@@ -14,17 +15,54 @@ pub struct MbtilesPool {
 impl MbtilesPool {
     pub async fn new<P: AsRef<Path>>(filepath: P) -> MbtResult<Self> {
         let mbtiles = Mbtiles::new(filepath)?;
-        let pool = SqlitePool::connect(mbtiles.filepath()).await?;
+        let opt = SqliteConnectOptions::new()
+            .filename(mbtiles.sqlite_filename())
+            .create_if_missing(false);
+        let pool = SqlitePool::connect_with(opt)
+            .await
+            .map_err(|e| mbtiles.map_open_error(e))?;
         Ok(Self { mbtiles, pool })
     }
 
+    /// Acquire a connection and read the schema of the file, to check that it is still accessible
+    pub async fn check_connection(&self) -> MbtResult<()> {
+        let mut conn = self.pool.acquire().await?;
+        query("SELECT COUNT(*) FROM sqlite_master")
+            .execute(&mut *conn)
+            .await?;
+        Ok(())
+    }
+
     pub async fn get_metadata(&self) -> MbtResult<Metadata> {
         let mut conn = self.pool.acquire().await?;
         self.mbtiles.get_metadata(&mut *conn).await
     }
 
+    /// Same as [`MbtilesPool::get_metadata`], but if `strict` is set,
+    /// invalid metadata values are ignored instead of corrected.
+    pub async fn get_metadata_ext(&self, strict: bool) -> MbtResult<Metadata> {
+        let mut conn = self.pool.acquire().await?;
+        self.mbtiles.get_metadata_ext(&mut *conn, strict).await
+    }
+
+    /// Get the number of tiles for each zoom level, ordered by zoom
+    pub async fn get_tile_count_per_zoom(&self) -> MbtResult<Vec<(u8, u64)>> {
+        let mut conn = self.pool.acquire().await?;
+        compute_tile_count_per_zoom(&mut *conn).await
+    }
+
     pub async fn get_tile(&self, z: u8, x: u32, y: u32) -> MbtResult<Option<Vec<u8>>> {
         let mut conn = self.pool.acquire().await?;
         self.mbtiles.get_tile(&mut *conn, z, x, y).await
     }
+
+    pub async fn has_tile(&self, z: u8, x: u32, y: u32) -> MbtResult<bool> {
+        let mut conn = self.pool.acquire().await?;
+        self.mbtiles.has_tile(&mut *conn, z, x, y).await
+    }
+
+    pub async fn count_tiles(&self, filter: &TileFilter) -> MbtResult<u64> {
+        let mut conn = self.pool.acquire().await?;
+        self.mbtiles.count_tiles(&mut *conn, filter).await
+    }
 }
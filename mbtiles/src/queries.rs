@@ -3,7 +3,7 @@ use martin_tile_utils::MAX_ZOOM;
 use sqlite_compressions::rusqlite::Connection;
 use sqlx::{Executor as _, Row, SqliteConnection, SqliteExecutor, query};
 
-use crate::MbtError::InvalidZoomValue;
+use crate::MbtError::{InvalidPageSize, InvalidZoomValue};
 use crate::MbtType;
 use crate::bindiff::PatchType;
 use crate::errors::MbtResult;
@@ -323,12 +323,27 @@ where
     Ok(())
 }
 
-pub async fn reset_db_settings<T>(conn: &mut T) -> MbtResult<()>
+/// `SQLite` page size used by [`init_mbtiles_schema`] when creating new files
+pub const DEFAULT_PAGE_SIZE: u32 = 512;
+
+/// Returns an error unless the page size is a power of two between 512 and 65536, as required by `SQLite`
+pub fn validate_page_size(page_size: u32) -> MbtResult<u32> {
+    if page_size.is_power_of_two() && (512..=65536).contains(&page_size) {
+        Ok(page_size)
+    } else {
+        Err(InvalidPageSize(page_size))
+    }
+}
+
+pub async fn reset_db_settings<T>(conn: &mut T, page_size: u32) -> MbtResult<()>
 where
     for<'e> &'e mut T: SqliteExecutor<'e>,
 {
-    debug!("Resetting PRAGMA settings and vacuuming");
-    query!("PRAGMA page_size = 512").execute(&mut *conn).await?;
+    debug!("Resetting PRAGMA settings with page_size={page_size} and vacuuming");
+    let page_size = validate_page_size(page_size)?;
+    query(&format!("PRAGMA page_size = {page_size}"))
+        .execute(&mut *conn)
+        .await?;
     query!("PRAGMA encoding = 'UTF-8'")
         .execute(&mut *conn)
         .await?;
@@ -340,7 +355,18 @@ pub async fn init_mbtiles_schema<T>(conn: &mut T, mbt_type: MbtType) -> MbtResul
 where
     for<'e> &'e mut T: SqliteExecutor<'e>,
 {
-    reset_db_settings(conn).await?;
+    init_mbtiles_schema_with_page_size(conn, mbt_type, DEFAULT_PAGE_SIZE).await
+}
+
+pub async fn init_mbtiles_schema_with_page_size<T>(
+    conn: &mut T,
+    mbt_type: MbtType,
+    page_size: u32,
+) -> MbtResult<()>
+where
+    for<'e> &'e mut T: SqliteExecutor<'e>,
+{
+    reset_db_settings(conn, page_size).await?;
     create_metadata_table(&mut *conn).await?;
     match mbt_type {
         MbtType::Flat => create_flat_tables(&mut *conn).await,
@@ -403,6 +429,33 @@ FROM tiles;"
     }
 }
 
+/// Compute the number of tiles for each zoom level in the `tiles` table, ordered by zoom
+pub async fn compute_tile_count_per_zoom<T>(conn: &mut T) -> MbtResult<Vec<(u8, u64)>>
+where
+    for<'e> &'e mut T: SqliteExecutor<'e>,
+{
+    let rows = query(
+        "
+SELECT zoom_level, count() AS count
+FROM tiles
+GROUP BY zoom_level
+ORDER BY zoom_level;",
+    )
+    .fetch_all(conn)
+    .await?;
+
+    let mut res = Vec::with_capacity(rows.len());
+    for row in rows {
+        if let Some(zoom) = validate_zoom(row.get(0), "zoom_level")? {
+            res.push((
+                zoom,
+                u64::try_from(row.get::<i64, _>(1)).unwrap_or_default(),
+            ));
+        }
+    }
+    Ok(res)
+}
+
 pub async fn action_with_rusqlite(
     conn: &mut SqliteConnection,
     action: impl FnOnce(&Connection) -> MbtResult<()>,
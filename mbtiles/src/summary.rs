@@ -5,13 +5,12 @@
 )]
 
 use std::fmt::{Display, Formatter};
-use std::path::PathBuf;
-use std::str::FromStr;
 
 use martin_tile_utils::{get_zoom_precision, xyz_to_bbox};
 use serde::Serialize;
 use size_format::SizeFormatterBinary;
-use sqlx::{SqliteExecutor, query};
+use sqlx::sqlite::SqliteRow;
+use sqlx::{Row, SqliteExecutor, query};
 use tilejson::Bounds;
 
 use crate::{MbtResult, MbtType, Mbtiles, invert_y_value};
@@ -107,6 +106,93 @@ impl Display for Summary {
     }
 }
 
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct DedupStats {
+    pub tile_count: u64,
+    pub unique_count: u64,
+    pub total_size: u64,
+    pub unique_size: u64,
+}
+
+impl DedupStats {
+    fn from_row(r: &SqliteRow, offset: usize) -> Self {
+        Self {
+            tile_count: r.get::<i64, _>(offset) as u64,
+            unique_count: r.get::<i64, _>(offset + 1) as u64,
+            total_size: r.get::<Option<i64>, _>(offset + 2).unwrap_or(0) as u64,
+            unique_size: r.get::<Option<i64>, _>(offset + 3).unwrap_or(0) as u64,
+        }
+    }
+
+    #[must_use]
+    pub fn saved_size(&self) -> u64 {
+        self.total_size - self.unique_size
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct DedupZoomInfo {
+    pub zoom: u8,
+    #[serde(flatten)]
+    pub stats: DedupStats,
+}
+
+/// Estimate of how much space tile deduplication would save (for flat files),
+/// or how much it already saves (for normalized files).
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct DedupEstimate {
+    pub mbt_type: MbtType,
+    pub zoom_info: Vec<DedupZoomInfo>,
+    /// Totals for the whole file. Identical tiles at different zooms are only counted once.
+    pub overall: Option<DedupStats>,
+}
+
+impl Display for DedupEstimate {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Schema: {}", self.mbt_type)?;
+        if self.mbt_type.is_normalized() {
+            writeln!(
+                f,
+                "Tiles are already deduplicated, showing realized savings"
+            )?;
+        } else {
+            writeln!(f, "Showing savings if converted to the normalized schema")?;
+        }
+        writeln!(f)?;
+        writeln!(
+            f,
+            " {:^4} | {:^9} | {:^9} | {:^9} | {:^9} | {:^9} | Ratio",
+            "Zoom", "Count", "Unique", "Size", "Unique", "Saved"
+        )?;
+
+        let mut write_row = |zoom: &dyn Display, l: &DedupStats| {
+            let ratio = if l.total_size == 0 {
+                0.0
+            } else {
+                l.saved_size() as f64 / l.total_size as f64 * 100.0
+            };
+            writeln!(
+                f,
+                " {zoom:>4} | {:>9} | {:>9} | {:>9} | {:>9} | {:>9} | {ratio:.1}%",
+                l.tile_count,
+                l.unique_count,
+                format!("{:.1}B", SizeFormatterBinary::new(l.total_size)),
+                format!("{:.1}B", SizeFormatterBinary::new(l.unique_size)),
+                format!("{:.1}B", SizeFormatterBinary::new(l.saved_size())),
+            )
+        };
+
+        for l in &self.zoom_info {
+            write_row(&l.zoom, &l.stats)?;
+        }
+        if let Some(overall) = &self.overall {
+            write_row(&"all", overall)?;
+        }
+
+        Ok(())
+    }
+}
+
 impl Mbtiles {
     /// Compute `MBTiles` file summary
     pub async fn summary<T>(&self, conn: &mut T) -> MbtResult<Summary>
@@ -114,10 +200,7 @@ impl Mbtiles {
         for<'e> &'e mut T: SqliteExecutor<'e>,
     {
         let mbt_type = self.detect_type(&mut *conn).await?;
-        let file_size = PathBuf::from_str(self.filepath())
-            .ok()
-            .and_then(|p| p.metadata().ok())
-            .map(|m| m.len());
+        let file_size = self.filepath().metadata().ok().map(|m| m.len());
 
         let sql = query!("PRAGMA page_size;");
         let page_size = sql.fetch_one(&mut *conn).await?.page_size.unwrap() as u64;
@@ -185,6 +268,76 @@ impl Mbtiles {
             zoom_info,
         })
     }
+
+    /// Count unique tiles and their sizes per zoom level and for the whole file.
+    ///
+    /// Flat files are compared using the MD5 hash of each tile, so only the hashes and
+    /// tile sizes are kept by `SQLite` while grouping, never the tile data itself.
+    /// Files with a hash column or normalized files use the existing hash or tile ID.
+    pub async fn dedup_estimate<T>(&self, conn: &mut T) -> MbtResult<DedupEstimate>
+    where
+        for<'e> &'e mut T: SqliteExecutor<'e>,
+    {
+        let mbt_type = self.detect_type(&mut *conn).await?;
+        let tiles = match mbt_type {
+            MbtType::Flat => {
+                "SELECT zoom_level, md5_hex(tile_data) AS hash, length(tile_data) AS size FROM tiles"
+            }
+            MbtType::FlatWithHash => {
+                "SELECT zoom_level, tile_hash AS hash, length(tile_data) AS size FROM tiles_with_hash"
+            }
+            MbtType::Normalized { .. } => {
+                "SELECT zoom_level, map.tile_id AS hash, length(images.tile_data) AS size
+                 FROM map JOIN images ON map.tile_id = images.tile_id"
+            }
+        };
+
+        let sql = format!(
+            "
+    SELECT zoom_level,
+           sum(cnt)        AS tile_count,
+           count()         AS unique_count,
+           sum(cnt * size) AS total_size,
+           sum(size)       AS unique_size
+    FROM (SELECT zoom_level, hash, count() AS cnt, max(size) AS size
+          FROM ({tiles})
+          GROUP BY zoom_level, hash)
+    GROUP BY zoom_level
+    ORDER BY zoom_level"
+        );
+        let zoom_info = query(&sql)
+            .fetch_all(&mut *conn)
+            .await?
+            .into_iter()
+            .map(|r| DedupZoomInfo {
+                zoom: u8::try_from(r.get::<i64, _>(0)).expect("zoom_level is not a u8"),
+                stats: DedupStats::from_row(&r, 1),
+            })
+            .collect::<Vec<_>>();
+
+        let overall = if zoom_info.is_empty() {
+            None
+        } else {
+            let sql = format!(
+                "
+    SELECT sum(cnt)        AS tile_count,
+           count()         AS unique_count,
+           sum(cnt * size) AS total_size,
+           sum(size)       AS unique_size
+    FROM (SELECT hash, count() AS cnt, max(size) AS size
+          FROM ({tiles})
+          GROUP BY hash)"
+            );
+            let r = query(&sql).fetch_one(&mut *conn).await?;
+            Some(DedupStats::from_row(&r, 0))
+        };
+
+        Ok(DedupEstimate {
+            mbt_type,
+            zoom_info,
+            overall,
+        })
+    }
 }
 
 #[cfg(test)]
@@ -193,7 +346,8 @@ mod tests {
 
     use insta::assert_yaml_snapshot;
 
-    use crate::{MbtResult, MbtType, Mbtiles, init_mbtiles_schema};
+    use super::{DedupStats, DedupZoomInfo};
+    use crate::{CopyDuplicateMode, MbtResult, MbtType, Mbtiles, init_mbtiles_schema};
 
     #[actix_rt::test]
     async fn summary_empty_file() -> MbtResult<()> {
@@ -318,4 +472,53 @@ mod tests {
 
         Ok(())
     }
+
+    #[actix_rt::test]
+    async fn dedup_estimate() -> MbtResult<()> {
+        let tiles = [
+            (0, 0, 0, vec![1, 2, 3, 4]),
+            (1, 0, 0, vec![1, 2, 3, 4]),
+            (1, 0, 1, vec![1, 2, 3, 4]),
+            (1, 1, 0, vec![5, 6]),
+        ];
+        let stats = |tile_count, unique_count, total_size, unique_size| DedupStats {
+            tile_count,
+            unique_count,
+            total_size,
+            unique_size,
+        };
+
+        for mbt_type in [
+            MbtType::Flat,
+            MbtType::FlatWithHash,
+            MbtType::Normalized { hash_view: true },
+        ] {
+            let mbt = Mbtiles::new(format!(
+                "file:mbtiles_dedup_estimate_{mbt_type}?mode=memory&cache=shared"
+            ))?;
+            let mut conn = mbt.open().await?;
+            init_mbtiles_schema(&mut conn, mbt_type).await?;
+            mbt.insert_tiles(&mut conn, mbt_type, CopyDuplicateMode::Override, &tiles)
+                .await?;
+
+            let res = mbt.dedup_estimate(&mut conn).await?;
+            assert_eq!(res.mbt_type, mbt_type);
+            assert_eq!(
+                res.zoom_info,
+                vec![
+                    DedupZoomInfo {
+                        zoom: 0,
+                        stats: stats(1, 1, 4, 4),
+                    },
+                    DedupZoomInfo {
+                        zoom: 1,
+                        stats: stats(3, 2, 10, 6),
+                    },
+                ]
+            );
+            assert_eq!(res.overall, Some(stats(4, 2, 14, 6)));
+        }
+
+        Ok(())
+    }
 }
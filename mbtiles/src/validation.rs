@@ -2,8 +2,10 @@ use std::collections::HashSet;
 use std::str::from_utf8;
 
 use enum_display::EnumDisplay;
+use flate2::{Decompress, FlushDecompress, Status};
+use futures::TryStreamExt as _;
 use log::{debug, info, warn};
-use martin_tile_utils::{Format, MAX_ZOOM, TileInfo};
+use martin_tile_utils::{Encoding, Format, MAX_ZOOM, TileInfo, decode_gzip, decode_mvt};
 use serde::Serialize;
 use serde_json::Value;
 use sqlx::sqlite::SqliteRow;
@@ -12,7 +14,7 @@ use tilejson::TileJSON;
 
 use crate::MbtError::{
     AggHashMismatch, AggHashValueNotFound, FailedIntegrityCheck, IncorrectTileHash,
-    InvalidTileIndex,
+    InvalidTileContent, InvalidTileIndex,
 };
 use crate::errors::{MbtError, MbtResult};
 use crate::mbtiles::PatchFileInfo;
@@ -239,7 +241,7 @@ impl Mbtiles {
         } else if is_flat_tables_type(&mut *conn).await? {
             MbtType::Flat
         } else {
-            return Err(MbtError::InvalidDataFormat(self.filepath().to_string()));
+            return Err(MbtError::InvalidDataFormat(self.to_string()));
         };
 
         self.check_for_uniqueness_constraint(&mut *conn, typ)
@@ -292,9 +294,7 @@ impl Mbtiles {
             }
         }
 
-        Err(MbtError::NoUniquenessConstraint(
-            self.filepath().to_string(),
-        ))
+        Err(MbtError::NoUniquenessConstraint(self.to_string()))
     }
 
     /// Perform `SQLite` internal integrity check
@@ -324,11 +324,11 @@ impl Mbtiles {
 
         if result.len() > 1
             || result.first().ok_or(FailedIntegrityCheck(
-                self.filepath().to_string(),
+                self.to_string(),
                 vec!["SQLite could not perform integrity check".to_string()],
             ))? != "ok"
         {
-            return Err(FailedIntegrityCheck(self.filepath().to_string(), result));
+            return Err(FailedIntegrityCheck(self.to_string(), result));
         }
 
         info!("{integrity_check:?} integrity check passed for {self}");
@@ -382,7 +382,7 @@ LIMIT 1;"
             }
 
             return Err(InvalidTileIndex(
-                self.filepath().to_string(),
+                self.to_string(),
                 res.pop().unwrap(),
                 res.pop().unwrap(),
                 res.pop().unwrap(),
@@ -393,16 +393,50 @@ LIMIT 1;"
         Ok(())
     }
 
+    /// Check that every tile blob is intact: compressed MVT tiles decompress fully and decode,
+    /// image headers and chunk lengths are consistent with the blob size, and no tile is empty.
+    /// Tiles are streamed one by one, and at most `max_reported` invalid tiles are logged.
+    pub async fn check_tiles_content<T>(&self, conn: &mut T, max_reported: u64) -> MbtResult<()>
+    where
+        for<'e> &'e mut T: SqliteExecutor<'e>,
+    {
+        let mut rows = query("SELECT zoom_level, tile_column, tile_row, tile_data FROM tiles")
+            .fetch(&mut *conn);
+        let mut checked = 0_u64;
+        let mut invalid = 0_u64;
+        while let Some(row) = rows.try_next().await? {
+            checked += 1;
+            let data: Option<Vec<u8>> = row.get(3);
+            if let Err(reason) = check_tile_content(data.as_deref().unwrap_or_default()) {
+                if invalid < max_reported {
+                    let (z, x, y): (i64, i64, i64) = (row.get(0), row.get(1), row.get(2));
+                    warn!(
+                        "Invalid tile zoom_level={z}, tile_column={x}, tile_row={y} in {self}: {reason}"
+                    );
+                } else if invalid == max_reported {
+                    warn!("Too many invalid tiles, not reporting any more of them");
+                }
+                invalid += 1;
+            }
+        }
+
+        if invalid > 0 {
+            return Err(InvalidTileContent(self.to_string(), invalid));
+        }
+        info!("All {checked} tiles have valid content in {self}");
+        Ok(())
+    }
+
     pub async fn check_agg_tiles_hashes<T>(&self, conn: &mut T) -> MbtResult<String>
     where
         for<'e> &'e mut T: SqliteExecutor<'e>,
     {
         let Some(stored) = self.get_agg_tiles_hash(&mut *conn).await? else {
-            return Err(AggHashValueNotFound(self.filepath().to_string()));
+            return Err(AggHashValueNotFound(self.to_string()));
         };
         let computed = calc_agg_tiles_hash(&mut *conn).await?;
         if stored != computed {
-            let file = self.filepath().to_string();
+            let file = self.to_string();
             return Err(AggHashMismatch(computed, stored, file));
         }
 
@@ -469,11 +503,7 @@ LIMIT 1;"
             .fetch_optional(&mut *conn)
             .await?
             .map_or(Ok(()), |v| {
-                Err(IncorrectTileHash(
-                    self.filepath().to_string(),
-                    v.get(0),
-                    v.get(1),
-                ))
+                Err(IncorrectTileHash(self.to_string(), v.get(0), v.get(1)))
             })?;
 
         info!("All tile hashes are valid for {self}");
@@ -499,9 +529,7 @@ LIMIT 1;"
     pub fn assert_hashes(&self, info: &PatchFileInfo, force: bool) -> MbtResult<()> {
         if info.agg_tiles_hash.is_none() {
             if !force {
-                return Err(MbtError::CannotDiffFileWithoutHash(
-                    self.filepath().to_string(),
-                ));
+                return Err(MbtError::CannotDiffFileWithoutHash(self.to_string()));
             }
             warn!(
                 "File {self} has no {AGG_TILES_HASH} metadata field, probably because it was created by an older version of the `mbtiles` tool.  Use this command to update the value:\nmbtiles validate --agg-hash update {self}"
@@ -510,7 +538,7 @@ LIMIT 1;"
             || info.agg_tiles_hash_after_apply.is_some()
         {
             if !force {
-                return Err(MbtError::DiffingDiffFile(self.filepath().to_string()));
+                return Err(MbtError::DiffingDiffFile(self.to_string()));
             }
             warn!(
                 "File {self} has {AGG_TILES_HASH_BEFORE_APPLY} or {AGG_TILES_HASH_AFTER_APPLY} metadata field, indicating it is a patch file which should not be diffed with another file."
@@ -531,9 +559,7 @@ LIMIT 1;"
             }
             (None, Some(_)) => {
                 if !force {
-                    return Err(MbtError::PatchFileHasNoBeforeHash(
-                        self.filepath().to_string(),
-                    ));
+                    return Err(MbtError::PatchFileHasNoBeforeHash(self.to_string()));
                 }
                 warn!(
                     "The patch file {self} has no {AGG_TILES_HASH_BEFORE_APPLY} metadata field, probably because it was created by an older version of the `mbtiles` tool."
@@ -541,7 +567,7 @@ LIMIT 1;"
             }
             _ => {
                 if !force {
-                    return Err(MbtError::PatchFileHasNoHashes(self.filepath().to_string()));
+                    return Err(MbtError::PatchFileHasNoHashes(self.to_string()));
                 }
                 warn!(
                     "The patch file {self} has no {AGG_TILES_HASH_AFTER_APPLY} metadata field, probably because it was not properly created by the `mbtiles` tool."
@@ -583,6 +609,97 @@ FROM tiles;
     Ok(query.fetch_one(conn).await?.get::<String, _>(0))
 }
 
+/// Verify the container integrity of a single tile, decoding the layers and features of compressed MVT tiles,
+/// but without decoding images
+fn check_tile_content(data: &[u8]) -> Result<(), String> {
+    if data.is_empty() {
+        return Err("zero-byte tile".to_string());
+    }
+    let Some(info) = TileInfo::detect(data) else {
+        // Uncompressed MVT and unknown formats have no container to verify
+        return Ok(());
+    };
+    match (info.format, info.encoding) {
+        (Format::Mvt, Encoding::Gzip) => decode_gzip(data)
+            .map_err(|e| format!("gzip stream does not decompress: {e}"))
+            .and_then(|tile| check_mvt(&tile)),
+        (Format::Mvt, Encoding::Zlib) => decompress_zlib(data).and_then(|tile| check_mvt(&tile)),
+        (Format::Png, _) => check_png(data),
+        (Format::Jpeg, _) if !data.ends_with(b"\xFF\xD9") => {
+            Err("JPEG end of image marker is missing".to_string())
+        }
+        (Format::Gif, _) if !data.ends_with(b"\x3B") => Err("GIF trailer is missing".to_string()),
+        (Format::Webp, _) => {
+            let size = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
+            if u64::from(size) + 8 == data.len() as u64 {
+                Ok(())
+            } else {
+                Err(format!(
+                    "WebP RIFF size {size} does not match the tile size {}",
+                    data.len()
+                ))
+            }
+        }
+        (Format::Json, _) => serde_json::from_slice::<Value>(data)
+            .map(|_| ())
+            .map_err(|e| format!("JSON does not parse: {e}")),
+        _ => Ok(()),
+    }
+}
+
+fn check_mvt(tile: &[u8]) -> Result<(), String> {
+    decode_mvt(tile)
+        .map(|_| ())
+        .map_err(|e| format!("MVT does not decode: {e}"))
+}
+
+/// Decompress a zlib stream, failing unless the end of the stream is reached
+fn decompress_zlib(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut decompress = Decompress::new(true);
+    let mut buf = vec![0; 64 * 1024];
+    let mut tile = Vec::new();
+    loop {
+        let (total_in, total_out) = (decompress.total_in(), decompress.total_out());
+        let input = &data[usize::try_from(total_in).unwrap_or(data.len())..];
+        let status = decompress
+            .decompress(input, &mut buf, FlushDecompress::None)
+            .map_err(|e| format!("zlib stream does not decompress: {e}"))?;
+        let produced = usize::try_from(decompress.total_out() - total_out).unwrap_or_default();
+        tile.extend_from_slice(&buf[..produced]);
+        if status == Status::StreamEnd {
+            return Ok(tile);
+        }
+        if decompress.total_in() == total_in && decompress.total_out() == total_out {
+            return Err("zlib stream is truncated".to_string());
+        }
+    }
+}
+
+/// Walk the PNG chunks, verifying that their lengths fit in the tile and that it ends with an `IEND` chunk
+fn check_png(data: &[u8]) -> Result<(), String> {
+    let mut pos = 8;
+    loop {
+        let Some(header) = data.get(pos..pos + 8) else {
+            return Err(format!(
+                "PNG is truncated at byte {pos}, `IEND` chunk is missing"
+            ));
+        };
+        let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+        // Chunk length, type, data, and CRC
+        let end = pos + 12 + usize::try_from(len).map_err(|e| e.to_string())?;
+        if end > data.len() {
+            return Err(format!(
+                "PNG chunk at byte {pos} has length {len}, exceeding the tile size {}",
+                data.len()
+            ));
+        }
+        if &header[4..8] == b"IEND" {
+            return Ok(());
+        }
+        pos = end;
+    }
+}
+
 #[cfg(test)]
 pub(crate) mod tests {
     use super::*;
@@ -625,4 +742,55 @@ pub(crate) mod tests {
         assert!(matches!(result, Err(AggHashMismatch(..))));
         Ok(())
     }
+
+    #[actix_rt::test]
+    async fn validate_tiles_content() -> MbtResult<()> {
+        let (mut conn, mbt) = open("../tests/fixtures/mbtiles/world_cities.mbtiles").await?;
+        mbt.check_tiles_content(&mut conn, 10).await?;
+        Ok(())
+    }
+
+    #[test]
+    fn tile_content() {
+        use std::io::Write as _;
+
+        // A tile with an empty layer named "a"
+        let tile = b"\x1a\x05\x0a\x01a\x78\x02";
+        let gzip = martin_tile_utils::encode_gzip(tile).unwrap();
+        let mut encoder =
+            flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(tile).unwrap();
+        let zlib = encoder.finish().unwrap();
+        let not_mvt = martin_tile_utils::encode_gzip(b"tile").unwrap();
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        png.extend_from_slice(b"\0\0\0\x0dIHDR");
+        png.extend_from_slice(&[0; 13 + 4]);
+        png.extend_from_slice(b"\0\0\0\0IEND\xAE\x42\x60\x82");
+        let mut webp = b"RIFF\x08\0\0\0WEBP".to_vec();
+        webp.extend_from_slice(b"VP8L");
+
+        for valid in [
+            &gzip[..],
+            &zlib[..],
+            &png[..],
+            &webp[..],
+            &b"{}"[..],
+            &b"\x1a\x00"[..],
+        ] {
+            assert_eq!(check_tile_content(valid), Ok(()));
+        }
+        for invalid in [
+            &[][..],
+            &gzip[..gzip.len() - 4],
+            &not_mvt[..],
+            &zlib[..zlib.len() - 3],
+            &png[..png.len() - 12],
+            &png[..30],
+            &webp[..webp.len() - 1],
+            &b"\xFF\xD8\xFF\xE0\0\x10JFIF"[..],
+            &b"{\"a\":"[..],
+        ] {
+            assert!(check_tile_content(invalid).is_err(), "{invalid:?}");
+        }
+    }
 }
@@ -0,0 +1,37 @@
+#![cfg(feature = "cli")]
+
+use std::process::{Command, Output};
+
+/// Copy the `world_cities` fixture with the given global flags, returning the output of the CLI
+fn run_copy(name: &str, flags: &[&str]) -> String {
+    let dir = std::env::temp_dir().join("mbtiles-cli-log-level");
+    std::fs::create_dir_all(&dir).unwrap();
+    let dst = dir.join(format!("{name}.mbtiles"));
+    let _ = std::fs::remove_file(&dst);
+    let Output { status, stderr, .. } = Command::new(env!("CARGO_BIN_EXE_mbtiles"))
+        .env_remove("RUST_LOG")
+        .args(flags)
+        .arg("copy")
+        .arg("../tests/fixtures/mbtiles/world_cities.mbtiles")
+        .arg(&dst)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8(stderr).unwrap();
+    assert!(status.success(), "{stderr}");
+    std::fs::remove_file(&dst).unwrap();
+    stderr
+}
+
+#[test]
+fn copy_log_levels() {
+    let stderr = run_copy("verbose", &["-v"]);
+    assert!(stderr.contains("Finished writing "), "{stderr}");
+    assert!(stderr.contains("Copying metadata with "), "{stderr}");
+
+    let stderr = run_copy("default", &[]);
+    assert!(stderr.contains("Finished writing "), "{stderr}");
+    assert!(!stderr.contains("Copying metadata with "), "{stderr}");
+
+    let stderr = run_copy("quiet", &["--quiet"]);
+    assert_eq!(stderr, "");
+}
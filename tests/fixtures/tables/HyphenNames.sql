@@ -0,0 +1,16 @@
+DROP SCHEMA IF EXISTS "Hyphen-Schema" CASCADE;
+CREATE SCHEMA "Hyphen-Schema";
+
+CREATE TABLE "Hyphen-Schema"."My-Table"
+(
+    "Row-Id"   SERIAL PRIMARY KEY,
+    "select"   TEXT,
+    "Geom-Col" GEOMETRY(POINT, 4326)
+);
+
+INSERT INTO "Hyphen-Schema"."My-Table"
+values (1, 'first', '0101000020E6100000EC3A2806EDDA61401C2041E87DDA2740'),
+       (2, 'second', '0101000020E61000005947D7F5EF045FC0560BE226301A4BC0'),
+       (3, 'third', '0101000020E6100000E101F56A99164940960D11FF91024540');
+
+CREATE INDEX ON "Hyphen-Schema"."My-Table" USING GIST ("Geom-Col");
@@ -62,3 +62,17 @@ DO $do$ BEGIN
     }
     $$::json || '$tj$';
 END $do$;
+
+CREATE MATERIALIZED VIEW points1_mvw AS
+SELECT gid, geom
+FROM points1;
+
+CREATE INDEX ON points1_mvw USING GIST (geom);
+
+DO $do$ BEGIN
+    EXECUTE 'COMMENT ON MATERIALIZED VIEW points1_mvw IS $tj$' || $$
+    {
+        "description": "description of a materialized view from SQL comment"
+    }
+    $$::json || '$tj$';
+END $do$;